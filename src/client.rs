@@ -1,32 +1,42 @@
 use std::convert::TryInto;
-use std::ffi::{CStr, OsStr};
+use std::ffi::{c_void, CStr, OsStr};
 use std::fs::File;
 use std::io::BufReader;
 use std::slice;
 use std::sync::Arc;
 
 use libc::{c_char, size_t};
+use rustls::internal::msgs::enums::NamedGroup;
 use rustls::{
-    sign::CertifiedKey, Certificate, ClientConfig, ClientSession, ResolvesClientCert,
-    RootCertStore, ServerCertVerified, SupportedCipherSuite, TLSError, ALL_CIPHERSUITES,
+    sign::CertifiedKey, Certificate, ClientConfig, ResolvesClientCert, RootCertStore,
+    ServerCertVerified, SupportedCipherSuite, TLSError, WebPKIVerifier, ALL_CIPHERSUITES,
 };
 
 use webpki::DNSNameRef;
 
 use crate::cipher::{rustls_certified_key, rustls_root_cert_store, rustls_supported_ciphersuite};
 use crate::connection::{rustls_connection, Connection};
-use crate::enums::rustls_tls_version_from_u16;
+use crate::ct::CtLog;
+use crate::der;
+use crate::enums::{
+    kx_groups_from_u16s, rustls_hash_algorithm, rustls_ocsp_policy, rustls_tls_version_from_u16,
+    signature_schemes_from_u16s,
+};
 use crate::error::rustls_result::{InvalidParameter, NullParameter};
 use crate::error::{self, result_to_tlserror, rustls_result};
+use crate::hooks;
 use crate::rslice::NulByte;
 use crate::rslice::{rustls_slice_bytes, rustls_slice_slice_bytes, rustls_str};
+use crate::serialize;
 use crate::session::{
     rustls_session_store_get_callback, rustls_session_store_put_callback, SessionStoreBroker,
-    SessionStoreGetCallback, SessionStorePutCallback,
+    SessionStoreGetCallback, SessionStoreKind, SessionStorePutCallback, TicketReporter,
 };
+use crate::verify::{FetchIntermediates, ReportVerifyFailure, VerifyPolicy};
+use crate::x509;
 use crate::{
-    arc_with_incref_from_raw, ffi_panic_boundary, try_mut_from_ptr, try_ref_from_ptr, try_slice,
-    userdata_get, CastPtr,
+    arc_with_incref_from_raw, ffi_panic_boundary, try_mut_from_ptr, try_mut_slice,
+    try_ref_from_ptr, try_slice, userdata_get, CastPtr,
 };
 
 /// A client config being constructed. A builder can be modified by,
@@ -34,7 +44,7 @@ use crate::{
 /// done configuring settings, call rustls_client_config_builder_build
 /// to turn it into a *rustls_client_config. This object is not safe
 /// for concurrent mutation. Under the hood, it corresponds to a
-/// Box<ClientConfig>.
+/// Box<ClientConfigBuilder>.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html
 pub struct rustls_client_config_builder {
     // We use the opaque struct pattern to tell C about our types without
@@ -44,11 +54,84 @@ pub struct rustls_client_config_builder {
 }
 
 impl CastPtr for rustls_client_config_builder {
-    type RustType = ClientConfig;
+    type RustType = ClientConfigBuilder;
+}
+
+/// A ClientConfig plus settings that can't be stored in, or read back from,
+/// a ClientConfig until it's built.
+pub(crate) struct ClientConfigBuilder {
+    config: ClientConfig,
+    /// Set once any certificate verification option is used. On build, this
+    /// becomes the config's certificate verifier.
+    verify_policy: Option<VerifyPolicy>,
+    /// Key exchange groups set with rustls_client_config_builder_set_kx_groups.
+    kx_groups: Vec<NamedGroup>,
+    /// Made by rustls_client_config_builder_new_no_roots, so building fails
+    /// unless a custom verifier is in place.
+    requires_custom_verifier: bool,
+    /// The certificate verifier was set with
+    /// rustls_client_config_builder_dangerous_set_certificate_verifier, and
+    /// hasn't been replaced since.
+    has_custom_verifier: bool,
+    session_store: SessionStoreKind,
+    /// The session store of the config the builder was made from, until
+    /// rustls_client_config_builder_set_persistence replaces it.
+    inherited_tickets: Option<Arc<TicketReporter>>,
+    /// Added with rustls_client_config_builder_add_client_identity, and
+    /// already installed in the config's resolver.
+    client_identities: Vec<Arc<CertifiedKey>>,
+}
+
+impl ClientConfigBuilder {
+    fn new(config: ClientConfig, session_store: SessionStoreKind) -> Self {
+        ClientConfigBuilder {
+            config,
+            verify_policy: None,
+            kx_groups: Vec::new(),
+            requires_custom_verifier: false,
+            has_custom_verifier: false,
+            session_store,
+            inherited_tickets: None,
+            client_identities: Vec::new(),
+        }
+    }
+
+    fn verify_policy(&mut self) -> &mut VerifyPolicy {
+        self.has_custom_verifier = false;
+        self.verify_policy.get_or_insert_with(VerifyPolicy::default)
+    }
+
+    fn build(self) -> Result<BuiltClientConfig, rustls_result> {
+        if self.requires_custom_verifier && !self.has_custom_verifier {
+            return Err(rustls_result::NoVerifierConfigured);
+        }
+        let mut config = self.config;
+        if let Some(policy) = self.verify_policy {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(policy));
+        }
+        // A config made from another already has that config's
+        // TicketReporter, which mustn't be wrapped in a second one.
+        let tickets = Arc::new(match self.inherited_tickets {
+            Some(parent) => parent.derive(self.kx_groups),
+            None => TicketReporter::new(
+                config.session_persistence.clone(),
+                self.kx_groups,
+                self.session_store,
+            ),
+        });
+        config.session_persistence = tickets.clone();
+        Ok(BuiltClientConfig {
+            config: Arc::new(config),
+            tickets,
+        })
+    }
 }
 
 /// A client config that is done being constructed and is now read-only.
-/// Under the hood, this object corresponds to an Arc<ClientConfig>.
+/// Under the hood, this object corresponds to an Arc<BuiltClientConfig>,
+/// which holds an Arc<ClientConfig>.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html
 pub struct rustls_client_config {
     // We use the opaque struct pattern to tell C about our types without
@@ -58,7 +141,14 @@ pub struct rustls_client_config {
 }
 
 impl CastPtr for rustls_client_config {
-    type RustType = ClientConfig;
+    type RustType = BuiltClientConfig;
+}
+
+pub(crate) struct BuiltClientConfig {
+    config: Arc<ClientConfig>,
+    /// The config's session store, which rustls only lets us see as a
+    /// StoresClientSessions.
+    tickets: Arc<TicketReporter>,
 }
 
 /// Create a rustls_client_config_builder. Caller owns the memory and must
@@ -69,9 +159,37 @@ impl CastPtr for rustls_client_config {
 #[no_mangle]
 pub extern "C" fn rustls_client_config_builder_new() -> *mut rustls_client_config_builder {
     ffi_panic_boundary! {
-        let config = rustls::ClientConfig::new();
-        let b = Box::new(config);
-        Box::into_raw(b) as *mut _
+        let builder = ClientConfigBuilder::new(new_config(), SessionStoreKind::Default);
+        Box::into_raw(Box::new(builder)) as *mut _
+    }
+}
+
+/// A ClientConfig with the defaults for a new builder.
+fn new_config() -> ClientConfig {
+    let mut config = rustls::ClientConfig::new();
+    // Equivalent to rustls' default, but records the peer's signature
+    // schemes.
+    config.client_auth_cert_resolver = Arc::new(ResolvesClientCertFromChoices {
+        keys: Vec::new(),
+        match_issuers: false,
+    });
+    config
+}
+
+/// Create a rustls_client_config_builder for a client that verifies server
+/// certificates only with a custom verifier, and so needs no trusted roots.
+/// Building the config fails with RUSTLS_RESULT_NO_VERIFIER_CONFIGURED
+/// unless a verifier has been set with
+/// rustls_client_config_builder_dangerous_set_certificate_verifier, and
+/// not replaced since by another certificate verification option. Caller
+/// owns the memory, as with rustls_client_config_builder_new.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_new_no_roots() -> *mut rustls_client_config_builder {
+    ffi_panic_boundary! {
+        let builder = rustls_client_config_builder_new();
+        let b: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        b.requires_custom_verifier = true;
+        builder
     }
 }
 
@@ -83,21 +201,60 @@ pub extern "C" fn rustls_client_config_builder_from_config(
     config: *const rustls_client_config,
 ) -> *mut rustls_client_config_builder {
     ffi_panic_boundary! {
-        let config: &ClientConfig = try_ref_from_ptr!(config);
-        Box::into_raw(Box::new(config.clone())) as *mut _
+        let config: &BuiltClientConfig = try_ref_from_ptr!(config);
+        let mut builder =
+            ClientConfigBuilder::new(config.config.as_ref().clone(), SessionStoreKind::Default);
+        builder.kx_groups = config.tickets.kx_groups().to_vec();
+        builder.inherited_tickets = Some(config.tickets.clone());
+        Box::into_raw(Box::new(builder)) as *mut _
     }
 }
 
 /// Turn a *rustls_client_config_builder (mutable) into a *rustls_client_config
-/// (read-only).
+/// (read-only). Returns NULL if the builder was made by
+/// rustls_client_config_builder_new_no_roots and no custom verifier is set;
+/// use rustls_client_config_builder_try_build to get the reason. The
+/// builder is freed either way.
 #[no_mangle]
 pub extern "C" fn rustls_client_config_builder_build(
     builder: *mut rustls_client_config_builder,
 ) -> *const rustls_client_config {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(builder);
-        let b = unsafe { Box::from_raw(config) };
-        Arc::into_raw(Arc::new(*b)) as *const _
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let builder = unsafe { Box::from_raw(builder) };
+        match builder.build() {
+            Ok(config) => Arc::into_raw(Arc::new(config)) as *const _,
+            Err(_) => std::ptr::null(),
+        }
+    }
+}
+
+/// Like rustls_client_config_builder_build, but returns why building
+/// failed, and on success writes the config to `config_out`. Returns
+/// RUSTLS_RESULT_NO_VERIFIER_CONFIGURED if the builder was made by
+/// rustls_client_config_builder_new_no_roots and no custom verifier is set.
+/// The builder is freed either way, unless `config_out` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_try_build(
+    builder: *mut rustls_client_config_builder,
+    config_out: *mut *const rustls_client_config,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let config_out: &mut *const rustls_client_config = unsafe {
+            match config_out.as_mut() {
+                Some(c) => c,
+                None => return NullParameter,
+            }
+        };
+        let builder = unsafe { Box::from_raw(builder) };
+        match builder.build() {
+            Ok(config) => {
+                *config_out = Arc::into_raw(Arc::new(config)) as *const _;
+                rustls_result::Ok
+            }
+            Err(rr) => rr,
+        }
     }
 }
 
@@ -180,14 +337,14 @@ impl rustls::ServerCertVerifier for Verifier {
             }
         };
         let intermediates = rustls_slice_slice_bytes {
-            inner: &*certificates,
+            inner: &certificates,
         };
 
         let params = rustls_verify_server_cert_params {
             roots: (roots as *const RootCertStore) as *const rustls_root_cert_store,
             end_entity_cert_der: end_entity.into(),
             intermediate_certs_der: &intermediates,
-            dns_name: dns_name.into(),
+            dns_name,
             ocsp_response: ocsp_response.into(),
         };
         let userdata = userdata_get().map_err(|_| {
@@ -246,9 +403,494 @@ pub extern "C" fn rustls_client_config_builder_dangerous_set_certificate_verifie
             Some(cb) => cb,
             None => return,
         };
-        let config: &mut ClientConfig = try_mut_from_ptr!(config);
-        let verifier: Verifier = Verifier{callback: callback};
-        config.dangerous().set_certificate_verifier(Arc::new(verifier));
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(config);
+        let verifier: Verifier = Verifier{callback};
+        builder.config.dangerous().set_certificate_verifier(Arc::new(verifier));
+        builder.verify_policy = None;
+        builder.has_custom_verifier = true;
+    }
+}
+
+/// Accepts every server certificate chain.
+struct NoServerCertVerification;
+
+impl rustls::ServerCertVerifier for NoServerCertVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// DANGER: with `verify` false, accept any certificate chain from the server,
+/// for any hostname, without checking it against the trusted roots. This is
+/// only meant for TLS-inspecting proxies that pass the server's certificates
+/// on to be validated elsewhere, for example with
+/// `rustls_root_cert_store_verify_chain`. Anything else that uses it can be
+/// impersonated by any attacker on the network. rustls still checks that
+/// the server holds the private key for the certificate it presents.
+///
+/// With `verify` true, the default verifier is restored. Like a custom
+/// verifier set with
+/// `rustls_client_config_builder_dangerous_set_certificate_verifier`, this
+/// replaces the certificate verification options on the builder, such as
+/// `rustls_client_config_builder_set_verification_time`, and is replaced by
+/// them.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_dangerous_set_verify_server_cert(
+    builder: *mut rustls_client_config_builder,
+    verify: bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let verifier: Arc<dyn rustls::ServerCertVerifier> = if verify {
+            Arc::new(WebPKIVerifier::new())
+        } else {
+            Arc::new(NoServerCertVerification)
+        };
+        builder.config.dangerous().set_certificate_verifier(verifier);
+        builder.verify_policy = None;
+        builder.has_custom_verifier = false;
+        rustls_result::Ok
+    }
+}
+
+/// Verify server certificates as of `unix_seconds` (seconds since the Unix
+/// epoch, UTC) instead of the current system time. This is primarily meant
+/// for tests that need expired or not-yet-valid certificates to behave
+/// deterministically, and for embedded devices without a reliable real-time
+/// clock. It has no effect on a custom verifier installed with
+/// `rustls_client_config_builder_dangerous_set_certificate_verifier`; setting
+/// one replaces this option, and setting this option replaces it.
+///
+/// Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `unix_seconds` is negative.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_verification_time(
+    builder: *mut rustls_client_config_builder,
+    unix_seconds: i64,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let unix_seconds: u64 = match unix_seconds.try_into() {
+            Ok(s) => s,
+            Err(_) => return InvalidParameter,
+        };
+        builder.verify_policy().time =
+            Some(webpki::Time::from_seconds_since_unix_epoch(unix_seconds));
+        rustls_result::Ok
+    }
+}
+
+/// DANGEROUS: when `accept` is true, accept server certificate chains that
+/// have expired but are otherwise valid: trusted, correctly signed, and
+/// issued for the server's name. Use `rustls_connection_peer_cert_is_expired`
+/// after the handshake to find out whether this happened. This is meant for
+/// monitoring tools that need to report on expired certificates, not for
+/// ordinary connections, where an expired certificate may have been
+/// compromised long ago.
+///
+/// Like `rustls_client_config_builder_set_verification_time`, this is
+/// replaced by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_dangerous_accept_expired(
+    builder: *mut rustls_client_config_builder,
+    accept: bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.verify_policy().accept_expired = accept;
+        rustls_result::Ok
+    }
+}
+
+/// Check the OCSP response that the server staples to its certificate,
+/// according to `policy`. The default, `RUSTLS_OCSP_POLICY_IGNORE`, doesn't
+/// look at it. With `RUSTLS_OCSP_POLICY_SOFT_FAIL` a certificate is rejected
+/// if a usable response says it is revoked. With
+/// `RUSTLS_OCSP_POLICY_HARD_FAIL` it is also rejected if there is no usable
+/// response saying it is good, including when the server staples nothing.
+/// Either way, rustls_connection_process_new_packets returns
+/// `RUSTLS_RESULT_CERTIFICATE_REVOKED` for a rejected certificate.
+///
+/// A response is usable if it is signed by the certificate's issuer, which
+/// must be among the certificates the server sent (or that an intermediate
+/// fetch callback supplied), and, unless it reports revocation, if it is
+/// current as of the verification time. Responses signed by a delegated
+/// OCSP responder certificate are not supported, so they are never usable.
+///
+/// The policy is checked only once the certificate chain has otherwise been
+/// verified, and not at all for a server whose key is pinned with
+/// `rustls_client_config_builder_dangerous_set_pinned_spki`. Like
+/// `rustls_client_config_builder_set_verification_time`, this is replaced
+/// by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_ocsp_policy(
+    builder: *mut rustls_client_config_builder,
+    policy: rustls_ocsp_policy,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.verify_policy().ocsp_policy = policy;
+        rustls_result::Ok
+    }
+}
+
+/// Reject servers whose end-entity certificate has an RSA key of fewer than
+/// `bits` bits, for example to enforce a 2048-bit minimum. Keys of other
+/// types are not affected. rustls_connection_process_new_packets returns
+/// `RUSTLS_RESULT_WEAK_KEY` for a rejected certificate. The default, 0,
+/// accepts any size that webpki does; webpki itself rejects RSA keys
+/// smaller than 2048 bits when they are used to sign the handshake.
+///
+/// The key size is checked before anything else, including for a server
+/// whose key is pinned with
+/// `rustls_client_config_builder_dangerous_set_pinned_spki`. Like
+/// `rustls_client_config_builder_set_verification_time`, this is replaced
+/// by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_min_rsa_key_bits(
+    builder: *mut rustls_client_config_builder,
+    bits: u32,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.verify_policy().min_rsa_key_bits = bits;
+        rustls_result::Ok
+    }
+}
+
+/// Restrict the signature schemes the client offers to the server and
+/// accepts in the server's handshake signature to the `count` IANA-assigned
+/// values at `schemes`, for example to forbid RSA PKCS#1 v1.5 signatures.
+/// The order of `schemes` doesn't matter: the schemes are offered in
+/// rustls' order of preference. Returns RUSTLS_RESULT_INVALID_PARAMETER if
+/// the list is empty or contains a scheme rustls doesn't support. Use
+/// `rustls_signature_schemes_for_version` to check that the list leaves
+/// something usable for each enabled protocol version.
+///
+/// Like `rustls_client_config_builder_set_verification_time`, this is
+/// replaced by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_signature_schemes(
+    builder: *mut rustls_client_config_builder,
+    schemes: *const u16,
+    count: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let schemes = match signature_schemes_from_u16s(try_slice!(schemes, count)) {
+            Some(schemes) => schemes,
+            None => return rustls_result::InvalidParameter,
+        };
+        builder.verify_policy().signature_schemes = Some(schemes);
+        rustls_result::Ok
+    }
+}
+
+/// Require the server's certificate to have valid SCTs (Signed Certificate
+/// Timestamps) from at least `min_scts` different Certificate Transparency
+/// logs among the `log_count` at `logs`, each given by its DER-encoded
+/// SubjectPublicKeyInfo, as published in CT log lists. Logs must have ECDSA
+/// P-256 or RSA keys. rustls_connection_process_new_packets returns
+/// `RUSTLS_RESULT_CERTIFICATE_TRANSPARENCY_REQUIRED` for a certificate
+/// that doesn't meet the policy. A `min_scts` of 0 removes the policy.
+/// Returns RUSTLS_RESULT_INVALID_PARAMETER if a key can't be used, or if
+/// `min_scts` is more than `log_count`.
+///
+/// Only SCTs embedded in the certificate count. The version of rustls used
+/// by crustls doesn't pass SCTs sent in the TLS handshake or in a stapled
+/// OCSP response to verifiers. SCTs timestamped after the verification
+/// time don't count.
+///
+/// The policy is checked only once the certificate chain has otherwise been
+/// verified, and not at all for a server whose key is pinned with
+/// `rustls_client_config_builder_dangerous_set_pinned_spki`. Like
+/// `rustls_client_config_builder_set_verification_time`, this is replaced
+/// by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_ct_policy(
+    builder: *mut rustls_client_config_builder,
+    logs: *const rustls_slice_bytes,
+    log_count: size_t,
+    min_scts: u8,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let logs: &[rustls_slice_bytes] = try_slice!(logs, log_count);
+        let mut ct_logs: Vec<CtLog> = Vec::with_capacity(logs.len());
+        for log in logs {
+            match CtLog::new(try_slice!(log.data, log.len)) {
+                Some(log) => ct_logs.push(log),
+                None => return rustls_result::InvalidParameter,
+            }
+        }
+        if min_scts as usize > ct_logs.len() {
+            return rustls_result::InvalidParameter;
+        }
+        let policy = builder.verify_policy();
+        policy.ct_logs = ct_logs;
+        policy.min_scts = min_scts as usize;
+        rustls_result::Ok
+    }
+}
+
+/// Require the server's end-entity certificate to assert the certificate
+/// policy `oid` in its certificate policies extension, as enterprise PKIs
+/// use to mark certificates issued under a particular policy. `oid` is the
+/// DER encoding of the OID's value, without tag and length: for example,
+/// 2.23.140.1.2.1 is the 6 bytes `67 81 0c 01 02 01`. Each call adds a
+/// requirement, and a certificate must meet all of them.
+/// rustls_connection_process_new_packets returns
+/// `RUSTLS_RESULT_CERTIFICATE_POLICY_MISSING` for a certificate that
+/// doesn't. Returns RUSTLS_RESULT_INVALID_PARAMETER if `oid` isn't a valid
+/// OID encoding.
+///
+/// The OID must appear itself: a certificate asserting anyPolicy
+/// (2.5.29.32.0) doesn't meet the requirement, and the policies of
+/// intermediate certificates aren't considered.
+///
+/// The requirement is checked only once the certificate chain has
+/// otherwise been verified, and not at all for a server whose key is
+/// pinned with `rustls_client_config_builder_dangerous_set_pinned_spki`.
+/// Like `rustls_client_config_builder_set_verification_time`, this is
+/// replaced by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_require_cert_policy_oid(
+    builder: *mut rustls_client_config_builder,
+    oid: *const u8,
+    oid_len: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let oid: &[u8] = try_slice!(oid, oid_len);
+        if der::oid_to_string(oid).is_err() {
+            return rustls_result::InvalidParameter;
+        }
+        let policy = builder.verify_policy();
+        if !policy.required_policies.iter().any(|p| p == oid) {
+            policy.required_policies.push(oid.to_vec());
+        }
+        rustls_result::Ok
+    }
+}
+
+/// A list of certificates being collected by a
+/// `rustls_intermediate_fetch_callback`.
+pub struct rustls_intermediate_certs {
+    _private: [u8; 0],
+}
+
+impl CastPtr for rustls_intermediate_certs {
+    type RustType = Vec<Certificate>;
+}
+
+/// Add the DER-encoded certificate in `der` to `certs`. The data is copied.
+/// Returns RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR, and adds nothing, if it
+/// can't be parsed as a certificate.
+#[no_mangle]
+pub extern "C" fn rustls_intermediate_certs_add(
+    certs: *mut rustls_intermediate_certs,
+    der: *const u8,
+    der_len: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let certs: &mut Vec<Certificate> = try_mut_from_ptr!(certs);
+        let der: &[u8] = try_slice!(der, der_len);
+        if webpki::EndEntityCert::from(der).is_err() {
+            return rustls_result::CertificateParseError;
+        }
+        certs.push(Certificate(der.to_vec()));
+        rustls_result::Ok
+    }
+}
+
+/// A callback for `rustls_client_config_builder_set_intermediate_fetch_callback`.
+/// `cert` is the DER encoding of the certificate whose issuer couldn't be
+/// found, and `issuer` the DER encoding of its issuer's Name, for looking
+/// up a certificate by subject. Both are only valid during the callback.
+/// The callback adds any certificates it finds with
+/// `rustls_intermediate_certs_add`; `certs` is also only valid during the
+/// callback.
+#[allow(non_camel_case_types)]
+pub type rustls_intermediate_fetch_callback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        cert: *const rustls_slice_bytes,
+        issuer: *const rustls_slice_bytes,
+        certs: *mut rustls_intermediate_certs,
+    ),
+>;
+
+/// Fetches intermediates using a C callback.
+struct IntermediateFetcher {
+    callback: unsafe extern "C" fn(
+        userdata: *mut c_void,
+        cert: *const rustls_slice_bytes,
+        issuer: *const rustls_slice_bytes,
+        certs: *mut rustls_intermediate_certs,
+    ),
+    userdata: *mut c_void,
+}
+
+/// Safety: the callback and userdata must be usable from any thread, as
+/// documented in rustls_client_config_builder_set_intermediate_fetch_callback.
+unsafe impl Send for IntermediateFetcher {}
+unsafe impl Sync for IntermediateFetcher {}
+
+impl FetchIntermediates for IntermediateFetcher {
+    fn fetch(&self, cert: &Certificate) -> Vec<Certificate> {
+        let issuer: &[u8] = match x509::issuer(&cert.0) {
+            Ok(issuer) => issuer,
+            Err(_) => return Vec::new(),
+        };
+        let mut certs: Vec<Certificate> = Vec::new();
+        let cert: rustls_slice_bytes = cert.0.as_slice().into();
+        let issuer: rustls_slice_bytes = issuer.into();
+        let out = &mut certs as *mut Vec<Certificate> as *mut rustls_intermediate_certs;
+        unsafe { (self.callback)(self.userdata, &cert, &issuer, out) };
+        certs
+    }
+}
+
+/// Set a callback to supply intermediate certificates that a server failed
+/// to send, for instance by following the certificate's Authority
+/// Information Access extension (AIA chasing). If the server's chain doesn't
+/// lead to a trusted root because an issuer is missing, `callback` is called
+/// with the last certificate in the chain. Any certificates it adds are
+/// appended to the chain and verification is tried again, asking again for
+/// the next missing issuer, up to four times per verification. A NULL
+/// `callback` removes the callback.
+///
+/// Fetched certificates are treated exactly like ones the server sent: the
+/// completed chain must still lead to one of the config's trusted roots,
+/// with every signature, validity period and name constraint checked. So a
+/// fetcher can't make an untrusted server trusted, and may fetch over plain
+/// HTTP. It can still be used to make verification slow, so it should use
+/// timeouts and cache results.
+///
+/// `userdata` is passed to every call of `callback`, for every connection
+/// made with the config. The callback must be safe to call on any thread
+/// at any time, including multiple concurrent calls. It is called from
+/// within `rustls_connection_process_new_packets`, so a slow fetch blocks
+/// that call.
+///
+/// Like `rustls_client_config_builder_set_verification_time`, this is
+/// replaced by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_intermediate_fetch_callback(
+    builder: *mut rustls_client_config_builder,
+    callback: rustls_intermediate_fetch_callback,
+    userdata: *mut c_void,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.verify_policy().fetch_intermediates = match callback {
+            Some(callback) => Some(Arc::new(IntermediateFetcher { callback, userdata })),
+            None => None,
+        };
+        rustls_result::Ok
+    }
+}
+
+/// A callback for `rustls_client_config_builder_set_verify_report_callback`.
+/// `result` is what `rustls_connection_process_new_packets` would have
+/// returned for the server's certificate.
+#[allow(non_camel_case_types)]
+pub type rustls_verify_report_callback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, result: rustls_result)>;
+
+/// Reports verification failures to a C callback.
+struct VerifyReporter {
+    callback: unsafe extern "C" fn(userdata: *mut c_void, result: rustls_result),
+    userdata: *mut c_void,
+}
+
+/// Safety: the callback and userdata must be usable from any thread, as
+/// documented in rustls_client_config_builder_set_verify_report_callback.
+unsafe impl Send for VerifyReporter {}
+unsafe impl Sync for VerifyReporter {}
+
+impl ReportVerifyFailure for VerifyReporter {
+    fn report(&self, result: rustls_result) {
+        unsafe { (self.callback)(self.userdata, result) };
+    }
+}
+
+/// DANGER: verify server certificates as usual, but instead of failing the
+/// handshake when a certificate is rejected, call `callback` with the error
+/// that `rustls_connection_process_new_packets` would have returned and
+/// carry on with the handshake. A NULL `callback` removes the callback and
+/// enforces verification again.
+///
+/// This is for a "report-only" phase when tightening verification, for
+/// instance before requiring SCTs or certificate policies: it counts the
+/// connections that the stricter policy would break, without breaking
+/// them. While it is set, the config doesn't authenticate servers at all,
+/// and any attacker on the network can impersonate one, so only use it for
+/// as long as the measurement needs, and never where the connection's
+/// security matters. rustls still checks that the server holds the private
+/// key for the certificate it presents, and a server still fails if its
+/// handshake signature uses a scheme excluded by
+/// `rustls_client_config_builder_set_signature_schemes`.
+///
+/// `userdata` is passed to every call of `callback`, for every connection
+/// made with the config. The callback must be safe to call on any thread
+/// at any time, including multiple concurrent calls. It is called from
+/// within `rustls_connection_process_new_packets`.
+///
+/// Like `rustls_client_config_builder_set_verification_time`, this is
+/// replaced by a custom verifier and vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_verify_report_callback(
+    builder: *mut rustls_client_config_builder,
+    callback: rustls_verify_report_callback,
+    userdata: *mut c_void,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.verify_policy().report_only = match callback {
+            Some(callback) => Some(Arc::new(VerifyReporter { callback, userdata })),
+            None => None,
+        };
+        rustls_result::Ok
+    }
+}
+
+/// DANGEROUS: pin the server's public key. `spki_sha256` must point to the
+/// 32-byte SHA-256 hash of a DER-encoded SubjectPublicKeyInfo, as used by
+/// HPKP (RFC 7469). Call this more than once to pin several keys, such as a
+/// primary and a backup; a server whose end-entity certificate has any of
+/// the pinned keys is accepted.
+///
+/// Once a key is pinned, the server's certificate chain is not otherwise
+/// checked at all: the trusted roots, the certificate's validity period and
+/// the names it is issued for are all ignored. This is only appropriate when
+/// the server's key is known in advance and its private key is as well
+/// protected as a CA's.
+///
+/// Like `rustls_client_config_builder_set_verification_time`, this is
+/// replaced by a custom verifier and vice versa.
+///
+/// Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `len` is not 32.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_dangerous_set_pinned_spki(
+    builder: *mut rustls_client_config_builder,
+    spki_sha256: *const u8,
+    len: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let spki_sha256: &[u8] = try_slice!(spki_sha256, len);
+        let pin: [u8; 32] = match spki_sha256.try_into() {
+            Ok(pin) => pin,
+            Err(_) => return InvalidParameter,
+        };
+        builder.verify_policy().pinned_spki.push(pin);
+        rustls_result::Ok
     }
 }
 
@@ -264,7 +906,7 @@ pub extern "C" fn rustls_client_config_builder_use_roots(
     roots: *const rustls_root_cert_store,
 ) {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(config);
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(config).config;
         let root_store: &RootCertStore = try_ref_from_ptr!(roots);
         config.root_store = root_store.clone();
     }
@@ -284,7 +926,7 @@ pub extern "C" fn rustls_client_config_builder_load_roots_from_file(
             }
             CStr::from_ptr(filename)
         };
-        let config: &mut ClientConfig = try_mut_from_ptr!(config);
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(config).config;
         let filename: &[u8] = filename.to_bytes();
         let filename: &str = match std::str::from_utf8(filename) {
             Ok(s) => s,
@@ -319,7 +961,7 @@ pub extern "C" fn rustls_client_config_builder_set_versions(
     len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(builder);
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(builder).config;
         let tls_versions: &[u16] = try_slice!(tls_versions, len);
         config.versions.clear();
 
@@ -333,6 +975,40 @@ pub extern "C" fn rustls_client_config_builder_set_versions(
     }
 }
 
+/// Set the key exchange groups the client prefers, as `count` IANA-assigned
+/// NamedGroup values at `groups`, most preferred first. rustls supports
+/// X25519 (0x001d), secp384r1 (0x0018) and secp256r1 (0x0017), and by
+/// default prefers them in that order. Returns
+/// RUSTLS_RESULT_INVALID_PARAMETER if the list is empty or contains a group
+/// rustls doesn't support.
+///
+/// A TLS 1.3 client sends a key share for just one group in its
+/// ClientHello. If the server doesn't accept that group, it sends a
+/// HelloRetryRequest, costing a round trip. The client sends its share for
+/// the first group in this list, unless an earlier connection to the same
+/// server, using the same config, chose another group in the list; then it
+/// guesses that group instead.
+///
+/// The version of rustls used by crustls always advertises all of its
+/// groups, in its default order, so this doesn't stop the server from
+/// choosing a group that isn't listed, and doesn't affect TLS 1.2, where
+/// the server chooses the group.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_kx_groups(
+    builder: *mut rustls_client_config_builder,
+    groups: *const u16,
+    count: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.kx_groups = match kx_groups_from_u16s(try_slice!(groups, count)) {
+            Some(groups) => groups,
+            None => return rustls_result::InvalidParameter,
+        };
+        rustls_result::Ok
+    }
+}
+
 /// Set the ALPN protocol list to the given protocols. `protocols` must point
 /// to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
 /// elements. Each element of the buffer must be a rustls_slice_bytes whose
@@ -351,7 +1027,7 @@ pub extern "C" fn rustls_client_config_builder_set_protocols(
     len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(builder);
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(builder).config;
         let protocols: &[rustls_slice_bytes] = try_slice!(protocols, len);
 
         let mut vv: Vec<Vec<u8>> = Vec::with_capacity(protocols.len());
@@ -364,7 +1040,14 @@ pub extern "C" fn rustls_client_config_builder_set_protocols(
     }
 }
 
-/// Enable or disable SNI.
+/// Enable or disable sending SNI (Server Name Indication), which is enabled
+/// by default. When disabled, the ClientHello has no server_name extension.
+///
+/// This only controls what is sent to the server. The hostname passed to
+/// rustls_client_connection_new is still used to verify the server's
+/// certificate either way, so disabling SNI does not weaken verification,
+/// and a server that picks its certificate based on SNI will have to fall
+/// back to a default one.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html#structfield.enable_sni
 #[no_mangle]
 pub extern "C" fn rustls_client_config_builder_set_enable_sni(
@@ -372,11 +1055,166 @@ pub extern "C" fn rustls_client_config_builder_set_enable_sni(
     enable: bool,
 ) {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(config);
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(config).config;
         config.enable_sni = enable;
     }
 }
 
+/// Enable or disable TLS certificate compression (RFC 8879). When disabled,
+/// no compression algorithms are advertised to the server.
+///
+/// The version of rustls used by crustls has no support for RFC 8879, so
+/// certificate compression is never negotiated and this call has no
+/// effect. Passing `false` matches that and returns RUSTLS_RESULT_OK;
+/// passing `true` returns RUSTLS_RESULT_UNIMPLEMENTED, so callers aren't
+/// told compression is on. Once crustls moves to a rustls release that
+/// supports compression, `true` will enable it.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_cert_compression(
+    builder: *mut rustls_client_config_builder,
+    enabled: bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        if enabled {
+            return rustls_result::Unimplemented;
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Enable or disable TLS 1.3 middlebox compatibility mode (RFC 8446 appendix
+/// D.4), in which the client sends a legacy session ID and a dummy
+/// ChangeCipherSpec record so the handshake looks more like TLS 1.2 resumption
+/// to middleboxes that would otherwise interfere with it.
+///
+/// The version of rustls used by crustls always sends the compatibility
+/// messages in TLS 1.3 handshakes and cannot turn them off, so this changes
+/// nothing: compatibility mode is always enabled. Passing `true` returns
+/// RUSTLS_RESULT_OK; passing `false` returns RUSTLS_RESULT_UNSUPPORTED.
+/// Returns RUSTLS_RESULT_NULL_PARAMETER if `builder` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_tls13_compat_mode(
+    builder: *mut rustls_client_config_builder,
+    enabled: bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        if !enabled {
+            return rustls_result::Unsupported;
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Set the ECHConfigList to use for Encrypted Client Hello, as published in
+/// the server's DNS HTTPS record (draft-ietf-tls-esni).
+///
+/// PLACEHOLDER: rustls 0.19 doesn't implement ECH, so this returns
+/// RUSTLS_RESULT_UNIMPLEMENTED for any well-formed `ech_config_list`, and
+/// the ClientHello is sent unencrypted as before. It returns
+/// RUSTLS_RESULT_INVALID_PARAMETER if `ech_config_list` is not a
+/// well-formed ECHConfigList. Don't rely on it for privacy.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_ech_config(
+    builder: *mut rustls_client_config_builder,
+    ech_config_list: *const u8,
+    len: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let ech_config_list: &[u8] = try_slice!(ech_config_list, len);
+        if !is_ech_config_list(ech_config_list) {
+            return rustls_result::InvalidParameter;
+        }
+        rustls_result::Unimplemented
+    }
+}
+
+/// Add an external pre-shared key (RFC 8446 section 2.2) for TLS 1.3, to
+/// authenticate both sides without certificates, as some IoT deployments
+/// do. `identity` is the name the server knows the key by, and `key` is the
+/// secret itself. The key is used with cipher suites whose hash is `hash`.
+/// See also rustls_server_config_builder_set_external_psk_resolver.
+///
+/// PLACEHOLDER: rustls 0.19 only supports pre-shared keys for session
+/// resumption, so this always returns RUSTLS_RESULT_UNIMPLEMENTED and adds
+/// nothing.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_add_external_psk(
+    builder: *mut rustls_client_config_builder,
+    identity: rustls_slice_bytes,
+    key: rustls_slice_bytes,
+    hash: rustls_hash_algorithm,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let _ = (identity, key, hash);
+        rustls_result::Unimplemented
+    }
+}
+
+// Check the framing of an ECHConfigList: a non-empty, u16-length-prefixed
+// list of ECHConfigs, each a u16 version followed by u16-length-prefixed
+// contents. The contents depend on the version, so they aren't examined.
+fn is_ech_config_list(input: &[u8]) -> bool {
+    fn take_u16_prefixed<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+        if input.len() < 2 {
+            return None;
+        }
+        let len = u16::from_be_bytes([input[0], input[1]]) as usize;
+        let rest = &input[2..];
+        if rest.len() < len {
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        *input = rest;
+        Some(value)
+    }
+
+    let mut input = input;
+    let mut list = match take_u16_prefixed(&mut input) {
+        Some(list) if input.is_empty() && !list.is_empty() => list,
+        _ => return false,
+    };
+    while !list.is_empty() {
+        if list.len() < 2 {
+            return false;
+        }
+        list = &list[2..];
+        if take_u16_prefixed(&mut list).is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Set the largest plaintext fragment that will be put in one outgoing TLS
+/// record, for example to keep records within a path MTU. Larger writes are
+/// split across several records. `max_fragment_size` must be between 32 and
+/// 16384 (the TLS maximum and the default), or 0 to restore the default.
+/// This only affects what is sent; it does not negotiate the max_fragment_length
+/// extension with the peer.
+///
+/// Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `max_fragment_size` is out
+/// of range.
+/// https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html#structfield.mtu
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_set_max_fragment_size(
+    builder: *mut rustls_client_config_builder,
+    max_fragment_size: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(builder).config;
+        config.mtu = match max_fragment_size {
+            0 => None,
+            32..=16384 => Some(max_fragment_size),
+            _ => return InvalidParameter,
+        };
+        rustls_result::Ok
+    }
+}
+
 /// Set the cipher suite list, in preference order. The `ciphersuites`
 /// parameter must point to an array containing `len` pointers to
 /// `rustls_supported_ciphersuite` previously obtained from
@@ -389,10 +1227,10 @@ pub extern "C" fn rustls_client_config_builder_set_ciphersuites(
     len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(builder);
+        let config: &mut ClientConfig = &mut try_mut_from_ptr!(builder).config;
         let ciphersuites: &[*const rustls_supported_ciphersuite] = try_slice!(ciphersuites, len);
         let mut cs_vec: Vec<&'static SupportedCipherSuite> = Vec::new();
-        for &cs in ciphersuites.into_iter() {
+        for &cs in ciphersuites.iter() {
             let cs = try_ref_from_ptr!(cs);
             match ALL_CIPHERSUITES.iter().find(|&acs| cs.eq(acs)) {
                 Some(scs) => cs_vec.push(scs),
@@ -423,7 +1261,7 @@ pub extern "C" fn rustls_client_config_builder_set_certified_key(
     certified_keys_len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(builder);
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
         let keys_ptrs: &[*const rustls_certified_key] = try_slice!(certified_keys, certified_keys_len);
         let mut keys: Vec<Arc<CertifiedKey>> = Vec::new();
         for &key_ptr in keys_ptrs {
@@ -436,28 +1274,79 @@ pub extern "C" fn rustls_client_config_builder_set_certified_key(
             };
             keys.push(certified_key);
         }
-        config.client_auth_cert_resolver = Arc::new(ResolvesClientCertFromChoices { keys });
+        builder.client_identities.clear();
+        builder.config.client_auth_cert_resolver = Arc::new(ResolvesClientCertFromChoices {
+            keys,
+            match_issuers: false,
+        });
+        rustls_result::Ok
+    }
+}
+
+/// Add a client identity, a certificate chain and private key, for the
+/// server to choose among. Call this once for each identity. When a server
+/// asks for a client certificate, crustls sends the first identity, in the
+/// order they were added, that:
+///
+/// - has a key that can make a signature with one of the schemes the
+///   server accepts, and
+/// - has a certificate in its chain issued by one of the certificate
+///   authorities the server lists as acceptable, if it lists any.
+///
+/// If the server lists no authorities, the first identity with a usable key
+/// is sent. If no identity qualifies, no certificate is sent, and it is up
+/// to the server whether to continue without one.
+///
+/// A chain should include the intermediates up to the root, or at least
+/// one certificate issued by the authority the server will name. The built
+/// configuration keeps a reference to each certified key, as with
+/// `rustls_client_config_builder_set_certified_key`. The identities replace
+/// any keys set with `rustls_client_config_builder_set_certified_key`, and
+/// vice versa.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_builder_add_client_identity(
+    builder: *mut rustls_client_config_builder,
+    key: *const rustls_certified_key,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        let key: &CertifiedKey = try_ref_from_ptr!(key);
+        let key: Arc<CertifiedKey> = unsafe { arc_with_incref_from_raw(key) };
+        builder.client_identities.push(key);
+        builder.config.client_auth_cert_resolver = Arc::new(ResolvesClientCertFromChoices {
+            keys: builder.client_identities.clone(),
+            match_issuers: true,
+        });
         rustls_result::Ok
     }
 }
 
-/// Always send the same client certificate.
+/// Sends the first of several client certificates that suits the server.
 struct ResolvesClientCertFromChoices {
     keys: Vec<Arc<CertifiedKey>>,
+    /// Only send a certificate whose chain reaches one of the certificate
+    /// authorities the server names, if it names any.
+    match_issuers: bool,
 }
 
 impl ResolvesClientCert for ResolvesClientCertFromChoices {
     fn resolve(
         &self,
-        _acceptable_issuers: &[&[u8]],
+        acceptable_issuers: &[&[u8]],
         sig_schemes: &[rustls::SignatureScheme],
     ) -> Option<rustls::sign::CertifiedKey> {
-        for key in self.keys.iter() {
-            if key.key.choose_scheme(sig_schemes).is_some() {
-                return Some(key.as_ref().clone());
-            }
+        let schemes = sig_schemes.iter().map(|s| s.get_u16()).collect();
+        hooks::report(|r| r.peer_signature_schemes = Some(schemes));
+        let check_issuers = self.match_issuers && !acceptable_issuers.is_empty();
+        let key = self
+            .keys
+            .iter()
+            .filter(|key| key.key.choose_scheme(sig_schemes).is_some())
+            .find(|key| !check_issuers || chain_has_issuer(&key.cert, acceptable_issuers))?;
+        if !key.cert.is_empty() {
+            hooks::report(|r| r.client_cert_sent = true);
         }
-        None
+        Some(key.as_ref().clone())
     }
 
     fn has_certs(&self) -> bool {
@@ -465,6 +1354,42 @@ impl ResolvesClientCert for ResolvesClientCertFromChoices {
     }
 }
 
+/// Return true if `conn` is a client connection that has completed its
+/// handshake, and sent the server a certificate chain during it. In TLS
+/// 1.3 the client's handshake completes with its certificate still queued
+/// for `rustls_connection_write_tls`, and this already counts it. This is
+/// for a client to confirm that its client certificate configuration took
+/// effect: a server that doesn't ask for a certificate isn't sent one, and
+/// neither is one whose acceptable certificate authorities or signature
+/// schemes none of the client's certificates suit.
+///
+/// A resumed session doesn't send a certificate, so this is false for it,
+/// even if the original handshake sent one. Returns false for server
+/// connections, while handshaking, and if `conn` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_connection_client_cert_was_sent(conn: *const rustls_connection) -> bool {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        conn.client_cert_was_sent()
+    }
+}
+
+/// Whether any certificate in `chain` was issued by one of `issuers`, the
+/// DER-encoded Names from a CertificateRequest. Names sent without their
+/// outer SEQUENCE, as some servers do, match too.
+fn chain_has_issuer(chain: &[Certificate], issuers: &[&[u8]]) -> bool {
+    chain.iter().any(|cert| {
+        let name = match x509::issuer(&cert.0) {
+            Ok(name) => name,
+            Err(_) => return false,
+        };
+        let contents = der::expect_only(name, der::SEQUENCE).ok();
+        issuers
+            .iter()
+            .any(|&issuer| issuer == name || Some(issuer) == contents)
+    })
+}
+
 /// "Free" a client_config_builder before transmogrifying it into a client_config.
 /// Normally builders are consumed to client_configs via `rustls_client_config_builder_build`
 /// and may not be free'd or otherwise used afterwards.
@@ -473,9 +1398,9 @@ impl ResolvesClientCert for ResolvesClientCertFromChoices {
 #[no_mangle]
 pub extern "C" fn rustls_client_config_builder_free(config: *mut rustls_client_config_builder) {
     ffi_panic_boundary! {
-        let config: &mut ClientConfig = try_mut_from_ptr!(config);
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(config);
         // Convert the pointer to a Box and drop it.
-        unsafe { Box::from_raw(config); }
+        unsafe { drop(Box::from_raw(builder)) }
     }
 }
 
@@ -488,20 +1413,104 @@ pub extern "C" fn rustls_client_config_builder_free(config: *mut rustls_client_c
 #[no_mangle]
 pub extern "C" fn rustls_client_config_free(config: *const rustls_client_config) {
     ffi_panic_boundary! {
-        let config: &ClientConfig = try_ref_from_ptr!(config);
+        let config: &BuiltClientConfig = try_ref_from_ptr!(config);
         // To free the client_config, we reconstruct the Arc and then drop it. It should
         // have a refcount of 1, representing the C code's copy. When it drops, that
-        // refcount will go down to 0 and the inner ClientConfig will be dropped.
+        // refcount will go down to 0 and the inner ClientConfig will be dropped once
+        // no connection uses it.
         unsafe { drop(Arc::from_raw(config)) };
     }
 }
 
+/// Serialize the protocol versions, cipher suites, ALPN protocols and
+/// trusted roots of `config` into `buf`, which can hold up to `len` bytes,
+/// and store the number of bytes written in `out_n`. Load them with
+/// rustls_client_config_deserialize, for example in a later process, to
+/// avoid parsing the root certificates again.
+///
+/// No other settings are saved. In particular, client certificates and
+/// their private keys, session tickets, callbacks and certificate
+/// verification options are not, so the output contains nothing secret.
+///
+/// Returns RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if `buf` is
+/// too small; in that case the number of bytes needed is stored in `out_n`.
+/// Returns RUSTLS_RESULT_INVALID_PARAMETER if an ALPN protocol name is
+/// longer than 255 bytes or a root certificate's subject is longer than
+/// 65535 bytes.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_serialize(
+    config: *const rustls_client_config,
+    buf: *mut u8,
+    len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let config: &BuiltClientConfig = try_ref_from_ptr!(config);
+        let write_buf: &mut [u8] = try_mut_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let encoded = match serialize::encode(&config.config) {
+            Some(encoded) => encoded,
+            None => return rustls_result::InvalidParameter,
+        };
+        *out_n = encoded.len();
+        if encoded.len() > write_buf.len() {
+            return rustls_result::InsufficientSize;
+        }
+        write_buf[..encoded.len()].copy_from_slice(&encoded);
+        rustls_result::Ok
+    }
+}
+
+/// Create a client config from the output of rustls_client_config_serialize,
+/// and on success write it to `config_out`. The config has the serialized
+/// protocol versions, cipher suites, ALPN protocols and trusted roots, and
+/// the defaults of rustls_client_config_builder_new for everything else. To
+/// change other settings, pass it to
+/// rustls_client_config_builder_from_config. The caller owns the config and
+/// must free it with rustls_client_config_free.
+///
+/// Returns RUSTLS_RESULT_INVALID_PARAMETER if `buf` isn't a serialized
+/// config, for example because it is truncated or was made by an
+/// incompatible version of crustls.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_deserialize(
+    buf: *const u8,
+    len: size_t,
+    config_out: *mut *const rustls_client_config,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let input: &[u8] = try_slice!(buf, len);
+        let config_out: &mut *const rustls_client_config = unsafe {
+            match config_out.as_mut() {
+                Some(c) => c,
+                None => return NullParameter,
+            }
+        };
+        let mut builder = ClientConfigBuilder::new(new_config(), SessionStoreKind::Default);
+        if serialize::decode(input, &mut builder.config).is_err() {
+            return rustls_result::InvalidParameter;
+        }
+        match builder.build() {
+            Ok(config) => {
+                *config_out = Arc::into_raw(Arc::new(config)) as *const _;
+                rustls_result::Ok
+            }
+            Err(rr) => rr,
+        }
+    }
+}
+
 /// Create a new rustls_connection containing a client connection and return it
 /// in the output parameter `out`. If this returns an error code, the memory
 /// pointed to by `session_out` remains unchanged.
 /// If this returns a non-error, the memory pointed to by `conn_out` is modified to point
 /// at a valid rustls_connection. The caller now owns the rustls_connection and must call
 /// `rustls_client_connection_free` when done with it.
+///
+/// `hostname` must be a DNS name. The version of webpki used by crustls
+/// can't verify certificates issued for IP addresses, so an IP address
+/// literal such as "192.0.2.1" is rejected with
+/// RUSTLS_RESULT_INVALID_DNS_NAME_ERROR rather than used as a hostname.
 #[no_mangle]
 pub extern "C" fn rustls_client_connection_new(
     config: *const rustls_client_config,
@@ -515,12 +1524,7 @@ pub extern "C" fn rustls_client_connection_new(
             }
             CStr::from_ptr(hostname)
         };
-        let config: Arc<ClientConfig> = unsafe {
-            match (config as *const ClientConfig).as_ref() {
-                Some(c) => arc_with_incref_from_raw(c),
-                None => return NullParameter,
-            }
-        };
+        let config: &BuiltClientConfig = try_ref_from_ptr!(config);
         let hostname: &str = match hostname.to_str() {
             Ok(s) => s,
             Err(std::str::Utf8Error { .. }) => return rustls_result::InvalidDnsNameError,
@@ -533,7 +1537,7 @@ pub extern "C" fn rustls_client_connection_new(
         // We've succeeded. Put the client on the heap, and transfer ownership
         // to the caller. After this point, we must return CRUSTLS_OK so the
         // caller knows it is responsible for this memory.
-        let c = Connection::from_client( ClientSession::new(&config, name_ref));
+        let c = Connection::from_client(&config.config, name_ref);
         unsafe {
             *conn_out = Box::into_raw(Box::new(c)) as *mut _;
         }
@@ -565,10 +1569,1052 @@ pub extern "C" fn rustls_client_config_builder_set_persistence(
             Some(cb) => cb,
             None => return rustls_result::NullParameter,
         };
-        let config: &mut ClientConfig = try_mut_from_ptr!(builder);
-        config.set_persistence(Arc::new(SessionStoreBroker::new(
+        let builder: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+        builder.config.set_persistence(Arc::new(SessionStoreBroker::new(
             get_cb, put_cb
         )));
+        builder.session_store = SessionStoreKind::Callbacks;
+        builder.inherited_tickets = None;
         rustls_result::Ok
     }
 }
+
+/// Make connections created from `config` from now on perform full
+/// handshakes with every server, rather than resuming sessions stored
+/// before this call. Connections that already exist aren't affected; see
+/// `rustls_connection_forget_resumption`. Sessions stored afterwards are
+/// used as usual.
+///
+/// Resumption state lives in the config's session store, shared by every
+/// connection created from it, which is either an in-memory cache or the
+/// callbacks set with `rustls_client_config_builder_set_persistence`. The
+/// cache's copies are erased when they are next looked up. The callbacks
+/// can't be reached outside a connection, so the sessions the application
+/// stores are only ignored by this config, and the application should
+/// delete them itself. A config
+/// built from a builder made by `rustls_client_config_builder_from_config`
+/// clears the sessions it shares with the original config, for both configs.
+#[no_mangle]
+pub extern "C" fn rustls_client_config_clear_session_cache(
+    config: *const rustls_client_config,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let config: &BuiltClientConfig = try_ref_from_ptr!(config);
+        config.tickets.clear();
+        rustls_result::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::{rustls_root_cert_store_add_pem, rustls_root_cert_store_new};
+    use crate::testutil;
+    use std::ffi::c_void;
+
+    fn new_builder() -> *mut rustls_client_config_builder {
+        let builder = rustls_client_config_builder_new();
+        let store = rustls_root_cert_store_new();
+        let ca = testutil::CA_PEM;
+        let result = rustls_root_cert_store_add_pem(store, ca.as_ptr(), ca.len(), true);
+        assert_eq!(result, rustls_result::Ok);
+        rustls_client_config_builder_use_roots(builder, store);
+        crate::cipher::rustls_root_cert_store_free(store);
+        builder
+    }
+
+    fn build(builder: *mut rustls_client_config_builder) -> Arc<ClientConfig> {
+        let config = rustls_client_config_builder_build(builder);
+        config_of(config)
+    }
+
+    /// The ClientConfig of `config`, which is freed.
+    fn config_of(config: *const rustls_client_config) -> Arc<ClientConfig> {
+        let built = unsafe { Arc::from_raw(config as *const BuiltClientConfig) };
+        built.config.clone()
+    }
+
+    /// The ClientConfig of `config`, which the caller still owns.
+    fn config_in(config: *const rustls_client_config) -> Arc<ClientConfig> {
+        let built: &BuiltClientConfig = crate::try_from(config).unwrap();
+        built.config.clone()
+    }
+
+    fn connect(client_config: &Arc<ClientConfig>, cert_pem: &[u8]) -> Result<(), TLSError> {
+        let server_config = Arc::new(testutil::server_config_with_cert(cert_pem));
+        let (mut client, mut server) = testutil::connection_pair(client_config, &server_config);
+        testutil::handshake(&mut client, &mut server)
+    }
+
+    #[test]
+    fn test_verification_time() {
+        let result = connect(&build(new_builder()), testutil::LOCALHOST_2030_PEM);
+        assert!(matches!(
+            result,
+            Err(TLSError::WebPKIError(webpki::Error::CertNotValidYet))
+        ));
+
+        // 2030-06-01T00:00:00Z
+        let builder = new_builder();
+        let result = rustls_client_config_builder_set_verification_time(builder, 1906502400);
+        assert_eq!(result, rustls_result::Ok);
+        let config = build(builder);
+        assert!(connect(&config, testutil::LOCALHOST_2030_PEM).is_ok());
+
+        // 2020-01-01T00:00:00Z, before any of the test certificates.
+        let builder = new_builder();
+        let result = rustls_client_config_builder_set_verification_time(builder, 1577836800);
+        assert_eq!(result, rustls_result::Ok);
+        assert!(matches!(
+            connect(&build(builder), testutil::LOCALHOST_PEM),
+            Err(TLSError::WebPKIError(webpki::Error::CertNotValidYet))
+        ));
+
+        let builder = new_builder();
+        let result = rustls_client_config_builder_set_verification_time(builder, -1);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        rustls_client_config_builder_free(builder);
+    }
+
+    fn connect_and_check_expired(
+        client_config: &Arc<ClientConfig>,
+        cert_pem: &[u8],
+    ) -> Result<bool, TLSError> {
+        let server_config = Arc::new(testutil::server_config_with_cert(cert_pem));
+        let (mut client, mut server) = testutil::connection_pair(client_config, &server_config);
+        testutil::handshake(&mut client, &mut server)?;
+        let client = &client as *const Connection as *const rustls_connection;
+        Ok(crate::connection::rustls_connection_peer_cert_is_expired(
+            client,
+        ))
+    }
+
+    #[test]
+    fn test_dangerous_accept_expired() {
+        let config = build(new_builder());
+        assert!(matches!(
+            connect_and_check_expired(&config, testutil::LOCALHOST_EXPIRED_PEM),
+            Err(TLSError::WebPKIError(webpki::Error::CertExpired))
+        ));
+
+        let builder = new_builder();
+        let result = rustls_client_config_builder_dangerous_accept_expired(builder, true);
+        assert_eq!(result, rustls_result::Ok);
+        let config = build(builder);
+        assert_eq!(
+            connect_and_check_expired(&config, testutil::LOCALHOST_EXPIRED_PEM),
+            Ok(true)
+        );
+        assert_eq!(
+            connect_and_check_expired(&config, testutil::LOCALHOST_PEM),
+            Ok(false)
+        );
+        // Expiry is the only thing that's forgiven.
+        assert!(matches!(
+            connect_and_check_expired(&config, testutil::LOCALHOST_2030_PEM),
+            Err(TLSError::WebPKIError(webpki::Error::CertNotValidYet))
+        ));
+    }
+
+    fn peer_signature_schemes(conn: &Connection) -> Vec<u16> {
+        unsafe extern "C" fn push(userdata: *mut c_void, scheme: u16) {
+            (*(userdata as *mut Vec<u16>)).push(scheme);
+        }
+        let mut schemes: Vec<u16> = Vec::new();
+        let result = crate::connection::rustls_connection_get_peer_signature_schemes(
+            conn as *const Connection as *const rustls_connection,
+            Some(push),
+            &mut schemes as *mut Vec<u16> as *mut c_void,
+        );
+        assert_eq!(result, rustls_result::Ok);
+        schemes
+    }
+
+    #[test]
+    fn test_peer_signature_schemes() {
+        let client_config = build(new_builder());
+        let mut server_config = testutil::server_config();
+        let (mut client, mut server) =
+            testutil::connection_pair(&client_config, &Arc::new(server_config.clone()));
+        assert!(peer_signature_schemes(&server).is_empty());
+        testutil::handshake(&mut client, &mut server).unwrap();
+        // ECDSA_NISTP256_SHA256 is offered by every rustls client.
+        assert!(peer_signature_schemes(&server).contains(&0x0403));
+        // The server didn't ask for a client certificate.
+        assert!(peer_signature_schemes(&client).is_empty());
+
+        let roots = client_config.root_store.clone();
+        server_config.set_client_certificate_verifier(
+            rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+        );
+        // A fresh client config, so the session isn't resumed.
+        let client_config = build(new_builder());
+        let (mut client, mut server) =
+            testutil::connection_pair(&client_config, &Arc::new(server_config));
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert!(peer_signature_schemes(&client).contains(&0x0403));
+    }
+
+    #[test]
+    fn test_add_client_identity() {
+        let key_pem = testutil::LOCALHOST_KEY_PEM;
+        let via_intermediate = [
+            testutil::LOCALHOST_VIA_INTERMEDIATE_PEM,
+            testutil::INTERMEDIATE_PEM,
+        ]
+        .concat();
+        // Which of `chains` the client sends to a server that names the
+        // issuer of `root_pem` as acceptable.
+        let sent = |chains: &[&[u8]], root_pem: &[u8]| -> Option<usize> {
+            let builder = new_builder();
+            for chain in chains {
+                let mut key: *const rustls_certified_key = std::ptr::null();
+                let result = crate::cipher::rustls_certified_key_build(
+                    chain.as_ptr(),
+                    chain.len(),
+                    key_pem.as_ptr(),
+                    key_pem.len(),
+                    &mut key,
+                );
+                assert_eq!(result, rustls_result::Ok);
+                let result = rustls_client_config_builder_add_client_identity(builder, key);
+                assert_eq!(result, rustls_result::Ok);
+                crate::cipher::rustls_certified_key_free(key);
+            }
+            let mut roots = RootCertStore::empty();
+            roots.add(&testutil::certs(root_pem)[0]).unwrap();
+            let mut server_config = testutil::server_config();
+            server_config.set_client_certificate_verifier(
+                rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+            );
+            let (mut client, mut server) =
+                testutil::connection_pair(&build(builder), &Arc::new(server_config));
+            testutil::handshake(&mut client, &mut server).unwrap();
+            let peer_certs = server.as_ref().get_peer_certificates()?;
+            let leaf = &peer_certs[0];
+            chains
+                .iter()
+                .position(|chain| testutil::certs(chain)[0] == *leaf)
+        };
+        let localhost = testutil::LOCALHOST_PEM;
+        let intermediate = testutil::INTERMEDIATE_PEM;
+        assert_eq!(
+            sent(&[localhost, &via_intermediate], testutil::CA_PEM),
+            Some(0)
+        );
+        assert_eq!(
+            sent(&[&via_intermediate, localhost], testutil::CA_PEM),
+            Some(0)
+        );
+        assert_eq!(sent(&[localhost, &via_intermediate], intermediate), Some(1));
+        // Nothing matches, so nothing is sent.
+        assert_eq!(sent(&[localhost], intermediate), None);
+
+        let builder = new_builder();
+        let result = rustls_client_config_builder_add_client_identity(builder, std::ptr::null());
+        assert_eq!(result, rustls_result::NullParameter);
+        rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_client_cert_was_sent() {
+        let chain = testutil::LOCALHOST_PEM;
+        let key_pem = testutil::LOCALHOST_KEY_PEM;
+        let mut key: *const rustls_certified_key = std::ptr::null();
+        let result = crate::cipher::rustls_certified_key_build(
+            chain.as_ptr(),
+            chain.len(),
+            key_pem.as_ptr(),
+            key_pem.len(),
+            &mut key,
+        );
+        assert_eq!(result, rustls_result::Ok);
+        // A fresh config each time, so that sessions aren't resumed.
+        let new_client_config = || {
+            let builder = new_builder();
+            let result = rustls_client_config_builder_set_certified_key(builder, &key, 1);
+            assert_eq!(result, rustls_result::Ok);
+            build(builder)
+        };
+
+        let was_sent = |conn: &Connection| {
+            rustls_connection_client_cert_was_sent(
+                conn as *const Connection as *const rustls_connection,
+            )
+        };
+        let mut server_config = testutil::server_config();
+        let (mut client, mut server) =
+            testutil::connection_pair(&new_client_config(), &Arc::new(server_config.clone()));
+        testutil::handshake(&mut client, &mut server).unwrap();
+        // The server didn't ask for one.
+        assert!(!was_sent(&client));
+
+        let client_config = new_client_config();
+        let roots = client_config.root_store.clone();
+        server_config.set_client_certificate_verifier(
+            rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+        );
+        let server_config = Arc::new(server_config);
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert!(!was_sent(&client));
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert!(was_sent(&client));
+        assert!(!was_sent(&server));
+
+        // A resumed session doesn't authenticate with a certificate.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert!(client.handshake_details().resumed);
+        assert!(!was_sent(&client));
+        assert!(!rustls_connection_client_cert_was_sent(std::ptr::null()));
+        crate::cipher::rustls_certified_key_free(key);
+    }
+
+    fn server_sni(client_config: &Arc<ClientConfig>) -> Option<String> {
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        server
+            .as_server()
+            .unwrap()
+            .get_sni_hostname()
+            .map(String::from)
+    }
+
+    #[test]
+    fn test_enable_sni() {
+        let config = build(new_builder());
+        assert_eq!(server_sni(&config), Some("localhost".to_string()));
+
+        // The certificate is still verified against "localhost".
+        let builder = new_builder();
+        rustls_client_config_builder_set_enable_sni(builder, false);
+        let config = build(builder);
+        assert_eq!(server_sni(&config), None);
+    }
+
+    #[test]
+    fn test_set_ct_policy() {
+        let connect_ct = |logs: &[&[u8]], min_scts: u8, cert_pem: &[u8]| {
+            let builder = new_builder();
+            // 2027-01-01T00:00:00Z, after the test SCTs were made.
+            let result = rustls_client_config_builder_set_verification_time(builder, 1798761600);
+            assert_eq!(result, rustls_result::Ok);
+            let logs: Vec<rustls_slice_bytes> = logs.iter().map(|l| (*l).into()).collect();
+            let result = rustls_client_config_builder_set_ct_policy(
+                builder,
+                logs.as_ptr(),
+                logs.len(),
+                min_scts,
+            );
+            if result != rustls_result::Ok {
+                rustls_client_config_builder_free(builder);
+                return result;
+            }
+            let client_config = build(builder);
+            let server_config = Arc::new(testutil::server_config_with_cert(cert_pem));
+            let (mut client, mut server) =
+                testutil::connection_pair(&client_config, &server_config);
+            let _ = testutil::handshake(&mut client, &mut server);
+            let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+            crate::connection::rustls_connection_process_new_packets(client_ptr)
+        };
+        let all: &[&[u8]] = &[testutil::CT_LOG1, testutil::CT_LOG2, testutil::CT_LOG3];
+        let sct_pem = testutil::LOCALHOST_SCT_PEM;
+        assert_eq!(connect_ct(all, 2, sct_pem), rustls_result::Ok);
+        assert_eq!(
+            connect_ct(all, 3, sct_pem),
+            rustls_result::CertificateTransparencyRequired
+        );
+        assert_eq!(
+            connect_ct(&all[1..], 2, sct_pem),
+            rustls_result::CertificateTransparencyRequired
+        );
+        assert_eq!(
+            connect_ct(all, 1, testutil::LOCALHOST_PEM),
+            rustls_result::CertificateTransparencyRequired
+        );
+        assert_eq!(
+            connect_ct(&[], 0, testutil::LOCALHOST_PEM),
+            rustls_result::Ok
+        );
+
+        assert_eq!(
+            connect_ct(&all[..1], 2, sct_pem),
+            rustls_result::InvalidParameter
+        );
+        assert_eq!(
+            connect_ct(&[b"not a key"], 1, sct_pem),
+            rustls_result::InvalidParameter
+        );
+    }
+
+    #[test]
+    fn test_require_cert_policy_oid() {
+        // 2.23.140.1.2.1 and 1.3.6.1.4.1.55555.1.2.
+        let dv: &[u8] = &[0x67, 0x81, 0x0c, 0x01, 0x02, 0x01];
+        let enterprise: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xb2, 0x03, 0x01, 0x02];
+        let connect_policy = |oids: &[&[u8]], cert_pem: &[u8]| {
+            let builder = new_builder();
+            for oid in oids {
+                let result = rustls_client_config_builder_require_cert_policy_oid(
+                    builder,
+                    oid.as_ptr(),
+                    oid.len(),
+                );
+                assert_eq!(result, rustls_result::Ok);
+            }
+            let server_config = Arc::new(testutil::server_config_with_cert(cert_pem));
+            let (mut client, mut server) =
+                testutil::connection_pair(&build(builder), &server_config);
+            let _ = testutil::handshake(&mut client, &mut server);
+            let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+            crate::connection::rustls_connection_process_new_packets(client_ptr)
+        };
+        let policy_pem = testutil::LOCALHOST_POLICY_PEM;
+        assert_eq!(
+            connect_policy(&[], testutil::LOCALHOST_PEM),
+            rustls_result::Ok
+        );
+        assert_eq!(connect_policy(&[dv], policy_pem), rustls_result::Ok);
+        assert_eq!(
+            connect_policy(&[enterprise, dv], policy_pem),
+            rustls_result::Ok
+        );
+        assert_eq!(
+            connect_policy(&[dv], testutil::LOCALHOST_PEM),
+            rustls_result::CertificatePolicyMissing
+        );
+        // 2.23.140.1.2.2
+        let ov: &[u8] = &[0x67, 0x81, 0x0c, 0x01, 0x02, 0x02];
+        assert_eq!(
+            connect_policy(&[dv, ov], policy_pem),
+            rustls_result::CertificatePolicyMissing
+        );
+        assert!(crate::error::rustls_result_is_cert_error(
+            rustls_result::CertificatePolicyMissing
+        ));
+
+        let builder = new_builder();
+        for bad in &[&[][..], &[0x67, 0x81][..]] {
+            assert_eq!(
+                rustls_client_config_builder_require_cert_policy_oid(
+                    builder,
+                    bad.as_ptr(),
+                    bad.len()
+                ),
+                rustls_result::InvalidParameter
+            );
+        }
+        assert_eq!(
+            rustls_client_config_builder_require_cert_policy_oid(builder, std::ptr::null(), 1),
+            rustls_result::NullParameter
+        );
+        build(builder);
+    }
+
+    #[test]
+    fn test_min_rsa_key_bits() {
+        use rustls::{NoClientAuth, ServerConfig};
+
+        let connect_rsa = |bits: u32| {
+            let builder = new_builder();
+            let result = rustls_client_config_builder_set_min_rsa_key_bits(builder, bits);
+            assert_eq!(result, rustls_result::Ok);
+            let client_config = build(builder);
+            let mut server_config = ServerConfig::new(NoClientAuth::new());
+            let chain = testutil::certs(testutil::LOCALHOST_RSA2048_PEM);
+            let key = testutil::private_key(testutil::LOCALHOST_RSA2048_KEY_PEM);
+            server_config.set_single_cert(chain, key).unwrap();
+            let (mut client, mut server) =
+                testutil::connection_pair(&client_config, &Arc::new(server_config));
+            let _ = testutil::handshake(&mut client, &mut server);
+            let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+            crate::connection::rustls_connection_process_new_packets(client_ptr)
+        };
+        assert_eq!(connect_rsa(0), rustls_result::Ok);
+        assert_eq!(connect_rsa(2048), rustls_result::Ok);
+        assert_eq!(connect_rsa(3072), rustls_result::WeakKey);
+        // Only RSA keys are affected.
+        let builder = new_builder();
+        rustls_client_config_builder_set_min_rsa_key_bits(builder, 3072);
+        assert!(connect(&build(builder), testutil::LOCALHOST_PEM).is_ok());
+    }
+
+    #[test]
+    fn test_set_signature_schemes() {
+        let with_schemes = |schemes: &[u16]| {
+            let builder = new_builder();
+            let result = rustls_client_config_builder_set_signature_schemes(
+                builder,
+                schemes.as_ptr(),
+                schemes.len(),
+            );
+            (result, build(builder))
+        };
+        let (result, config) = with_schemes(&[0x0403, 0x0804]);
+        assert_eq!(result, rustls_result::Ok);
+        assert!(connect(&config, testutil::LOCALHOST_PEM).is_ok());
+        assert_eq!(
+            config.get_verifier().supported_verify_schemes(),
+            vec![
+                rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+                rustls::SignatureScheme::RSA_PSS_SHA256
+            ]
+        );
+
+        // The server's ECDSA P-256 key can't sign with any allowed scheme.
+        let (_, config) = with_schemes(&[0x0503, 0x0804]);
+        assert!(connect(&config, testutil::LOCALHOST_PEM).is_err());
+
+        // RSA-PKCS1-SHA1 isn't supported.
+        assert_eq!(
+            with_schemes(&[0x0403, 0x0201]).0,
+            rustls_result::InvalidParameter
+        );
+        assert_eq!(with_schemes(&[]).0, rustls_result::InvalidParameter);
+    }
+
+    #[test]
+    fn test_dangerous_set_verify_server_cert() {
+        let with_verify = |verify: bool| {
+            // No trusted roots at all.
+            let builder = rustls_client_config_builder_new();
+            let result =
+                rustls_client_config_builder_dangerous_set_verify_server_cert(builder, verify);
+            assert_eq!(result, rustls_result::Ok);
+            build(builder)
+        };
+        assert!(connect(&with_verify(false), testutil::LOCALHOST_EXPIRED_PEM).is_ok());
+        assert!(matches!(
+            connect(&with_verify(true), testutil::LOCALHOST_PEM),
+            Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer))
+        ));
+
+        // Verification options replace the setting.
+        let builder = rustls_client_config_builder_new();
+        rustls_client_config_builder_dangerous_set_verify_server_cert(builder, false);
+        rustls_client_config_builder_dangerous_accept_expired(builder, true);
+        assert!(connect(&build(builder), testutil::LOCALHOST_PEM).is_err());
+    }
+
+    #[test]
+    fn test_new_no_roots() {
+        unsafe extern "C" fn accept(
+            _userdata: rustls_verify_server_cert_user_data,
+            _params: *const rustls_verify_server_cert_params,
+        ) -> rustls_result {
+            rustls_result::Ok
+        }
+
+        let try_build = |builder| {
+            let mut config = std::ptr::null();
+            let result = rustls_client_config_builder_try_build(builder, &mut config);
+            (result, config)
+        };
+        let (result, config) = try_build(rustls_client_config_builder_new_no_roots());
+        assert_eq!(result, rustls_result::NoVerifierConfigured);
+        assert!(config.is_null());
+        assert!(
+            rustls_client_config_builder_build(rustls_client_config_builder_new_no_roots())
+                .is_null()
+        );
+
+        let builder = rustls_client_config_builder_new_no_roots();
+        rustls_client_config_builder_dangerous_set_certificate_verifier(builder, Some(accept));
+        let (result, config) = try_build(builder);
+        assert_eq!(result, rustls_result::Ok);
+        rustls_client_config_free(config);
+
+        // Verification options replace the custom verifier.
+        let builder = rustls_client_config_builder_new_no_roots();
+        rustls_client_config_builder_dangerous_set_certificate_verifier(builder, Some(accept));
+        rustls_client_config_builder_dangerous_accept_expired(builder, true);
+        assert_eq!(try_build(builder).0, rustls_result::NoVerifierConfigured);
+
+        let builder = rustls_client_config_builder_new_no_roots();
+        rustls_client_config_builder_dangerous_set_verify_server_cert(builder, false);
+        assert_eq!(try_build(builder).0, rustls_result::NoVerifierConfigured);
+    }
+
+    #[test]
+    fn test_kx_hint_format() {
+        #[derive(Default)]
+        struct Recorder {
+            puts: std::sync::Mutex<Vec<(Vec<u8>, Vec<u8>)>>,
+            gets: std::sync::Mutex<Vec<Vec<u8>>>,
+        }
+        impl rustls::StoresClientSessions for Recorder {
+            fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+                self.puts.lock().unwrap().push((key, value));
+                true
+            }
+            fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+                self.gets.lock().unwrap().push(key.to_vec());
+                None
+            }
+        }
+
+        // TicketReporter rewrites the key exchange hint rustls stores, so
+        // this fails if a rustls upgrade changes how hints are stored.
+        let recorder = Arc::new(Recorder::default());
+        let mut client_config = testutil::client_config();
+        client_config.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+        client_config.set_persistence(recorder.clone());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) =
+            testutil::connection_pair(&Arc::new(client_config), &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+
+        let key = b"kx-hint\x09localhost".to_vec();
+        assert!(recorder.gets.lock().unwrap().contains(&key));
+        let puts = recorder.puts.lock().unwrap();
+        // X25519, the group rustls uses without a hint.
+        assert!(puts.contains(&(key, vec![0x00, 0x1d])));
+    }
+
+    #[test]
+    fn test_set_kx_groups() {
+        let with_groups = |groups: &[u16]| {
+            let builder = new_builder();
+            let result =
+                rustls_client_config_builder_set_kx_groups(builder, groups.as_ptr(), groups.len());
+            (result, build(builder))
+        };
+        let kx_group = |config: &Arc<ClientConfig>| {
+            let server_config = Arc::new(testutil::server_config());
+            let (mut client, mut server) = testutil::connection_pair(config, &server_config);
+            testutil::handshake(&mut client, &mut server).unwrap();
+            client.handshake_details().kx_group
+        };
+        let (result, config) = with_groups(&[0x0017, 0x001d]);
+        assert_eq!(result, rustls_result::Ok);
+        assert_eq!(kx_group(&config), 0x0017);
+        // Later connections use the hint rustls saved.
+        assert_eq!(kx_group(&config), 0x0017);
+        assert_eq!(kx_group(&build(new_builder())), 0x001d);
+
+        // X448 isn't supported.
+        assert_eq!(
+            with_groups(&[0x001d, 0x001e]).0,
+            rustls_result::InvalidParameter
+        );
+        assert_eq!(with_groups(&[]).0, rustls_result::InvalidParameter);
+    }
+
+    #[test]
+    fn test_set_cert_compression() {
+        let builder = new_builder();
+        let result = rustls_client_config_builder_set_cert_compression(builder, true);
+        assert_eq!(result, rustls_result::Unimplemented);
+        let result = rustls_client_config_builder_set_cert_compression(builder, false);
+        assert_eq!(result, rustls_result::Ok);
+        let result = rustls_client_config_builder_set_cert_compression(std::ptr::null_mut(), true);
+        assert_eq!(result, rustls_result::NullParameter);
+        assert!(connect(&build(builder), testutil::LOCALHOST_PEM).is_ok());
+    }
+
+    #[test]
+    fn test_set_tls13_compat_mode() {
+        let builder = rustls_client_config_builder_new();
+        let result = rustls_client_config_builder_set_tls13_compat_mode(builder, true);
+        assert_eq!(result, rustls_result::Ok);
+        let result = rustls_client_config_builder_set_tls13_compat_mode(builder, false);
+        assert_eq!(result, rustls_result::Unsupported);
+        let result = rustls_client_config_builder_set_tls13_compat_mode(std::ptr::null_mut(), true);
+        assert_eq!(result, rustls_result::NullParameter);
+        rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_set_ech_config() {
+        // One ECHConfig with version 0xfe0d and three bytes of contents.
+        let list = [0x00, 0x07, 0xfe, 0x0d, 0x00, 0x03, 0x01, 0x02, 0x03];
+        assert!(is_ech_config_list(&list));
+        assert!(!is_ech_config_list(&[]));
+        assert!(!is_ech_config_list(&[0x00, 0x00]));
+        assert!(!is_ech_config_list(&list[..8]));
+        assert!(!is_ech_config_list(&[0x00, 0x01, 0xfe]));
+
+        let builder = rustls_client_config_builder_new();
+        let result =
+            rustls_client_config_builder_set_ech_config(builder, list.as_ptr(), list.len());
+        assert_eq!(result, rustls_result::Unimplemented);
+        let result = rustls_client_config_builder_set_ech_config(builder, list.as_ptr(), 8);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let builder = new_builder();
+        let protocols: Vec<rustls_slice_bytes> = vec![b"h2"[..].into()];
+        let result = rustls_client_config_builder_set_protocols(
+            builder,
+            protocols.as_ptr(),
+            protocols.len(),
+        );
+        assert_eq!(result, rustls_result::Ok);
+        let config_ptr = rustls_client_config_builder_build(builder);
+
+        let mut buf = vec![0u8; 4096];
+        let mut n = 0;
+        let result = rustls_client_config_serialize(config_ptr, buf.as_mut_ptr(), 10, &mut n);
+        assert_eq!(result, rustls_result::InsufficientSize);
+        let result =
+            rustls_client_config_serialize(config_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!(result, rustls_result::Ok);
+        buf.truncate(n);
+
+        let mut restored = std::ptr::null();
+        let result = rustls_client_config_deserialize(buf.as_ptr(), buf.len(), &mut restored);
+        assert_eq!(result, rustls_result::Ok);
+        let restored = config_of(restored);
+        assert_eq!(restored.alpn_protocols, vec![b"h2".to_vec()]);
+        assert!(connect(&restored, testutil::LOCALHOST_PEM).is_ok());
+
+        let mut restored = std::ptr::null();
+        let result = rustls_client_config_deserialize(buf.as_ptr(), n - 1, &mut restored);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        assert!(restored.is_null());
+        rustls_client_config_free(config_ptr);
+    }
+
+    #[test]
+    fn test_add_external_psk() {
+        let builder = rustls_client_config_builder_new();
+        let result = rustls_client_config_builder_add_external_psk(
+            builder,
+            b"device-1"[..].into(),
+            (&[7; 32][..]).into(),
+            rustls_hash_algorithm::Sha256,
+        );
+        assert_eq!(result, rustls_result::Unimplemented);
+        rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_new_ticket_callback() {
+        unsafe extern "C" fn push(userdata: *mut c_void, ticket_id: *const rustls_slice_bytes) {
+            let ticket_id = &*ticket_id;
+            let ticket_id = slice::from_raw_parts(ticket_id.data, ticket_id.len);
+            (*(userdata as *mut Vec<Vec<u8>>)).push(ticket_id.to_vec());
+        }
+
+        let server_config = Arc::new(testutil::server_config());
+        for &version in &[
+            rustls::ProtocolVersion::TLSv1_3,
+            rustls::ProtocolVersion::TLSv1_2,
+        ] {
+            let builder = new_builder();
+            unsafe { (*(builder as *mut ClientConfigBuilder)).config.versions = vec![version] };
+            let config_ptr = rustls_client_config_builder_build(builder);
+            // A config made from another reports each ticket once, too.
+            let derived = build(rustls_client_config_builder_from_config(config_ptr));
+            for client_config in &[config_in(config_ptr), derived] {
+                let (mut client, mut server) =
+                    testutil::connection_pair(client_config, &server_config);
+                let mut tickets: Vec<Vec<u8>> = Vec::new();
+                let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+                crate::connection::rustls_connection_set_userdata(
+                    client_ptr,
+                    &mut tickets as *mut Vec<Vec<u8>> as *mut c_void,
+                );
+                crate::connection::rustls_connection_set_new_ticket_callback(
+                    client_ptr,
+                    Some(push),
+                );
+                testutil::handshake(&mut client, &mut server).unwrap();
+                assert_eq!(tickets.len(), 1);
+                assert!(!tickets[0].is_empty());
+            }
+            rustls_client_config_free(config_ptr);
+        }
+    }
+
+    #[test]
+    fn test_clear_session_cache() {
+        let server_config = Arc::new(testutil::server_config());
+        let resumed = |client_config: &Arc<ClientConfig>| {
+            let (mut client, mut server) = testutil::connection_pair(client_config, &server_config);
+            testutil::handshake(&mut client, &mut server).unwrap();
+            client.handshake_details().resumed
+        };
+        let clear = rustls_client_config_clear_session_cache;
+
+        let config_ptr = rustls_client_config_builder_build(new_builder());
+        let config = config_in(config_ptr);
+        assert!(!resumed(&config));
+        assert!(resumed(&config));
+        assert_eq!(clear(config_ptr), rustls_result::Ok);
+        assert!(!resumed(&config));
+        // The new session is stored as usual.
+        assert!(resumed(&config));
+
+        // A config built from another shares its sessions, and clearing
+        // either clears them for both.
+        let derived_ptr = rustls_client_config_builder_build(
+            rustls_client_config_builder_from_config(config_ptr),
+        );
+        let derived = config_in(derived_ptr);
+        assert!(resumed(&derived));
+        assert_eq!(clear(derived_ptr), rustls_result::Ok);
+        assert!(!resumed(&config));
+        assert_eq!(clear(config_ptr), rustls_result::Ok);
+        assert!(!resumed(&derived));
+        assert!(resumed(&config));
+        rustls_client_config_free(config_ptr);
+        rustls_client_config_free(derived_ptr);
+
+        assert_eq!(
+            rustls_client_config_clear_session_cache(std::ptr::null()),
+            rustls_result::NullParameter
+        );
+    }
+
+    #[test]
+    fn test_verify_report_callback() {
+        unsafe extern "C" fn push(userdata: *mut c_void, result: rustls_result) {
+            (*(userdata as *mut Vec<rustls_result>)).push(result);
+        }
+
+        let mut reported: Vec<rustls_result> = Vec::new();
+        let userdata = &mut reported as *mut Vec<rustls_result> as *mut c_void;
+        let builder = new_builder();
+        let result =
+            rustls_client_config_builder_set_verify_report_callback(builder, Some(push), userdata);
+        assert_eq!(result, rustls_result::Ok);
+        // 2.23.140.1.2.1, which only the policy test certificate asserts.
+        let dv: &[u8] = &[0x67, 0x81, 0x0c, 0x01, 0x02, 0x01];
+        let result =
+            rustls_client_config_builder_require_cert_policy_oid(builder, dv.as_ptr(), dv.len());
+        assert_eq!(result, rustls_result::Ok);
+        let config = build(builder);
+
+        let connect_reported = |cert_pem: &[u8]| {
+            let server_config = Arc::new(testutil::server_config_with_cert(cert_pem));
+            let (mut client, mut server) = testutil::connection_pair(&config, &server_config);
+            testutil::handshake(&mut client, &mut server).unwrap();
+            let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+            crate::connection::rustls_connection_process_new_packets(client_ptr)
+        };
+        assert_eq!(
+            connect_reported(testutil::LOCALHOST_POLICY_PEM),
+            rustls_result::Ok
+        );
+        assert_eq!(connect_reported(testutil::LOCALHOST_PEM), rustls_result::Ok);
+        assert_eq!(
+            connect_reported(testutil::LOCALHOST_EXPIRED_PEM),
+            rustls_result::Ok
+        );
+        assert_eq!(
+            reported,
+            vec![
+                rustls_result::CertificatePolicyMissing,
+                rustls_result::CertExpired
+            ]
+        );
+
+        // Removing the callback enforces verification again.
+        let builder = new_builder();
+        rustls_client_config_builder_set_verify_report_callback(builder, Some(push), userdata);
+        rustls_client_config_builder_set_verify_report_callback(
+            builder,
+            None,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(
+            connect(&build(builder), testutil::LOCALHOST_EXPIRED_PEM),
+            Err(TLSError::WebPKIError(webpki::Error::CertExpired))
+        ));
+    }
+
+    fn spki_sha256(cert_pem: &[u8]) -> Vec<u8> {
+        let cert = testutil::certs(cert_pem).remove(0);
+        let spki = crate::x509::subject_public_key_info(&cert.0).unwrap();
+        ring::digest::digest(&ring::digest::SHA256, spki)
+            .as_ref()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_dangerous_set_pinned_spki() {
+        let pin = spki_sha256(testutil::LOCALHOST_PEM);
+        let backup = [0xaa; 32];
+
+        // No trusted roots: the pin alone decides.
+        let builder = rustls_client_config_builder_new();
+        let result =
+            rustls_client_config_builder_dangerous_set_pinned_spki(builder, backup.as_ptr(), 32);
+        assert_eq!(result, rustls_result::Ok);
+        let result =
+            rustls_client_config_builder_dangerous_set_pinned_spki(builder, pin.as_ptr(), 32);
+        assert_eq!(result, rustls_result::Ok);
+        let config = build(builder);
+        assert!(connect(&config, testutil::LOCALHOST_PEM).is_ok());
+        assert!(connect(&config, testutil::LOCALHOST_EXPIRED_PEM).is_ok());
+
+        let builder = new_builder();
+        let result =
+            rustls_client_config_builder_dangerous_set_pinned_spki(builder, backup.as_ptr(), 32);
+        assert_eq!(result, rustls_result::Ok);
+        let result =
+            rustls_client_config_builder_dangerous_set_pinned_spki(builder, pin.as_ptr(), 31);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        // The server's chain is trusted, but its key isn't pinned.
+        assert!(matches!(
+            connect(&build(builder), testutil::LOCALHOST_PEM),
+            Err(TLSError::General(_))
+        ));
+    }
+
+    /// Userdata for fetch_intermediates: the certificates to supply on every
+    /// call, and how many calls there were.
+    struct Intermediates {
+        certs: Vec<Certificate>,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    unsafe extern "C" fn fetch_intermediates(
+        userdata: *mut c_void,
+        cert: *const rustls_slice_bytes,
+        issuer: *const rustls_slice_bytes,
+        out: *mut rustls_intermediate_certs,
+    ) {
+        let intermediates = &*(userdata as *const Intermediates);
+        intermediates
+            .calls
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (cert, issuer) = (&*cert, &*issuer);
+        let cert = slice::from_raw_parts(cert.data, cert.len);
+        let issuer = slice::from_raw_parts(issuer.data, issuer.len);
+        assert_eq!(crate::x509::issuer(cert), Ok(issuer));
+        for c in &intermediates.certs {
+            let result = rustls_intermediate_certs_add(out, c.0.as_ptr(), c.0.len());
+            assert_eq!(result, rustls_result::Ok);
+        }
+    }
+
+    fn connect_fetching(certs: Vec<Certificate>) -> (Result<(), TLSError>, usize) {
+        let intermediates = Intermediates {
+            certs,
+            calls: Default::default(),
+        };
+        let builder = new_builder();
+        let userdata = &intermediates as *const Intermediates as *mut c_void;
+        let result = rustls_client_config_builder_set_intermediate_fetch_callback(
+            builder,
+            Some(fetch_intermediates),
+            userdata,
+        );
+        assert_eq!(result, rustls_result::Ok);
+        let result = connect(&build(builder), testutil::LOCALHOST_VIA_INTERMEDIATE_PEM);
+        (result, intermediates.calls.into_inner())
+    }
+
+    #[test]
+    fn test_intermediate_fetch_callback() {
+        let unknown_issuer = Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer));
+        let result = connect(
+            &build(new_builder()),
+            testutil::LOCALHOST_VIA_INTERMEDIATE_PEM,
+        );
+        assert_eq!(result, unknown_issuer);
+
+        let intermediate = testutil::certs(testutil::INTERMEDIATE_PEM);
+        assert_eq!(connect_fetching(intermediate), (Ok(()), 1));
+        assert_eq!(connect_fetching(vec![]), (unknown_issuer.clone(), 1));
+        // A certificate that doesn't help is asked about a limited number of
+        // times.
+        let unrelated = testutil::certs(testutil::LOCALHOST_PEM);
+        assert_eq!(connect_fetching(unrelated), (unknown_issuer, 4));
+
+        let mut out: Vec<Certificate> = Vec::new();
+        let garbage = [0x30, 0x00];
+        let out_ptr = &mut out as *mut Vec<Certificate> as *mut rustls_intermediate_certs;
+        let result = rustls_intermediate_certs_add(out_ptr, garbage.as_ptr(), garbage.len());
+        assert_eq!(result, rustls_result::CertificateParseError);
+        assert!(out.is_empty());
+    }
+
+    fn connect_with_ocsp(
+        policy: rustls_ocsp_policy,
+        version: rustls::ProtocolVersion,
+        ocsp: &[u8],
+    ) -> rustls_result {
+        let builder = new_builder();
+        assert_eq!(
+            rustls_client_config_builder_set_ocsp_policy(builder, policy),
+            rustls_result::Ok
+        );
+        // 2030-06-01T00:00:00Z, while the test OCSP responses are current.
+        let result = rustls_client_config_builder_set_verification_time(builder, 1906502400);
+        assert_eq!(result, rustls_result::Ok);
+        let mut client_config = (*build(builder)).clone();
+        client_config.versions = vec![version];
+        let chain = [
+            testutil::LOCALHOST_VIA_INTERMEDIATE_PEM,
+            testutil::INTERMEDIATE_PEM,
+        ]
+        .concat();
+        let server_config = testutil::server_config_with_ocsp(&chain, ocsp);
+        let (mut client, mut server) =
+            testutil::connection_pair(&Arc::new(client_config), &Arc::new(server_config));
+        let _ = testutil::handshake(&mut client, &mut server);
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        crate::connection::rustls_connection_process_new_packets(client_ptr)
+    }
+
+    #[test]
+    fn test_ocsp_policy() {
+        use rustls_ocsp_policy::*;
+        use rustls_result::CertificateRevoked;
+
+        let cases: &[(rustls_ocsp_policy, &[u8], rustls_result)] = &[
+            (Ignore, testutil::OCSP_REVOKED, rustls_result::Ok),
+            (SoftFail, testutil::OCSP_GOOD, rustls_result::Ok),
+            (SoftFail, testutil::OCSP_REVOKED, CertificateRevoked),
+            (SoftFail, testutil::OCSP_WRONG_SIGNER, rustls_result::Ok),
+            (SoftFail, b"", rustls_result::Ok),
+            (HardFail, testutil::OCSP_GOOD, rustls_result::Ok),
+            (HardFail, testutil::OCSP_REVOKED, CertificateRevoked),
+            (HardFail, testutil::OCSP_WRONG_SIGNER, CertificateRevoked),
+            (HardFail, b"", CertificateRevoked),
+        ];
+        for &version in &[
+            rustls::ProtocolVersion::TLSv1_3,
+            rustls::ProtocolVersion::TLSv1_2,
+        ] {
+            for (policy, ocsp, expected) in cases {
+                let result = connect_with_ocsp(*policy, version, ocsp);
+                assert_eq!(
+                    result,
+                    *expected,
+                    "{:?} {:?} {}",
+                    policy,
+                    version,
+                    ocsp.len()
+                );
+            }
+        }
+        assert!(crate::error::rustls_result_is_cert_error(
+            CertificateRevoked
+        ));
+    }
+
+    #[test]
+    fn test_connection_new_rejects_ip_address() {
+        let config = rustls_client_config_builder_build(new_builder());
+        let connect = |hostname: &[u8]| {
+            let mut conn: *mut rustls_connection = std::ptr::null_mut();
+            let result = rustls_client_connection_new(config, hostname.as_ptr() as _, &mut conn);
+            crate::connection::rustls_connection_free(conn);
+            result
+        };
+        assert_eq!(connect(b"localhost\0"), rustls_result::Ok);
+        assert_eq!(connect(b"192.0.2.1\0"), rustls_result::InvalidDnsNameError);
+        assert_eq!(connect(b"::1\0"), rustls_result::InvalidDnsNameError);
+        rustls_client_config_free(config);
+    }
+}
@@ -1,19 +1,36 @@
+use std::cell::OnceCell;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{ffi::c_void, ptr::null};
 use std::{ptr::null_mut, slice};
 
 use libc::{size_t, EIO};
-use rustls::{Certificate, ClientSession, ServerSession, Session, SupportedCipherSuite};
+use ring::digest;
+use rustls::internal::msgs::codec::Codec;
+use rustls::internal::msgs::enums::AlertDescription;
+use rustls::internal::msgs::fragmenter::MAX_FRAGMENT_LEN;
+use rustls::{Certificate, ClientConfig, ClientSession, ServerConfig, ServerSession, Session};
+use rustls::{ProtocolVersion, SupportedCipherSuite, TLSError};
+use webpki::DNSNameRef;
 
+use crate::enums::{named_group_name, rustls_connection_side};
+use crate::handshake::{HandshakeDetails, HandshakeObserver, ObservedReader, ObservedWriter};
+use crate::hooks::{self, AlpnSelection, HookReport};
 use crate::io::{
-    rustls_write_vectored_callback, CallbackReader, CallbackWriter, ReadCallback,
+    rustls_write_vectored_callback, CallbackReader, CallbackWriter, EofReader, ReadCallback,
     VectoredCallbackWriter, VectoredWriteCallback, WriteCallback,
 };
 use crate::is_close_notify;
 use crate::log::{ensure_log_registered, rustls_log_callback};
+use crate::server::alpn_replay_config;
+use crate::session::IgnoreStoredSessions;
+use crate::x509;
 use crate::{
     cipher::{rustls_certificate, rustls_supported_ciphersuite},
     error::{map_error, rustls_io_result, rustls_result},
     io::{rustls_read_callback, rustls_write_callback},
+    rslice::rustls_slice_bytes,
     try_callback, try_mut_slice,
 };
 use crate::{ffi_panic_boundary, try_ref_from_ptr};
@@ -22,36 +39,177 @@ use rustls_result::NullParameter;
 
 pub(crate) struct Connection {
     conn: Inner,
+    setup: Setup,
     userdata: *mut c_void,
     log_callback: rustls_log_callback,
-    peer_certs: Option<Vec<Certificate>>,
+    new_ticket_callback: rustls_new_ticket_callback,
+    /// The peer's certificate chain, once it has been asked for after the
+    /// peer sent it. rustls copies the chain each time it is asked.
+    peer_certs: OnceCell<Vec<Certificate>>,
+    // rustls doesn't expose how much of its buffer limit is in use, so we
+    // keep our own estimate for rustls_connection_write_capacity.
+    buffer_limit: usize,
+    buffered: usize,
+    /// The most plaintext a single read returns, or 0 for no cap.
+    max_plaintext_read: usize,
+    /// Plaintext moved out of rustls for rustls_connection_take_received_plaintext,
+    /// and how much of it has been consumed.
+    taken_plaintext: Vec<u8>,
+    taken_consumed: usize,
+    /// Where the records that taken_plaintext came from ended, as indices
+    /// into it past taken_consumed, in order.
+    record_ends: VecDeque<usize>,
+    /// The most plaintext rustls puts in one outgoing record.
+    max_fragment_size: usize,
+    /// Small writes are held back and given to rustls a record's worth at
+    /// a time.
+    coalesce_writes: bool,
+    /// Plaintext accepted by write_plaintext but not yet given to rustls.
+    coalesced: Vec<u8>,
+    handshake: HandshakeObserver,
+    peer_cert_expired: bool,
+    /// The verifier rejected the peer's certificate under the OCSP policy.
+    ocsp_rejected: bool,
+    /// The verifier rejected the peer's certificate for its RSA key size.
+    weak_key: bool,
+    /// The verifier rejected the peer's certificate under the CT policy.
+    ct_rejected: bool,
+    /// The verifier rejected the peer's certificate for lacking a required
+    /// certificate policy.
+    policy_missing: bool,
+    /// The fatal alert rustls queued when processing failed, until it has
+    /// been written.
+    pending_alert: Option<u8>,
+    /// A server's ALPN select callback declined every offered protocol.
+    alpn_declined: bool,
+    /// When the handshake must have completed by, if a deadline is set.
+    handshake_deadline: Option<Instant>,
+    /// The deadline passed while handshaking.
+    handshake_timed_out: bool,
+    peer_signature_schemes: Vec<u16>,
+    served_cert: Option<Certificate>,
+    /// A client sent a certificate chain to the server.
+    client_cert_sent: bool,
+    stats: rustls_connection_stats,
+    handshake_bytes: HandshakeBytes,
+    records_read: RecordCounter,
+    /// Complete records sent via write_tls.
+    records_written: RecordCounter,
+    /// A read has reported the peer's close_notify.
+    peer_closed: bool,
+    /// The read callback has reported the end of the stream.
+    socket_eof: bool,
+    /// We have queued a close_notify of our own.
+    close_notify_queued: bool,
+    /// The errno of the last I/O error reported as RUSTLS_RESULT_IO, or 0.
+    last_io_error: libc::c_int,
+    /// How much early data a client session could send when it was created,
+    /// from the resumption ticket it offered.
+    early_data_limit: usize,
 }
 
+const RECORD_HEADER_LEN: usize = 5;
+const CONTENT_APPLICATION_DATA: u8 = 23;
+/// Every cipher suite rustls supports is an AEAD with a 16-byte tag.
+const AEAD_TAG_LEN: usize = 16;
+/// TLS 1.3 encrypts the content type along with the fragment.
+const TLS13_RECORD_OVERHEAD: usize = 1 + AEAD_TAG_LEN;
+/// The most any supported cipher suite adds to a record: TLS 1.2 AES-GCM's
+/// 8-byte explicit nonce and its tag.
+const MAX_RECORD_OVERHEAD: usize = 8 + AEAD_TAG_LEN;
+
 enum Inner {
     Client(ClientSession),
     Server(ServerSession),
 }
 
-impl Connection {
-    pub(crate) fn from_client(s: ClientSession) -> Self {
-        Connection {
-            conn: Inner::Client(s),
-            userdata: null_mut(),
-            log_callback: None,
-            peer_certs: None,
+/// What the session was created from, so that it can be recreated with an
+/// adjusted config before any I/O has happened.
+#[derive(Clone)]
+enum Setup {
+    Client(Arc<ClientConfig>, webpki::DNSName),
+    /// The flag is set if the config has an ALPN select callback.
+    Server(Arc<ServerConfig>, bool),
+}
+
+impl Setup {
+    fn new_session(&self) -> Inner {
+        match self {
+            Setup::Client(config, hostname) => Inner::Client(ClientSession::new(
+                config,
+                webpki::DNSName::as_ref(hostname),
+            )),
+            Setup::Server(config, _) => Inner::Server(ServerSession::new(config)),
         }
     }
+}
+
+impl Connection {
+    pub(crate) fn from_client(config: &Arc<ClientConfig>, hostname: DNSNameRef) -> Self {
+        Connection::new(Setup::Client(config.clone(), hostname.into()))
+    }
+
+    pub(crate) fn from_server(config: &Arc<ServerConfig>, selects_alpn: bool) -> Self {
+        Connection::new(Setup::Server(config.clone(), selects_alpn))
+    }
 
-    pub(crate) fn from_server(s: ServerSession) -> Self {
-        Connection {
-            conn: Inner::Server(s),
+    /// A connection with a new session made from `setup`, and nothing set
+    /// on it yet.
+    fn new(setup: Setup) -> Self {
+        let max_fragment_size = match &setup {
+            Setup::Client(config, _) => config.mtu,
+            Setup::Server(config, _) => config.mtu,
+        }
+        .unwrap_or(MAX_FRAGMENT_LEN);
+        let mut handshake = HandshakeObserver::default();
+        if let Setup::Server(_, true) = setup {
+            // Kept in case the ALPN select callback needs the ClientHello
+            // replayed.
+            handshake.retain_received();
+        }
+        let mut conn = Connection {
+            conn: setup.new_session(),
+            setup,
+            max_fragment_size,
             userdata: null_mut(),
             log_callback: None,
-            peer_certs: None,
-        }
+            new_ticket_callback: None,
+            peer_certs: OnceCell::new(),
+            buffer_limit: 0,
+            buffered: 0,
+            max_plaintext_read: 0,
+            taken_plaintext: Vec::new(),
+            taken_consumed: 0,
+            record_ends: VecDeque::new(),
+            coalesce_writes: false,
+            coalesced: Vec::new(),
+            handshake,
+            peer_cert_expired: false,
+            ocsp_rejected: false,
+            weak_key: false,
+            ct_rejected: false,
+            policy_missing: false,
+            pending_alert: None,
+            alpn_declined: false,
+            handshake_deadline: None,
+            handshake_timed_out: false,
+            peer_signature_schemes: Vec::new(),
+            served_cert: None,
+            client_cert_sent: false,
+            stats: rustls_connection_stats::default(),
+            handshake_bytes: HandshakeBytes::default(),
+            records_read: RecordCounter::default(),
+            records_written: RecordCounter::default(),
+            peer_closed: false,
+            socket_eof: false,
+            close_notify_queued: false,
+            last_io_error: 0,
+            early_data_limit: 0,
+        };
+        conn.note_early_data_limit();
+        conn
     }
 
-    #[allow(dead_code)]
     pub(crate) fn as_client(&self) -> Option<&ClientSession> {
         match &self.conn {
             Inner::Client(c) => Some(c),
@@ -81,6 +239,643 @@ impl Connection {
             _ => None,
         }
     }
+
+    /// Whether the session can still be replaced without anything being
+    /// lost: no TLS data has been read or written, and no plaintext written.
+    fn is_untouched(&self) -> bool {
+        self.stats.bytes_read_tls == 0
+            && self.stats.bytes_written_tls == 0
+            && self.buffered == 0
+            && self.coalesced.is_empty()
+    }
+
+    /// Replace the session with a new one made from the config and hostname
+    /// in `setup`.
+    fn recreate_session(&mut self) {
+        let conn = self.setup.new_session();
+        self.install_session(conn);
+    }
+
+    /// Replace the session with `conn`, applying the connection's settings
+    /// to it.
+    fn install_session(&mut self, conn: Inner) {
+        self.conn = conn;
+        self.note_early_data_limit();
+        let limit = self.buffer_limit;
+        self.as_mut().set_buffer_limit(limit);
+    }
+
+    /// Replace the ALPN protocols from the config with `protocols`, by
+    /// recreating the session from a modified copy of the config. rustls
+    /// queues the ClientHello as soon as a client session is created, so this
+    /// is only possible until the first TLS bytes are read or written.
+    fn set_alpn_protocols(&mut self, protocols: &[Vec<u8>]) -> Result<(), rustls_result> {
+        if !self.is_untouched() {
+            return Err(rustls_result::InvalidParameter);
+        }
+        match &mut self.setup {
+            Setup::Client(config, _) => {
+                let mut new_config: ClientConfig = (**config).clone();
+                new_config.set_protocols(protocols);
+                *config = Arc::new(new_config);
+            }
+            Setup::Server(config, _) => {
+                let mut new_config: ServerConfig = (**config).clone();
+                new_config.set_protocols(protocols);
+                *config = Arc::new(new_config);
+            }
+        }
+        self.recreate_session();
+        Ok(())
+    }
+
+    /// Recreate a client session so that it doesn't resume a stored
+    /// session, from a copy of the config whose session store finds
+    /// nothing. New sessions are still stored.
+    fn forget_resumption(&mut self) -> Result<(), rustls_result> {
+        if !self.is_untouched() {
+            return Err(rustls_result::InvalidParameter);
+        }
+        let config = match &mut self.setup {
+            Setup::Client(config, _) => config,
+            Setup::Server(..) => return Err(rustls_result::InvalidParameter),
+        };
+        let mut new_config: ClientConfig = (**config).clone();
+        new_config.session_persistence = Arc::new(IgnoreStoredSessions {
+            inner: config.session_persistence.clone(),
+        });
+        *config = Arc::new(new_config);
+        self.recreate_session();
+        Ok(())
+    }
+
+    /// Record how much early data a newly created session may send. A
+    /// client session decides whether to offer early data as it builds its
+    /// ClientHello, and crustls never writes any, so its allowance is only
+    /// full at this point.
+    fn note_early_data_limit(&mut self) {
+        self.early_data_limit = match &mut self.conn {
+            Inner::Client(c) => c.early_data().map_or(0, |ed| ed.bytes_left()),
+            Inner::Server(_) => 0,
+        };
+    }
+
+    /// The most early data the server allows this connection to send, or 0
+    /// if early data isn't available: it wasn't offered, or the server
+    /// rejected it. rustls 0.19 servers never accept early data over TCP.
+    fn max_early_data_size(&self) -> usize {
+        match self.as_client() {
+            Some(c) if c.is_handshaking() || c.is_early_data_accepted() => self.early_data_limit,
+            _ => 0,
+        }
+    }
+
+    /// Replace the session with a new one made from the same config and
+    /// hostname, and forget everything learned from the old one. Settings
+    /// made on the connection are kept, except for the userdata and the
+    /// handshake deadline; buffers owned by crustls are cleared but keep
+    /// their capacity.
+    fn reset(&mut self) {
+        let mut fresh = Connection::new(self.setup.clone());
+        fresh.log_callback = self.log_callback;
+        fresh.new_ticket_callback = self.new_ticket_callback;
+        fresh.max_plaintext_read = self.max_plaintext_read;
+        fresh.coalesce_writes = self.coalesce_writes;
+        fresh.set_buffer_limit(self.buffer_limit);
+        // Record boundaries stay tracked if they were.
+        if self.records_read.keeps_records() {
+            fresh.records_read = RecordCounter::keeping_records();
+        }
+        fresh.taken_plaintext = std::mem::take(&mut self.taken_plaintext);
+        fresh.taken_plaintext.clear();
+        fresh.record_ends = std::mem::take(&mut self.record_ends);
+        fresh.record_ends.clear();
+        fresh.coalesced = std::mem::take(&mut self.coalesced);
+        fresh.coalesced.clear();
+        fresh.peer_signature_schemes = std::mem::take(&mut self.peer_signature_schemes);
+        fresh.peer_signature_schemes.clear();
+        *self = fresh;
+    }
+
+    /// Remember the errno of `e`, which is being reported to the caller as
+    /// RUSTLS_RESULT_IO.
+    fn io_error(&mut self, e: &std::io::Error) -> rustls_result {
+        self.last_io_error = e.raw_os_error().unwrap_or(EIO);
+        rustls_result::Io
+    }
+
+    fn set_buffer_limit(&mut self, limit: usize) {
+        self.as_mut().set_buffer_limit(limit);
+        self.buffer_limit = limit;
+    }
+
+    /// Grow the buffer that received plaintext is moved into, so that it
+    /// holds `bytes` without reallocating.
+    fn reserve_plaintext(&mut self, bytes: usize) {
+        self.track_boundaries();
+        let len = self.taken_plaintext.len();
+        self.taken_plaintext.reserve(bytes.saturating_sub(len));
+    }
+
+    fn write_plaintext(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.coalesce_writes && self.coalesced.is_empty() {
+            let n = self.as_mut().write(buf)?;
+            self.buffered = self.buffered.saturating_add(n);
+            self.stats.plaintext_written += n as u64;
+            return Ok(n);
+        }
+        let n = buf.len().min(self.write_capacity());
+        self.coalesced.extend_from_slice(&buf[..n]);
+        self.stats.plaintext_written += n as u64;
+        // Only whole records' worth goes to rustls while coalescing; what
+        // is left over waits for more writes or a flush.
+        let len = match self.coalesce_writes {
+            true => self.coalesced.len() - self.coalesced.len() % self.max_fragment_size,
+            false => self.coalesced.len(),
+        };
+        if len != 0 {
+            self.feed_coalesced(len)?;
+        }
+        Ok(n)
+    }
+
+    /// Give all the plaintext held back by write coalescing to rustls.
+    /// Returns false if rustls didn't accept all of it because the buffer
+    /// limit was reached.
+    fn flush_plaintext(&mut self) -> std::io::Result<bool> {
+        self.feed_coalesced(self.coalesced.len())
+    }
+
+    /// Give the first `len` bytes of coalesced plaintext to rustls,
+    /// returning whether it accepted them all.
+    fn feed_coalesced(&mut self, len: usize) -> std::io::Result<bool> {
+        let session: &mut dyn Session = match &mut self.conn {
+            Inner::Client(c) => c,
+            Inner::Server(s) => s,
+        };
+        let n = session.write(&self.coalesced[..len])?;
+        self.coalesced.drain(..n);
+        self.buffered = self.buffered.saturating_add(n);
+        Ok(n == len)
+    }
+
+    fn set_write_coalescing(&mut self, enabled: bool) {
+        self.coalesce_writes = enabled;
+        if !enabled {
+            // Anything rustls doesn't accept goes to it on the next write.
+            let _ = self.flush_plaintext();
+        }
+    }
+
+    fn read_plaintext(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = match self.max_plaintext_read {
+            0 => buf.len(),
+            max => max.min(buf.len()),
+        };
+        let buf = &mut buf[..len];
+        let taken = &self.taken_plaintext[self.taken_consumed..];
+        if !taken.is_empty() {
+            // Plaintext already moved out of rustls comes first.
+            let n = taken.len().min(buf.len());
+            buf[..n].copy_from_slice(&taken[..n]);
+            self.consume_plaintext(n);
+            return Ok(n);
+        }
+        let n = match self.as_mut().read(buf) {
+            Ok(n) => n,
+            Err(e) => {
+                if is_close_notify(&e) {
+                    self.peer_closed = true;
+                }
+                return Err(e);
+            }
+        };
+        self.stats.plaintext_read += n as u64;
+        Ok(n)
+    }
+
+    /// Like read_plaintext, but stop at the end of the next record, if
+    /// where it ends is known. Also returns whether the bytes read end
+    /// exactly at the end of a record.
+    fn read_to_boundary(&mut self, buf: &mut [u8]) -> std::io::Result<(usize, bool)> {
+        self.track_boundaries();
+        let end = self.record_ends.front().copied();
+        let len = match end {
+            Some(end) => buf.len().min(end - self.taken_consumed),
+            None => buf.len(),
+        };
+        let n = self.read_plaintext(&mut buf[..len])?;
+        Ok((n, n > 0 && end == Some(self.taken_consumed)))
+    }
+
+    /// Return the received plaintext that hasn't been consumed yet, moving
+    /// everything rustls has buffered out of it if none is left.
+    fn take_plaintext(&mut self) -> std::io::Result<&[u8]> {
+        if self.taken_consumed == self.taken_plaintext.len() {
+            self.taken_plaintext.clear();
+            self.taken_consumed = 0;
+            let session: &mut dyn Session = match &mut self.conn {
+                Inner::Client(c) => c,
+                Inner::Server(s) => s,
+            };
+            if let Err(e) = session.read_to_end(&mut self.taken_plaintext) {
+                // Report the error once what was read before it is consumed.
+                if self.taken_plaintext.is_empty() {
+                    if is_close_notify(&e) {
+                        self.peer_closed = true;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let taken = &self.taken_plaintext[self.taken_consumed..];
+        Ok(match self.max_plaintext_read {
+            0 => taken,
+            max => &taken[..max.min(taken.len())],
+        })
+    }
+
+    /// The number of bytes take_plaintext has moved out of rustls that
+    /// haven't been consumed.
+    fn taken_plaintext_len(&self) -> usize {
+        self.taken_plaintext.len() - self.taken_consumed
+    }
+
+    fn consume_plaintext(&mut self, n: usize) {
+        self.taken_consumed += n;
+        self.stats.plaintext_read += n as u64;
+        while self
+            .record_ends
+            .front()
+            .is_some_and(|&end| end <= self.taken_consumed)
+        {
+            self.record_ends.pop_front();
+        }
+    }
+
+    /// From now on, move the plaintext rustls decrypts out of it as each
+    /// batch of records is processed, noting where the records end. This
+    /// costs a copy of all plaintext received, so it is only done for
+    /// connections that ask. Everything rustls has decrypted so far is
+    /// treated as one record.
+    fn track_boundaries(&mut self) {
+        if !self.records_read.keeps_records() {
+            self.records_read.keep_records();
+            self.take_new_plaintext(&[]);
+        }
+    }
+
+    /// Move the plaintext that rustls has just decrypted out of it, noting
+    /// where the records it came from ended. `records` are the content type
+    /// and length of each record rustls has just processed.
+    ///
+    /// rustls doesn't say where records end, so their plaintext lengths are
+    /// worked out from their lengths on the wire. That is exact for TLS 1.2,
+    /// but a TLS 1.3 record may be padded or carry a handshake message or
+    /// alert instead, which shows up as less plaintext than expected; then
+    /// only the end of everything decrypted is known to end a record.
+    fn take_new_plaintext(&mut self, records: &[(u8, usize)]) {
+        self.taken_plaintext.drain(..self.taken_consumed);
+        for end in &mut self.record_ends {
+            *end -= self.taken_consumed;
+        }
+        self.taken_consumed = 0;
+        let start = self.taken_plaintext.len();
+        let session: &mut dyn Session = match &mut self.conn {
+            Inner::Client(c) => c,
+            Inner::Server(s) => s,
+        };
+        // The only error is the peer's close_notify, which reads report
+        // again once the plaintext before it is consumed.
+        let _ = session.read_to_end(&mut self.taken_plaintext);
+        let received = self.taken_plaintext.len() - start;
+        if received == 0 {
+            return;
+        }
+        match self.plaintext_lengths(records) {
+            Some(lengths) if lengths.iter().sum::<usize>() == received => {
+                let mut end = start;
+                for len in lengths.into_iter().filter(|&len| len > 0) {
+                    end += len;
+                    self.record_ends.push_back(end);
+                }
+            }
+            _ => self.record_ends.push_back(start + received),
+        }
+    }
+
+    /// The plaintext lengths of the application data records among
+    /// `records`, if each carries only application data, unpadded.
+    fn plaintext_lengths(&self, records: &[(u8, usize)]) -> Option<Vec<usize>> {
+        let session = self.as_ref();
+        let overhead = match session.get_negotiated_ciphersuite()? {
+            _ if session.get_protocol_version() == Some(ProtocolVersion::TLSv1_3) => {
+                TLS13_RECORD_OVERHEAD
+            }
+            suite => suite.explicit_nonce_len + AEAD_TAG_LEN,
+        };
+        records
+            .iter()
+            .filter(|&&(typ, _)| typ == CONTENT_APPLICATION_DATA)
+            .map(|&(_, len)| len.checked_sub(overhead))
+            .collect()
+    }
+
+    /// rustls wants more TLS data: all the plaintext received so far has
+    /// been read.
+    fn wants_read(&self) -> bool {
+        self.taken_plaintext_len() == 0 && self.as_ref().wants_read()
+    }
+
+    fn send_close_notify(&mut self) {
+        if !self.coalesced.is_empty() {
+            // Everything written must go out before the close_notify, even
+            // if that exceeds the buffer limit.
+            self.as_mut().set_buffer_limit(0);
+            let _ = self.flush_plaintext();
+            let limit = self.buffer_limit;
+            self.as_mut().set_buffer_limit(limit);
+        }
+        self.as_mut().send_close_notify();
+        self.close_notify_queued = true;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.peer_closed || (self.close_notify_queued && !self.as_ref().wants_write())
+    }
+
+    /// True if the socket reached EOF without the peer's close_notify and
+    /// everything received before that has been read.
+    fn truncated(&mut self) -> bool {
+        if !self.socket_eof || self.peer_closed || self.taken_plaintext_len() != 0 {
+            return false;
+        }
+        // rustls reports the close_notify from a read only once its
+        // plaintext is drained, so an empty read tells the cases apart.
+        match self.as_mut().read(&mut []) {
+            Ok(n) => n == 0 && self.as_ref().wants_read(),
+            Err(e) => !is_close_notify(&e),
+        }
+    }
+
+    pub(crate) fn read_tls(&mut self, reader: &mut dyn std::io::Read) -> std::io::Result<usize> {
+        let session: &mut dyn Session = match &mut self.conn {
+            Inner::Client(c) => c,
+            Inner::Server(s) => s,
+        };
+        let mut eof_reader = EofReader {
+            inner: reader,
+            eof: false,
+        };
+        let mut counter = RecordCountingReader {
+            inner: &mut eof_reader,
+            counter: &mut self.records_read,
+        };
+        let mut reader = ObservedReader {
+            inner: &mut counter,
+            observer: &mut self.handshake,
+        };
+        let handshaking = session.is_handshaking();
+        let n = session.read_tls(&mut reader)?;
+        if eof_reader.eof {
+            self.socket_eof = true;
+        }
+        self.stats.bytes_read_tls += n as u64;
+        if handshaking {
+            self.handshake_bytes.received += n as u64;
+        }
+        Ok(n)
+    }
+
+    pub(crate) fn write_tls(&mut self, writer: &mut dyn std::io::Write) -> std::io::Result<usize> {
+        let session: &mut dyn Session = match &mut self.conn {
+            Inner::Client(c) => c,
+            Inner::Server(s) => s,
+        };
+        let mut counter = RecordCountingWriter {
+            inner: writer,
+            counter: &mut self.records_written,
+        };
+        let mut writer = ObservedWriter {
+            inner: &mut counter,
+            observer: &mut self.handshake,
+        };
+        let handshaking = session.is_handshaking() || self.handshake_bytes.final_flight;
+        let n = session.write_tls(&mut writer)?;
+        self.stats.bytes_written_tls += n as u64;
+        if handshaking {
+            self.handshake_bytes.sent += n as u64;
+        }
+        let session = self.as_ref();
+        if !session.is_handshaking() {
+            // Until the handshake completes, plaintext is held back rather
+            // than encrypted, so TLS writes don't drain it.
+            self.buffered = if session.wants_write() {
+                self.buffered.saturating_sub(n)
+            } else {
+                0
+            };
+        }
+        if !self.as_ref().wants_write() {
+            self.pending_alert = None;
+            self.handshake_bytes.final_flight = false;
+        }
+        Ok(n)
+    }
+
+    /// The certificates the peer presented, or nothing if it hasn't yet.
+    /// They are kept once seen, so that pointers into them stay valid until
+    /// the connection is freed or reset.
+    fn peer_certs(&self) -> &[Certificate] {
+        if let Some(certs) = self.peer_certs.get() {
+            return certs;
+        }
+        match self.as_ref().get_peer_certificates() {
+            Some(certs) => self.peer_certs.get_or_init(|| certs),
+            None => &[],
+        }
+    }
+
+    /// The signature schemes the peer advertised, in its order of
+    /// preference, or nothing if they haven't been seen yet.
+    pub(crate) fn peer_signature_schemes(&self) -> &[u16] {
+        &self.peer_signature_schemes
+    }
+
+    /// The end-entity certificate this server connection chose to send, if
+    /// the config was built by crustls and a certificate has been chosen.
+    pub(crate) fn served_cert(&self) -> Option<&Certificate> {
+        self.served_cert.as_ref()
+    }
+
+    /// Whether a client's completed handshake authenticated it with a
+    /// certificate.
+    pub(crate) fn client_cert_was_sent(&self) -> bool {
+        match self.as_client() {
+            Some(c) => self.client_cert_sent && !c.is_handshaking(),
+            None => false,
+        }
+    }
+
+    /// Require the handshake to complete by `unix_millis`, or remove the
+    /// deadline if it is 0. The deadline is kept as a time on the monotonic
+    /// clock, so it doesn't move if the system clock is changed later.
+    fn set_handshake_deadline(&mut self, unix_millis: u64) {
+        if unix_millis == 0 {
+            self.handshake_deadline = None;
+            return;
+        }
+        let deadline = UNIX_EPOCH + Duration::from_millis(unix_millis);
+        let now = Instant::now();
+        self.handshake_deadline = match deadline.duration_since(SystemTime::now()) {
+            Ok(remaining) => now.checked_add(remaining),
+            Err(_) => Some(now),
+        };
+    }
+
+    pub(crate) fn process_new_packets(&mut self) -> Result<(), TLSError> {
+        if self.alpn_declined {
+            return Err(TLSError::NoApplicationProtocol);
+        }
+        if let Some(deadline) = self.handshake_deadline {
+            if self.as_ref().is_handshaking() && Instant::now() >= deadline {
+                self.handshake_timed_out = true;
+            }
+        }
+        if self.handshake_timed_out {
+            return Err(TLSError::General("handshake timed out".to_string()));
+        }
+        let was_handshaking = self.as_ref().is_handshaking();
+        let (result, mut report) = hooks::capture(|| self.as_mut().process_new_packets());
+        let (result, report) = match report.alpn_selection.take() {
+            Some(selection) => self.replay_with_alpn(selection),
+            None => (result, report),
+        };
+        if let Some(expired) = report.peer_cert_expired {
+            self.peer_cert_expired = expired;
+        }
+        self.ocsp_rejected |= report.ocsp_rejected;
+        self.weak_key |= report.weak_key;
+        self.ct_rejected |= report.ct_rejected;
+        self.policy_missing |= report.policy_missing;
+        if let Err(e) = &result {
+            let is_client = matches!(self.conn, Inner::Client(_));
+            if let Some(alert) = fatal_alert(e, is_client, &report) {
+                self.pending_alert = Some(alert.get_u8());
+            }
+        }
+        if let Some(schemes) = report.peer_signature_schemes {
+            self.peer_signature_schemes = schemes;
+        }
+        if let Some(cert) = report.served_cert {
+            self.served_cert = Some(cert);
+        }
+        self.client_cert_sent |= report.client_cert_sent;
+        if let Some(schemes) = self.handshake.take_client_signature_schemes() {
+            self.peer_signature_schemes = schemes;
+        }
+        if let Some(callback) = self.new_ticket_callback {
+            for id in &report.new_tickets {
+                let id: rustls_slice_bytes = id.as_slice().into();
+                unsafe { callback(self.userdata, &id) };
+            }
+        }
+        // rustls processes every complete record it has been given, unless
+        // one of them fails.
+        let complete = self.records_read.take_complete();
+        if result.is_ok() {
+            self.stats.records_processed += complete;
+        }
+        if self.records_read.keeps_records() {
+            let records = self.records_read.take_records();
+            self.take_new_plaintext(&records);
+        }
+        if was_handshaking && !self.as_ref().is_handshaking() {
+            // The session stops handshaking once it has queued its last
+            // handshake messages, which are yet to be written.
+            self.handshake_bytes.final_flight = self.as_ref().wants_write();
+        }
+        result
+    }
+
+    /// Recreate a server session whose ALPN select callback has chosen a
+    /// protocol, so that it negotiates that protocol, and have it process
+    /// the retained ClientHello again. If the callback declined, the
+    /// session is replaced by one with nothing to send, and the connection
+    /// fails.
+    fn replay_with_alpn(&mut self, selection: AlpnSelection) -> (Result<(), TLSError>, HookReport) {
+        let config = match &self.setup {
+            Setup::Server(config, _) => config.clone(),
+            Setup::Client(..) => unreachable!("only server configs select ALPN by callback"),
+        };
+        let protocol = match selection.protocol {
+            Some(protocol) => protocol,
+            None => {
+                self.alpn_declined = true;
+                self.install_session(Inner::Server(ServerSession::new(&config)));
+                return (Err(TLSError::NoApplicationProtocol), HookReport::default());
+            }
+        };
+        let retained = match self.handshake.retained_received() {
+            Some(retained) => retained.to_vec(),
+            None => {
+                let error = "ClientHello too large to replay for ALPN selection".to_string();
+                return (Err(TLSError::General(error)), HookReport::default());
+            }
+        };
+        let config = Arc::new(alpn_replay_config(&config, protocol, selection.key));
+        let mut session = ServerSession::new(&config);
+        let mut input = &retained[..];
+        while !input.is_empty() {
+            if let Err(e) = session.read_tls(&mut input) {
+                return (Err(TLSError::General(e.to_string())), HookReport::default());
+            }
+        }
+        self.install_session(Inner::Server(session));
+        hooks::capture(|| self.as_mut().process_new_packets())
+    }
+
+    pub(crate) fn handshake_details(&self) -> &HandshakeDetails {
+        self.handshake.details()
+    }
+
+    fn handshake_info(&self) -> rustls_handshake_info {
+        let session = self.as_ref();
+        let details = self.handshake_details();
+        rustls_handshake_info {
+            protocol_version: session.get_protocol_version().map_or(0, |v| v.get_u16()),
+            cipher_suite: session
+                .get_negotiated_ciphersuite()
+                .map_or(0, |cs| cs.suite.get_u16()),
+            kx_group: details.kx_group,
+            resumed: details.resumed,
+            is_handshaking: session.is_handshaking(),
+        }
+    }
+
+    /// The largest TLS record, including its header, that `write_tls` will
+    /// emit from now on.
+    fn max_output_record_size(&self) -> usize {
+        let session = self.as_ref();
+        let overhead = match session.get_negotiated_ciphersuite() {
+            Some(_) if session.get_protocol_version() == Some(ProtocolVersion::TLSv1_3) => {
+                TLS13_RECORD_OVERHEAD
+            }
+            Some(suite) => suite.explicit_nonce_len + AEAD_TAG_LEN,
+            None => MAX_RECORD_OVERHEAD,
+        };
+        RECORD_HEADER_LEN + self.max_fragment_size + overhead
+    }
+
+    /// An estimate of how many more plaintext bytes `write_plaintext` will
+    /// accept. It doesn't account for TLS record overhead or handshake
+    /// messages, so it may overestimate slightly.
+    fn write_capacity(&self) -> usize {
+        match self.buffer_limit {
+            0 => usize::MAX,
+            limit => limit.saturating_sub(self.buffered.saturating_add(self.coalesced.len())),
+        }
+    }
 }
 
 impl<'conn> AsRef<dyn Session + 'conn> for Connection {
@@ -101,6 +896,180 @@ impl<'conn> AsMut<dyn Session + 'conn> for Connection {
     }
 }
 
+/// The TLS bytes a connection has exchanged while handshaking.
+#[derive(Default)]
+struct HandshakeBytes {
+    sent: u64,
+    received: u64,
+    /// The handshake has completed, but what was queued to send as it did
+    /// hasn't all been written yet.
+    final_flight: bool,
+}
+
+/// Counts the TLS records in a stream of bytes, by following the length
+/// fields of their headers.
+#[derive(Default)]
+struct RecordCounter {
+    header: [u8; 5],
+    header_len: usize,
+    body_remaining: usize,
+    complete: u64,
+    /// The content type and length of each record completed since the
+    /// last take_records, if they are being kept.
+    records: Option<Vec<(u8, usize)>>,
+}
+
+impl RecordCounter {
+    fn keeping_records() -> Self {
+        RecordCounter {
+            records: Some(Vec::new()),
+            ..Default::default()
+        }
+    }
+
+    /// Start keeping the records completed from now on.
+    fn keep_records(&mut self) {
+        self.records.get_or_insert_with(Vec::new);
+    }
+
+    fn keeps_records(&self) -> bool {
+        self.records.is_some()
+    }
+
+    fn count(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.header_len < self.header.len() {
+                let n = (self.header.len() - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+                self.header_len += n;
+                data = &data[n..];
+                if self.header_len < self.header.len() {
+                    return;
+                }
+                self.body_remaining = u16::from_be_bytes([self.header[3], self.header[4]]) as usize;
+            }
+            let n = self.body_remaining.min(data.len());
+            self.body_remaining -= n;
+            data = &data[n..];
+            if self.body_remaining == 0 {
+                self.complete += 1;
+                self.header_len = 0;
+                if let Some(records) = &mut self.records {
+                    let len = u16::from_be_bytes([self.header[3], self.header[4]]);
+                    records.push((self.header[0], len as usize));
+                }
+            }
+        }
+    }
+
+    /// If `data`, following what has been counted so far, completes the
+    /// current record, return the number of bytes of `data` that complete
+    /// it.
+    fn record_end(&self, data: &[u8]) -> Option<usize> {
+        let mut header = self.header;
+        let mut pos = 0;
+        let mut body_remaining = self.body_remaining;
+        if self.header_len < header.len() {
+            pos = (header.len() - self.header_len).min(data.len());
+            header[self.header_len..self.header_len + pos].copy_from_slice(&data[..pos]);
+            if self.header_len + pos < header.len() {
+                return None;
+            }
+            body_remaining = u16::from_be_bytes([header[3], header[4]]) as usize;
+        }
+        if data.len() - pos < body_remaining {
+            return None;
+        }
+        Some(pos + body_remaining)
+    }
+
+    /// Return the number of records completed since the last call.
+    fn take_complete(&mut self) -> u64 {
+        std::mem::take(&mut self.complete)
+    }
+
+    /// Return the records completed since the last call, if they are being
+    /// kept.
+    fn take_records(&mut self) -> Vec<(u8, usize)> {
+        self.records
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Return the number of records completed so far, without resetting it.
+    fn complete(&self) -> u64 {
+        self.complete
+    }
+}
+
+struct RecordCountingReader<'a> {
+    inner: &'a mut dyn std::io::Read,
+    counter: &'a mut RecordCounter,
+}
+
+impl std::io::Read for RecordCountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.count(&buf[..n]);
+        Ok(n)
+    }
+}
+
+struct RecordCountingWriter<'a> {
+    inner: &'a mut dyn std::io::Write,
+    counter: &'a mut RecordCounter,
+}
+
+impl std::io::Write for RecordCountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counter.count(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[std::io::IoSlice<'_>]) -> std::io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        let mut remaining = n;
+        for buf in bufs {
+            let len = remaining.min(buf.len());
+            self.counter.count(&buf[..len]);
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that accepts at most `remaining` more bytes, then reports
+/// writing nothing.
+struct LimitedWriter<'a> {
+    inner: &'a mut dyn std::io::Write,
+    remaining: usize,
+}
+
+impl std::io::Write for LimitedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.write(&buf[..len])?;
+        self.remaining -= n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub struct rustls_connection {
     _private: [u8; 0],
 }
@@ -134,6 +1103,36 @@ pub extern "C" fn rustls_connection_set_log_callback(
     conn.log_callback = cb;
 }
 
+/// A callback for `rustls_connection_set_new_ticket_callback`. `ticket_id`
+/// identifies the new resumption state; it is the ticket the server issued
+/// or, for TLS 1.2 resumption by session ID, the session ID. It is only
+/// valid during the callback.
+#[allow(non_camel_case_types)]
+pub type rustls_new_ticket_callback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, ticket_id: *const rustls_slice_bytes)>;
+
+/// Set a callback to be invoked from `rustls_connection_process_new_packets`
+/// whenever this client connection receives new resumption state from the
+/// server: each NewSessionTicket in TLS 1.3, which may arrive at any time
+/// after the handshake, or the end of a TLS 1.2 handshake. By the time it is
+/// called, the state has been handed to the config's session store, such as
+/// the callbacks set with `rustls_client_config_builder_set_persistence`.
+///
+/// The callback is invoked with the userdata parameter previously set by
+/// rustls_connection_set_userdata, or NULL if no userdata was set. It is
+/// never invoked for server connections, or for configs not built by
+/// `rustls_client_config_builder_build`.
+#[no_mangle]
+pub extern "C" fn rustls_connection_set_new_ticket_callback(
+    conn: *mut rustls_connection,
+    cb: rustls_new_ticket_callback,
+) {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        conn.new_ticket_callback = cb;
+    }
+}
+
 /// Read some TLS bytes from the network into internal buffers. The actual network
 /// I/O is performed by `callback`, which you provide. Rustls will invoke your
 /// callback with a suitable buffer to store the read bytes into. You don't have
@@ -143,6 +1142,14 @@ pub extern "C" fn rustls_connection_set_log_callback(
 /// `rustls_connection_set_userdata`.
 /// Returns 0 for success, or an errno value on error. Passes through return values
 /// from callback. See rustls_read_callback for more details.
+///
+/// A loop around this must stop when `*out_n` is 0. That means the callback
+/// reported EOF, after which `rustls_connection_process_new_packets` returns
+/// RUSTLS_RESULT_UNEXPECTED_EOF once the plaintext received before it has
+/// been read, unless the peer sent close_notify. It also happens without
+/// calling the callback once rustls has buffered as much of a malformed
+/// record as it will, which `rustls_connection_process_new_packets` reports
+/// as an error.
 /// https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.read_tls
 #[no_mangle]
 pub extern "C" fn rustls_connection_read_tls(
@@ -157,7 +1164,7 @@ pub extern "C" fn rustls_connection_read_tls(
         let callback: ReadCallback = try_callback!(callback);
 
         let mut reader = CallbackReader { callback, userdata };
-        let n_read: usize = match conn.as_mut().read_tls(&mut reader) {
+        let n_read: usize = match conn.read_tls(&mut reader) {
             Ok(n) => n,
             Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
         };
@@ -167,12 +1174,92 @@ pub extern "C" fn rustls_connection_read_tls(
     }
 }
 
-/// Write some TLS bytes to the network. The actual network I/O is performed by
-/// `callback`, which you provide. Rustls will invoke your callback with a
-/// suitable buffer containing TLS bytes to send. You don't have to write them
-/// all, just as many as you can in one syscall.
-/// The `userdata` parameter is passed through directly to `callback`. Note that
-/// this is distinct from the `userdata` parameter set with
+/// Like `rustls_connection_read_tls`, but on success also stores in
+/// `out_needs_more` whether rustls wants to read more TLS bytes, as
+/// `rustls_connection_wants_read` would report straight afterwards. This
+/// saves a call in event loops that decide after each read whether to keep
+/// reading, such as edge-triggered epoll loops.
+///
+/// rustls stops wanting to read while it holds plaintext that hasn't been
+/// read with `rustls_connection_read`. The bytes just read are not
+/// decrypted until `rustls_connection_process_new_packets`, so they don't
+/// count yet.
+#[no_mangle]
+pub extern "C" fn rustls_connection_read_tls2(
+    conn: *mut rustls_connection,
+    callback: rustls_read_callback,
+    userdata: *mut c_void,
+    out_n: *mut size_t,
+    out_needs_more: *mut bool,
+) -> rustls_io_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let out_needs_more: &mut bool = try_mut_from_ptr!(out_needs_more);
+        let callback: ReadCallback = try_callback!(callback);
+
+        let mut reader = CallbackReader { callback, userdata };
+        let n_read: usize = match conn.read_tls(&mut reader) {
+            Ok(n) => n,
+            Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
+        };
+        *out_n = n_read;
+        *out_needs_more = conn.wants_read();
+
+        rustls_io_result(0)
+    }
+}
+
+/// Feed TLS bytes from `buf`, which holds `len` bytes, to the connection,
+/// taking at most one TLS record, for transports that carry one record per
+/// datagram. Store the number of bytes taken in `out_n`, and in
+/// `out_complete` whether they completed a record. Bytes after the end of
+/// the record are not taken; pass them in a later call. If `buf` ends
+/// partway through a record, all of it is taken and `out_complete` is
+/// false; the partial record is kept until the rest arrives in later calls.
+/// Call `rustls_connection_process_new_packets` after each complete record.
+///
+/// This only frames records over datagrams. It is not DTLS, which rustls
+/// doesn't implement: the datagrams must arrive reliably and in order, as
+/// over a stream, and lost or reordered datagrams break the connection.
+#[no_mangle]
+pub extern "C" fn rustls_connection_read_tls_record(
+    conn: *mut rustls_connection,
+    buf: *const u8,
+    len: size_t,
+    out_n: *mut size_t,
+    out_complete: *mut bool,
+) -> rustls_io_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let input: &[u8] = try_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let out_complete: &mut bool = try_mut_from_ptr!(out_complete);
+
+        let end = conn.records_read.record_end(input);
+        let mut record = &input[..end.unwrap_or(input.len())];
+        let mut n_read = 0;
+        while !record.is_empty() {
+            let n = match conn.read_tls(&mut record) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
+            };
+            n_read += n;
+        }
+        *out_n = n_read;
+        *out_complete = end == Some(n_read);
+
+        rustls_io_result(0)
+    }
+}
+
+/// Write some TLS bytes to the network. The actual network I/O is performed by
+/// `callback`, which you provide. Rustls will invoke your callback with a
+/// suitable buffer containing TLS bytes to send. You don't have to write them
+/// all, just as many as you can in one syscall.
+/// The `userdata` parameter is passed through directly to `callback`. Note that
+/// this is distinct from the `userdata` parameter set with
 /// `rustls_connection_set_userdata`.
 /// Returns 0 for success, or an errno value on error. Passes through return values
 /// from callback. See rustls_write_callback for more details.
@@ -190,7 +1277,7 @@ pub extern "C" fn rustls_connection_write_tls(
         let callback: WriteCallback = try_callback!(callback);
 
         let mut writer = CallbackWriter { callback, userdata };
-        let n_written: usize = match conn.as_mut().write_tls(&mut writer) {
+        let n_written: usize = match conn.write_tls(&mut writer) {
             Ok(n) => n,
             Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
         };
@@ -200,6 +1287,49 @@ pub extern "C" fn rustls_connection_write_tls(
     }
 }
 
+/// Like `rustls_connection_write_tls`, but keeps calling `callback` until
+/// `max_bytes` have been written or nothing is left to write, and never
+/// hands it more than the rest of that budget. A server multiplexing many
+/// connections on one thread can use this to flush a large backlog in
+/// fair-sized slices, rather than all at once or one record at a time.
+///
+/// Stores the total number of bytes written in `out_n`. Stops early if
+/// `callback` writes nothing. If `callback` returns an error after some
+/// bytes have been written, returns 0 with those bytes in `out_n`; the
+/// error will come back from the next call.
+#[no_mangle]
+pub extern "C" fn rustls_connection_write_tls_limited(
+    conn: *mut rustls_connection,
+    callback: rustls_write_callback,
+    userdata: *mut c_void,
+    max_bytes: size_t,
+    out_n: *mut size_t,
+) -> rustls_io_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let callback: WriteCallback = try_callback!(callback);
+
+        let mut writer = CallbackWriter { callback, userdata };
+        let mut n_written: usize = 0;
+        while n_written < max_bytes && conn.as_ref().wants_write() {
+            let mut limited = LimitedWriter {
+                inner: &mut writer,
+                remaining: max_bytes - n_written,
+            };
+            match conn.write_tls(&mut limited) {
+                Ok(0) => break,
+                Ok(n) => n_written += n,
+                Err(_) if n_written > 0 => break,
+                Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
+            }
+        }
+        *out_n = n_written;
+
+        rustls_io_result(0)
+    }
+}
+
 /// Write all available TLS bytes to the network. The actual network I/O is performed by
 /// `callback`, which you provide. Rustls will invoke your callback with an array
 /// of rustls_slice_bytes, each containing a buffer with TLS bytes to send.
@@ -223,7 +1353,7 @@ pub extern "C" fn rustls_connection_write_tls_vectored(
         let callback: VectoredWriteCallback = try_callback!(callback);
 
         let mut writer = VectoredCallbackWriter { callback, userdata };
-        let n_written: usize = match conn.as_mut().write_tls(&mut writer) {
+        let n_written: usize = match conn.write_tls(&mut writer) {
             Ok(n) => n,
             Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
         };
@@ -233,6 +1363,26 @@ pub extern "C" fn rustls_connection_write_tls_vectored(
     }
 }
 
+/// Process all TLS records passed in by `rustls_connection_read_tls` since
+/// the last call, decrypting application data and advancing the handshake.
+/// Callbacks set on the connection, such as the log callback, are invoked
+/// from here and receive the connection's userdata.
+///
+/// rustls can't stop partway through its buffered records, so the work
+/// done by one call is bounded by the amount of TLS data read since the
+/// previous call, not by anything the peer controls. A single
+/// `rustls_connection_read_tls` call reads at most 18437 bytes (one
+/// maximum-size record). To keep each call short under load, alternate
+/// `rustls_connection_read_tls` and this function rather than reading
+/// everything available first, and have the read callback return fewer
+/// bytes if a smaller budget is needed.
+///
+/// Once the read callback has reported the end of the stream, this returns
+/// RUSTLS_RESULT_UNEXPECTED_EOF if the peer didn't send close_notify first,
+/// since the data may have been truncated by an attacker. The error waits
+/// until all plaintext received before the EOF has been read, so that none
+/// of it is lost.
+/// https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.process_new_packets
 #[no_mangle]
 pub extern "C" fn rustls_connection_process_new_packets(
     conn: *mut rustls_connection,
@@ -243,8 +1393,14 @@ pub extern "C" fn rustls_connection_process_new_packets(
             Ok(g) => g,
             Err(_) => return rustls_result::Panic,
         };
-        let result = match conn.as_mut().process_new_packets() {
+        let result = match conn.process_new_packets() {
+            Ok(()) if conn.truncated() => rustls_result::UnexpectedEof,
             Ok(()) => rustls_result::Ok,
+            Err(_) if conn.ocsp_rejected => rustls_result::CertificateRevoked,
+            Err(_) if conn.weak_key => rustls_result::WeakKey,
+            Err(_) if conn.ct_rejected => rustls_result::CertificateTransparencyRequired,
+            Err(_) if conn.policy_missing => rustls_result::CertificatePolicyMissing,
+            Err(_) if conn.handshake_timed_out => rustls_result::HandshakeTimeout,
             Err(e) => map_error(e),
         };
         match guard.try_drop() {
@@ -254,11 +1410,27 @@ pub extern "C" fn rustls_connection_process_new_packets(
     }
 }
 
+/// Returns true if decrypted plaintext is waiting to be read, so that
+/// `rustls_connection_read` would return at least one byte. Returns false
+/// if a read would return 0 bytes (or, after the peer's close_notify, the
+/// close_notify result), in which case more TLS data has to be read and
+/// processed first.
+///
+/// This is the opposite of `rustls_connection_wants_read`, which is true
+/// exactly when no plaintext is buffered.
+#[no_mangle]
+pub extern "C" fn rustls_connection_read_has_more(conn: *const rustls_connection) -> bool {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        !conn.wants_read()
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn rustls_connection_wants_read(conn: *const rustls_connection) -> bool {
     ffi_panic_boundary! {
         let conn: &Connection = try_ref_from_ptr!(conn);
-        conn.as_ref().wants_read()
+        conn.wants_read()
     }
 }
 
@@ -278,6 +1450,68 @@ pub extern "C" fn rustls_connection_is_handshaking(conn: *const rustls_connectio
     }
 }
 
+/// Return whether `conn` is the client or the server side of its session,
+/// for code shared between both that needs to call side-specific functions
+/// such as `rustls_server_connection_get_sni_hostname`. Returns
+/// RUSTLS_CONNECTION_SIDE_UNKNOWN if `conn` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_side(
+    conn: *const rustls_connection,
+) -> rustls_connection_side {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        match conn.conn {
+            Inner::Client(_) => rustls_connection_side::Client,
+            Inner::Server(_) => rustls_connection_side::Server,
+        }
+    }
+}
+
+/// Store the results of `rustls_connection_wants_read`,
+/// `rustls_connection_wants_write` and `rustls_connection_is_handshaking`
+/// in `out_wants_read`, `out_wants_write` and `out_is_handshaking`, for
+/// event loops that check all three on every iteration.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_io_state(
+    conn: *const rustls_connection,
+    out_wants_read: *mut bool,
+    out_wants_write: *mut bool,
+    out_is_handshaking: *mut bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out_wants_read: &mut bool = try_mut_from_ptr!(out_wants_read);
+        let out_wants_write: &mut bool = try_mut_from_ptr!(out_wants_write);
+        let out_is_handshaking: &mut bool = try_mut_from_ptr!(out_is_handshaking);
+        let session = conn.as_ref();
+        *out_wants_read = conn.wants_read();
+        *out_wants_write = session.wants_write();
+        *out_is_handshaking = session.is_handshaking();
+        rustls_result::Ok
+    }
+}
+
+/// Returns true once no more application data can flow on the connection:
+/// either `rustls_connection_read` has returned
+/// `RUSTLS_RESULT_ALERT_CLOSE_NOTIFY`, meaning the peer closed its side and
+/// all plaintext before the alert has been read, or this side has queued a
+/// close_notify (with `rustls_connection_send_close_notify`,
+/// `rustls_connection_send_alert` or `rustls_connection_close`) and it has
+/// been written out, so `rustls_connection_wants_write` is false.
+///
+/// This is the terminal condition for an event loop, unlike
+/// `rustls_connection_is_handshaking`, which only says whether the handshake
+/// is still in progress and is false both before close and after it.
+/// Connections that fail with an error are not reported as closed; the
+/// error itself is the terminal condition there.
+#[no_mangle]
+pub extern "C" fn rustls_connection_is_closed(conn: *const rustls_connection) -> bool {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        conn.is_closed()
+    }
+}
+
 /// Sets a limit on the internal buffers used to buffer unsent plaintext (prior
 /// to completing the TLS handshake) and unsent TLS records. By default, there
 /// is no limit. The limit can be set at any time, even if the current buffer
@@ -287,189 +1521,2779 @@ pub extern "C" fn rustls_connection_is_handshaking(conn: *const rustls_connectio
 pub extern "C" fn rustls_connection_set_buffer_limit(conn: *mut rustls_connection, n: usize) {
     ffi_panic_boundary! {
         let conn: &mut Connection = try_mut_from_ptr!(conn);
-        conn.as_mut().set_buffer_limit(n);
+        conn.set_buffer_limit(n);
     }
 }
 
-/// Queues a close_notify fatal alert to be sent in the next write_tls call.
-/// https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.send_close_notify
+/// Reserve room for `bytes` of received plaintext in the buffer that
+/// `rustls_connection_process_new_packets` moves it into. Unlike
+/// `rustls_connection_set_buffer_limit`, which caps buffering, this only
+/// grows the buffer ahead of time, so that receiving a message of up to
+/// `bytes` that is read only once it has all arrived doesn't reallocate it
+/// as it fills. The buffer is never shrunk, so the memory stays allocated
+/// until the connection is freed, including across
+/// `rustls_connection_reset`; a hint smaller than the current capacity does
+/// nothing.
+///
+/// Plaintext is only moved out of rustls for connections that use this
+/// function or `rustls_connection_read_until_boundary`; calling either
+/// turns it on for the connection. rustls 0.19 decrypts each record into a
+/// buffer of its own, which this doesn't affect. Moving the plaintext
+/// copies it once more, and in measurements that cost outweighs the
+/// reallocations the hint saves, so use the hint to allocate memory up
+/// front rather than to make reads faster.
 #[no_mangle]
-pub extern "C" fn rustls_connection_send_close_notify(conn: *mut rustls_connection) {
+pub extern "C" fn rustls_connection_set_read_buffer_hint(
+    conn: *mut rustls_connection,
+    bytes: size_t,
+) {
     ffi_panic_boundary! {
         let conn: &mut Connection = try_mut_from_ptr!(conn);
-        conn.as_mut().send_close_notify();
+        conn.reserve_plaintext(bytes);
     }
 }
 
-/// Return the i-th certificate provided by the peer.
-/// Index 0 is the end entity certificate. Higher indexes are certificates
-/// in the chain. Requesting an index higher than what is available returns
-/// NULL.
+/// Enable or disable write coalescing, which is off by default. While it is
+/// on, `rustls_connection_write` holds plaintext back until it has a full
+/// record's worth, so that many small writes are sent as a few large
+/// records. That improves throughput and hides the boundaries between
+/// writes from observers, at the cost of latency: a partial record is only
+/// sent once `rustls_connection_flush_plaintext` is called, so callers must
+/// flush after writing each complete message. Held-back plaintext counts
+/// against the limit set with `rustls_connection_set_buffer_limit`, but not
+/// towards `rustls_connection_wants_write`.
+///
+/// Disabling coalescing flushes whatever was held back.
+/// `rustls_connection_send_close_notify` also flushes it first, so that it
+/// is sent before the close_notify.
 #[no_mangle]
-pub extern "C" fn rustls_connection_get_peer_certificate(
+pub extern "C" fn rustls_connection_set_write_coalescing(
     conn: *mut rustls_connection,
-    i: size_t,
-) -> *const rustls_certificate {
-    // TODO: this should be changed in the next rustls release where the
-    // API no longer returns copies but references to the certificates it
-    // keeps. We then no longer have to hold our own Vec.
+    enabled: bool,
+) {
     ffi_panic_boundary! {
         let conn: &mut Connection = try_mut_from_ptr!(conn);
-        let certs = match &conn.peer_certs {
-            Some(certs) => certs,
-            None => {
-                match conn.as_ref().get_peer_certificates() {
-                    Some(certs) => {
-                        conn.peer_certs = Some(certs);
-                        conn.peer_certs.as_ref().unwrap()
-                    },
-                    None => return null()
-                }
-            }
-        };
-        match certs.get(i) {
-            Some(cert) => cert as *const Certificate as *const _,
-            None => null()
+        conn.set_write_coalescing(enabled);
+    }
+}
+
+/// Hand any plaintext held back by write coalescing (see
+/// `rustls_connection_set_write_coalescing`) to rustls, to be encrypted
+/// and sent by `rustls_connection_write_tls`. Returns
+/// RUSTLS_RESULT_WRITE_WOULD_BLOCK if the limit set with
+/// `rustls_connection_set_buffer_limit` stopped some of it: write out TLS
+/// bytes with `rustls_connection_write_tls`, then flush again.
+#[no_mangle]
+pub extern "C" fn rustls_connection_flush_plaintext(conn: *mut rustls_connection) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        match conn.flush_plaintext() {
+            Ok(true) => rustls_result::Ok,
+            Ok(false) => rustls_result::WriteWouldBlock,
+            Err(e) => conn.io_error(&e),
         }
     }
 }
 
-/// Get the ALPN protocol that was negotiated, if any. Stores a pointer to a
-/// borrowed buffer of bytes, and that buffer's len, in the output parameters.
-/// The borrow lives as long as the connection.
-/// If the connection is still handshaking, or no ALPN protocol was negotiated,
-/// stores NULL and 0 in the output parameters.
-/// https://www.iana.org/assignments/tls-parameters/
-/// https://docs.rs/rustls/0.19.1/rustls/trait.Session.html#tymethod.get_alpn_protocol
+/// Require the handshake to complete by `unix_millis`, in milliseconds
+/// since the Unix epoch, UTC. If `rustls_connection_process_new_packets` is
+/// called after that while the connection is still handshaking, it returns
+/// RUSTLS_RESULT_HANDSHAKE_TIMEOUT without processing anything, and keeps
+/// returning it; the caller should then close the connection. This lets an
+/// event loop enforce handshake timeouts without a timer per connection,
+/// although a connection that never receives anything still needs to be
+/// checked some other way. A value of 0, the default, removes the deadline.
+///
+/// The deadline is converted to a time on the monotonic clock when it is
+/// set, so later changes to the system clock, in either direction, don't
+/// make it expire early or late. A deadline that has already passed
+/// expires at the next call.
 #[no_mangle]
-pub extern "C" fn rustls_connection_get_alpn_protocol(
-    conn: *const rustls_connection,
-    protocol_out: *mut *const u8,
-    protocol_out_len: *mut usize,
+pub extern "C" fn rustls_connection_set_handshake_deadline(
+    conn: *mut rustls_connection,
+    unix_millis: u64,
 ) {
     ffi_panic_boundary! {
-        let conn: &Connection = try_ref_from_ptr!(conn);
-        let protocol_out = try_mut_from_ptr!(protocol_out);
-        let protocol_out_len = try_mut_from_ptr!(protocol_out_len);
-        match conn.as_ref().get_alpn_protocol() {
-            Some(p) => {
-                *protocol_out = p.as_ptr();
-                *protocol_out_len = p.len();
-            },
-            None => {
-                *protocol_out = null();
-                *protocol_out_len = 0;
-            }
-        }
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        conn.set_handshake_deadline(unix_millis);
     }
 }
 
-/// Return the TLS protocol version that has been negotiated. Before this
-/// has been decided during the handshake, this will return 0. Otherwise,
-/// the u16 version number as defined in the relevant RFC is returned.
-/// https://docs.rs/rustls/0.19.1/rustls/trait.Session.html#tymethod.get_protocol_version
-/// https://docs.rs/rustls/0.19.1/rustls/internal/msgs/enums/enum.ProtocolVersion.html
+/// Cap the number of plaintext bytes a single `rustls_connection_read` will
+/// return, even if more are buffered. An event loop serving many connections
+/// can use this to stop one busy connection from starving the others; the
+/// rest of the plaintext is returned by later calls. A value of 0, the
+/// default, means no cap.
 #[no_mangle]
-pub extern "C" fn rustls_connection_get_protocol_version(conn: *const rustls_connection) -> u16 {
+pub extern "C" fn rustls_connection_set_max_plaintext_read(
+    conn: *mut rustls_connection,
+    n: size_t,
+) {
     ffi_panic_boundary! {
-        let conn: &Connection = try_ref_from_ptr!(conn);
-        match conn.as_ref().get_protocol_version() {
-            Some(p) => p.get_u16(),
-            _ => 0,
-        }
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        conn.max_plaintext_read = n;
     }
 }
 
-/// Retrieves the cipher suite agreed with the peer.
-/// This returns NULL until the ciphersuite is agreed.
-/// https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.get_negotiated_ciphersuite
+/// Return the size in bytes of the largest TLS record, including its 5-byte
+/// header, that `rustls_connection_write_tls` will emit from now on. This is
+/// the config's max fragment size (by default 16384, the TLS maximum) plus
+/// the encryption overhead of the negotiated cipher suite, or of the most
+/// expensive one rustls supports if none has been negotiated yet. Large
+/// writes are split across several records to stay within it.
 #[no_mangle]
-pub extern "C" fn rustls_connection_get_negotiated_ciphersuite(
+pub extern "C" fn rustls_connection_max_output_record_size(
     conn: *const rustls_connection,
-) -> *const rustls_supported_ciphersuite {
+) -> size_t {
     ffi_panic_boundary! {
         let conn: &Connection = try_ref_from_ptr!(conn);
-        match conn.as_ref().get_negotiated_ciphersuite() {
-            Some(cs) => cs as *const SupportedCipherSuite as *const _,
-            None => null(),
-        }
+        conn.max_output_record_size()
     }
 }
 
-/// Write up to `count` plaintext bytes from `buf` into the `rustls_connection`.
-/// This will increase the number of output bytes available to
-/// `rustls_connection_write_tls`.
-/// On success, store the number of bytes actually written in *out_n
-/// (this may be less than `count`).
+/// Return how many plaintext bytes `rustls_connection_write` can currently
+/// accept before hitting the limit set with
+/// `rustls_connection_set_buffer_limit`. If no limit is set, returns SIZE_MAX.
+///
+/// The value is an estimate: it doesn't include TLS record overhead or
+/// handshake messages queued by rustls itself, so a subsequent write may
+/// accept somewhat fewer bytes than reported. Calling
+/// `rustls_connection_write_tls` frees up capacity once the handshake is
+/// complete. Returns 0 if `conn` is NULL.
 #[no_mangle]
-pub extern "C" fn rustls_connection_write(
-    conn: *mut rustls_connection,
-    buf: *const u8,
-    count: size_t,
-    out_n: *mut size_t,
-) -> rustls_result {
+pub extern "C" fn rustls_connection_write_capacity(conn: *const rustls_connection) -> size_t {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        conn.write_capacity()
+    }
+}
+
+/// Queues a close_notify fatal alert to be sent in the next write_tls call.
+/// This performs no I/O itself: the caller is responsible for flushing the
+/// alert with `rustls_connection_write_tls` (or `rustls_connection_close`)
+/// before calling `rustls_connection_free`, which discards anything still
+/// queued.
+/// https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.send_close_notify
+#[no_mangle]
+pub extern "C" fn rustls_connection_send_close_notify(conn: *mut rustls_connection) {
     ffi_panic_boundary! {
         let conn: &mut Connection = try_mut_from_ptr!(conn);
-        let write_buf: &[u8] = try_slice!(buf, count);
-        let out_n: &mut size_t = unsafe {
-            match out_n.as_mut() {
-                Some(out_n) => out_n,
-                None => return NullParameter,
-            }
-        };
-        let n_written: usize = match conn.as_mut().write(write_buf) {
-            Ok(n) => n,
-            Err(_) => return rustls_result::Io,
-        };
-        *out_n = n_written;
-        rustls_result::Ok
+        conn.send_close_notify();
     }
 }
 
-/// Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
-/// On success, store the number of bytes read in *out_n (this may be less
-/// than `count`). A success with *out_n set to 0 means "all bytes currently
-/// available have been read, but more bytes may become available after
-/// subsequent calls to rustls_connection_read_tls and
-/// rustls_connection_process_new_packets."
+/// Queue a fatal alert with the given AlertDescription value, from
+/// https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-6,
+/// to be sent by the next `rustls_connection_write_tls` call.
 ///
-/// Subtle note: Even though this function only writes to `buf` and does not
-/// read from it, the memory in `buf` must be initialized before the call (for
-/// Rust-internal reasons). Initializing a buffer once and then using it
-/// multiple times without zeroizing before each call is fine.
+/// Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `alert_description` isn't a
+/// known alert. The version of rustls used by crustls can only send
+/// close_notify (0) on request; any other known alert returns
+/// `RUSTLS_RESULT_UNIMPLEMENTED` without queueing anything, and the caller
+/// should close the connection with `rustls_connection_send_close_notify`
+/// instead.
 #[no_mangle]
-pub extern "C" fn rustls_connection_read(
+pub extern "C" fn rustls_connection_send_alert(
     conn: *mut rustls_connection,
-    buf: *mut u8,
-    count: size_t,
-    out_n: *mut size_t,
+    alert_description: u8,
 ) -> rustls_result {
     ffi_panic_boundary! {
         let conn: &mut Connection = try_mut_from_ptr!(conn);
-        let read_buf: &mut [u8] = try_mut_slice!(buf, count);
-        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        match AlertDescription::read_bytes(&[alert_description]) {
+            Some(AlertDescription::CloseNotify) => {
+                conn.send_close_notify();
+                rustls_result::Ok
+            }
+            Some(AlertDescription::Unknown(_)) | None => rustls_result::InvalidParameter,
+            Some(_) => rustls_result::Unimplemented,
+        }
+    }
+}
 
-        let n_read: usize = match conn.as_mut().read(read_buf) {
-            Ok(n) => n,
-            // Rustls turns close_notify alerts into `io::Error` of kind `ConnectionAborted`.
-            // https://docs.rs/rustls/0.19.0/rustls/struct.ClientSession.html#impl-Read.
-            Err(e) if is_close_notify(&e) => {
-                return rustls_result::AlertCloseNotify;
+/// If a fatal alert is queued to be sent by `rustls_connection_write_tls`
+/// because `rustls_connection_process_new_packets` failed, write its
+/// AlertDescription value to `out_description` and return true. This lets
+/// the caller log what it is about to tell the peer, such as
+/// bad_certificate (42), before writing it. Returns false once the alert
+/// has been written, and if `conn` or `out_description` is NULL.
+///
+/// rustls doesn't say which alert it queued, so crustls works it out from
+/// the error. This covers the errors that determine the alert: an
+/// unexpected message, an oversized record, and a rejected peer
+/// certificate, except that a server can't tell a rejected client
+/// certificate from a bad client signature unless the rejection was for
+/// the minimum RSA key size. For other errors this returns false even
+/// though an alert may be queued.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_pending_alert(
+    conn: *const rustls_connection,
+    out_description: *mut u8,
+) -> bool {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out_description: &mut u8 = try_mut_from_ptr!(out_description);
+        match conn.pending_alert {
+            Some(alert) => {
+                *out_description = alert;
+                true
             }
-            Err(_) => return rustls_result::Io,
-        };
-        *out_n = n_read;
-        rustls_result::Ok
+            None => false,
+        }
     }
 }
 
-/// Free a rustls_connection. Calling with NULL is fine.
-/// Must not be called twice with the same value.
+/// Queue a close_notify alert and write all pending TLS bytes, including the
+/// alert, using `callback`. `callback` and `userdata` behave as in
+/// `rustls_connection_write_tls`, except that `callback` may be invoked
+/// several times. Returns 0 once nothing is left to write. If `callback`
+/// returns an error, that error is returned and any remaining bytes stay
+/// queued, so the caller may finish the job with `rustls_connection_write_tls`
+/// (for instance after EAGAIN). If `callback` reports writing zero bytes,
+/// returns EIO. Does not free the connection.
 #[no_mangle]
-pub extern "C" fn rustls_connection_free(conn: *mut rustls_connection) {
+pub extern "C" fn rustls_connection_close(
+    conn: *mut rustls_connection,
+    callback: rustls_write_callback,
+    userdata: *mut c_void,
+) -> rustls_io_result {
     ffi_panic_boundary! {
         let conn: &mut Connection = try_mut_from_ptr!(conn);
-        // Convert the pointer to a Box and drop it.
-        unsafe { Box::from_raw(conn); }
+        let callback: WriteCallback = try_callback!(callback);
+
+        conn.send_close_notify();
+        let mut writer = CallbackWriter { callback, userdata };
+        while conn.as_ref().wants_write() {
+            match conn.write_tls(&mut writer) {
+                Ok(0) => return rustls_io_result(EIO),
+                Ok(_) => {}
+                Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
+            }
+        }
+        rustls_io_result(0)
+    }
+}
+
+/// Returns true if the peer's certificate chain was accepted despite having
+/// expired, which can only happen on a client connection whose config was
+/// built with `rustls_client_config_builder_dangerous_accept_expired`.
+/// Returns false until the peer's certificate has been verified.
+#[no_mangle]
+pub extern "C" fn rustls_connection_peer_cert_is_expired(conn: *const rustls_connection) -> bool {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        conn.peer_cert_expired
+    }
+}
+
+/// A callback for `rustls_connection_get_peer_signature_schemes` and
+/// `rustls_signature_schemes_for_version`, called once per signature scheme
+/// with its IANA-assigned u16 value.
+/// https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-signaturescheme
+#[allow(non_camel_case_types)]
+pub type rustls_signature_scheme_callback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, scheme: u16)>;
+
+/// Call `callback` with each signature scheme the peer advertised, in the
+/// peer's order of preference, passing `userdata` through unchanged.
+///
+/// On a server connection these are the schemes from the client's
+/// ClientHello. On a client connection they are the schemes from the
+/// server's CertificateRequest, so they are only available if the server
+/// asked for a client certificate, and only if the config's client
+/// certificate resolver is one installed by crustls. For TLS 1.3, rustls
+/// filters out schemes that can't be used with that version.
+///
+/// Before the relevant handshake message has been processed, `callback`
+/// is not called at all.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_peer_signature_schemes(
+    conn: *const rustls_connection,
+    callback: rustls_signature_scheme_callback,
+    userdata: *mut c_void,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let callback = try_callback!(callback);
+        for &scheme in conn.peer_signature_schemes() {
+            unsafe { callback(userdata, scheme) };
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Return the number of certificates provided by the peer: the end entity
+/// certificate and the rest of its chain. Returns 0 before the peer has
+/// sent its certificates, and if `conn` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_peer_cert_count(conn: *const rustls_connection) -> size_t {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        conn.peer_certs().len()
+    }
+}
+
+/// Return the i-th certificate provided by the peer.
+/// Index 0 is the end entity certificate. Higher indexes are certificates
+/// in the chain. Requesting an index higher than what is available returns
+/// NULL.
+///
+/// The chain is copied out of rustls the first time any of the peer
+/// certificate functions finds it, and kept: the returned pointer is the
+/// same on every call, and remains valid until the connection is freed or
+/// reset with `rustls_connection_reset`.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_peer_certificate(
+    conn: *mut rustls_connection,
+    i: size_t,
+) -> *const rustls_certificate {
+    // TODO: this should be changed in the next rustls release where the
+    // API no longer returns copies but references to the certificates it
+    // keeps. We then no longer have to hold our own Vec.
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        match conn.peer_certs().get(i) {
+            Some(cert) => cert as *const Certificate as *const _,
+            None => null()
+        }
+    }
+}
+
+/// Point `out` at the DER encoding of the i-th certificate provided by the
+/// peer, as numbered by `rustls_connection_get_peer_certificate`. This is
+/// the same as calling that function and then `rustls_certificate_get_der`.
+/// If there is no i-th certificate, including before the peer has sent its
+/// certificates, `out` is set to an empty slice and RUSTLS_RESULT_OK is
+/// returned.
+///
+/// The slice borrows from the connection: it remains valid until the
+/// connection is freed or reset with `rustls_connection_reset`, and must
+/// not be used after that.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_peer_certificate_der(
+    conn: *mut rustls_connection,
+    i: size_t,
+    out: *mut rustls_slice_bytes,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out: &mut rustls_slice_bytes = try_mut_from_ptr!(out);
+        let der: &[u8] = match conn.peer_certs().get(i) {
+            Some(cert) => cert.as_ref(),
+            None => &[],
+        };
+        *out = der.into();
+        rustls_result::Ok
+    }
+}
+
+/// Copy the DER encoding of every certificate provided by the peer into
+/// `buf`, which can hold up to `len` bytes, and store the number of bytes
+/// written in `out_n`.
+///
+/// The output uses the same framing as the `certificate_list` of a TLS 1.2
+/// Certificate message (RFC 5246, section 7.4.2): each certificate is
+/// preceded by its length as a 3-byte big-endian integer, and the
+/// certificates follow one another with no padding. The end entity
+/// certificate comes first, followed by the rest of the chain in the order
+/// the peer sent it. There is no overall length prefix; the total length
+/// is `*out_n`.
+///
+/// Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if `buf` is too small; in that
+/// case, and as an exception to the usual convention, the number of bytes
+/// needed is stored in `out_n` so the caller can retry with a larger buffer.
+/// Returns Ok with *out_n == 0 if the peer has not provided any certificates
+/// (yet).
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_peer_certs_der_concat(
+    conn: *const rustls_connection,
+    buf: *mut u8,
+    len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let write_buf: &mut [u8] = try_mut_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let certs: &[Certificate] = conn.peer_certs();
+        let needed: usize = certs_der_concat_len(certs);
+        if needed > write_buf.len() {
+            *out_n = needed;
+            return rustls_result::InsufficientSize;
+        }
+        *out_n = certs_der_concat(certs, write_buf);
+        rustls_result::Ok
+    }
+}
+
+/// Write the SHA-256 hash of the SubjectPublicKeyInfo of the peer's end
+/// entity certificate to `buf`, which can hold up to `len` bytes, and store
+/// the number of bytes written (always 32) in `out_n`. This is the value
+/// that `rustls_client_config_builder_dangerous_set_pinned_spki` compares
+/// against, and the one usually published as an SPKI pin (RFC 7469), before
+/// base64 encoding.
+///
+/// Returns RUSTLS_RESULT_NO_CERTIFICATES_PRESENTED if the peer has not
+/// provided a certificate (yet), RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if
+/// the SubjectPublicKeyInfo can't be found in it, and
+/// RUSTLS_RESULT_INSUFFICIENT_SIZE if `len` is less than 32.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_peer_cert_spki_sha256(
+    conn: *const rustls_connection,
+    buf: *mut u8,
+    len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let write_buf: &mut [u8] = try_mut_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let cert: &Certificate = match conn.peer_certs().first() {
+            Some(cert) => cert,
+            None => return rustls_result::NoCertificatesPresented,
+        };
+        let spki: &[u8] = match x509::subject_public_key_info(&cert.0) {
+            Ok(spki) => spki,
+            Err(_) => return rustls_result::CertificateParseError,
+        };
+        let hash = digest::digest(&digest::SHA256, spki);
+        let hash: &[u8] = hash.as_ref();
+        if hash.len() > write_buf.len() {
+            return rustls_result::InsufficientSize;
+        }
+        write_buf[..hash.len()].copy_from_slice(hash);
+        *out_n = hash.len();
+        rustls_result::Ok
+    }
+}
+
+// The fatal alert rustls 0.19.1 queues when processing fails with `error`,
+// if the error determines it. A client sends bad_certificate for any
+// certificate or signature its verifier rejects (decode_error for BadDER),
+// which includes crustls' own policies, reported through `report`. A server
+// sends handshake_failure for a rejected client certificate, but
+// access_denied for a bad signature, and both fail with WebPKIError.
+fn fatal_alert(error: &TLSError, is_client: bool, report: &HookReport) -> Option<AlertDescription> {
+    let rejected =
+        report.ocsp_rejected || report.weak_key || report.ct_rejected || report.policy_missing;
+    match error {
+        TLSError::InappropriateMessage { .. } | TLSError::InappropriateHandshakeMessage { .. } => {
+            Some(AlertDescription::UnexpectedMessage)
+        }
+        TLSError::PeerSentOversizedRecord => Some(AlertDescription::RecordOverflow),
+        TLSError::WebPKIError(webpki::Error::BadDER) if is_client => {
+            Some(AlertDescription::DecodeError)
+        }
+        TLSError::WebPKIError(_) if is_client => Some(AlertDescription::BadCertificate),
+        TLSError::General(_) if is_client && rejected => Some(AlertDescription::BadCertificate),
+        TLSError::General(_) if report.weak_key => Some(AlertDescription::HandshakeFailure),
+        _ => None,
+    }
+}
+
+// The length of `certs` when framed by certs_der_concat.
+fn certs_der_concat_len(certs: &[Certificate]) -> usize {
+    certs.iter().map(|c| 3 + c.0.len()).sum()
+}
+
+// Write each certificate in `certs` to `out`, prefixed with its 3-byte
+// big-endian length. `out` must be at least certs_der_concat_len(certs)
+// bytes long. Returns the number of bytes written.
+fn certs_der_concat(certs: &[Certificate], out: &mut [u8]) -> usize {
+    let mut offset: usize = 0;
+    for cert in certs {
+        let der: &[u8] = cert.as_ref();
+        let der_len = der.len() as u32;
+        out[offset..offset + 3].copy_from_slice(&der_len.to_be_bytes()[1..]);
+        offset += 3;
+        out[offset..offset + der.len()].copy_from_slice(der);
+        offset += der.len();
+    }
+    offset
+}
+
+/// Set the ALPN protocols for this connection, replacing the list from its
+/// config. `protocols` and `len` are as for
+/// `rustls_client_config_builder_set_protocols`, and the data is copied.
+/// A client offers these protocols instead of the config's; a server picks
+/// from these instead. An empty list disables ALPN for this connection.
+///
+/// rustls only reads ALPN protocols from the config, and a client session
+/// builds its ClientHello as soon as it is created. So crustls implements
+/// this by recreating the connection's session from a copy of the config
+/// with the new list. Everything else about the config is kept, including
+/// any session storage, so resumption still works. This must be called
+/// before any TLS data is read or written and before any plaintext is
+/// written; otherwise it returns RUSTLS_RESULT_INVALID_PARAMETER and
+/// changes nothing.
+#[no_mangle]
+pub extern "C" fn rustls_connection_set_alpn_protocols(
+    conn: *mut rustls_connection,
+    protocols: *const rustls_slice_bytes,
+    len: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let protocols: &[rustls_slice_bytes] = try_slice!(protocols, len);
+
+        let mut vv: Vec<Vec<u8>> = Vec::with_capacity(protocols.len());
+        for p in protocols {
+            let v: &[u8] = try_slice!(p.data, p.len);
+            vv.push(v.to_vec());
+        }
+        match conn.set_alpn_protocols(&vv) {
+            Ok(()) => rustls_result::Ok,
+            Err(rr) => rr,
+        }
+    }
+}
+
+/// Make a client connection perform a full handshake, rather than resume a
+/// session stored in its config, while leaving the config's stored sessions
+/// for other connections to use. The session this connection establishes
+/// is stored as usual. To stop every connection from a config resuming, see
+/// `rustls_client_config_clear_session_cache`.
+///
+/// A client session looks up the session to resume as soon as it is
+/// created, so like `rustls_connection_set_alpn_protocols` this recreates
+/// it, and must be called before any TLS data is read or written and before
+/// any plaintext is written. It is kept across `rustls_connection_reset`.
+/// Returns RUSTLS_RESULT_INVALID_PARAMETER, changing nothing, if called too
+/// late or on a server connection.
+#[no_mangle]
+pub extern "C" fn rustls_connection_forget_resumption(
+    conn: *mut rustls_connection,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        match conn.forget_resumption() {
+            Ok(()) => rustls_result::Ok,
+            Err(rr) => rr,
+        }
+    }
+}
+
+/// Get the ALPN protocol that was negotiated, if any. Stores a pointer to a
+/// borrowed buffer of bytes, and that buffer's len, in the output parameters.
+/// The borrow lives as long as the connection.
+/// If the connection is still handshaking, or no ALPN protocol was negotiated,
+/// stores NULL and 0 in the output parameters.
+/// https://www.iana.org/assignments/tls-parameters/
+/// https://docs.rs/rustls/0.19.1/rustls/trait.Session.html#tymethod.get_alpn_protocol
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_alpn_protocol(
+    conn: *const rustls_connection,
+    protocol_out: *mut *const u8,
+    protocol_out_len: *mut usize,
+) {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let protocol_out = try_mut_from_ptr!(protocol_out);
+        let protocol_out_len = try_mut_from_ptr!(protocol_out_len);
+        match conn.as_ref().get_alpn_protocol() {
+            Some(p) => {
+                *protocol_out = p.as_ptr();
+                *protocol_out_len = p.len();
+            },
+            None => {
+                *protocol_out = null();
+                *protocol_out_len = 0;
+            }
+        }
+    }
+}
+
+/// Get the ALPN protocol that was negotiated, or `default_protocol` if none
+/// was. This is for applications, such as HTTP servers, that fall back to a
+/// fixed protocol like "http/1.1" when the peer doesn't use ALPN.
+///
+/// Stores a borrowed slice in `out`. Where it is borrowed from depends on
+/// the outcome:
+///
+/// - If a protocol was negotiated, the slice points into the connection and
+///   lives as long as the connection.
+/// - Otherwise, including while the connection is still handshaking, the
+///   slice is `default_protocol` and `default_protocol_len` exactly as
+///   passed in. That memory belongs to the caller, who must keep it alive
+///   for as long as they use `out`.
+///
+/// `default_protocol` may be NULL if `default_protocol_len` is 0, in which
+/// case `out` is NULL and 0 when no protocol was negotiated, as with
+/// rustls_connection_get_alpn_protocol.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_alpn_protocol_or(
+    conn: *const rustls_connection,
+    default_protocol: *const u8,
+    default_protocol_len: size_t,
+    out: *mut rustls_slice_bytes,
+) {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out: &mut rustls_slice_bytes = try_mut_from_ptr!(out);
+        *out = match conn.as_ref().get_alpn_protocol() {
+            Some(p) => p.into(),
+            None if default_protocol.is_null() => {
+                let mut empty: rustls_slice_bytes = (&[][..]).into();
+                empty.data = null();
+                empty
+            }
+            None => try_slice!(default_protocol, default_protocol_len).into(),
+        };
+    }
+}
+
+/// Return the TLS protocol version that has been negotiated. Before this
+/// has been decided during the handshake, this will return 0. Otherwise,
+/// the u16 version number as defined in the relevant RFC is returned.
+///
+/// The version is known well before the handshake completes. A server
+/// decides it while processing the ClientHello, so it is non-zero after the
+/// `rustls_connection_process_new_packets` call that handles the
+/// ClientHello, before the server has sent anything. A client learns it
+/// from the ServerHello (or a TLS 1.3 HelloRetryRequest), so it is non-zero
+/// after the call that processes that message, while the rest of the
+/// server's first flight may still be outstanding. It does not change once
+/// set.
+/// https://docs.rs/rustls/0.19.1/rustls/trait.Session.html#tymethod.get_protocol_version
+/// https://docs.rs/rustls/0.19.1/rustls/internal/msgs/enums/enum.ProtocolVersion.html
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_protocol_version(conn: *const rustls_connection) -> u16 {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        match conn.as_ref().get_protocol_version() {
+            Some(p) => p.get_u16(),
+            _ => 0,
+        }
+    }
+}
+
+/// Retrieves the cipher suite agreed with the peer.
+/// This returns NULL until the ciphersuite is agreed.
+/// If you only need the suite's u16 value, use
+/// `rustls_connection_get_negotiated_ciphersuite_id`.
+/// https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.get_negotiated_ciphersuite
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_negotiated_ciphersuite(
+    conn: *const rustls_connection,
+) -> *const rustls_supported_ciphersuite {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        match conn.as_ref().get_negotiated_ciphersuite() {
+            Some(cs) => cs as *const SupportedCipherSuite as *const _,
+            None => null(),
+        }
+    }
+}
+
+/// Return the IANA-assigned u16 value of the cipher suite agreed with the
+/// peer, or 0 until the cipher suite is agreed. This is the same as calling
+/// `rustls_supported_ciphersuite_get_suite` on the result of
+/// `rustls_connection_get_negotiated_ciphersuite`, without the NULL check.
+/// https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-4
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_negotiated_ciphersuite_id(
+    conn: *const rustls_connection,
+) -> u16 {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        match conn.as_ref().get_negotiated_ciphersuite() {
+            Some(cs) => cs.suite.get_u16(),
+            None => 0,
+        }
+    }
+}
+
+/// A snapshot of what has been negotiated on a connection so far, filled in
+/// by `rustls_connection_get_handshake_info`. Fields that aren't known yet
+/// are zero (or false).
+///
+/// Callers allocate this struct, so its layout is part of the ABI: fields
+/// will not be reordered or removed within a release series, and any new
+/// fields will be appended at the end and noted in the changelog.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct rustls_handshake_info {
+    /// The negotiated protocol version, as for
+    /// `rustls_connection_get_protocol_version`.
+    pub protocol_version: u16,
+    /// The IANA-assigned value of the negotiated cipher suite, as for
+    /// `rustls_supported_ciphersuite_get_suite`.
+    pub cipher_suite: u16,
+    /// The IANA-assigned NamedGroup used for key exchange, e.g. 0x001d for
+    /// X25519. Always 0 for resumed TLS 1.2 sessions, which don't perform a
+    /// key exchange.
+    pub kx_group: u16,
+    /// Whether the session was resumed rather than fully negotiated.
+    pub resumed: bool,
+    /// Whether the handshake is still in progress, as for
+    /// `rustls_connection_is_handshaking`.
+    pub is_handshaking: bool,
+}
+
+impl CastPtr for rustls_handshake_info {
+    type RustType = rustls_handshake_info;
+}
+
+/// Fill in `out` with what the connection has negotiated so far, in a single
+/// call. See `rustls_handshake_info` for the meaning of each field.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_handshake_info(
+    conn: *const rustls_connection,
+    out: *mut rustls_handshake_info,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out: &mut rustls_handshake_info = try_mut_from_ptr!(out);
+        *out = conn.handshake_info();
+        rustls_result::Ok
+    }
+}
+
+/// Write the name of the negotiated key exchange group, such as "X25519"
+/// or "secp256r1", into `buf`, which can hold up to `len` bytes, and store
+/// the number of bytes written in `out_n`. The name is UTF-8 and is not
+/// NUL-terminated. A group crustls doesn't know is written as "Unknown("
+/// followed by its value in hex and ")", such as "Unknown(0x1234)". Until
+/// the group is known, and for resumed TLS 1.2 sessions, which don't perform
+/// a key exchange, nothing is written and `out_n` is 0. The group's value
+/// is the `kx_group` field of `rustls_handshake_info`.
+///
+/// Returns RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if `buf` is
+/// too small; in that case the number of bytes needed is stored in `out_n`.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_negotiated_group_name(
+    conn: *const rustls_connection,
+    buf: *mut u8,
+    len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let write_buf: &mut [u8] = try_mut_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let name = match conn.handshake_details().kx_group {
+            0 => String::new(),
+            group => named_group_name(group),
+        };
+        *out_n = name.len();
+        if name.len() > write_buf.len() {
+            return rustls_result::InsufficientSize;
+        }
+        write_buf[..name.len()].copy_from_slice(name.as_bytes());
+        rustls_result::Ok
+    }
+}
+
+/// Return true if the connection completed a TLS 1.2 handshake that
+/// negotiated the Extended Master Secret extension (RFC 7627), which binds
+/// the session's keys to the whole handshake. Returns false for TLS 1.3,
+/// where the extension doesn't apply because the key schedule always does
+/// this, while the handshake is in progress, and if `conn` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_used_extended_master_secret(
+    conn: *const rustls_connection,
+) -> bool {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let session = conn.as_ref();
+        !session.is_handshaking()
+            && session.get_protocol_version() == Some(ProtocolVersion::TLSv1_2)
+            && conn.handshake_details().extended_master_secret
+    }
+}
+
+/// Return the most early (0-RTT) data, in bytes, that the server allows a
+/// client connection to send: the limit in the resumption ticket it
+/// offered, if its config enables early data. Returns 0 if early data isn't
+/// available, which includes once the server has rejected it, and if `conn`
+/// is NULL.
+///
+/// Always 0 for server connections: rustls 0.19 servers never accept early
+/// data over TLS, so there is no configured limit to report.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_max_early_data_size(conn: *const rustls_connection) -> u32 {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        conn.max_early_data_size().min(u32::MAX as usize) as u32
+    }
+}
+
+/// Counters of the data that has passed through a connection, filled in by
+/// `rustls_connection_get_stats`. The counters start at zero when the
+/// connection is created and only ever increase; they are discarded when the
+/// connection is freed.
+///
+/// Callers allocate this struct, so its layout is part of the ABI: fields
+/// will not be reordered or removed within a release series, and any new
+/// fields will be appended at the end and noted in the changelog.
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct rustls_connection_stats {
+    /// TLS bytes received via `rustls_connection_read_tls`.
+    pub bytes_read_tls: u64,
+    /// TLS bytes sent via `rustls_connection_write_tls` and
+    /// `rustls_connection_write_tls_vectored`.
+    pub bytes_written_tls: u64,
+    /// Plaintext bytes returned by `rustls_connection_read` or consumed with
+    /// `rustls_connection_consume_plaintext`.
+    pub plaintext_read: u64,
+    /// Plaintext bytes accepted by `rustls_connection_write`.
+    pub plaintext_written: u64,
+    /// TLS records received and successfully processed by
+    /// `rustls_connection_process_new_packets`. Records passed to a call
+    /// that returns an error are not counted.
+    pub records_processed: u64,
+}
+
+impl CastPtr for rustls_connection_stats {
+    type RustType = rustls_connection_stats;
+}
+
+/// Fill in `out` with the connection's counters. See
+/// `rustls_connection_stats` for the meaning of each one.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_stats(
+    conn: *const rustls_connection,
+    out: *mut rustls_connection_stats,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out: &mut rustls_connection_stats = try_mut_from_ptr!(out);
+        *out = conn.stats.clone();
+        rustls_result::Ok
+    }
+}
+
+/// Store the number of TLS records this connection has processed from the
+/// peer in `out_read`, and the number it has sent to the peer in
+/// `out_write`. This is for debugging: comparing the counts at each end can
+/// show a middlebox that injects or drops records.
+///
+/// rustls doesn't expose the record layer's real sequence numbers, so these
+/// are counts rather than sequence numbers, and they differ in two ways:
+///
+/// - They count every record since the connection was created, including
+///   unencrypted handshake and ChangeCipherSpec records. TLS sequence
+///   numbers start again from zero whenever new keys are installed (at
+///   ChangeCipherSpec in TLS 1.2, and at each stage of the handshake and on
+///   KeyUpdate in TLS 1.3), but these counts never do.
+/// - A record counts towards `out_read` once
+///   rustls_connection_process_new_packets has processed it without error,
+///   as for `records_processed` in rustls_connection_stats, and towards
+///   `out_write` once all of its bytes have been passed to the write
+///   callback of rustls_connection_write_tls or
+///   rustls_connection_write_tls_vectored.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_sequence_numbers(
+    conn: *const rustls_connection,
+    out_read: *mut u64,
+    out_write: *mut u64,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out_read: &mut u64 = unsafe {
+            match out_read.as_mut() {
+                Some(out) => out,
+                None => return NullParameter,
+            }
+        };
+        let out_write: &mut u64 = unsafe {
+            match out_write.as_mut() {
+                Some(out) => out,
+                None => return NullParameter,
+            }
+        };
+        *out_read = conn.stats.records_processed;
+        *out_write = conn.records_written.complete();
+        rustls_result::Ok
+    }
+}
+
+/// Store the number of TLS bytes this connection has sent while
+/// handshaking in `out_sent`, and the number it has received in
+/// `out_received`. Together with `rustls_connection_get_stats` this shows
+/// how much of a connection's traffic was handshake overhead.
+///
+/// Bytes are attributed a call at a time: everything a
+/// `rustls_connection_read_tls` call reads counts if the handshake was still
+/// in progress when it was called, and everything a
+/// `rustls_connection_write_tls` or `rustls_connection_write_tls_vectored`
+/// call writes counts until the last handshake messages have been written.
+/// So application data that arrives in the same read as the end of the
+/// handshake, and plaintext written before the handshake completed, which
+/// is sent along with the last handshake messages, are counted too. A TLS
+/// 1.3 server sends its session tickets as its handshake completes, so they
+/// count for the server, but not for the client, which receives them after
+/// its handshake has completed.
+#[no_mangle]
+pub extern "C" fn rustls_connection_get_handshake_bytes(
+    conn: *const rustls_connection,
+    out_sent: *mut u64,
+    out_received: *mut u64,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let out_sent: &mut u64 = unsafe {
+            match out_sent.as_mut() {
+                Some(out) => out,
+                None => return NullParameter,
+            }
+        };
+        let out_received: &mut u64 = unsafe {
+            match out_received.as_mut() {
+                Some(out) => out,
+                None => return NullParameter,
+            }
+        };
+        *out_sent = conn.handshake_bytes.sent;
+        *out_received = conn.handshake_bytes.received;
+        rustls_result::Ok
+    }
+}
+
+/// Write up to `count` plaintext bytes from `buf` into the `rustls_connection`.
+/// This will increase the number of output bytes available to
+/// `rustls_connection_write_tls`.
+/// Store the number of bytes actually written in *out_n. That is less than
+/// `count` only when the limit set with `rustls_connection_set_buffer_limit`
+/// was reached, and then RUSTLS_RESULT_WRITE_WOULD_BLOCK is returned
+/// instead of RUSTLS_RESULT_OK: write out TLS bytes with
+/// `rustls_connection_write_tls`, then write the rest of `buf`.
+#[no_mangle]
+pub extern "C" fn rustls_connection_write(
+    conn: *mut rustls_connection,
+    buf: *const u8,
+    count: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let write_buf: &[u8] = try_slice!(buf, count);
+        let out_n: &mut size_t = unsafe {
+            match out_n.as_mut() {
+                Some(out_n) => out_n,
+                None => return NullParameter,
+            }
+        };
+        let n_written: usize = match conn.write_plaintext(write_buf) {
+            Ok(n) => n,
+            Err(e) => return conn.io_error(&e),
+        };
+        *out_n = n_written;
+        if n_written < count {
+            return rustls_result::WriteWouldBlock;
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
+/// On success, store the number of bytes read in *out_n (this may be less
+/// than `count`). A success with *out_n set to 0 means "all bytes currently
+/// available have been read, but more bytes may become available after
+/// subsequent calls to rustls_connection_read_tls and
+/// rustls_connection_process_new_packets." It is the equivalent of
+/// EWOULDBLOCK: calling this again without reading more TLS data will keep
+/// returning 0, so a read loop should stop there and wait for the socket to
+/// become readable. `rustls_connection_read_has_more` tells whether a call
+/// would return any bytes, without reading them.
+///
+/// The end of the stream is never signalled by *out_n == 0. A clean close
+/// by the peer returns RUSTLS_RESULT_ALERT_CLOSE_NOTIFY once all plaintext
+/// sent before it has been read; see also `rustls_connection_is_closed`. If
+/// the socket closes without a close_notify, the next call to
+/// `rustls_connection_process_new_packets` after that plaintext has been read
+/// returns RUSTLS_RESULT_UNEXPECTED_EOF.
+///
+/// Subtle note: Even though this function only writes to `buf` and does not
+/// read from it, the memory in `buf` must be initialized before the call (for
+/// Rust-internal reasons). Initializing a buffer once and then using it
+/// multiple times without zeroizing before each call is fine.
+#[no_mangle]
+pub extern "C" fn rustls_connection_read(
+    conn: *mut rustls_connection,
+    buf: *mut u8,
+    count: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let read_buf: &mut [u8] = try_mut_slice!(buf, count);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+
+        let n_read: usize = match conn.read_plaintext(read_buf) {
+            Ok(n) => n,
+            // Rustls turns close_notify alerts into `io::Error` of kind `ConnectionAborted`.
+            // https://docs.rs/rustls/0.19.0/rustls/struct.ClientSession.html#impl-Read.
+            Err(e) if is_close_notify(&e) => {
+                return rustls_result::AlertCloseNotify;
+            }
+            Err(e) => return conn.io_error(&e),
+        };
+        *out_n = n_read;
+        rustls_result::Ok
+    }
+}
+
+/// Read plaintext like `rustls_connection_read`, but stop at the end of a
+/// TLS record: the bytes read never span the end of one record and the
+/// start of the next. *out_at_boundary is set to true if they end exactly
+/// where a record ended, and false if more of that record remains, or if
+/// nothing was read.
+///
+/// Record framing is up to the peer's TLS implementation, which may split
+/// one write over several records or combine several writes into one, so
+/// it is not a reliable message delimiter. It is only useful for protocols
+/// whose peers are known to send each message in records of its own.
+///
+/// The end of each record is known exactly for TLS 1.2. TLS 1.3 encrypts
+/// records' real content types, so if records processed together include
+/// a padded record, or one carrying a handshake message or alert, only the
+/// end of all their plaintext is known to be a record boundary.
+///
+/// Record boundaries are only tracked for connections that use this
+/// function, since it costs an extra copy of all plaintext received. The
+/// first call turns tracking on, and all plaintext processed before it is
+/// treated as one record, so to know every boundary, call this once with a
+/// `count` of 0 before the first call to
+/// rustls_connection_process_new_packets. Tracking stays on for the
+/// connection's lifetime, including after rustls_connection_reset.
+#[no_mangle]
+pub extern "C" fn rustls_connection_read_until_boundary(
+    conn: *mut rustls_connection,
+    buf: *mut u8,
+    count: size_t,
+    out_n: *mut size_t,
+    out_at_boundary: *mut bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let read_buf: &mut [u8] = try_mut_slice!(buf, count);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let out_at_boundary: &mut bool = try_mut_from_ptr!(out_at_boundary);
+
+        let (n_read, at_boundary) = match conn.read_to_boundary(read_buf) {
+            Ok(read) => read,
+            Err(e) if is_close_notify(&e) => {
+                return rustls_result::AlertCloseNotify;
+            }
+            Err(e) => return conn.io_error(&e),
+        };
+        *out_n = n_read;
+        *out_at_boundary = at_boundary;
+        rustls_result::Ok
+    }
+}
+
+/// Return the errno of the last I/O error that a function on `conn`
+/// reported as RUSTLS_RESULT_IO, such as EPIPE, or 0 if there hasn't been
+/// one since the connection was created or reset. Errors without an errno
+/// are reported as EIO. `rustls_connection_read_tls` and
+/// `rustls_connection_write_tls` return their errors directly, and don't
+/// change this. Returns EINVAL if `conn` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_connection_last_io_error(
+    conn: *const rustls_connection,
+) -> rustls_io_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        rustls_io_result(conn.last_io_error)
+    }
+}
+
+/// Borrow the plaintext received from the peer that hasn't been read yet,
+/// storing a view of it in `out`, without copying it into a buffer of the
+/// caller's. Once the caller has used some or all of it, they must call
+/// `rustls_connection_consume_plaintext` to move past those bytes; until
+/// then, each call returns the same bytes again. This suits proxies that
+/// forward plaintext straight on to another socket.
+///
+/// The view is borrowed from the connection and is invalidated by the next
+/// call to rustls_connection_read_tls, rustls_connection_process_new_packets,
+/// rustls_connection_read, rustls_connection_consume_plaintext or this
+/// function, and by freeing the connection. Copy anything needed beyond that.
+///
+/// The results match those of `rustls_connection_read`: an empty view (`len`
+/// 0) means no plaintext is available until more TLS data is read and
+/// processed, RUSTLS_RESULT_ALERT_CLOSE_NOTIFY means the peer closed the
+/// connection cleanly and all plaintext sent before that has been consumed,
+/// and the view is capped by `rustls_connection_set_max_plaintext_read`.
+///
+/// The version of rustls used by crustls doesn't give access to its buffer
+/// of decrypted plaintext, so processing new packets still copies it once,
+/// into a buffer inside the connection. This saves the
+/// caller's own copy, and the other one will go away when crustls moves to a
+/// rustls release that exposes the buffer.
+#[no_mangle]
+pub extern "C" fn rustls_connection_take_received_plaintext(
+    conn: *mut rustls_connection,
+    out: *mut rustls_slice_bytes,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        let out: &mut rustls_slice_bytes = try_mut_from_ptr!(out);
+        if let Err(e) = conn.take_plaintext() {
+            if is_close_notify(&e) {
+                return rustls_result::AlertCloseNotify;
+            }
+            return conn.io_error(&e);
+        }
+        // `out` borrows from `conn` for the rest of the function, so take
+        // the view again now that it is known to succeed: the same bytes
+        // come back until they are consumed.
+        if let Ok(taken) = conn.take_plaintext() {
+            *out = taken.into();
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Mark the first `n` bytes of the view returned by
+/// `rustls_connection_take_received_plaintext` as used, so that they aren't
+/// returned again. Returns RUSTLS_RESULT_INVALID_PARAMETER, and consumes
+/// nothing, if `n` is more than the bytes taken and not yet consumed.
+#[no_mangle]
+pub extern "C" fn rustls_connection_consume_plaintext(
+    conn: *mut rustls_connection,
+    n: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        if n > conn.taken_plaintext_len() {
+            return rustls_result::InvalidParameter;
+        }
+        conn.consume_plaintext(n);
+        rustls_result::Ok
+    }
+}
+
+/// Return a connection to the state it was in when created, so that it
+/// can be reused for a new TLS session with the same peer, such as by a
+/// connection pool. The new session uses the same config and, for a
+/// client, the same hostname. Any TLS bytes or plaintext still queued are
+/// discarded, and everything learned from the old session (peer
+/// certificates, negotiated parameters, statistics, close state) is
+/// forgotten.
+///
+/// Settings made on the connection are kept: the buffer limit, the
+/// maximum plaintext read, the protocols set with
+/// rustls_connection_set_alpn_protocols, and the log and new ticket
+/// callbacks. The userdata pointer is cleared, so set it again before
+/// processing packets if callbacks need it. So is the handshake deadline,
+/// which was for the old handshake: set a new one with
+/// rustls_connection_set_handshake_deadline if needed.
+///
+/// The version of rustls used by crustls can't reset its session in place,
+/// so the rustls session is allocated afresh; buffers owned by crustls keep
+/// their capacity. Calling with NULL does nothing.
+///
+/// crustls doesn't offer a pool of connections. The fresh rustls session is
+/// where a handshake's allocations happen (record buffers, the handshake
+/// hash, the peer's certificate chain), so a pool could only recycle the
+/// outer connection and a few small buffers, which this function already
+/// does for a connection the application keeps.
+#[no_mangle]
+pub extern "C" fn rustls_connection_reset(conn: *mut rustls_connection) {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        conn.reset();
+    }
+}
+
+/// Free a rustls_connection. Calling with NULL is fine.
+/// Must not be called twice with the same value.
+/// This never performs I/O or invokes callbacks: any TLS bytes still queued,
+/// such as a close_notify alert from `rustls_connection_send_close_notify`,
+/// are discarded. Flush them first if the peer should receive them.
+#[no_mangle]
+pub extern "C" fn rustls_connection_free(conn: *mut rustls_connection) {
+    ffi_panic_boundary! {
+        let conn: &mut Connection = try_mut_from_ptr!(conn);
+        // Convert the pointer to a Box and drop it.
+        unsafe { drop(Box::from_raw(conn)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use rustls::internal::msgs::persist::ClientSessionValue;
+    use rustls::{ClientConfig, ProtocolVersion};
+    use std::sync::Arc;
+
+    fn client_connection() -> Connection {
+        let config = Arc::new(ClientConfig::new());
+        let name = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+        Connection::from_client(&config, name)
+    }
+
+    #[test]
+    fn test_write_would_block() {
+        let mut conn = client_connection();
+        conn.set_buffer_limit(1000);
+        let conn = &mut conn as *mut Connection as *mut rustls_connection;
+        let data = [0; 1000];
+        let mut n = 0;
+
+        let result = rustls_connection_write(conn, data.as_ptr(), 600, &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 600));
+        // Exactly up to the limit.
+        let result = rustls_connection_write(conn, data.as_ptr(), 400, &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 400));
+        let result = rustls_connection_write(conn, data.as_ptr(), 0, &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 0));
+        let result = rustls_connection_write(conn, data.as_ptr(), 1, &mut n);
+        assert_eq!((result, n), (rustls_result::WriteWouldBlock, 0));
+
+        let mut conn = client_connection();
+        conn.set_buffer_limit(1000);
+        let conn = &mut conn as *mut Connection as *mut rustls_connection;
+        let result = rustls_connection_write(conn, data.as_ptr(), 700, &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 700));
+        // Past the limit.
+        let result = rustls_connection_write(conn, data.as_ptr(), 500, &mut n);
+        assert_eq!((result, n), (rustls_result::WriteWouldBlock, 300));
+    }
+
+    #[test]
+    fn test_write_capacity() {
+        let mut conn = client_connection();
+        assert_eq!(conn.write_capacity(), usize::MAX);
+
+        conn.set_buffer_limit(1000);
+        assert_eq!(conn.write_capacity(), 1000);
+        assert_eq!(conn.write_plaintext(&[0; 300]).unwrap(), 300);
+        assert_eq!(conn.write_capacity(), 700);
+
+        // Sending the ClientHello doesn't drain the held-back plaintext.
+        let mut out = Vec::new();
+        assert!(conn.write_tls(&mut out).unwrap() > 0);
+        assert_eq!(conn.write_capacity(), 700);
+
+        assert_eq!(conn.write_plaintext(&[0; 1000]).unwrap(), 700);
+        assert_eq!(conn.write_capacity(), 0);
+    }
+
+    #[test]
+    fn test_last_io_error() {
+        let mut conn = client_connection();
+        let conn_ptr = &mut conn as *mut Connection as *mut rustls_connection;
+        assert_eq!(rustls_connection_last_io_error(conn_ptr).0, 0);
+        let error = std::io::Error::from_raw_os_error(libc::EPIPE);
+        assert_eq!(conn.io_error(&error), rustls_result::Io);
+        assert_eq!(rustls_connection_last_io_error(conn_ptr).0, libc::EPIPE);
+        let error = std::io::Error::other("no errno");
+        assert_eq!(conn.io_error(&error), rustls_result::Io);
+        assert_eq!(rustls_connection_last_io_error(conn_ptr).0, EIO);
+        rustls_connection_reset(conn_ptr);
+        assert_eq!(rustls_connection_last_io_error(conn_ptr).0, 0);
+        assert_eq!(rustls_connection_last_io_error(null()).0, libc::EINVAL);
+    }
+
+    #[test]
+    fn test_write_coalescing() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        rustls_connection_set_write_coalescing(client_ptr, true);
+
+        // Small writes are held back until flushed, then sent as one record.
+        let records = client.records_written.complete();
+        for _ in 0..100 {
+            assert_eq!(client.write_plaintext(b"0123456789").unwrap(), 10);
+        }
+        assert!(!client.as_ref().wants_write());
+        assert_eq!(
+            rustls_connection_flush_plaintext(client_ptr),
+            rustls_result::Ok
+        );
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(client.records_written.complete(), records + 1);
+        let mut buf = vec![0; 20000];
+        assert_eq!(server.read_plaintext(&mut buf).unwrap(), 1000);
+
+        // Whole records go out straight away, and the rest is flushed
+        // before a close_notify.
+        assert_eq!(client.write_plaintext(&[7; 20000]).unwrap(), 20000);
+        assert!(client.as_ref().wants_write());
+        assert_eq!(client.coalesced.len(), 20000 - MAX_FRAGMENT_LEN);
+        client.send_close_notify();
+        testutil::transfer(&mut client, &mut server).unwrap();
+        let mut received = 0;
+        while let Ok(n) = server.read_plaintext(&mut buf) {
+            received += n;
+        }
+        assert_eq!(received, 20000);
+        assert!(server.peer_closed);
+
+        // Held-back plaintext counts against the buffer limit.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        client.set_buffer_limit(1000);
+        client.set_write_coalescing(true);
+        assert_eq!(client.write_plaintext(&[0; 300]).unwrap(), 300);
+        assert_eq!(client.write_capacity(), 700);
+        client.set_write_coalescing(false);
+        assert!(client.coalesced.is_empty());
+        assert!(client.as_ref().wants_write());
+    }
+
+    extern "C" fn counting_write(
+        userdata: *mut c_void,
+        _buf: *const u8,
+        n: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_io_result {
+        unsafe {
+            *(userdata as *mut usize) += 1;
+            *out_n = n;
+        }
+        rustls_io_result(0)
+    }
+
+    #[test]
+    fn test_reset() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        client.set_buffer_limit(1000);
+        let mut data = 0u8;
+        client.userdata = &mut data as *mut u8 as *mut c_void;
+        testutil::handshake(&mut client, &mut server).unwrap();
+        client.write_plaintext(b"hello").unwrap();
+        client.send_close_notify();
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert!(client.is_closed());
+
+        let conn = &mut client as *mut Connection as *mut rustls_connection;
+        rustls_connection_reset(conn);
+        rustls_connection_reset(null_mut());
+        assert!(client.as_ref().is_handshaking());
+        assert!(!client.is_closed());
+        assert!(client.as_ref().get_peer_certificates().is_none());
+        assert_eq!(client.stats, rustls_connection_stats::default());
+        assert!(client.userdata.is_null());
+        assert_eq!(client.write_capacity(), 1000);
+
+        let mut server = Connection::from_server(&server_config, false);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        client.write_plaintext(b"again").unwrap();
+        testutil::transfer(&mut client, &mut server).unwrap();
+        let mut buf = [0; 16];
+        let n = server.read_plaintext(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"again");
+        assert!(client.as_ref().get_peer_certificates().is_some());
+    }
+
+    #[test]
+    fn test_client_hello_retained_only_for_alpn_select() {
+        let server_config = Arc::new(testutil::server_config());
+        let mut plain = Connection::from_server(&server_config, false);
+        let mut selecting = Connection::from_server(&server_config, true);
+        for _ in 0..2 {
+            assert!(plain.handshake.retained_received().is_none());
+            assert_eq!(selecting.handshake.retained_received(), Some(&[][..]));
+            plain.reset();
+            selecting.reset();
+        }
+    }
+
+    #[test]
+    fn test_send_alert() {
+        let conn = Box::into_raw(Box::new(client_connection())) as *mut rustls_connection;
+        // no_application_protocol
+        assert_eq!(
+            rustls_connection_send_alert(conn, 120),
+            rustls_result::Unimplemented
+        );
+        assert_eq!(
+            rustls_connection_send_alert(conn, 255),
+            rustls_result::InvalidParameter
+        );
+        assert_eq!(rustls_connection_send_alert(conn, 0), rustls_result::Ok);
+        rustls_connection_free(conn);
+    }
+
+    #[test]
+    fn test_free_does_no_io() {
+        let mut calls: usize = 0;
+        let userdata = &mut calls as *mut usize as *mut c_void;
+        let mut conn = client_connection();
+        conn.userdata = userdata;
+        let conn = Box::into_raw(Box::new(conn)) as *mut rustls_connection;
+
+        rustls_connection_send_close_notify(conn);
+        assert!(rustls_connection_wants_write(conn));
+        rustls_connection_free(conn);
+        assert_eq!(calls, 0);
+    }
+
+    #[test]
+    fn test_get_pending_alert() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let name = webpki::DNSNameRef::try_from_ascii_str("example.org").unwrap();
+        let mut client = Connection::from_client(&client_config, name);
+        let mut server = Connection::from_server(&server_config, false);
+        let client_ptr = &mut client as *mut Connection as *const rustls_connection;
+        let mut alert = 0xff;
+        assert!(!rustls_connection_get_pending_alert(client_ptr, &mut alert));
+
+        // The server's certificate isn't valid for example.org.
+        assert!(testutil::handshake(&mut client, &mut server).is_err());
+        assert!(rustls_connection_get_pending_alert(client_ptr, &mut alert));
+        assert_eq!(alert, 42);
+        assert!(!rustls_connection_get_pending_alert(client_ptr, null_mut()));
+
+        let mut out = Vec::new();
+        while client.as_ref().wants_write() {
+            client.write_tls(&mut out).unwrap();
+        }
+        assert!(!rustls_connection_get_pending_alert(client_ptr, &mut alert));
+    }
+
+    #[test]
+    fn test_close_flushes() {
+        let mut calls: usize = 0;
+        let userdata = &mut calls as *mut usize as *mut c_void;
+        let conn = Box::into_raw(Box::new(client_connection())) as *mut rustls_connection;
+
+        let result = rustls_connection_close(conn, Some(counting_write), userdata);
+        assert_eq!(result.0, 0);
+        assert!(calls > 0);
+        assert!(!rustls_connection_wants_write(conn));
+        rustls_connection_free(conn);
+    }
+
+    fn handshake_info(
+        client_config: &Arc<ClientConfig>,
+        server_config: &Arc<rustls::ServerConfig>,
+    ) -> (rustls_handshake_info, rustls_handshake_info) {
+        let (mut client, mut server) = testutil::connection_pair(client_config, server_config);
+        assert!(client.handshake_info().is_handshaking);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        (client.handshake_info(), server.handshake_info())
+    }
+
+    #[test]
+    fn test_negotiated_ciphersuite_id() {
+        let mut client_config = testutil::client_config();
+        client_config.ciphersuites = vec![&rustls::ciphersuite::TLS13_AES_256_GCM_SHA384];
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) =
+            testutil::connection_pair(&Arc::new(client_config), &server_config);
+        let client_ptr = &client as *const Connection as *const rustls_connection;
+        assert_eq!(
+            rustls_connection_get_negotiated_ciphersuite_id(client_ptr),
+            0
+        );
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let client_ptr = &client as *const Connection as *const rustls_connection;
+        assert_eq!(
+            rustls_connection_get_negotiated_ciphersuite_id(client_ptr),
+            0x1302
+        );
+    }
+
+    #[test]
+    fn test_record_counter() {
+        let mut counter = RecordCounter::default();
+        let records = [22, 3, 3, 0, 2, 0xaa, 0xbb, 23, 3, 3, 0, 0, 21, 3, 3, 0, 1];
+        for chunk in records.chunks(3) {
+            counter.count(chunk);
+        }
+        assert_eq!(counter.take_complete(), 2);
+        counter.count(&[0xcc]);
+        assert_eq!(counter.take_complete(), 1);
+        assert_eq!(counter.take_complete(), 0);
+        assert!(counter.take_records().is_empty());
+
+        let mut counter = RecordCounter::keeping_records();
+        counter.count(&records);
+        counter.count(&[0xcc]);
+        assert_eq!(counter.take_records(), vec![(22, 2), (23, 0), (21, 1)]);
+        assert!(counter.take_records().is_empty());
+    }
+
+    #[test]
+    fn test_read_until_boundary() {
+        let server_config = Arc::new(testutil::server_config());
+        for &version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+            let mut client_config = testutil::client_config();
+            client_config.versions = vec![version];
+            let client_config = Arc::new(client_config);
+            let (mut client, mut server) =
+                testutil::connection_pair(&client_config, &server_config);
+            testutil::handshake(&mut client, &mut server).unwrap();
+            let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+            let read = |len: usize| {
+                let mut buf = vec![0u8; len];
+                let (mut n, mut at_boundary) = (0, false);
+                let result = rustls_connection_read_until_boundary(
+                    server_ptr,
+                    buf.as_mut_ptr(),
+                    len,
+                    &mut n,
+                    &mut at_boundary,
+                );
+                assert_eq!(result, rustls_result::Ok);
+                (n, at_boundary)
+            };
+            // Nothing is tracked until the first call.
+            assert!(!server.records_read.keeps_records());
+            assert_eq!(read(0), (0, false));
+            assert!(server.records_read.keeps_records());
+
+            // The first three records are processed together.
+            assert_eq!(client.write_plaintext(b"hello").unwrap(), 5);
+            assert_eq!(client.write_plaintext(b"world!").unwrap(), 6);
+            assert_eq!(client.write_plaintext(&[7; 20000]).unwrap(), 20000);
+            testutil::transfer(&mut client, &mut server).unwrap();
+
+            assert_eq!(read(3), (3, false));
+            assert_eq!(read(100), (2, true));
+            assert_eq!(read(100), (6, true));
+            assert_eq!(read(100_000), (MAX_FRAGMENT_LEN, true));
+            assert_eq!(read(100_000), (20000 - MAX_FRAGMENT_LEN, true));
+            assert_eq!(read(100), (0, false));
+            assert!(rustls_connection_wants_read(server_ptr));
+
+            // A close_notify processed with the data is fine.
+            assert_eq!(client.write_plaintext(b"bye").unwrap(), 3);
+            client.send_close_notify();
+            testutil::transfer(&mut client, &mut server).unwrap();
+            assert!(!rustls_connection_wants_read(server_ptr));
+            assert_eq!(read(100), (3, true));
+            let (mut n, mut at_boundary) = (0, false);
+            let mut buf = [0u8; 8];
+            let result = rustls_connection_read_until_boundary(
+                server_ptr,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut n,
+                &mut at_boundary,
+            );
+            assert_eq!(result, rustls_result::AlertCloseNotify);
+        }
+
+        // Plaintext processed before the first call counts as one record.
+        let client_config = Arc::new(testutil::client_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(client.write_plaintext(b"hello").unwrap(), 5);
+        assert_eq!(client.write_plaintext(b"world!").unwrap(), 6);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(server.read_to_boundary(&mut [0; 100]).unwrap(), (11, true));
+    }
+
+    #[test]
+    fn test_max_plaintext_read() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(client.write_plaintext(&[7; 100]).unwrap(), 100);
+        testutil::transfer(&mut client, &mut server).unwrap();
+
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        rustls_connection_set_max_plaintext_read(server_ptr, 30);
+        let mut buf = [0u8; 64];
+        let mut reads = Vec::new();
+        while rustls_connection_read_has_more(server_ptr) {
+            let mut n = 0;
+            let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+            assert_eq!(result, rustls_result::Ok);
+            reads.push(n);
+        }
+        assert_eq!(reads, vec![30, 30, 30, 10]);
+        let mut n = 1;
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 0));
+    }
+
+    #[test]
+    fn test_take_received_plaintext() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let take = |conn: *mut rustls_connection| {
+            let mut out: rustls_slice_bytes = (&[][..]).into();
+            let result = rustls_connection_take_received_plaintext(conn, &mut out);
+            let taken = unsafe { slice::from_raw_parts(out.data, out.len) }.to_vec();
+            (result, taken)
+        };
+        assert_eq!(take(server_ptr), (rustls_result::Ok, vec![]));
+
+        assert_eq!(client.write_plaintext(b"hello ").unwrap(), 6);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(client.write_plaintext(b"world").unwrap(), 5);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(
+            take(server_ptr),
+            (rustls_result::Ok, b"hello world".to_vec())
+        );
+        assert_eq!(
+            rustls_connection_consume_plaintext(server_ptr, 12),
+            rustls_result::InvalidParameter
+        );
+        assert_eq!(
+            rustls_connection_consume_plaintext(server_ptr, 6),
+            rustls_result::Ok
+        );
+        assert_eq!(take(server_ptr), (rustls_result::Ok, b"world".to_vec()));
+        assert!(rustls_connection_read_has_more(server_ptr));
+
+        // Reads pick up where the view left off.
+        let mut buf = [0u8; 3];
+        let mut n = 0;
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, &buf[..n]), (rustls_result::Ok, &b"wor"[..]));
+        assert_eq!(take(server_ptr), (rustls_result::Ok, b"ld".to_vec()));
+        assert_eq!(
+            rustls_connection_consume_plaintext(server_ptr, 2),
+            rustls_result::Ok
+        );
+        assert!(!rustls_connection_read_has_more(server_ptr));
+        assert_eq!(server.stats.plaintext_read, 11);
+
+        client.send_close_notify();
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(take(server_ptr), (rustls_result::AlertCloseNotify, vec![]));
+    }
+
+    #[test]
+    fn test_is_closed() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        assert!(!rustls_connection_is_closed(client_ptr));
+
+        assert_eq!(client.write_plaintext(b"bye").unwrap(), 3);
+        rustls_connection_send_close_notify(client_ptr);
+        assert!(!rustls_connection_is_closed(client_ptr));
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert!(rustls_connection_is_closed(client_ptr));
+
+        // The server isn't closed until it has read everything sent before
+        // the close_notify.
+        assert!(!rustls_connection_is_closed(server_ptr));
+        let mut buf = [0u8; 10];
+        let mut n = 0;
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 3));
+        assert!(!rustls_connection_is_closed(server_ptr));
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!(result, rustls_result::AlertCloseNotify);
+        assert!(rustls_connection_is_closed(server_ptr));
+        assert!(!rustls_connection_is_handshaking(server_ptr));
+    }
+
+    #[test]
+    fn test_get_io_state() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let io_state = |conn: &Connection| {
+            let conn = conn as *const Connection as *const rustls_connection;
+            let (mut read, mut write, mut handshaking) = (false, false, false);
+            let result =
+                rustls_connection_get_io_state(conn, &mut read, &mut write, &mut handshaking);
+            assert_eq!(result, rustls_result::Ok);
+            (read, write, handshaking)
+        };
+        // The client has its ClientHello to send.
+        assert_eq!(io_state(&client), (true, true, true));
+        assert_eq!(io_state(&server), (true, false, true));
+
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(client.write_plaintext(b"hi").unwrap(), 2);
+        assert_eq!(io_state(&client), (true, true, false));
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(io_state(&server), (false, false, false));
+
+        let server_ptr = &server as *const Connection as *const rustls_connection;
+        let mut flag = false;
+        let result = rustls_connection_get_io_state(server_ptr, &mut flag, null_mut(), &mut flag);
+        assert_eq!(result, rustls_result::NullParameter);
+    }
+
+    /// A rustls_read_callback that reads from the `&[u8]` at `userdata`.
+    unsafe extern "C" fn read_from_slice(
+        userdata: *mut c_void,
+        buf: *mut u8,
+        n: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_io_result {
+        let input = &mut *(userdata as *mut &[u8]);
+        let buf = std::slice::from_raw_parts_mut(buf, n);
+        *out_n = std::io::Read::read(input, buf).unwrap();
+        rustls_io_result(0)
+    }
+
+    /// A rustls_write_callback that appends to the `Vec<u8>` at `userdata`.
+    unsafe extern "C" fn write_to_vec(
+        userdata: *mut c_void,
+        buf: *const u8,
+        n: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_io_result {
+        let output = &mut *(userdata as *mut Vec<u8>);
+        output.extend_from_slice(std::slice::from_raw_parts(buf, n));
+        *out_n = n;
+        rustls_io_result(0)
+    }
+
+    #[test]
+    fn test_write_tls_limited() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(client.write_plaintext(&[7; 50000]).unwrap(), 50000);
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        let mut tls: Vec<u8> = Vec::new();
+        let mut write = |max_bytes: usize| {
+            let mut n = 0;
+            let userdata = &mut tls as *mut Vec<u8> as *mut c_void;
+            let result = rustls_connection_write_tls_limited(
+                client_ptr,
+                Some(write_to_vec),
+                userdata,
+                max_bytes,
+                &mut n,
+            );
+            assert_eq!(result.0, 0);
+            n
+        };
+        assert_eq!(write(1000), 1000);
+        assert_eq!(write(0), 0);
+        // More than one record's worth.
+        assert_eq!(write(20000), 20000);
+        let rest = write(usize::MAX);
+        assert!(rest > 0);
+        assert_eq!(write(usize::MAX), 0);
+        assert!(!client.as_ref().wants_write());
+        assert_eq!(tls.len(), 21000 + rest);
+
+        let mut reader = &tls[..];
+        while !reader.is_empty() {
+            server.read_tls(&mut reader).unwrap();
+            server.process_new_packets().unwrap();
+        }
+        let mut received = vec![0; 60000];
+        let mut n = 0;
+        while let Ok(len @ 1..) = server.read_plaintext(&mut received[n..]) {
+            n += len;
+        }
+        assert_eq!(n, 50000);
+    }
+
+    #[test]
+    fn test_read_tls2() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let mut send = |data: &[u8]| {
+            assert_eq!(client.write_plaintext(data).unwrap(), data.len());
+            let mut tls = Vec::new();
+            client.write_tls(&mut tls).unwrap();
+            let mut input = &tls[..];
+            let (mut n, mut needs_more) = (0, false);
+            let userdata = &mut input as *mut &[u8] as *mut c_void;
+            let result = rustls_connection_read_tls2(
+                server_ptr,
+                Some(read_from_slice),
+                userdata,
+                &mut n,
+                &mut needs_more,
+            );
+            assert_eq!(result.0, 0);
+            assert_eq!(n, tls.len());
+            assert_eq!(
+                rustls_connection_process_new_packets(server_ptr),
+                rustls_result::Ok
+            );
+            needs_more
+        };
+        // The record isn't decrypted until it's processed.
+        assert!(send(b"hello"));
+        // Now there's plaintext waiting to be read.
+        assert!(!send(b"world"));
+
+        let mut n = 0;
+        let result = rustls_connection_read_tls2(
+            server_ptr,
+            Some(read_from_slice),
+            null_mut(),
+            &mut n,
+            null_mut(),
+        );
+        assert_eq!(result.0, libc::EINVAL);
+    }
+
+    #[test]
+    fn test_read_tls_record() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let mut flight = Vec::new();
+        while client.as_ref().wants_write() {
+            client.write_tls(&mut flight).unwrap();
+        }
+        // The ClientHello record, followed by the start of another record.
+        let record_len = 5 + u16::from_be_bytes([flight[3], flight[4]]) as usize;
+        assert_eq!(record_len, flight.len());
+        flight.extend_from_slice(&[0x16, 0x03]);
+
+        let read = |data: &[u8]| {
+            let (mut n, mut complete) = (0, true);
+            let result = rustls_connection_read_tls_record(
+                server_ptr,
+                data.as_ptr(),
+                data.len(),
+                &mut n,
+                &mut complete,
+            );
+            assert_eq!(result.0, 0);
+            (n, complete)
+        };
+        // A partial header, then a partial body, then the rest.
+        assert_eq!(read(&flight[..3]), (3, false));
+        assert_eq!(read(&flight[3..100]), (97, false));
+        assert_eq!(read(&flight[100..]), (record_len - 100, true));
+        assert_eq!(read(&[]), (0, false));
+        let server = unsafe { &mut *(server_ptr as *mut Connection) };
+        server.process_new_packets().unwrap();
+        assert_eq!(server.stats.records_processed, 1);
+        assert!(server.as_ref().wants_write());
+    }
+
+    #[test]
+    fn test_handshake_deadline() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        // 2001-09-09T01:46:40Z, long gone.
+        rustls_connection_set_handshake_deadline(server_ptr, 1_000_000_000_000);
+        assert!(testutil::handshake(&mut client, &mut server).is_err());
+        assert_eq!(
+            rustls_connection_process_new_packets(server_ptr),
+            rustls_result::HandshakeTimeout
+        );
+
+        // Resetting the connection removes the deadline.
+        rustls_connection_reset(server_ptr);
+        let (mut client, _) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+        let in_an_hour = in_an_hour.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        rustls_connection_set_handshake_deadline(server_ptr, in_an_hour);
+        testutil::handshake(&mut client, &mut server).unwrap();
+
+        // Once the handshake is done, the deadline no longer matters.
+        rustls_connection_set_handshake_deadline(server_ptr, 1);
+        assert_eq!(
+            rustls_connection_process_new_packets(server_ptr),
+            rustls_result::Ok
+        );
+
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        rustls_connection_set_handshake_deadline(server_ptr, 1);
+        rustls_connection_set_handshake_deadline(server_ptr, 0);
+        testutil::handshake(&mut client, &mut server).unwrap();
+    }
+
+    #[test]
+    fn test_unexpected_eof() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let eof = |conn: &mut Connection| {
+            assert_eq!(conn.read_tls(&mut &b""[..]).unwrap(), 0);
+            let conn = conn as *mut Connection as *mut rustls_connection;
+            rustls_connection_process_new_packets(conn)
+        };
+        let mut buf = [0u8; 10];
+        let mut n = 0;
+
+        // A clean close.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        assert_eq!(client.write_plaintext(b"bye").unwrap(), 3);
+        client.send_close_notify();
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(eof(&mut server), rustls_result::Ok);
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 3));
+        assert_eq!(eof(&mut server), rustls_result::Ok);
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!(result, rustls_result::AlertCloseNotify);
+
+        // The socket closes without a close_notify. The plaintext that did
+        // arrive is readable before the error is reported.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        assert_eq!(client.write_plaintext(b"bye").unwrap(), 3);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(eof(&mut server), rustls_result::Ok);
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 3));
+        assert_eq!(
+            rustls_connection_process_new_packets(server_ptr),
+            rustls_result::UnexpectedEof
+        );
+        let result = rustls_connection_read(server_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, n), (rustls_result::Ok, 0));
+
+        // Or during the handshake.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(eof(&mut server), rustls_result::UnexpectedEof);
+
+        // A reset connection starts over.
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        assert_eq!(eof(&mut client), rustls_result::UnexpectedEof);
+        rustls_connection_reset(client_ptr);
+        assert_eq!(
+            rustls_connection_process_new_packets(client_ptr),
+            rustls_result::Ok
+        );
+    }
+
+    /// Like `read_from_slice`, but fails with EINVAL if asked to read into an
+    /// empty buffer.
+    unsafe extern "C" fn read_from_slice_nonempty(
+        userdata: *mut c_void,
+        buf: *mut u8,
+        n: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_io_result {
+        if n == 0 {
+            return rustls_io_result(libc::EINVAL);
+        }
+        read_from_slice(userdata, buf, n, out_n)
+    }
+
+    #[test]
+    fn test_read_tls_callback_eof() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let read_tls = |conn: *mut rustls_connection, input: &mut &[u8]| {
+            let mut n = 0;
+            let userdata = input as *mut &[u8] as *mut c_void;
+            let result =
+                rustls_connection_read_tls(conn, Some(read_from_slice_nonempty), userdata, &mut n);
+            assert_eq!(result.0, 0);
+            n
+        };
+
+        // A callback that stores no bytes and succeeds reports EOF.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let mut hello = Vec::new();
+        client.write_tls(&mut hello).unwrap();
+        let mut input = &hello[..];
+        assert_eq!(read_tls(server_ptr, &mut input), hello.len());
+        assert_eq!(read_tls(server_ptr, &mut input), 0);
+        assert_eq!(
+            rustls_connection_process_new_packets(server_ptr),
+            rustls_result::UnexpectedEof
+        );
+
+        // A record too long to be valid stops rustls reading once its
+        // buffer is full. The callback isn't asked to read nothing, and the
+        // error is reported rather than EOF.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let mut bad = vec![0x17, 0x03, 0x03, 0xff, 0xff];
+        bad.resize(50000, 0);
+        let mut input = &bad[..];
+        let mut reads = 0;
+        while read_tls(server_ptr, &mut input) > 0 {
+            reads += 1;
+            assert!(reads < 100);
+        }
+        assert!(!input.is_empty());
+        assert_eq!(
+            rustls_connection_process_new_packets(server_ptr),
+            rustls_result::CorruptMessage
+        );
+    }
+
+    /// Split a stream of TLS records into records.
+    fn split_records(mut tls: &[u8]) -> Vec<&[u8]> {
+        let mut records = Vec::new();
+        while !tls.is_empty() {
+            let len = 5 + u16::from_be_bytes([tls[3], tls[4]]) as usize;
+            let (record, rest) = tls.split_at(len);
+            records.push(record);
+            tls = rest;
+        }
+        records
+    }
+
+    #[test]
+    fn test_max_output_record_size() {
+        for &version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+            let mut client_config = testutil::client_config();
+            client_config.versions = vec![version];
+            client_config.mtu = Some(512);
+            let server_config = Arc::new(testutil::server_config());
+            let (mut client, mut server) =
+                testutil::connection_pair(&Arc::new(client_config), &server_config);
+            assert_eq!(client.max_output_record_size(), 5 + 512 + 24);
+            assert_eq!(server.max_output_record_size(), 5 + 16384 + 24);
+            testutil::handshake(&mut client, &mut server).unwrap();
+            let cap = client.max_output_record_size();
+            assert!(cap <= 5 + 512 + 24);
+
+            assert_eq!(client.write_plaintext(&[0; 10000]).unwrap(), 10000);
+            let mut tls = Vec::new();
+            while client.as_ref().wants_write() {
+                client.write_tls(&mut tls).unwrap();
+            }
+            let records = split_records(&tls);
+            assert!(records.len() >= 10000 / 512);
+            assert!(records.iter().all(|r| r.len() <= cap));
+            assert!(records.iter().any(|r| r.len() == cap));
+
+            // The server still gets all the plaintext.
+            server.read_tls(&mut &tls[..]).unwrap();
+            server.process_new_packets().unwrap();
+            let mut buf = vec![0; 20000];
+            assert_eq!(server.read_plaintext(&mut buf).unwrap(), 10000);
+        }
+    }
+
+    #[test]
+    fn test_set_read_buffer_hint() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        rustls_connection_set_read_buffer_hint(server_ptr, 100_000);
+        let buffer = (
+            server.taken_plaintext.as_ptr(),
+            server.taken_plaintext.capacity(),
+        );
+        assert!(buffer.1 >= 100_000);
+        rustls_connection_set_read_buffer_hint(server_ptr, 10);
+        assert_eq!(server.taken_plaintext.capacity(), buffer.1);
+
+        // A message that arrives over many records and is read once it has
+        // all arrived is gathered without reallocating.
+        let message = vec![0x5a; 64 * 1024];
+        let mut written = 0;
+        while written < message.len() {
+            written += client.write_plaintext(&message[written..]).unwrap();
+            testutil::transfer(&mut client, &mut server).unwrap();
+        }
+        assert_eq!(server.taken_plaintext_len(), message.len());
+        assert_eq!(
+            (
+                server.taken_plaintext.as_ptr(),
+                server.taken_plaintext.capacity()
+            ),
+            buffer
+        );
+        let mut buf = vec![0; message.len()];
+        assert_eq!(server.read_plaintext(&mut buf).unwrap(), message.len());
+        assert_eq!(buf, message);
+    }
+
+    /// Compare receiving a large message that is read only once it has all
+    /// arrived without moving plaintext out of rustls, and moving it with
+    /// and without a read buffer hint. Run with
+    /// `cargo test --release -- --ignored --nocapture bench_read_buffer_hint`.
+    #[test]
+    #[ignore]
+    fn bench_read_buffer_hint() {
+        const MESSAGE_LEN: usize = 1 << 20;
+        const ROUNDS: u32 = 50;
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let message = vec![0x5a; MESSAGE_LEN];
+        let mut buf = vec![0; MESSAGE_LEN];
+        for &hint in &[None, Some(0), Some(MESSAGE_LEN)] {
+            let mut elapsed = Duration::default();
+            for _ in 0..ROUNDS {
+                let (mut client, mut server) =
+                    testutil::connection_pair(&client_config, &server_config);
+                testutil::handshake(&mut client, &mut server).unwrap();
+                if let Some(hint) = hint {
+                    let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+                    rustls_connection_set_read_buffer_hint(server_ptr, hint);
+                }
+                client.write_plaintext(&message).unwrap();
+                let mut tls = Vec::new();
+                while client.as_ref().wants_write() {
+                    client.write_tls(&mut tls).unwrap();
+                }
+
+                let start = Instant::now();
+                let mut reader = std::io::Cursor::new(&tls[..]);
+                while (reader.position() as usize) < tls.len() {
+                    server.read_tls(&mut reader).unwrap();
+                    server.process_new_packets().unwrap();
+                }
+                let mut read = 0;
+                while read < MESSAGE_LEN {
+                    read += server.read_plaintext(&mut buf[read..]).unwrap();
+                }
+                elapsed += start.elapsed();
+            }
+            let mib_per_sec = f64::from(ROUNDS) / elapsed.as_secs_f64();
+            println!(
+                "hint {:>13}: {:.0} MiB/s",
+                format!("{:?}", hint),
+                mib_per_sec
+            );
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(client.write_plaintext(b"hello").unwrap(), 5);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(server.read_plaintext(&mut buf).unwrap(), 5);
+
+        let (client, server) = (&client.stats, &server.stats);
+        assert_eq!(client.bytes_written_tls, server.bytes_read_tls);
+        assert_eq!(client.bytes_read_tls, server.bytes_written_tls);
+        assert_eq!(client.plaintext_written, 5);
+        assert_eq!(server.plaintext_read, 5);
+        assert!(client.records_processed > 0);
+        assert!(server.records_processed > 0);
+    }
+
+    #[test]
+    fn test_records_processed_only_on_success() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let processed = server.stats.records_processed;
+
+        // An application data record that doesn't decrypt.
+        let record = [0x17, 0x03, 0x03, 0x00, 0x05, 1, 2, 3, 4, 5];
+        server.read_tls(&mut &record[..]).unwrap();
+        assert!(server.process_new_packets().is_err());
+        assert_eq!(server.stats.records_processed, processed);
+    }
+
+    #[test]
+    fn test_get_handshake_bytes() {
+        let handshake_bytes = |conn: &Connection| {
+            let conn = conn as *const Connection as *const rustls_connection;
+            let (mut sent, mut received) = (99, 99);
+            let result = rustls_connection_get_handshake_bytes(conn, &mut sent, &mut received);
+            assert_eq!(result, rustls_result::Ok);
+            (sent, received)
+        };
+        let server_config = Arc::new(testutil::server_config());
+        for &version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+            let mut client_config = testutil::client_config();
+            client_config.versions = vec![version];
+            let client_config = Arc::new(client_config);
+            let (mut client, mut server) =
+                testutil::connection_pair(&client_config, &server_config);
+            assert_eq!(handshake_bytes(&client), (0, 0));
+            testutil::handshake(&mut client, &mut server).unwrap();
+            let (client_sent, client_received) = handshake_bytes(&client);
+            let (server_sent, server_received) = handshake_bytes(&server);
+            assert_eq!(client_sent, server_received);
+            assert_eq!(client_sent, client.stats.bytes_written_tls);
+            assert_eq!(server_sent, client.stats.bytes_read_tls);
+            // A TLS 1.3 server sends its tickets as its handshake completes,
+            // but the client's has completed before they arrive.
+            match version {
+                ProtocolVersion::TLSv1_3 => assert!(client_received < server_sent),
+                _ => assert_eq!(client_received, server_sent),
+            }
+
+            // Application data doesn't count.
+            assert_eq!(client.write_plaintext(b"hello").unwrap(), 5);
+            testutil::transfer(&mut client, &mut server).unwrap();
+            assert_eq!(server.write_plaintext(b"hello").unwrap(), 5);
+            testutil::transfer(&mut server, &mut client).unwrap();
+            assert_eq!(handshake_bytes(&client), (client_sent, client_received));
+            assert_eq!(handshake_bytes(&server), (server_sent, server_received));
+        }
+
+        let client_config = Arc::new(testutil::client_config());
+        let (client, _) = testutil::connection_pair(&client_config, &server_config);
+        let client = &client as *const Connection as *const rustls_connection;
+        let mut n = 0;
+        let result = rustls_connection_get_handshake_bytes(client, null_mut(), &mut n);
+        assert_eq!(result, rustls_result::NullParameter);
+        let result = rustls_connection_get_handshake_bytes(client, &mut n, null_mut());
+        assert_eq!(result, rustls_result::NullParameter);
+    }
+
+    #[test]
+    fn test_get_side() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (client, server) = testutil::connection_pair(&client_config, &server_config);
+        let side = |conn: &Connection| {
+            rustls_connection_get_side(conn as *const Connection as *const rustls_connection)
+        };
+        assert_eq!(side(&client), rustls_connection_side::Client);
+        assert_eq!(side(&server), rustls_connection_side::Server);
+        assert_eq!(
+            rustls_connection_get_side(null()),
+            rustls_connection_side::Unknown
+        );
+    }
+
+    #[test]
+    fn test_sequence_numbers() {
+        let sequence_numbers = |conn: &Connection| {
+            let (mut read, mut write) = (0u64, 0u64);
+            let conn_ptr = conn as *const Connection as *const rustls_connection;
+            let result = rustls_connection_get_sequence_numbers(conn_ptr, &mut read, &mut write);
+            assert_eq!(result, rustls_result::Ok);
+            (read, write)
+        };
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        assert_eq!(sequence_numbers(&client), (0, 0));
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let (client_read, client_write) = sequence_numbers(&client);
+        assert_eq!(sequence_numbers(&server), (client_write, client_read));
+        assert!(client_read > 0 && client_write > 0);
+
+        assert_eq!(client.write_plaintext(b"hello").unwrap(), 5);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(sequence_numbers(&client), (client_read, client_write + 1));
+        assert_eq!(sequence_numbers(&server), (client_write + 1, client_read));
+    }
+
+    #[test]
+    fn test_handshake_info() {
+        let server_config = Arc::new(testutil::server_config());
+        for &version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+            let mut client_config = testutil::client_config();
+            client_config.versions = vec![version];
+            let client_config = Arc::new(client_config);
+
+            let (client, server) = handshake_info(&client_config, &server_config);
+            assert_eq!(client.protocol_version, version.get_u16());
+            assert_ne!(client.cipher_suite, 0);
+            assert_eq!(client.kx_group, 0x001d);
+            assert!(!client.resumed);
+            assert!(!client.is_handshaking);
+            assert_eq!(server, client);
+
+            // The client config's session cache lets the second connection
+            // resume the first. TLS 1.2 resumption skips the key exchange.
+            let (client, server) = handshake_info(&client_config, &server_config);
+            assert!(client.resumed);
+            match version {
+                ProtocolVersion::TLSv1_3 => assert_eq!(client.kx_group, 0x001d),
+                _ => assert_eq!(client.kx_group, 0),
+            }
+            assert_eq!(server, client);
+        }
+    }
+
+    #[test]
+    fn test_get_negotiated_group_name() {
+        let group_name = |conn: &Connection, len: usize| {
+            let conn = conn as *const Connection as *const rustls_connection;
+            let mut buf = vec![0u8; len];
+            let mut n = 99;
+            let result =
+                rustls_connection_get_negotiated_group_name(conn, buf.as_mut_ptr(), len, &mut n);
+            buf.truncate(n.min(len));
+            (result, n, String::from_utf8(buf).unwrap())
+        };
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        assert_eq!(
+            group_name(&client, 16),
+            (rustls_result::Ok, 0, String::new())
+        );
+
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(
+            group_name(&client, 16),
+            (rustls_result::Ok, 6, "X25519".to_string())
+        );
+        assert_eq!(group_name(&server, 6).2, "X25519");
+        assert_eq!(group_name(&client, 5).0, rustls_result::InsufficientSize);
+
+        assert_eq!(named_group_name(0x0017), "secp256r1");
+        assert_eq!(named_group_name(0x1234), "Unknown(0x1234)");
+    }
+
+    #[test]
+    fn test_get_used_extended_master_secret() {
+        let used = |conn: &Connection| {
+            let conn = conn as *const Connection as *const rustls_connection;
+            rustls_connection_get_used_extended_master_secret(conn)
+        };
+        let server_config = Arc::new(testutil::server_config());
+        for &(version, expected) in &[
+            (ProtocolVersion::TLSv1_2, true),
+            (ProtocolVersion::TLSv1_3, false),
+        ] {
+            let mut client_config = testutil::client_config();
+            client_config.versions = vec![version];
+            let client_config = Arc::new(client_config);
+            let (mut client, mut server) =
+                testutil::connection_pair(&client_config, &server_config);
+            assert!(!used(&client));
+            testutil::handshake(&mut client, &mut server).unwrap();
+            assert_eq!(used(&client), expected);
+            assert_eq!(used(&server), expected);
+        }
+        assert!(!rustls_connection_get_used_extended_master_secret(null()));
+    }
+
+    /// A client session store that claims every ticket allows early data,
+    /// which rustls 0.19 servers only do for QUIC.
+    struct EarlyDataTickets(Arc<rustls::ClientSessionMemoryCache>);
+
+    impl rustls::StoresClientSessions for EarlyDataTickets {
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+            let value = match ClientSessionValue::read_bytes(&value) {
+                Some(mut session) => {
+                    session.set_max_early_data_size(1000);
+                    session.get_encoding()
+                }
+                None => value,
+            };
+            self.0.put(key, value)
+        }
+
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.get(key)
+        }
+    }
+
+    #[test]
+    fn test_get_max_early_data_size() {
+        let max_early_data = |conn: &Connection| {
+            let conn = conn as *const Connection as *const rustls_connection;
+            rustls_connection_get_max_early_data_size(conn)
+        };
+        let mut client_config = testutil::client_config();
+        client_config.enable_early_data = true;
+        client_config.set_persistence(Arc::new(EarlyDataTickets(
+            rustls::ClientSessionMemoryCache::new(8),
+        )));
+        let client_config = Arc::new(client_config);
+        let server_config = Arc::new(testutil::server_config());
+
+        // Nothing to resume, so no early data.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        assert_eq!(max_early_data(&client), 0);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(max_early_data(&client), 0);
+        assert_eq!(max_early_data(&server), 0);
+
+        // The ticket allows early data until the server rejects it.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        assert_eq!(max_early_data(&client), 1000);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert!(client.handshake_details().resumed);
+        assert_eq!(max_early_data(&client), 0);
+
+        assert_eq!(rustls_connection_get_max_early_data_size(null()), 0);
+    }
+
+    #[test]
+    fn test_certs_der_concat() {
+        let certs = vec![
+            Certificate(vec![0xaa; 3]),
+            Certificate(vec![]),
+            Certificate(vec![0xbb; 0x0102]),
+        ];
+        let needed = certs_der_concat_len(&certs);
+        assert_eq!(needed, 3 + 3 + 3 + 3 + 0x0102);
+
+        let mut buf = vec![0u8; needed];
+        assert_eq!(certs_der_concat(&certs, &mut buf), needed);
+        assert_eq!(&buf[..6], &[0, 0, 3, 0xaa, 0xaa, 0xaa]);
+        assert_eq!(&buf[6..9], &[0, 0, 0]);
+        assert_eq!(&buf[9..12], &[0, 1, 2]);
+        assert!(buf[12..].iter().all(|&b| b == 0xbb));
+    }
+
+    fn negotiated_alpn(conn: &Connection) -> Option<Vec<u8>> {
+        conn.as_ref().get_alpn_protocol().map(|p| p.to_vec())
+    }
+
+    #[test]
+    fn test_set_alpn_protocols() {
+        let mut client_config = testutil::client_config();
+        client_config.set_protocols(&[b"http/1.1".to_vec()]);
+        let mut server_config = testutil::server_config();
+        server_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        let (client_config, server_config) = (Arc::new(client_config), Arc::new(server_config));
+
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(negotiated_alpn(&client), Some(b"http/1.1".to_vec()));
+
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        client.set_buffer_limit(1000);
+        let h2 = b"h2";
+        let protocols = [rustls_slice_bytes::from(&h2[..])];
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        let result = rustls_connection_set_alpn_protocols(client_ptr, protocols.as_ptr(), 1);
+        assert_eq!(result, rustls_result::Ok);
+        assert_eq!(client.write_capacity(), 1000);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(negotiated_alpn(&client), Some(b"h2".to_vec()));
+        assert_eq!(negotiated_alpn(&server), Some(b"h2".to_vec()));
+        // The config itself is unchanged.
+        assert_eq!(client_config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        let result = rustls_connection_set_alpn_protocols(client_ptr, protocols.as_ptr(), 1);
+        assert_eq!(result, rustls_result::InvalidParameter);
+
+        // A server can narrow its own list too.
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let result = rustls_connection_set_alpn_protocols(server_ptr, protocols.as_ptr(), 1);
+        assert_eq!(result, rustls_result::Ok);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(negotiated_alpn(&server), None);
+    }
+
+    #[test]
+    fn test_forget_resumption() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let resumed = |forget: bool| {
+            let (mut client, mut server) =
+                testutil::connection_pair(&client_config, &server_config);
+            if forget {
+                let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+                let result = rustls_connection_forget_resumption(client_ptr);
+                assert_eq!(result, rustls_result::Ok);
+            }
+            testutil::handshake(&mut client, &mut server).unwrap();
+            client.handshake_details().resumed
+        };
+        assert!(!resumed(false));
+        assert!(resumed(false));
+        assert!(!resumed(true));
+        // The stored session is still there for other connections.
+        assert!(resumed(false));
+
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+        let result = rustls_connection_forget_resumption(server_ptr);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        let result = rustls_connection_forget_resumption(client_ptr);
+        assert_eq!(result, rustls_result::InvalidParameter);
+    }
+
+    #[test]
+    fn test_get_alpn_protocol_or() {
+        let mut client_config = testutil::client_config();
+        client_config.set_protocols(&[b"h2".to_vec()]);
+        let mut server_config = testutil::server_config();
+        server_config.set_protocols(&[b"h2".to_vec()]);
+        let (client_config, server_config) = (Arc::new(client_config), Arc::new(server_config));
+        let default = b"http/1.1";
+        let get = |conn: &Connection, default: *const u8, default_len: usize| {
+            let mut out: rustls_slice_bytes = (&[][..]).into();
+            let conn_ptr = conn as *const Connection as *const rustls_connection;
+            rustls_connection_get_alpn_protocol_or(conn_ptr, default, default_len, &mut out);
+            out
+        };
+
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let out = get(&server, default.as_ptr(), default.len());
+        assert_eq!(out.data, default.as_ptr());
+        assert_eq!(out.len, default.len());
+
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let out = get(&server, default.as_ptr(), default.len());
+        let negotiated = unsafe { slice::from_raw_parts(out.data, out.len) };
+        assert_eq!(negotiated, b"h2");
+
+        let plain_client_config = Arc::new(testutil::client_config());
+        let (mut client, mut server) =
+            testutil::connection_pair(&plain_client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let out = get(&server, default.as_ptr(), default.len());
+        assert_eq!(out.data, default.as_ptr());
+        assert_eq!(out.len, default.len());
+        let out = get(&server, null(), 0);
+        assert!(out.data.is_null());
+        assert_eq!(out.len, 0);
+    }
+
+    #[test]
+    fn test_protocol_version_before_handshake_completes() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let version = |conn: &Connection| {
+            rustls_connection_get_protocol_version(conn as *const Connection as *const _)
+        };
+        assert_eq!((version(&client), version(&server)), (0, 0));
+
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert!(server.as_ref().is_handshaking());
+        assert_eq!((version(&client), version(&server)), (0, 0x0304));
+
+        // Deliver only the record carrying the ServerHello.
+        let mut tls = Vec::new();
+        while server.as_ref().wants_write() {
+            server.write_tls(&mut tls).unwrap();
+        }
+        let first = split_records(&tls)[0];
+        client.read_tls(&mut &first[..]).unwrap();
+        client.process_new_packets().unwrap();
+        assert!(client.as_ref().is_handshaking());
+        assert_eq!(version(&client), 0x0304);
+    }
+
+    #[test]
+    fn test_peer_cert_spki_sha256() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let client_ptr = &client as *const Connection as *const rustls_connection;
+        let mut buf = [0u8; 40];
+        let mut n = 0;
+        let result =
+            rustls_connection_get_peer_cert_spki_sha256(client_ptr, buf.as_mut_ptr(), 40, &mut n);
+        assert_eq!(result, rustls_result::NoCertificatesPresented);
+
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let client_ptr = &client as *const Connection as *const rustls_connection;
+        let result =
+            rustls_connection_get_peer_cert_spki_sha256(client_ptr, buf.as_mut_ptr(), 31, &mut n);
+        assert_eq!(result, rustls_result::InsufficientSize);
+        let result =
+            rustls_connection_get_peer_cert_spki_sha256(client_ptr, buf.as_mut_ptr(), 40, &mut n);
+        assert_eq!(result, rustls_result::Ok);
+        assert_eq!(n, 32);
+        // openssl x509 -in testdata/localhost.pem -pubkey -noout |
+        //     openssl pkey -pubin -outform der | sha256sum
+        let expected = [
+            0xbf, 0x4c, 0x45, 0xd6, 0x7f, 0x26, 0xfd, 0x90, 0x63, 0xf1, 0xbe, 0xc2, 0xe6, 0x05,
+            0xf7, 0xf5, 0x72, 0xac, 0xfb, 0xc8, 0x71, 0x9c, 0x2f, 0x61, 0x32, 0x12, 0xd1, 0x7d,
+            0x5e, 0xe6, 0xbd, 0xd4,
+        ];
+        assert_eq!(buf[..32], expected);
+    }
+
+    #[test]
+    fn test_get_peer_certificate_der() {
+        let chain = [
+            testutil::LOCALHOST_VIA_INTERMEDIATE_PEM,
+            testutil::INTERMEDIATE_PEM,
+        ]
+        .concat();
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config_with_cert(&chain));
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        let der = |i: usize| {
+            let mut out: rustls_slice_bytes = (&b"x"[..]).into();
+            let result = rustls_connection_get_peer_certificate_der(client_ptr, i, &mut out);
+            assert_eq!(result, rustls_result::Ok);
+            unsafe { std::slice::from_raw_parts(out.data, out.len) }.to_vec()
+        };
+        assert!(der(0).is_empty());
+
+        testutil::handshake(&mut client, &mut server).unwrap();
+        let expected = testutil::certs(&chain);
+        assert_eq!(der(0), expected[0].0);
+        assert_eq!(der(1), expected[1].0);
+        assert!(der(2).is_empty());
+        assert_eq!(
+            rustls_connection_get_peer_certificate_der(client_ptr, 0, null_mut()),
+            rustls_result::NullParameter
+        );
+    }
+
+    #[test]
+    fn test_peer_cert_cache() {
+        let chain = [
+            testutil::LOCALHOST_VIA_INTERMEDIATE_PEM,
+            testutil::INTERMEDIATE_PEM,
+        ]
+        .concat();
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config_with_cert(&chain));
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let client_ptr = &mut client as *mut Connection as *mut rustls_connection;
+        // Asking before the peer has sent its chain doesn't stop it being
+        // found later.
+        assert_eq!(rustls_connection_get_peer_cert_count(client_ptr), 0);
+        assert!(rustls_connection_get_peer_certificate(client_ptr, 0).is_null());
+
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(rustls_connection_get_peer_cert_count(client_ptr), 2);
+        let first = rustls_connection_get_peer_certificate(client_ptr, 0);
+        assert!(!first.is_null());
+        for _ in 0..3 {
+            assert_eq!(rustls_connection_get_peer_certificate(client_ptr, 0), first);
+        }
+        let mut out: rustls_slice_bytes = (&b""[..]).into();
+        let result = rustls_connection_get_peer_certificate_der(client_ptr, 0, &mut out);
+        assert_eq!(result, rustls_result::Ok);
+        let der = unsafe { &*(first as *const Certificate) }.0.as_ptr();
+        assert_eq!(out.data, der);
+
+        rustls_connection_reset(client_ptr);
+        assert_eq!(rustls_connection_get_peer_cert_count(client_ptr), 0);
+        assert_eq!(rustls_connection_get_peer_cert_count(null()), 0);
+    }
+
+    /// Remembers the secrets rustls logs, so tests can encrypt records as
+    /// the peer would.
+    #[derive(Default)]
+    struct Secrets(std::sync::Mutex<Vec<(String, Vec<u8>)>>);
+
+    impl rustls::KeyLog for Secrets {
+        fn log(&self, label: &str, _client_random: &[u8], secret: &[u8]) {
+            let mut secrets = self.0.lock().unwrap();
+            secrets.push((label.to_string(), secret.to_vec()));
+        }
+    }
+
+    impl Secrets {
+        fn get(&self, label: &str) -> Vec<u8> {
+            let secrets = self.0.lock().unwrap();
+            let (_, secret) = secrets.iter().find(|(l, _)| l == label).unwrap();
+            secret.clone()
+        }
+    }
+
+    struct Len(usize);
+
+    impl ring::hkdf::KeyType for Len {
+        fn len(&self) -> usize {
+            self.0
+        }
+    }
+
+    /// HKDF-Expand-Label with SHA-256 and an empty context, RFC 8446
+    /// section 7.1.
+    fn expand_label(secret: &[u8], label: &[u8], len: usize) -> Vec<u8> {
+        let prk = ring::hkdf::Prk::new_less_safe(ring::hkdf::HKDF_SHA256, secret);
+        let full_label = [&b"tls13 "[..], label].concat();
+        let info = [
+            &(len as u16).to_be_bytes()[..],
+            &[full_label.len() as u8],
+            &full_label,
+            &[0],
+        ];
+        let mut out = vec![0; len];
+        prk.expand(&info, Len(len)).unwrap().fill(&mut out).unwrap();
+        out
+    }
+
+    /// Encrypt one TLS 1.3 record with TLS_CHACHA20_POLY1305_SHA256, using
+    /// the keys derived from the traffic secret `secret`.
+    fn seal_tls13_record(secret: &[u8], seq: u64, typ: u8, payload: &[u8]) -> Vec<u8> {
+        use ring::aead;
+        let key = expand_label(secret, b"key", 32);
+        let mut nonce = expand_label(secret, b"iv", 12);
+        for (n, s) in nonce[4..].iter_mut().zip(seq.to_be_bytes().iter()) {
+            *n ^= s;
+        }
+        let key = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key).unwrap();
+        let key = aead::LessSafeKey::new(key);
+        let nonce = aead::Nonce::try_assume_unique_for_key(&nonce).unwrap();
+
+        let mut body = [payload, &[typ]].concat();
+        let len = (body.len() + aead::CHACHA20_POLY1305.tag_len()) as u16;
+        let mut record = vec![23, 3, 3];
+        record.extend_from_slice(&len.to_be_bytes());
+        key.seal_in_place_append_tag(nonce, aead::Aad::from(&record[..]), &mut body)
+            .unwrap();
+        record.extend_from_slice(&body);
+        record
+    }
+
+    fn read_all(conn: &mut Connection) -> Vec<u8> {
+        let conn_ptr = conn as *mut Connection as *mut rustls_connection;
+        let mut out = Vec::new();
+        let mut buf = [0u8; 3];
+        loop {
+            let mut n = 0;
+            let result = rustls_connection_read(conn_ptr, buf.as_mut_ptr(), buf.len(), &mut n);
+            assert_eq!(result, rustls_result::Ok);
+            if n == 0 {
+                return out;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn test_read_across_key_update() {
+        let secrets = Arc::new(Secrets::default());
+        let mut client_config = testutil::client_config();
+        client_config.versions = vec![ProtocolVersion::TLSv1_3];
+        client_config.ciphersuites = vec![&rustls::ciphersuite::TLS13_CHACHA20_POLY1305_SHA256];
+        client_config.key_log = secrets.clone();
+        let mut server_config = testutil::server_config();
+        // Without session storage the server sends no tickets, so its first
+        // application data record has sequence number 0.
+        server_config.session_storage = Arc::new(rustls::NoServerSessionStorage {});
+        let (mut client, mut server) =
+            testutil::connection_pair(&Arc::new(client_config), &Arc::new(server_config));
+        testutil::handshake(&mut client, &mut server).unwrap();
+
+        // Application data, a KeyUpdate requesting one in return, and more
+        // application data under the new keys, all arriving together.
+        let secret = secrets.get("SERVER_TRAFFIC_SECRET_0");
+        let next_secret = expand_label(&secret, b"traffic upd", 32);
+        let key_update = [24, 0, 0, 1, 1];
+        let records = [
+            seal_tls13_record(&secret, 0, 23, b"hello, "),
+            seal_tls13_record(&secret, 1, 22, &key_update),
+            seal_tls13_record(&next_secret, 0, 23, b"world"),
+        ]
+        .concat();
+        assert_eq!(client.read_tls(&mut &records[..]).unwrap(), records.len());
+        client.process_new_packets().unwrap();
+        assert_eq!(read_all(&mut client), b"hello, world");
+
+        // The client answers with a KeyUpdate of its own, which the server
+        // must handle before the data that follows it.
+        assert_eq!(client.write_plaintext(b"ping").unwrap(), 4);
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(read_all(&mut server), b"ping");
     }
 }
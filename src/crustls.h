@@ -7,6 +7,50 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Which side of a TLS session a connection is. See
+ * rustls_connection_get_side.
+ */
+typedef enum rustls_connection_side {
+  /**
+   * Returned for a NULL connection.
+   */
+  RUSTLS_CONNECTION_SIDE_UNKNOWN = 0,
+  RUSTLS_CONNECTION_SIDE_CLIENT = 1,
+  RUSTLS_CONNECTION_SIDE_SERVER = 2,
+} rustls_connection_side;
+
+/**
+ * A hash algorithm, by its value in the TLS HashAlgorithm registry. An
+ * external pre-shared key can only be used with cipher suites that use its
+ * hash algorithm.
+ */
+typedef enum rustls_hash_algorithm {
+  RUSTLS_HASH_ALGORITHM_SHA256 = 4,
+  RUSTLS_HASH_ALGORITHM_SHA384 = 5,
+} rustls_hash_algorithm;
+
+/**
+ * What a client does with the OCSP response a server staples to its
+ * certificate. See rustls_client_config_builder_set_ocsp_policy.
+ */
+typedef enum rustls_ocsp_policy {
+  /**
+   * Don't look at stapled responses. This is the default.
+   */
+  RUSTLS_OCSP_POLICY_IGNORE = 0,
+  /**
+   * Reject the certificate if a usable stapled response says it is
+   * revoked. Accept it if there is no response, or it can't be used.
+   */
+  RUSTLS_OCSP_POLICY_SOFT_FAIL = 1,
+  /**
+   * Accept the certificate only if a usable stapled response says it is
+   * good.
+   */
+  RUSTLS_OCSP_POLICY_HARD_FAIL = 2,
+} rustls_ocsp_policy;
+
 typedef enum rustls_result {
   RUSTLS_RESULT_OK = 7000,
   RUSTLS_RESULT_IO = 7001,
@@ -18,6 +62,19 @@ typedef enum rustls_result {
   RUSTLS_RESULT_INSUFFICIENT_SIZE = 7007,
   RUSTLS_RESULT_NOT_FOUND = 7008,
   RUSTLS_RESULT_INVALID_PARAMETER = 7009,
+  RUSTLS_RESULT_UNSUPPORTED = 7010,
+  RUSTLS_RESULT_CERTIFICATE_REVOKED = 7011,
+  RUSTLS_RESULT_WEAK_KEY = 7012,
+  RUSTLS_RESULT_UNEXPECTED_EOF = 7013,
+  RUSTLS_RESULT_RANDOM_UNAVAILABLE = 7014,
+  RUSTLS_RESULT_CERTIFICATE_TRANSPARENCY_REQUIRED = 7015,
+  RUSTLS_RESULT_NO_VERIFIER_CONFIGURED = 7016,
+  RUSTLS_RESULT_WRITE_WOULD_BLOCK = 7017,
+  RUSTLS_RESULT_HANDSHAKE_TIMEOUT = 7018,
+  RUSTLS_RESULT_CANNOT_BUILD_CHAIN = 7019,
+  RUSTLS_RESULT_CERTIFICATE_POLICY_MISSING = 7020,
+  RUSTLS_RESULT_UNIMPLEMENTED = 7021,
+  RUSTLS_RESULT_FILE_READ_ERROR = 7022,
   RUSTLS_RESULT_CORRUPT_MESSAGE = 7100,
   RUSTLS_RESULT_NO_CERTIFICATES_PRESENTED = 7101,
   RUSTLS_RESULT_DECRYPT_ERROR = 7102,
@@ -140,7 +197,8 @@ typedef struct rustls_client_cert_verifier_optional rustls_client_cert_verifier_
 
 /**
  * A client config that is done being constructed and is now read-only.
- * Under the hood, this object corresponds to an Arc<ClientConfig>.
+ * Under the hood, this object corresponds to an Arc<BuiltClientConfig>,
+ * which holds an Arc<ClientConfig>.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html
  */
 typedef struct rustls_client_config rustls_client_config;
@@ -151,13 +209,19 @@ typedef struct rustls_client_config rustls_client_config;
  * done configuring settings, call rustls_client_config_builder_build
  * to turn it into a *rustls_client_config. This object is not safe
  * for concurrent mutation. Under the hood, it corresponds to a
- * Box<ClientConfig>.
+ * Box<ClientConfigBuilder>.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html
  */
 typedef struct rustls_client_config_builder rustls_client_config_builder;
 
 typedef struct rustls_connection rustls_connection;
 
+/**
+ * A list of certificates being collected by a
+ * `rustls_intermediate_fetch_callback`.
+ */
+typedef struct rustls_intermediate_certs rustls_intermediate_certs;
+
 /**
  * An alias for `struct iovec` from uio.h (on Unix) or `WSABUF` on Windows. You should cast
  * `const struct rustls_iovec *` to `const struct iovec *` on Unix, or `const *LPWSABUF`
@@ -175,7 +239,8 @@ typedef struct rustls_root_cert_store rustls_root_cert_store;
 
 /**
  * A server config that is done being constructed and is now read-only.
- * Under the hood, this object corresponds to an Arc<ServerConfig>.
+ * Under the hood, this object corresponds to an Arc<BuiltServerConfig>,
+ * which holds an Arc<ServerConfig>.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html
  */
 typedef struct rustls_server_config rustls_server_config;
@@ -186,7 +251,7 @@ typedef struct rustls_server_config rustls_server_config;
  * done configuring settings, call rustls_server_config_builder_build
  * to turn it into a *rustls_server_config. This object is not safe
  * for concurrent mutation. Under the hood, it corresponds to a
- * Box<ServerConfig>.
+ * Box<ServerConfigBuilder>.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html
  */
 typedef struct rustls_server_config_builder rustls_server_config_builder;
@@ -232,6 +297,41 @@ typedef struct rustls_slice_str rustls_slice_str;
  */
 typedef struct rustls_supported_ciphersuite rustls_supported_ciphersuite;
 
+/**
+ * A callback for `rustls_connection_get_peer_signature_schemes` and
+ * `rustls_signature_schemes_for_version`, called once per signature scheme
+ * with its IANA-assigned u16 value.
+ * https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-signaturescheme
+ */
+typedef void (*rustls_signature_scheme_callback)(void *userdata, uint16_t scheme);
+
+/**
+ * A read-only view on a Rust `&str`. The contents are guaranteed to be valid
+ * UTF-8. As an additional guarantee on top of Rust's normal UTF-8 guarantee,
+ * a `rustls_str` is guaranteed not to contain internal NUL bytes, so it is
+ * safe to interpolate into a C string or compare using strncmp. Keep in mind
+ * that it is not NUL-terminated.
+ *
+ * The memory exposed is available as specified by the function
+ * using this in its signature. For instance, when this is a parameter to a
+ * callback, the lifetime will usually be the duration of the callback.
+ * Functions that receive one of these must not dereference the data pointer
+ * beyond the allowed lifetime. To keep the string for longer, copy `len`
+ * bytes from `data` into memory you own (and NUL-terminate the copy if you
+ * want a C string).
+ */
+typedef struct rustls_str {
+  const char *data;
+  size_t len;
+} rustls_str;
+
+/**
+ * A callback for `rustls_supported_protocol_version_iter`, called once per
+ * protocol version with its u16 value, as in `rustls_tls_version`, and its
+ * name, such as "TLSv1.3". The name is only valid during the call.
+ */
+typedef void (*rustls_protocol_version_callback)(void *userdata, uint16_t version, struct rustls_str name);
+
 /**
  * A read-only view on a Rust byte slice.
  *
@@ -243,7 +343,8 @@ typedef struct rustls_supported_ciphersuite rustls_supported_ciphersuite;
  * using this in its signature. For instance, when this is a parameter to a
  * callback, the lifetime will usually be the duration of the callback.
  * Functions that receive one of these must not dereference the data pointer
- * beyond the allowed lifetime.
+ * beyond the allowed lifetime. To keep the bytes for longer, copy them into
+ * memory you own, for instance with rustls_slice_bytes_copy.
  */
 typedef struct rustls_slice_bytes {
   const uint8_t *data;
@@ -251,28 +352,16 @@ typedef struct rustls_slice_bytes {
 } rustls_slice_bytes;
 
 /**
- * User-provided input to a custom certificate verifier callback. See
- * rustls_client_config_builder_dangerous_set_certificate_verifier().
+ * A callback for `rustls_pem_split`, called with the DER encoding of one
+ * item from the PEM input. `der` is only valid during the callback.
  */
-typedef void *rustls_verify_server_cert_user_data;
+typedef void (*rustls_pem_item_callback)(void *userdata, const struct rustls_slice_bytes *der);
 
 /**
- * A read-only view on a Rust `&str`. The contents are guaranteed to be valid
- * UTF-8. As an additional guarantee on top of Rust's normal UTF-8 guarantee,
- * a `rustls_str` is guaranteed not to contain internal NUL bytes, so it is
- * safe to interpolate into a C string or compare using strncmp. Keep in mind
- * that it is not NUL-terminated.
- *
- * The memory exposed is available as specified by the function
- * using this in its signature. For instance, when this is a parameter to a
- * callback, the lifetime will usually be the duration of the callback.
- * Functions that receive one of these must not dereference the data pointer
- * beyond the allowed lifetime.
+ * User-provided input to a custom certificate verifier callback. See
+ * rustls_client_config_builder_dangerous_set_certificate_verifier().
  */
-typedef struct rustls_str {
-  const char *data;
-  size_t len;
-} rustls_str;
+typedef void *rustls_verify_server_cert_user_data;
 
 /**
  * Input to a custom certificate verifier callback. See
@@ -288,6 +377,24 @@ typedef struct rustls_verify_server_cert_params {
 
 typedef enum rustls_result (*rustls_verify_server_cert_callback)(rustls_verify_server_cert_user_data userdata, const struct rustls_verify_server_cert_params *params);
 
+/**
+ * A callback for `rustls_client_config_builder_set_intermediate_fetch_callback`.
+ * `cert` is the DER encoding of the certificate whose issuer couldn't be
+ * found, and `issuer` the DER encoding of its issuer's Name, for looking
+ * up a certificate by subject. Both are only valid during the callback.
+ * The callback adds any certificates it finds with
+ * `rustls_intermediate_certs_add`; `certs` is also only valid during the
+ * callback.
+ */
+typedef void (*rustls_intermediate_fetch_callback)(void *userdata, const struct rustls_slice_bytes *cert, const struct rustls_slice_bytes *issuer, struct rustls_intermediate_certs *certs);
+
+/**
+ * A callback for `rustls_client_config_builder_set_verify_report_callback`.
+ * `result` is what `rustls_connection_process_new_packets` would have
+ * returned for the server's certificate.
+ */
+typedef void (*rustls_verify_report_callback)(void *userdata, enum rustls_result result);
+
 /**
  * Any context information the callback will receive when invoked.
  */
@@ -340,13 +447,31 @@ typedef enum rustls_result (*rustls_session_store_put_callback)(rustls_session_s
 
 typedef size_t rustls_log_level;
 
+/**
+ * A log message, as passed to a rustls_log_callback. The message is
+ * prefixed with its target, the Rust module that logged it, which is also
+ * given separately.
+ *
+ * crustls allocates this struct, and new fields are only ever appended, so
+ * a callback built against an older header still reads `level` and
+ * `message` correctly. `target` was added in 0.8.0.
+ */
 typedef struct rustls_log_params {
   rustls_log_level level;
   struct rustls_str message;
+  struct rustls_str target;
 } rustls_log_params;
 
 typedef void (*rustls_log_callback)(void *userdata, const struct rustls_log_params *params);
 
+/**
+ * A callback for `rustls_connection_set_new_ticket_callback`. `ticket_id`
+ * identifies the new resumption state; it is the ticket the server issued
+ * or, for TLS 1.2 resumption by session ID, the session ID. It is only
+ * valid during the callback.
+ */
+typedef void (*rustls_new_ticket_callback)(void *userdata, const struct rustls_slice_bytes *ticket_id);
+
 /**
  * A return value for a function that may return either success (0) or a
  * non-zero value representing an error.
@@ -366,6 +491,11 @@ typedef int rustls_io_result;
  * `userdata` is set to the value provided to `rustls_*_session_set_userdata`. In most
  * cases that should be a struct that contains, at a minimum, a file descriptor.
  * The buf and out_n pointers are borrowed and should not be retained across calls.
+ *
+ * Returning 0 with out_n set to 0 means the end of the stream (EOF), as
+ * for read(2). A non-blocking callback that has nothing to read yet must
+ * return an error such as EAGAIN instead. The callback is never called
+ * with `n` of 0.
  */
 typedef rustls_io_result (*rustls_read_callback)(void *userdata, uint8_t *buf, size_t n, size_t *out_n);
 
@@ -401,6 +531,80 @@ typedef rustls_io_result (*rustls_write_callback)(void *userdata, const uint8_t
  */
 typedef rustls_io_result (*rustls_write_vectored_callback)(void *userdata, const struct rustls_iovec *iov, size_t count, size_t *out_n);
 
+/**
+ * A snapshot of what has been negotiated on a connection so far, filled in
+ * by `rustls_connection_get_handshake_info`. Fields that aren't known yet
+ * are zero (or false).
+ *
+ * Callers allocate this struct, so its layout is part of the ABI: fields
+ * will not be reordered or removed within a release series, and any new
+ * fields will be appended at the end and noted in the changelog.
+ */
+typedef struct rustls_handshake_info {
+  /**
+   * The negotiated protocol version, as for
+   * `rustls_connection_get_protocol_version`.
+   */
+  uint16_t protocol_version;
+  /**
+   * The IANA-assigned value of the negotiated cipher suite, as for
+   * `rustls_supported_ciphersuite_get_suite`.
+   */
+  uint16_t cipher_suite;
+  /**
+   * The IANA-assigned NamedGroup used for key exchange, e.g. 0x001d for
+   * X25519. Always 0 for resumed TLS 1.2 sessions, which don't perform a
+   * key exchange.
+   */
+  uint16_t kx_group;
+  /**
+   * Whether the session was resumed rather than fully negotiated.
+   */
+  bool resumed;
+  /**
+   * Whether the handshake is still in progress, as for
+   * `rustls_connection_is_handshaking`.
+   */
+  bool is_handshaking;
+} rustls_handshake_info;
+
+/**
+ * Counters of the data that has passed through a connection, filled in by
+ * `rustls_connection_get_stats`. The counters start at zero when the
+ * connection is created and only ever increase; they are discarded when the
+ * connection is freed.
+ *
+ * Callers allocate this struct, so its layout is part of the ABI: fields
+ * will not be reordered or removed within a release series, and any new
+ * fields will be appended at the end and noted in the changelog.
+ */
+typedef struct rustls_connection_stats {
+  /**
+   * TLS bytes received via `rustls_connection_read_tls`.
+   */
+  uint64_t bytes_read_tls;
+  /**
+   * TLS bytes sent via `rustls_connection_write_tls` and
+   * `rustls_connection_write_tls_vectored`.
+   */
+  uint64_t bytes_written_tls;
+  /**
+   * Plaintext bytes returned by `rustls_connection_read` or consumed with
+   * `rustls_connection_consume_plaintext`.
+   */
+  uint64_t plaintext_read;
+  /**
+   * Plaintext bytes accepted by `rustls_connection_write`.
+   */
+  uint64_t plaintext_written;
+  /**
+   * TLS records received and successfully processed by
+   * `rustls_connection_process_new_packets`. Records passed to a call
+   * that returns an error are not counted.
+   */
+  uint64_t records_processed;
+} rustls_connection_stats;
+
 /**
  * Any context information the callback will receive when invoked.
  */
@@ -467,6 +671,33 @@ typedef struct rustls_client_hello {
  */
 typedef const struct rustls_certified_key *(*rustls_client_hello_callback)(rustls_client_hello_userdata userdata, const struct rustls_client_hello *hello);
 
+/**
+ * Prototype of a callback that chooses the ALPN protocol for a server
+ * connection. `offered` points to `offered_len` protocol names, in the
+ * client's order of preference. The callback returns the index in
+ * `offered` of the protocol to use, or -1 to decline all of them.
+ * `userdata` will be set based on rustls_connection_set_userdata.
+ *
+ * NOTE: `offered` and the protocol names are only available during the
+ * callback invocation. The callback must be thread-safe, unless there is
+ * only a single config and session where it is installed.
+ */
+typedef int (*rustls_alpn_select_callback)(void *userdata, const struct rustls_slice_bytes *offered, size_t offered_len);
+
+/**
+ * Prototype of a callback that looks up an external pre-shared key by the
+ * identity a client offers. If it knows the key, the callback writes it to
+ * `key_buf`, which can hold up to `key_buf_len` bytes, stores its length
+ * in `out_key_len` and its hash algorithm in `out_hash`, and returns
+ * RUSTLS_RESULT_OK. Otherwise it returns RUSTLS_RESULT_NOT_FOUND.
+ * `userdata` will be set based on rustls_connection_set_userdata.
+ *
+ * NOTE: `identity` is only available during the callback invocation. The
+ * callback must be thread-safe, unless there is only a single config and
+ * session where it is installed.
+ */
+typedef enum rustls_result (*rustls_psk_resolver_callback)(void *userdata, const struct rustls_slice_bytes *identity, uint8_t *key_buf, size_t key_buf_len, size_t *out_key_len, enum rustls_hash_algorithm *out_hash);
+
 /**
  * Write the version of the crustls C bindings and rustls itself into the
  * provided buffer, up to a max of `len` bytes. Output is UTF-8 encoded
@@ -474,6 +705,36 @@ typedef const struct rustls_certified_key *(*rustls_client_hello_callback)(rustl
  */
 size_t rustls_version(char *buf, size_t len);
 
+/**
+ * Write the name of the cryptography library crustls was built against,
+ * such as "ring", into `buf`, which can hold up to `len` bytes, and store
+ * the number of bytes written in `out_n`. The name is UTF-8 and is not
+ * NUL-terminated. Applications with requirements such as FIPS validation
+ * can check this at startup.
+ *
+ * The version of rustls used by crustls only supports ring, so this
+ * always reports "ring" for now. Once crustls moves to a rustls release
+ * with pluggable crypto providers, it will gain a build-time option to use
+ * aws-lc-rs instead, and this will report "aws-lc-rs" when it is used.
+ *
+ * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if `buf` is
+ * too small; in that case the number of bytes needed is stored in `out_n`.
+ */
+enum rustls_result rustls_crypto_backend_name(uint8_t *buf, size_t len, size_t *out_n);
+
+/**
+ * Do crustls' one-time setup, and check that the environment can support
+ * TLS. Calling this is optional but recommended: call it once at startup,
+ * so that problems show up there rather than in the first handshake.
+ *
+ * This detects the CPU features the cryptography library can use, and
+ * checks that the system's random number generator works, returning
+ * RUSTLS_RESULT_RANDOM_UNAVAILABLE if it doesn't. The work is done only
+ * by the first call; it is safe to call this any number of times, from
+ * any thread, and every call returns the same result.
+ */
+enum rustls_result rustls_init(void);
+
 /**
  * Get the DER data of the certificate itself.
  * The data is owned by the certificate and has the same lifetime.
@@ -482,6 +743,62 @@ enum rustls_result rustls_certificate_get_der(const struct rustls_certificate *c
                                               const uint8_t **out_der_data,
                                               size_t *out_der_len);
 
+/**
+ * Check whether the certificate's subject alternative names match
+ * `hostname`, using the same name matching rules as certificate
+ * verification. No other checks are done: the certificate's validity
+ * period, issuer and signature are ignored. This is meant for diagnosing
+ * why a certificate was rejected for a host, without a live connection.
+ *
+ * Returns RUSTLS_RESULT_OK if the names match,
+ * RUSTLS_RESULT_CERT_NOT_VALID_FOR_NAME if they don't,
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't be parsed,
+ * and RUSTLS_RESULT_INVALID_DNS_NAME_ERROR if `hostname` is not a valid DNS
+ * name. IP addresses are not supported.
+ */
+enum rustls_result rustls_certificate_matches_hostname(const struct rustls_certificate *cert,
+                                                       const uint8_t *hostname,
+                                                       size_t hostname_len);
+
+/**
+ * Write the name of the algorithm of the certificate's public key, such as
+ * "RSA", "ECDSA P-256" or "Ed25519", into `buf`, which can hold up to `len`
+ * bytes, and store the number of bytes written in `out_n`. The name is
+ * UTF-8 and is not NUL-terminated. This tells which signature schemes the
+ * certificate can be used with, without loading its private key.
+ *
+ * An algorithm crustls doesn't know is reported as "Unknown(" followed by
+ * its OID in dotted decimal and ")", such as "Unknown(1.2.3.4)"; an ECDSA
+ * key on an unknown curve as "ECDSA Unknown(<curve OID>)".
+ *
+ * Returns RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't
+ * be parsed, and RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if
+ * `buf` is too small; in that case the number of bytes needed is stored in
+ * `out_n`.
+ */
+enum rustls_result rustls_certificate_get_key_algorithm_name(const struct rustls_certificate *cert,
+                                                             uint8_t *buf,
+                                                             size_t len,
+                                                             size_t *out_n);
+
+/**
+ * Write the certificate's serial number into `buf`, which can hold up to
+ * `len` bytes, and store the number of bytes written in `out_n`. The
+ * serial is written as an unsigned big-endian number, without the leading
+ * zero byte DER adds when the first byte is 0x80 or more; serials may be
+ * up to 20 bytes long. Together with the issuer, the serial identifies a
+ * certificate, as in OCSP requests and revocation lists.
+ *
+ * Returns RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't
+ * be parsed, and RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if
+ * `buf` is too small; in that case the number of bytes needed is stored in
+ * `out_n`.
+ */
+enum rustls_result rustls_certificate_get_serial_number(const struct rustls_certificate *cert,
+                                                        uint8_t *buf,
+                                                        size_t len,
+                                                        size_t *out_n);
+
 /**
  * Return a 16-bit unsigned integer corresponding to this cipher suite's assignment from
  * <https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-4>.
@@ -489,8 +806,19 @@ enum rustls_result rustls_certificate_get_der(const struct rustls_certificate *c
  */
 uint16_t rustls_supported_ciphersuite_get_suite(const struct rustls_supported_ciphersuite *supported_ciphersuite);
 
+/**
+ * Return the TLS protocol version this cipher suite is used with, as in
+ * `rustls_tls_version`: 0x0304 for TLS 1.3 suites and 0x0303 for TLS 1.2
+ * suites. Each suite rustls supports belongs to exactly one version.
+ * Returns 0 if `supported_ciphersuite` is NULL.
+ */
+uint16_t rustls_supported_ciphersuite_get_protocol_version(const struct rustls_supported_ciphersuite *supported_ciphersuite);
+
 /**
  * Return the length of rustls' list of supported cipher suites.
+ * Every suite in the list is an AEAD (AES-GCM or ChaCha20-Poly1305): rustls
+ * implements no CBC-mode suites, so no connection ever uses a CBC
+ * decryption path or needs Lucky13 mitigations.
  */
 size_t rustls_all_ciphersuites_len(void);
 
@@ -502,6 +830,33 @@ size_t rustls_all_ciphersuites_len(void);
  */
 const struct rustls_supported_ciphersuite *rustls_all_ciphersuites_get_entry(size_t i);
 
+/**
+ * Call `callback` with the IANA-assigned value of each signature scheme
+ * rustls supports that may be used with TLS protocol `version`, as in
+ * `rustls_tls_version`, passing `userdata` through unchanged. The schemes
+ * come in rustls' order of preference. TLS 1.3 doesn't allow the PKCS#1
+ * v1.5 RSA schemes, so only RSA-PSS is listed for RSA keys there. This
+ * helps build a list for `rustls_client_config_builder_set_signature_schemes`
+ * that works with every enabled version.
+ *
+ * rustls never negotiates versions before TLS 1.2, so for any version
+ * other than 0x0303 and 0x0304 this returns RUSTLS_RESULT_UNSUPPORTED
+ * without calling `callback`.
+ */
+enum rustls_result rustls_signature_schemes_for_version(uint16_t version,
+                                                        rustls_signature_scheme_callback callback,
+                                                        void *userdata);
+
+/**
+ * Call `callback` with each TLS protocol version rustls can negotiate, in
+ * rustls' order of preference, passing `userdata` through unchanged. These
+ * are the versions enabled by default in a new config; unlike
+ * `rustls_tls_version`, they don't include old versions such as SSLv3 that
+ * rustls doesn't implement.
+ */
+enum rustls_result rustls_supported_protocol_version_iter(rustls_protocol_version_callback callback,
+                                                          void *userdata);
+
 /**
  * Build a `rustls_certified_key` from a certificate chain and a private key.
  * `cert_chain` must point to a buffer of `cert_chain_len` bytes, containing
@@ -525,6 +880,95 @@ enum rustls_result rustls_certified_key_build(const uint8_t *cert_chain,
                                               size_t private_key_len,
                                               const struct rustls_certified_key **certified_key_out);
 
+/**
+ * Like `rustls_certified_key_build`, but on success also writes the number
+ * of certificates parsed from `cert_chain` to `out_cert_count`. A count of
+ * 1 means only the end-entity certificate was provided, which is usually a
+ * mistake if the server's certificate was issued by an intermediate.
+ * `rustls_certified_key_get_cert_count` gives the same count later.
+ */
+enum rustls_result rustls_certified_key_build_counted(const uint8_t *cert_chain,
+                                                      size_t cert_chain_len,
+                                                      const uint8_t *private_key,
+                                                      size_t private_key_len,
+                                                      const struct rustls_certified_key **certified_key_out,
+                                                      size_t *out_cert_count);
+
+/**
+ * Like `rustls_certified_key_build`, but also attaches the DER-encoded OCSP
+ * response in `ocsp_response`, which a server will staple to its
+ * certificate. This avoids building a key and then copying it with
+ * `rustls_certified_key_clone_with_ocsp`. Pass NULL, or a length of 0,
+ * for no OCSP response.
+ */
+enum rustls_result rustls_certified_key_build_with_ocsp(const uint8_t *cert_chain,
+                                                        size_t cert_chain_len,
+                                                        const uint8_t *private_key,
+                                                        size_t private_key_len,
+                                                        const uint8_t *ocsp_response,
+                                                        size_t ocsp_response_len,
+                                                        const struct rustls_certified_key **certified_key_out);
+
+/**
+ * Like `rustls_certified_key_build`, but forgiving about how `cert_chain`
+ * is put together: its certificates may come in any order, and may
+ * include duplicates and self-signed roots. The end-entity certificate is
+ * the one whose public key matches `private_key`, and it is followed by
+ * each certificate's issuer in turn, matched by name. Duplicates and
+ * self-signed certificates are dropped.
+ *
+ * Returns RUSTLS_RESULT_CANNOT_BUILD_CHAIN if no certificate matches the
+ * private key, or if some certificate that isn't self-signed doesn't fit
+ * into the chain. Ownership of the result is the same as for
+ * `rustls_certified_key_build`.
+ */
+enum rustls_result rustls_certified_key_build_auto_order(const uint8_t *cert_chain,
+                                                         size_t cert_chain_len,
+                                                         const uint8_t *private_key,
+                                                         size_t private_key_len,
+                                                         const struct rustls_certified_key **certified_key_out);
+
+/**
+ * Build a `rustls_certified_key` from a certificate chain file and a private
+ * key file, named by the NUL-terminated paths `cert_path` and `key_path`.
+ * The files have the same formats as the buffers passed to
+ * `rustls_certified_key_build`. If `cert_path` contains no certificates,
+ * the chain is read from `key_path` instead, for the common case of a
+ * single file holding both the key and the chain; `cert_path` may then be
+ * the same as `key_path`.
+ *
+ * Returns RUSTLS_RESULT_FILE_READ_ERROR if either file can't be read, for
+ * example because it doesn't exist, and
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR or
+ * RUSTLS_RESULT_PRIVATE_KEY_PARSE_ERROR if a file was read but its
+ * contents can't be parsed. On Unix a path may be any bytes; elsewhere it
+ * must be UTF-8, and RUSTLS_RESULT_INVALID_PARAMETER is returned if it
+ * isn't.
+ * Ownership of the result is the same as for `rustls_certified_key_build`.
+ */
+enum rustls_result rustls_certified_key_build_from_files(const char *cert_path,
+                                                         const char *key_path,
+                                                         const struct rustls_certified_key **certified_key_out);
+
+/**
+ * Build a `rustls_certified_key` from a DER-encoded certificate chain and a
+ * DER-encoded private key.
+ * `cert_chain` must point to a buffer of `cert_chain_len` bytes, containing
+ * one or more concatenated DER-encoded certificates, with the end-entity
+ * (leaf) certificate first.
+ *
+ * `private_key` must point to a buffer of `private_key_len` bytes, containing
+ * a single DER-encoded private key in PKCS#8, PKCS#1 (RSA), or SEC1 (EC)
+ * format. The format is detected from the structure of the key.
+ *
+ * Ownership of the result is the same as for `rustls_certified_key_build`.
+ */
+enum rustls_result rustls_certified_key_build_der(const uint8_t *cert_chain,
+                                                  size_t cert_chain_len,
+                                                  const uint8_t *private_key,
+                                                  size_t private_key_len,
+                                                  const struct rustls_certified_key **certified_key_out);
+
 /**
  * Return the i-th rustls_certificate in the rustls_certified_key. 0 gives the
  * end-entity certificate. 1 and higher give certificates from the chain.
@@ -535,6 +979,52 @@ enum rustls_result rustls_certified_key_build(const uint8_t *cert_chain,
 const struct rustls_certificate *rustls_certified_key_get_certificate(const struct rustls_certified_key *certified_key,
                                                                       size_t i);
 
+/**
+ * Return the number of certificates in the rustls_certified_key: the
+ * end-entity certificate plus any chain certificates. Returns 0 if
+ * `certified_key` is NULL.
+ */
+size_t rustls_certified_key_get_cert_count(const struct rustls_certified_key *certified_key);
+
+/**
+ * Copy the DER-encoded SubjectPublicKeyInfo of the certified key's private
+ * key into `buf`, which can hold up to `len` bytes, and store the number of
+ * bytes written in `out_n`. Comparing this with a certificate's
+ * SubjectPublicKeyInfo shows whether the key belongs to that certificate.
+ *
+ * rustls can't export a public key from a private key, so this is taken
+ * from the end-entity certificate, after checking that the private key
+ * really belongs to it by signing a test message and verifying the
+ * signature with the certificate. Returns RUSTLS_RESULT_INVALID_PARAMETER
+ * if it doesn't, and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the
+ * certificate can't be parsed.
+ *
+ * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if `buf` is too small; in that
+ * case the number of bytes needed is stored in `out_n`.
+ */
+enum rustls_result rustls_certified_key_get_public_key_der(const struct rustls_certified_key *certified_key,
+                                                           uint8_t *buf,
+                                                           size_t len,
+                                                           size_t *out_n);
+
+/**
+ * Check that the certified key is fit to present to servers that request
+ * client authentication, so that mistakes show up when it is loaded rather
+ * than as a rejected handshake. Returns:
+ *
+ * - RUSTLS_RESULT_NO_CERTIFICATES_PRESENTED if it has no certificates.
+ * - RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if one can't be parsed.
+ * - RUSTLS_RESULT_CERT_UNKNOWN_ISSUER if the chain is out of order: each
+ *   certificate must be followed by the one that issued it, starting with
+ *   the end-entity certificate. The root may be left out.
+ * - RUSTLS_RESULT_CERT_REQUIRED_EKU_NOT_FOUND if the end-entity certificate
+ *   has an extended key usage extension that doesn't allow clientAuth.
+ *
+ * The order is checked by comparing issuer and subject names; signatures
+ * are left to the server, which verifies the chain against its roots.
+ */
+enum rustls_result rustls_certified_key_validate_for_client_auth(const struct rustls_certified_key *certified_key);
+
 /**
  * Create a copy of the rustls_certified_key with the given OCSP response data
  * as DER encoded bytes. The OCSP response may be given as NULL to clear any
@@ -546,6 +1036,14 @@ enum rustls_result rustls_certified_key_clone_with_ocsp(const struct rustls_cert
                                                         const struct rustls_slice_bytes *ocsp_response,
                                                         const struct rustls_certified_key **cloned_key_out);
 
+/**
+ * Store a view of the DER-encoded OCSP response attached to the certified
+ * key in `out`, or an empty view if it has none. The view is valid until
+ * the rustls_certified_key is freed.
+ */
+enum rustls_result rustls_certified_key_get_ocsp(const struct rustls_certified_key *certified_key,
+                                                 struct rustls_slice_bytes *out);
+
 /**
  * "Free" a certified_key previously returned from
  * rustls_certified_key_build. Since certified_key is actually an
@@ -556,19 +1054,53 @@ enum rustls_result rustls_certified_key_clone_with_ocsp(const struct rustls_cert
  */
 void rustls_certified_key_free(const struct rustls_certified_key *key);
 
+/**
+ * Parse the PEM in `pem`, calling `cert_cb` with each certificate and
+ * `key_cb` with each private key, in the order they appear, and passing
+ * `userdata` through unchanged. Private keys may be PKCS#8 ("PRIVATE KEY")
+ * or PKCS#1 ("RSA PRIVATE KEY"); sections of any other type are skipped.
+ * Either callback may be NULL, in which case those items are only counted.
+ *
+ * On return, `*cert_count` and `*key_count` hold the number of certificates
+ * and private keys found. Finding none of either is not an error. Returns
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the PEM is malformed; the counts
+ * then cover the items found before the malformed one.
+ *
+ * This only decodes the PEM layer: the DER contents are not checked.
+ */
+enum rustls_result rustls_pem_split(const uint8_t *pem,
+                                    size_t pem_len,
+                                    rustls_pem_item_callback cert_cb,
+                                    rustls_pem_item_callback key_cb,
+                                    void *userdata,
+                                    size_t *cert_count,
+                                    size_t *key_count);
+
 /**
  * Create a rustls_root_cert_store. Caller owns the memory and must
  * eventually call rustls_root_cert_store_free. The store starts out empty.
- * Caller must add root certificates with rustls_root_cert_store_add_pem.
+ * Caller must add root certificates with rustls_root_cert_store_add_pem or
+ * rustls_root_cert_store_add_der_single.
  * https://docs.rs/rustls/0.19.0/rustls/struct.RootCertStore.html#method.empty
  */
 struct rustls_root_cert_store *rustls_root_cert_store_new(void);
 
+/**
+ * Create a rustls_root_cert_store holding the same root certificates as
+ * `store`, for configs that share a base set of roots but add a few of
+ * their own. The new store is independent: adding certificates to either
+ * store doesn't affect the other. Caller owns the memory and must
+ * eventually call rustls_root_cert_store_free. Returns NULL if `store` is
+ * NULL.
+ */
+struct rustls_root_cert_store *rustls_root_cert_store_clone(const struct rustls_root_cert_store *store);
+
 /**
  * Add one or more certificates to the root cert store using PEM encoded data.
  *
  * When `strict` is true an error will return a `CertificateParseError`
  * result. So will an attempt to parse data that has zero certificates.
+ *
  * When `strict` is false, unparseable root certificates will be ignored.
  * This may be useful on systems that have syntactically invalid root
  * certificates.
@@ -578,6 +1110,55 @@ enum rustls_result rustls_root_cert_store_add_pem(struct rustls_root_cert_store
                                                   size_t pem_len,
                                                   bool strict);
 
+/**
+ * Like `rustls_root_cert_store_add_pem`, but also writes the number of
+ * certificates added to `out_parsed`, and the number that couldn't be
+ * parsed to `out_rejected`. The counts are written whenever `pem` could be
+ * read, including when `strict` is true and some certificates were
+ * rejected, so that the caller can log them; the store is then unchanged,
+ * and `out_parsed` counts what would have been added.
+ */
+enum rustls_result rustls_root_cert_store_add_pem_counted(struct rustls_root_cert_store *store,
+                                                          const uint8_t *pem,
+                                                          size_t pem_len,
+                                                          bool strict,
+                                                          size_t *out_parsed,
+                                                          size_t *out_rejected);
+
+/**
+ * Add exactly one DER-encoded certificate to the root cert store. This is
+ * convenient when copying trust anchors one at a time out of some other
+ * store.
+ *
+ * Returns `CertificateParseError`, and leaves the store unchanged, if the
+ * certificate can't be parsed as a trust anchor.
+ */
+enum rustls_result rustls_root_cert_store_add_der_single(struct rustls_root_cert_store *store,
+                                                         const uint8_t *der,
+                                                         size_t der_len);
+
+/**
+ * Verify a certificate chain against the root cert store, as a client
+ * would verify a server's chain for `hostname` at `unix_time` (in seconds
+ * since the Unix epoch), but without a connection. `chain` points to
+ * `chain_len` DER-encoded certificates, starting with the end-entity
+ * certificate and followed by any intermediates. This is meant for tools
+ * that check a certificate bundle offline.
+ *
+ * Returns RUSTLS_RESULT_OK if the chain is valid. Otherwise returns the
+ * same error a handshake would, such as RUSTLS_RESULT_CERT_UNKNOWN_ISSUER,
+ * RUSTLS_RESULT_CERT_EXPIRED or RUSTLS_RESULT_CERT_NOT_VALID_FOR_NAME.
+ * Returns RUSTLS_RESULT_NO_CERTIFICATES_PRESENTED if `chain_len` is 0,
+ * RUSTLS_RESULT_INVALID_DNS_NAME_ERROR if `hostname` is not a valid DNS
+ * name, and RUSTLS_RESULT_INVALID_PARAMETER if `unix_time` is negative.
+ */
+enum rustls_result rustls_root_cert_store_verify_chain(const struct rustls_root_cert_store *store,
+                                                       const struct rustls_slice_bytes *chain,
+                                                       size_t chain_len,
+                                                       const uint8_t *hostname,
+                                                       size_t host_len,
+                                                       int64_t unix_time);
+
 /**
  * "Free" a rustls_root_cert_store previously returned from
  * rustls_root_cert_store_builder_build. Since rustls_root_cert_store is actually an
@@ -633,6 +1214,17 @@ void rustls_client_cert_verifier_optional_free(const struct rustls_client_cert_v
  */
 struct rustls_client_config_builder *rustls_client_config_builder_new(void);
 
+/**
+ * Create a rustls_client_config_builder for a client that verifies server
+ * certificates only with a custom verifier, and so needs no trusted roots.
+ * Building the config fails with RUSTLS_RESULT_NO_VERIFIER_CONFIGURED
+ * unless a verifier has been set with
+ * rustls_client_config_builder_dangerous_set_certificate_verifier, and
+ * not replaced since by another certificate verification option. Caller
+ * owns the memory, as with rustls_client_config_builder_new.
+ */
+struct rustls_client_config_builder *rustls_client_config_builder_new_no_roots(void);
+
 /**
  * Create a rustls_client_config_builder from an existing rustls_client_config. The
  * builder will be used to create a new, separate config that starts with the settings
@@ -642,10 +1234,23 @@ struct rustls_client_config_builder *rustls_client_config_builder_from_config(co
 
 /**
  * Turn a *rustls_client_config_builder (mutable) into a *rustls_client_config
- * (read-only).
+ * (read-only). Returns NULL if the builder was made by
+ * rustls_client_config_builder_new_no_roots and no custom verifier is set;
+ * use rustls_client_config_builder_try_build to get the reason. The
+ * builder is freed either way.
  */
 const struct rustls_client_config *rustls_client_config_builder_build(struct rustls_client_config_builder *builder);
 
+/**
+ * Like rustls_client_config_builder_build, but returns why building
+ * failed, and on success writes the config to `config_out`. Returns
+ * RUSTLS_RESULT_NO_VERIFIER_CONFIGURED if the builder was made by
+ * rustls_client_config_builder_new_no_roots and no custom verifier is set.
+ * The builder is freed either way, unless `config_out` is NULL.
+ */
+enum rustls_result rustls_client_config_builder_try_build(struct rustls_client_config_builder *builder,
+                                                          const struct rustls_client_config **config_out);
+
 /**
  * Set a custom server certificate verifier.
  *
@@ -683,6 +1288,257 @@ const struct rustls_client_config *rustls_client_config_builder_build(struct rus
 void rustls_client_config_builder_dangerous_set_certificate_verifier(struct rustls_client_config_builder *config,
                                                                      rustls_verify_server_cert_callback callback);
 
+/**
+ * DANGER: with `verify` false, accept any certificate chain from the server,
+ * for any hostname, without checking it against the trusted roots. This is
+ * only meant for TLS-inspecting proxies that pass the server's certificates
+ * on to be validated elsewhere, for example with
+ * `rustls_root_cert_store_verify_chain`. Anything else that uses it can be
+ * impersonated by any attacker on the network. rustls still checks that
+ * the server holds the private key for the certificate it presents.
+ *
+ * With `verify` true, the default verifier is restored. Like a custom
+ * verifier set with
+ * `rustls_client_config_builder_dangerous_set_certificate_verifier`, this
+ * replaces the certificate verification options on the builder, such as
+ * `rustls_client_config_builder_set_verification_time`, and is replaced by
+ * them.
+ */
+enum rustls_result rustls_client_config_builder_dangerous_set_verify_server_cert(struct rustls_client_config_builder *builder,
+                                                                                 bool verify);
+
+/**
+ * Verify server certificates as of `unix_seconds` (seconds since the Unix
+ * epoch, UTC) instead of the current system time. This is primarily meant
+ * for tests that need expired or not-yet-valid certificates to behave
+ * deterministically, and for embedded devices without a reliable real-time
+ * clock. It has no effect on a custom verifier installed with
+ * `rustls_client_config_builder_dangerous_set_certificate_verifier`; setting
+ * one replaces this option, and setting this option replaces it.
+ *
+ * Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `unix_seconds` is negative.
+ */
+enum rustls_result rustls_client_config_builder_set_verification_time(struct rustls_client_config_builder *builder,
+                                                                      int64_t unix_seconds);
+
+/**
+ * DANGEROUS: when `accept` is true, accept server certificate chains that
+ * have expired but are otherwise valid: trusted, correctly signed, and
+ * issued for the server's name. Use `rustls_connection_peer_cert_is_expired`
+ * after the handshake to find out whether this happened. This is meant for
+ * monitoring tools that need to report on expired certificates, not for
+ * ordinary connections, where an expired certificate may have been
+ * compromised long ago.
+ *
+ * Like `rustls_client_config_builder_set_verification_time`, this is
+ * replaced by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_dangerous_accept_expired(struct rustls_client_config_builder *builder,
+                                                                         bool accept);
+
+/**
+ * Check the OCSP response that the server staples to its certificate,
+ * according to `policy`. The default, `RUSTLS_OCSP_POLICY_IGNORE`, doesn't
+ * look at it. With `RUSTLS_OCSP_POLICY_SOFT_FAIL` a certificate is rejected
+ * if a usable response says it is revoked. With
+ * `RUSTLS_OCSP_POLICY_HARD_FAIL` it is also rejected if there is no usable
+ * response saying it is good, including when the server staples nothing.
+ * Either way, rustls_connection_process_new_packets returns
+ * `RUSTLS_RESULT_CERTIFICATE_REVOKED` for a rejected certificate.
+ *
+ * A response is usable if it is signed by the certificate's issuer, which
+ * must be among the certificates the server sent (or that an intermediate
+ * fetch callback supplied), and, unless it reports revocation, if it is
+ * current as of the verification time. Responses signed by a delegated
+ * OCSP responder certificate are not supported, so they are never usable.
+ *
+ * The policy is checked only once the certificate chain has otherwise been
+ * verified, and not at all for a server whose key is pinned with
+ * `rustls_client_config_builder_dangerous_set_pinned_spki`. Like
+ * `rustls_client_config_builder_set_verification_time`, this is replaced
+ * by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_set_ocsp_policy(struct rustls_client_config_builder *builder,
+                                                                enum rustls_ocsp_policy policy);
+
+/**
+ * Reject servers whose end-entity certificate has an RSA key of fewer than
+ * `bits` bits, for example to enforce a 2048-bit minimum. Keys of other
+ * types are not affected. rustls_connection_process_new_packets returns
+ * `RUSTLS_RESULT_WEAK_KEY` for a rejected certificate. The default, 0,
+ * accepts any size that webpki does; webpki itself rejects RSA keys
+ * smaller than 2048 bits when they are used to sign the handshake.
+ *
+ * The key size is checked before anything else, including for a server
+ * whose key is pinned with
+ * `rustls_client_config_builder_dangerous_set_pinned_spki`. Like
+ * `rustls_client_config_builder_set_verification_time`, this is replaced
+ * by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_set_min_rsa_key_bits(struct rustls_client_config_builder *builder,
+                                                                     uint32_t bits);
+
+/**
+ * Restrict the signature schemes the client offers to the server and
+ * accepts in the server's handshake signature to the `count` IANA-assigned
+ * values at `schemes`, for example to forbid RSA PKCS#1 v1.5 signatures.
+ * The order of `schemes` doesn't matter: the schemes are offered in
+ * rustls' order of preference. Returns RUSTLS_RESULT_INVALID_PARAMETER if
+ * the list is empty or contains a scheme rustls doesn't support. Use
+ * `rustls_signature_schemes_for_version` to check that the list leaves
+ * something usable for each enabled protocol version.
+ *
+ * Like `rustls_client_config_builder_set_verification_time`, this is
+ * replaced by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_set_signature_schemes(struct rustls_client_config_builder *builder,
+                                                                      const uint16_t *schemes,
+                                                                      size_t count);
+
+/**
+ * Require the server's certificate to have valid SCTs (Signed Certificate
+ * Timestamps) from at least `min_scts` different Certificate Transparency
+ * logs among the `log_count` at `logs`, each given by its DER-encoded
+ * SubjectPublicKeyInfo, as published in CT log lists. Logs must have ECDSA
+ * P-256 or RSA keys. rustls_connection_process_new_packets returns
+ * `RUSTLS_RESULT_CERTIFICATE_TRANSPARENCY_REQUIRED` for a certificate
+ * that doesn't meet the policy. A `min_scts` of 0 removes the policy.
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER if a key can't be used, or if
+ * `min_scts` is more than `log_count`.
+ *
+ * Only SCTs embedded in the certificate count. The version of rustls used
+ * by crustls doesn't pass SCTs sent in the TLS handshake or in a stapled
+ * OCSP response to verifiers. SCTs timestamped after the verification
+ * time don't count.
+ *
+ * The policy is checked only once the certificate chain has otherwise been
+ * verified, and not at all for a server whose key is pinned with
+ * `rustls_client_config_builder_dangerous_set_pinned_spki`. Like
+ * `rustls_client_config_builder_set_verification_time`, this is replaced
+ * by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_set_ct_policy(struct rustls_client_config_builder *builder,
+                                                              const struct rustls_slice_bytes *logs,
+                                                              size_t log_count,
+                                                              uint8_t min_scts);
+
+/**
+ * Require the server's end-entity certificate to assert the certificate
+ * policy `oid` in its certificate policies extension, as enterprise PKIs
+ * use to mark certificates issued under a particular policy. `oid` is the
+ * DER encoding of the OID's value, without tag and length: for example,
+ * 2.23.140.1.2.1 is the 6 bytes `67 81 0c 01 02 01`. Each call adds a
+ * requirement, and a certificate must meet all of them.
+ * rustls_connection_process_new_packets returns
+ * `RUSTLS_RESULT_CERTIFICATE_POLICY_MISSING` for a certificate that
+ * doesn't. Returns RUSTLS_RESULT_INVALID_PARAMETER if `oid` isn't a valid
+ * OID encoding.
+ *
+ * The OID must appear itself: a certificate asserting anyPolicy
+ * (2.5.29.32.0) doesn't meet the requirement, and the policies of
+ * intermediate certificates aren't considered.
+ *
+ * The requirement is checked only once the certificate chain has
+ * otherwise been verified, and not at all for a server whose key is
+ * pinned with `rustls_client_config_builder_dangerous_set_pinned_spki`.
+ * Like `rustls_client_config_builder_set_verification_time`, this is
+ * replaced by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_require_cert_policy_oid(struct rustls_client_config_builder *builder,
+                                                                        const uint8_t *oid,
+                                                                        size_t oid_len);
+
+/**
+ * Add the DER-encoded certificate in `der` to `certs`. The data is copied.
+ * Returns RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR, and adds nothing, if it
+ * can't be parsed as a certificate.
+ */
+enum rustls_result rustls_intermediate_certs_add(struct rustls_intermediate_certs *certs,
+                                                 const uint8_t *der,
+                                                 size_t der_len);
+
+/**
+ * Set a callback to supply intermediate certificates that a server failed
+ * to send, for instance by following the certificate's Authority
+ * Information Access extension (AIA chasing). If the server's chain doesn't
+ * lead to a trusted root because an issuer is missing, `callback` is called
+ * with the last certificate in the chain. Any certificates it adds are
+ * appended to the chain and verification is tried again, asking again for
+ * the next missing issuer, up to four times per verification. A NULL
+ * `callback` removes the callback.
+ *
+ * Fetched certificates are treated exactly like ones the server sent: the
+ * completed chain must still lead to one of the config's trusted roots,
+ * with every signature, validity period and name constraint checked. So a
+ * fetcher can't make an untrusted server trusted, and may fetch over plain
+ * HTTP. It can still be used to make verification slow, so it should use
+ * timeouts and cache results.
+ *
+ * `userdata` is passed to every call of `callback`, for every connection
+ * made with the config. The callback must be safe to call on any thread
+ * at any time, including multiple concurrent calls. It is called from
+ * within `rustls_connection_process_new_packets`, so a slow fetch blocks
+ * that call.
+ *
+ * Like `rustls_client_config_builder_set_verification_time`, this is
+ * replaced by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_set_intermediate_fetch_callback(struct rustls_client_config_builder *builder,
+                                                                                rustls_intermediate_fetch_callback callback,
+                                                                                void *userdata);
+
+/**
+ * DANGER: verify server certificates as usual, but instead of failing the
+ * handshake when a certificate is rejected, call `callback` with the error
+ * that `rustls_connection_process_new_packets` would have returned and
+ * carry on with the handshake. A NULL `callback` removes the callback and
+ * enforces verification again.
+ *
+ * This is for a "report-only" phase when tightening verification, for
+ * instance before requiring SCTs or certificate policies: it counts the
+ * connections that the stricter policy would break, without breaking
+ * them. While it is set, the config doesn't authenticate servers at all,
+ * and any attacker on the network can impersonate one, so only use it for
+ * as long as the measurement needs, and never where the connection's
+ * security matters. rustls still checks that the server holds the private
+ * key for the certificate it presents, and a server still fails if its
+ * handshake signature uses a scheme excluded by
+ * `rustls_client_config_builder_set_signature_schemes`.
+ *
+ * `userdata` is passed to every call of `callback`, for every connection
+ * made with the config. The callback must be safe to call on any thread
+ * at any time, including multiple concurrent calls. It is called from
+ * within `rustls_connection_process_new_packets`.
+ *
+ * Like `rustls_client_config_builder_set_verification_time`, this is
+ * replaced by a custom verifier and vice versa.
+ */
+enum rustls_result rustls_client_config_builder_set_verify_report_callback(struct rustls_client_config_builder *builder,
+                                                                           rustls_verify_report_callback callback,
+                                                                           void *userdata);
+
+/**
+ * DANGEROUS: pin the server's public key. `spki_sha256` must point to the
+ * 32-byte SHA-256 hash of a DER-encoded SubjectPublicKeyInfo, as used by
+ * HPKP (RFC 7469). Call this more than once to pin several keys, such as a
+ * primary and a backup; a server whose end-entity certificate has any of
+ * the pinned keys is accepted.
+ *
+ * Once a key is pinned, the server's certificate chain is not otherwise
+ * checked at all: the trusted roots, the certificate's validity period and
+ * the names it is issued for are all ignored. This is only appropriate when
+ * the server's key is known in advance and its private key is as well
+ * protected as a CA's.
+ *
+ * Like `rustls_client_config_builder_set_verification_time`, this is
+ * replaced by a custom verifier and vice versa.
+ *
+ * Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `len` is not 32.
+ */
+enum rustls_result rustls_client_config_builder_dangerous_set_pinned_spki(struct rustls_client_config_builder *builder,
+                                                                          const uint8_t *spki_sha256,
+                                                                          size_t len);
+
 /**
  * Use the trusted root certificates from the provided store.
  *
@@ -715,6 +1571,30 @@ enum rustls_result rustls_client_config_builder_set_versions(struct rustls_clien
                                                              const uint16_t *tls_versions,
                                                              size_t len);
 
+/**
+ * Set the key exchange groups the client prefers, as `count` IANA-assigned
+ * NamedGroup values at `groups`, most preferred first. rustls supports
+ * X25519 (0x001d), secp384r1 (0x0018) and secp256r1 (0x0017), and by
+ * default prefers them in that order. Returns
+ * RUSTLS_RESULT_INVALID_PARAMETER if the list is empty or contains a group
+ * rustls doesn't support.
+ *
+ * A TLS 1.3 client sends a key share for just one group in its
+ * ClientHello. If the server doesn't accept that group, it sends a
+ * HelloRetryRequest, costing a round trip. The client sends its share for
+ * the first group in this list, unless an earlier connection to the same
+ * server, using the same config, chose another group in the list; then it
+ * guesses that group instead.
+ *
+ * The version of rustls used by crustls always advertises all of its
+ * groups, in its default order, so this doesn't stop the server from
+ * choosing a group that isn't listed, and doesn't affect TLS 1.2, where
+ * the server chooses the group.
+ */
+enum rustls_result rustls_client_config_builder_set_kx_groups(struct rustls_client_config_builder *builder,
+                                                              const uint16_t *groups,
+                                                              size_t count);
+
 /**
  * Set the ALPN protocol list to the given protocols. `protocols` must point
  * to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
@@ -733,12 +1613,93 @@ enum rustls_result rustls_client_config_builder_set_protocols(struct rustls_clie
                                                               size_t len);
 
 /**
- * Enable or disable SNI.
+ * Enable or disable sending SNI (Server Name Indication), which is enabled
+ * by default. When disabled, the ClientHello has no server_name extension.
+ *
+ * This only controls what is sent to the server. The hostname passed to
+ * rustls_client_connection_new is still used to verify the server's
+ * certificate either way, so disabling SNI does not weaken verification,
+ * and a server that picks its certificate based on SNI will have to fall
+ * back to a default one.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html#structfield.enable_sni
  */
 void rustls_client_config_builder_set_enable_sni(struct rustls_client_config_builder *config,
                                                  bool enable);
 
+/**
+ * Enable or disable TLS certificate compression (RFC 8879). When disabled,
+ * no compression algorithms are advertised to the server.
+ *
+ * The version of rustls used by crustls has no support for RFC 8879, so
+ * certificate compression is never negotiated and this call has no
+ * effect. Passing `false` matches that and returns RUSTLS_RESULT_OK;
+ * passing `true` returns RUSTLS_RESULT_UNIMPLEMENTED, so callers aren't
+ * told compression is on. Once crustls moves to a rustls release that
+ * supports compression, `true` will enable it.
+ */
+enum rustls_result rustls_client_config_builder_set_cert_compression(struct rustls_client_config_builder *builder,
+                                                                     bool enabled);
+
+/**
+ * Enable or disable TLS 1.3 middlebox compatibility mode (RFC 8446 appendix
+ * D.4), in which the client sends a legacy session ID and a dummy
+ * ChangeCipherSpec record so the handshake looks more like TLS 1.2 resumption
+ * to middleboxes that would otherwise interfere with it.
+ *
+ * The version of rustls used by crustls always sends the compatibility
+ * messages in TLS 1.3 handshakes and cannot turn them off, so this changes
+ * nothing: compatibility mode is always enabled. Passing `true` returns
+ * RUSTLS_RESULT_OK; passing `false` returns RUSTLS_RESULT_UNSUPPORTED.
+ * Returns RUSTLS_RESULT_NULL_PARAMETER if `builder` is NULL.
+ */
+enum rustls_result rustls_client_config_builder_set_tls13_compat_mode(struct rustls_client_config_builder *builder,
+                                                                      bool enabled);
+
+/**
+ * Set the ECHConfigList to use for Encrypted Client Hello, as published in
+ * the server's DNS HTTPS record (draft-ietf-tls-esni).
+ *
+ * PLACEHOLDER: rustls 0.19 doesn't implement ECH, so this returns
+ * RUSTLS_RESULT_UNIMPLEMENTED for any well-formed `ech_config_list`, and
+ * the ClientHello is sent unencrypted as before. It returns
+ * RUSTLS_RESULT_INVALID_PARAMETER if `ech_config_list` is not a
+ * well-formed ECHConfigList. Don't rely on it for privacy.
+ */
+enum rustls_result rustls_client_config_builder_set_ech_config(struct rustls_client_config_builder *builder,
+                                                               const uint8_t *ech_config_list,
+                                                               size_t len);
+
+/**
+ * Add an external pre-shared key (RFC 8446 section 2.2) for TLS 1.3, to
+ * authenticate both sides without certificates, as some IoT deployments
+ * do. `identity` is the name the server knows the key by, and `key` is the
+ * secret itself. The key is used with cipher suites whose hash is `hash`.
+ * See also rustls_server_config_builder_set_external_psk_resolver.
+ *
+ * PLACEHOLDER: rustls 0.19 only supports pre-shared keys for session
+ * resumption, so this always returns RUSTLS_RESULT_UNIMPLEMENTED and adds
+ * nothing.
+ */
+enum rustls_result rustls_client_config_builder_add_external_psk(struct rustls_client_config_builder *builder,
+                                                                 struct rustls_slice_bytes identity,
+                                                                 struct rustls_slice_bytes key,
+                                                                 enum rustls_hash_algorithm hash);
+
+/**
+ * Set the largest plaintext fragment that will be put in one outgoing TLS
+ * record, for example to keep records within a path MTU. Larger writes are
+ * split across several records. `max_fragment_size` must be between 32 and
+ * 16384 (the TLS maximum and the default), or 0 to restore the default.
+ * This only affects what is sent; it does not negotiate the max_fragment_length
+ * extension with the peer.
+ *
+ * Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `max_fragment_size` is out
+ * of range.
+ * https://docs.rs/rustls/0.19.0/rustls/struct.ClientConfig.html#structfield.mtu
+ */
+enum rustls_result rustls_client_config_builder_set_max_fragment_size(struct rustls_client_config_builder *builder,
+                                                                      size_t max_fragment_size);
+
 /**
  * Set the cipher suite list, in preference order. The `ciphersuites`
  * parameter must point to an array containing `len` pointers to
@@ -768,6 +1729,47 @@ enum rustls_result rustls_client_config_builder_set_certified_key(struct rustls_
                                                                   const struct rustls_certified_key *const *certified_keys,
                                                                   size_t certified_keys_len);
 
+/**
+ * Add a client identity, a certificate chain and private key, for the
+ * server to choose among. Call this once for each identity. When a server
+ * asks for a client certificate, crustls sends the first identity, in the
+ * order they were added, that:
+ *
+ * - has a key that can make a signature with one of the schemes the
+ *   server accepts, and
+ * - has a certificate in its chain issued by one of the certificate
+ *   authorities the server lists as acceptable, if it lists any.
+ *
+ * If the server lists no authorities, the first identity with a usable key
+ * is sent. If no identity qualifies, no certificate is sent, and it is up
+ * to the server whether to continue without one.
+ *
+ * A chain should include the intermediates up to the root, or at least
+ * one certificate issued by the authority the server will name. The built
+ * configuration keeps a reference to each certified key, as with
+ * `rustls_client_config_builder_set_certified_key`. The identities replace
+ * any keys set with `rustls_client_config_builder_set_certified_key`, and
+ * vice versa.
+ */
+enum rustls_result rustls_client_config_builder_add_client_identity(struct rustls_client_config_builder *builder,
+                                                                    const struct rustls_certified_key *key);
+
+/**
+ * Return true if `conn` is a client connection that has completed its
+ * handshake, and sent the server a certificate chain during it. In TLS
+ * 1.3 the client's handshake completes with its certificate still queued
+ * for `rustls_connection_write_tls`, and this already counts it. This is
+ * for a client to confirm that its client certificate configuration took
+ * effect: a server that doesn't ask for a certificate isn't sent one, and
+ * neither is one whose acceptable certificate authorities or signature
+ * schemes none of the client's certificates suit.
+ *
+ * A resumed session doesn't send a certificate, so this is false for it,
+ * even if the original handshake sent one. Returns false for server
+ * connections, while handshaking, and if `conn` is NULL.
+ */
+bool rustls_connection_client_cert_was_sent(const struct rustls_connection *conn);
+
 /**
  * "Free" a client_config_builder before transmogrifying it into a client_config.
  * Normally builders are consumed to client_configs via `rustls_client_config_builder_build`
@@ -787,6 +1789,45 @@ void rustls_client_config_builder_free(struct rustls_client_config_builder *conf
  */
 void rustls_client_config_free(const struct rustls_client_config *config);
 
+/**
+ * Serialize the protocol versions, cipher suites, ALPN protocols and
+ * trusted roots of `config` into `buf`, which can hold up to `len` bytes,
+ * and store the number of bytes written in `out_n`. Load them with
+ * rustls_client_config_deserialize, for example in a later process, to
+ * avoid parsing the root certificates again.
+ *
+ * No other settings are saved. In particular, client certificates and
+ * their private keys, session tickets, callbacks and certificate
+ * verification options are not, so the output contains nothing secret.
+ *
+ * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if `buf` is
+ * too small; in that case the number of bytes needed is stored in `out_n`.
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER if an ALPN protocol name is
+ * longer than 255 bytes or a root certificate's subject is longer than
+ * 65535 bytes.
+ */
+enum rustls_result rustls_client_config_serialize(const struct rustls_client_config *config,
+                                                  uint8_t *buf,
+                                                  size_t len,
+                                                  size_t *out_n);
+
+/**
+ * Create a client config from the output of rustls_client_config_serialize,
+ * and on success write it to `config_out`. The config has the serialized
+ * protocol versions, cipher suites, ALPN protocols and trusted roots, and
+ * the defaults of rustls_client_config_builder_new for everything else. To
+ * change other settings, pass it to
+ * rustls_client_config_builder_from_config. The caller owns the config and
+ * must free it with rustls_client_config_free.
+ *
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER if `buf` isn't a serialized
+ * config, for example because it is truncated or was made by an
+ * incompatible version of crustls.
+ */
+enum rustls_result rustls_client_config_deserialize(const uint8_t *buf,
+                                                    size_t len,
+                                                    const struct rustls_client_config **config_out);
+
 /**
  * Create a new rustls_connection containing a client connection and return it
  * in the output parameter `out`. If this returns an error code, the memory
@@ -794,6 +1835,11 @@ void rustls_client_config_free(const struct rustls_client_config *config);
  * If this returns a non-error, the memory pointed to by `conn_out` is modified to point
  * at a valid rustls_connection. The caller now owns the rustls_connection and must call
  * `rustls_client_connection_free` when done with it.
+ *
+ * `hostname` must be a DNS name. The version of webpki used by crustls
+ * can't verify certificates issued for IP addresses, so an IP address
+ * literal such as "192.0.2.1" is rejected with
+ * RUSTLS_RESULT_INVALID_DNS_NAME_ERROR rather than used as a hostname.
  */
 enum rustls_result rustls_client_connection_new(const struct rustls_client_config *config,
                                                 const char *hostname,
@@ -813,6 +1859,25 @@ enum rustls_result rustls_client_config_builder_set_persistence(struct rustls_cl
                                                                 rustls_session_store_get_callback get_cb,
                                                                 rustls_session_store_put_callback put_cb);
 
+/**
+ * Make connections created from `config` from now on perform full
+ * handshakes with every server, rather than resuming sessions stored
+ * before this call. Connections that already exist aren't affected; see
+ * `rustls_connection_forget_resumption`. Sessions stored afterwards are
+ * used as usual.
+ *
+ * Resumption state lives in the config's session store, shared by every
+ * connection created from it, which is either an in-memory cache or the
+ * callbacks set with `rustls_client_config_builder_set_persistence`. The
+ * cache's copies are erased when they are next looked up. The callbacks
+ * can't be reached outside a connection, so the sessions the application
+ * stores are only ignored by this config, and the application should
+ * delete them itself. A config
+ * built from a builder made by `rustls_client_config_builder_from_config`
+ * clears the sessions it shares with the original config, for both configs.
+ */
+enum rustls_result rustls_client_config_clear_session_cache(const struct rustls_client_config *config);
+
 /**
  * Set the userdata pointer associated with this connection. This will be passed
  * to any callbacks invoked by the connection, if you've set up callbacks in the config.
@@ -827,6 +1892,22 @@ void rustls_connection_set_userdata(struct rustls_connection *conn, void *userda
  */
 void rustls_connection_set_log_callback(struct rustls_connection *conn, rustls_log_callback cb);
 
+/**
+ * Set a callback to be invoked from `rustls_connection_process_new_packets`
+ * whenever this client connection receives new resumption state from the
+ * server: each NewSessionTicket in TLS 1.3, which may arrive at any time
+ * after the handshake, or the end of a TLS 1.2 handshake. By the time it is
+ * called, the state has been handed to the config's session store, such as
+ * the callbacks set with `rustls_client_config_builder_set_persistence`.
+ *
+ * The callback is invoked with the userdata parameter previously set by
+ * rustls_connection_set_userdata, or NULL if no userdata was set. It is
+ * never invoked for server connections, or for configs not built by
+ * `rustls_client_config_builder_build`.
+ */
+void rustls_connection_set_new_ticket_callback(struct rustls_connection *conn,
+                                               rustls_new_ticket_callback cb);
+
 /**
  * Read some TLS bytes from the network into internal buffers. The actual network
  * I/O is performed by `callback`, which you provide. Rustls will invoke your
@@ -837,6 +1918,14 @@ void rustls_connection_set_log_callback(struct rustls_connection *conn, rustls_l
  * `rustls_connection_set_userdata`.
  * Returns 0 for success, or an errno value on error. Passes through return values
  * from callback. See rustls_read_callback for more details.
+ *
+ * A loop around this must stop when `*out_n` is 0. That means the callback
+ * reported EOF, after which `rustls_connection_process_new_packets` returns
+ * RUSTLS_RESULT_UNEXPECTED_EOF once the plaintext received before it has
+ * been read, unless the peer sent close_notify. It also happens without
+ * calling the callback once rustls has buffered as much of a malformed
+ * record as it will, which `rustls_connection_process_new_packets` reports
+ * as an error.
  * https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.read_tls
  */
 rustls_io_result rustls_connection_read_tls(struct rustls_connection *conn,
@@ -844,6 +1933,44 @@ rustls_io_result rustls_connection_read_tls(struct rustls_connection *conn,
                                             void *userdata,
                                             size_t *out_n);
 
+/**
+ * Like `rustls_connection_read_tls`, but on success also stores in
+ * `out_needs_more` whether rustls wants to read more TLS bytes, as
+ * `rustls_connection_wants_read` would report straight afterwards. This
+ * saves a call in event loops that decide after each read whether to keep
+ * reading, such as edge-triggered epoll loops.
+ *
+ * rustls stops wanting to read while it holds plaintext that hasn't been
+ * read with `rustls_connection_read`. The bytes just read are not
+ * decrypted until `rustls_connection_process_new_packets`, so they don't
+ * count yet.
+ */
+rustls_io_result rustls_connection_read_tls2(struct rustls_connection *conn,
+                                             rustls_read_callback callback,
+                                             void *userdata,
+                                             size_t *out_n,
+                                             bool *out_needs_more);
+
+/**
+ * Feed TLS bytes from `buf`, which holds `len` bytes, to the connection,
+ * taking at most one TLS record, for transports that carry one record per
+ * datagram. Store the number of bytes taken in `out_n`, and in
+ * `out_complete` whether they completed a record. Bytes after the end of
+ * the record are not taken; pass them in a later call. If `buf` ends
+ * partway through a record, all of it is taken and `out_complete` is
+ * false; the partial record is kept until the rest arrives in later calls.
+ * Call `rustls_connection_process_new_packets` after each complete record.
+ *
+ * This only frames records over datagrams. It is not DTLS, which rustls
+ * doesn't implement: the datagrams must arrive reliably and in order, as
+ * over a stream, and lost or reordered datagrams break the connection.
+ */
+rustls_io_result rustls_connection_read_tls_record(struct rustls_connection *conn,
+                                                   const uint8_t *buf,
+                                                   size_t len,
+                                                   size_t *out_n,
+                                                   bool *out_complete);
+
 /**
  * Write some TLS bytes to the network. The actual network I/O is performed by
  * `callback`, which you provide. Rustls will invoke your callback with a
@@ -861,6 +1988,24 @@ rustls_io_result rustls_connection_write_tls(struct rustls_connection *conn,
                                              void *userdata,
                                              size_t *out_n);
 
+/**
+ * Like `rustls_connection_write_tls`, but keeps calling `callback` until
+ * `max_bytes` have been written or nothing is left to write, and never
+ * hands it more than the rest of that budget. A server multiplexing many
+ * connections on one thread can use this to flush a large backlog in
+ * fair-sized slices, rather than all at once or one record at a time.
+ *
+ * Stores the total number of bytes written in `out_n`. Stops early if
+ * `callback` writes nothing. If `callback` returns an error after some
+ * bytes have been written, returns 0 with those bytes in `out_n`; the
+ * error will come back from the next call.
+ */
+rustls_io_result rustls_connection_write_tls_limited(struct rustls_connection *conn,
+                                                     rustls_write_callback callback,
+                                                     void *userdata,
+                                                     size_t max_bytes,
+                                                     size_t *out_n);
+
 /**
  * Write all available TLS bytes to the network. The actual network I/O is performed by
  * `callback`, which you provide. Rustls will invoke your callback with an array
@@ -878,14 +2023,84 @@ rustls_io_result rustls_connection_write_tls_vectored(struct rustls_connection *
                                                       void *userdata,
                                                       size_t *out_n);
 
+/**
+ * Process all TLS records passed in by `rustls_connection_read_tls` since
+ * the last call, decrypting application data and advancing the handshake.
+ * Callbacks set on the connection, such as the log callback, are invoked
+ * from here and receive the connection's userdata.
+ *
+ * rustls can't stop partway through its buffered records, so the work
+ * done by one call is bounded by the amount of TLS data read since the
+ * previous call, not by anything the peer controls. A single
+ * `rustls_connection_read_tls` call reads at most 18437 bytes (one
+ * maximum-size record). To keep each call short under load, alternate
+ * `rustls_connection_read_tls` and this function rather than reading
+ * everything available first, and have the read callback return fewer
+ * bytes if a smaller budget is needed.
+ *
+ * Once the read callback has reported the end of the stream, this returns
+ * RUSTLS_RESULT_UNEXPECTED_EOF if the peer didn't send close_notify first,
+ * since the data may have been truncated by an attacker. The error waits
+ * until all plaintext received before the EOF has been read, so that none
+ * of it is lost.
+ * https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.process_new_packets
+ */
 enum rustls_result rustls_connection_process_new_packets(struct rustls_connection *conn);
 
+/**
+ * Returns true if decrypted plaintext is waiting to be read, so that
+ * `rustls_connection_read` would return at least one byte. Returns false
+ * if a read would return 0 bytes (or, after the peer's close_notify, the
+ * close_notify result), in which case more TLS data has to be read and
+ * processed first.
+ *
+ * This is the opposite of `rustls_connection_wants_read`, which is true
+ * exactly when no plaintext is buffered.
+ */
+bool rustls_connection_read_has_more(const struct rustls_connection *conn);
+
 bool rustls_connection_wants_read(const struct rustls_connection *conn);
 
 bool rustls_connection_wants_write(const struct rustls_connection *conn);
 
 bool rustls_connection_is_handshaking(const struct rustls_connection *conn);
 
+/**
+ * Return whether `conn` is the client or the server side of its session,
+ * for code shared between both that needs to call side-specific functions
+ * such as `rustls_server_connection_get_sni_hostname`. Returns
+ * RUSTLS_CONNECTION_SIDE_UNKNOWN if `conn` is NULL.
+ */
+enum rustls_connection_side rustls_connection_get_side(const struct rustls_connection *conn);
+
+/**
+ * Store the results of `rustls_connection_wants_read`,
+ * `rustls_connection_wants_write` and `rustls_connection_is_handshaking`
+ * in `out_wants_read`, `out_wants_write` and `out_is_handshaking`, for
+ * event loops that check all three on every iteration.
+ */
+enum rustls_result rustls_connection_get_io_state(const struct rustls_connection *conn,
+                                                  bool *out_wants_read,
+                                                  bool *out_wants_write,
+                                                  bool *out_is_handshaking);
+
+/**
+ * Returns true once no more application data can flow on the connection:
+ * either `rustls_connection_read` has returned
+ * `RUSTLS_RESULT_ALERT_CLOSE_NOTIFY`, meaning the peer closed its side and
+ * all plaintext before the alert has been read, or this side has queued a
+ * close_notify (with `rustls_connection_send_close_notify`,
+ * `rustls_connection_send_alert` or `rustls_connection_close`) and it has
+ * been written out, so `rustls_connection_wants_write` is false.
+ *
+ * This is the terminal condition for an event loop, unlike
+ * `rustls_connection_is_handshaking`, which only says whether the handshake
+ * is still in progress and is false both before close and after it.
+ * Connections that fail with an error are not reported as closed; the
+ * error itself is the terminal condition there.
+ */
+bool rustls_connection_is_closed(const struct rustls_connection *conn);
+
 /**
  * Sets a limit on the internal buffers used to buffer unsent plaintext (prior
  * to completing the TLS handshake) and unsent TLS records. By default, there
@@ -895,21 +2110,302 @@ bool rustls_connection_is_handshaking(const struct rustls_connection *conn);
  */
 void rustls_connection_set_buffer_limit(struct rustls_connection *conn, size_t n);
 
+/**
+ * Reserve room for `bytes` of received plaintext in the buffer that
+ * `rustls_connection_process_new_packets` moves it into. Unlike
+ * `rustls_connection_set_buffer_limit`, which caps buffering, this only
+ * grows the buffer ahead of time, so that receiving a message of up to
+ * `bytes` that is read only once it has all arrived doesn't reallocate it
+ * as it fills. The buffer is never shrunk, so the memory stays allocated
+ * until the connection is freed, including across
+ * `rustls_connection_reset`; a hint smaller than the current capacity does
+ * nothing.
+ *
+ * Plaintext is only moved out of rustls for connections that use this
+ * function or `rustls_connection_read_until_boundary`; calling either
+ * turns it on for the connection. rustls 0.19 decrypts each record into a
+ * buffer of its own, which this doesn't affect. Moving the plaintext
+ * copies it once more, and in measurements that cost outweighs the
+ * reallocations the hint saves, so use the hint to allocate memory up
+ * front rather than to make reads faster.
+ */
+void rustls_connection_set_read_buffer_hint(struct rustls_connection *conn, size_t bytes);
+
+/**
+ * Enable or disable write coalescing, which is off by default. While it is
+ * on, `rustls_connection_write` holds plaintext back until it has a full
+ * record's worth, so that many small writes are sent as a few large
+ * records. That improves throughput and hides the boundaries between
+ * writes from observers, at the cost of latency: a partial record is only
+ * sent once `rustls_connection_flush_plaintext` is called, so callers must
+ * flush after writing each complete message. Held-back plaintext counts
+ * against the limit set with `rustls_connection_set_buffer_limit`, but not
+ * towards `rustls_connection_wants_write`.
+ *
+ * Disabling coalescing flushes whatever was held back.
+ * `rustls_connection_send_close_notify` also flushes it first, so that it
+ * is sent before the close_notify.
+ */
+void rustls_connection_set_write_coalescing(struct rustls_connection *conn, bool enabled);
+
+/**
+ * Hand any plaintext held back by write coalescing (see
+ * `rustls_connection_set_write_coalescing`) to rustls, to be encrypted
+ * and sent by `rustls_connection_write_tls`. Returns
+ * RUSTLS_RESULT_WRITE_WOULD_BLOCK if the limit set with
+ * `rustls_connection_set_buffer_limit` stopped some of it: write out TLS
+ * bytes with `rustls_connection_write_tls`, then flush again.
+ */
+enum rustls_result rustls_connection_flush_plaintext(struct rustls_connection *conn);
+
+/**
+ * Require the handshake to complete by `unix_millis`, in milliseconds
+ * since the Unix epoch, UTC. If `rustls_connection_process_new_packets` is
+ * called after that while the connection is still handshaking, it returns
+ * RUSTLS_RESULT_HANDSHAKE_TIMEOUT without processing anything, and keeps
+ * returning it; the caller should then close the connection. This lets an
+ * event loop enforce handshake timeouts without a timer per connection,
+ * although a connection that never receives anything still needs to be
+ * checked some other way. A value of 0, the default, removes the deadline.
+ *
+ * The deadline is converted to a time on the monotonic clock when it is
+ * set, so later changes to the system clock, in either direction, don't
+ * make it expire early or late. A deadline that has already passed
+ * expires at the next call.
+ */
+void rustls_connection_set_handshake_deadline(struct rustls_connection *conn, uint64_t unix_millis);
+
+/**
+ * Cap the number of plaintext bytes a single `rustls_connection_read` will
+ * return, even if more are buffered. An event loop serving many connections
+ * can use this to stop one busy connection from starving the others; the
+ * rest of the plaintext is returned by later calls. A value of 0, the
+ * default, means no cap.
+ */
+void rustls_connection_set_max_plaintext_read(struct rustls_connection *conn, size_t n);
+
+/**
+ * Return the size in bytes of the largest TLS record, including its 5-byte
+ * header, that `rustls_connection_write_tls` will emit from now on. This is
+ * the config's max fragment size (by default 16384, the TLS maximum) plus
+ * the encryption overhead of the negotiated cipher suite, or of the most
+ * expensive one rustls supports if none has been negotiated yet. Large
+ * writes are split across several records to stay within it.
+ */
+size_t rustls_connection_max_output_record_size(const struct rustls_connection *conn);
+
+/**
+ * Return how many plaintext bytes `rustls_connection_write` can currently
+ * accept before hitting the limit set with
+ * `rustls_connection_set_buffer_limit`. If no limit is set, returns SIZE_MAX.
+ *
+ * The value is an estimate: it doesn't include TLS record overhead or
+ * handshake messages queued by rustls itself, so a subsequent write may
+ * accept somewhat fewer bytes than reported. Calling
+ * `rustls_connection_write_tls` frees up capacity once the handshake is
+ * complete. Returns 0 if `conn` is NULL.
+ */
+size_t rustls_connection_write_capacity(const struct rustls_connection *conn);
+
 /**
  * Queues a close_notify fatal alert to be sent in the next write_tls call.
+ * This performs no I/O itself: the caller is responsible for flushing the
+ * alert with `rustls_connection_write_tls` (or `rustls_connection_close`)
+ * before calling `rustls_connection_free`, which discards anything still
+ * queued.
  * https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.send_close_notify
  */
 void rustls_connection_send_close_notify(struct rustls_connection *conn);
 
+/**
+ * Queue a fatal alert with the given AlertDescription value, from
+ * https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-6,
+ * to be sent by the next `rustls_connection_write_tls` call.
+ *
+ * Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `alert_description` isn't a
+ * known alert. The version of rustls used by crustls can only send
+ * close_notify (0) on request; any other known alert returns
+ * `RUSTLS_RESULT_UNIMPLEMENTED` without queueing anything, and the caller
+ * should close the connection with `rustls_connection_send_close_notify`
+ * instead.
+ */
+enum rustls_result rustls_connection_send_alert(struct rustls_connection *conn,
+                                                uint8_t alert_description);
+
+/**
+ * If a fatal alert is queued to be sent by `rustls_connection_write_tls`
+ * because `rustls_connection_process_new_packets` failed, write its
+ * AlertDescription value to `out_description` and return true. This lets
+ * the caller log what it is about to tell the peer, such as
+ * bad_certificate (42), before writing it. Returns false once the alert
+ * has been written, and if `conn` or `out_description` is NULL.
+ *
+ * rustls doesn't say which alert it queued, so crustls works it out from
+ * the error. This covers the errors that determine the alert: an
+ * unexpected message, an oversized record, and a rejected peer
+ * certificate, except that a server can't tell a rejected client
+ * certificate from a bad client signature unless the rejection was for
+ * the minimum RSA key size. For other errors this returns false even
+ * though an alert may be queued.
+ */
+bool rustls_connection_get_pending_alert(const struct rustls_connection *conn,
+                                         uint8_t *out_description);
+
+/**
+ * Queue a close_notify alert and write all pending TLS bytes, including the
+ * alert, using `callback`. `callback` and `userdata` behave as in
+ * `rustls_connection_write_tls`, except that `callback` may be invoked
+ * several times. Returns 0 once nothing is left to write. If `callback`
+ * returns an error, that error is returned and any remaining bytes stay
+ * queued, so the caller may finish the job with `rustls_connection_write_tls`
+ * (for instance after EAGAIN). If `callback` reports writing zero bytes,
+ * returns EIO. Does not free the connection.
+ */
+rustls_io_result rustls_connection_close(struct rustls_connection *conn,
+                                         rustls_write_callback callback,
+                                         void *userdata);
+
+/**
+ * Returns true if the peer's certificate chain was accepted despite having
+ * expired, which can only happen on a client connection whose config was
+ * built with `rustls_client_config_builder_dangerous_accept_expired`.
+ * Returns false until the peer's certificate has been verified.
+ */
+bool rustls_connection_peer_cert_is_expired(const struct rustls_connection *conn);
+
+/**
+ * Call `callback` with each signature scheme the peer advertised, in the
+ * peer's order of preference, passing `userdata` through unchanged.
+ *
+ * On a server connection these are the schemes from the client's
+ * ClientHello. On a client connection they are the schemes from the
+ * server's CertificateRequest, so they are only available if the server
+ * asked for a client certificate, and only if the config's client
+ * certificate resolver is one installed by crustls. For TLS 1.3, rustls
+ * filters out schemes that can't be used with that version.
+ *
+ * Before the relevant handshake message has been processed, `callback`
+ * is not called at all.
+ */
+enum rustls_result rustls_connection_get_peer_signature_schemes(const struct rustls_connection *conn,
+                                                                rustls_signature_scheme_callback callback,
+                                                                void *userdata);
+
+/**
+ * Return the number of certificates provided by the peer: the end entity
+ * certificate and the rest of its chain. Returns 0 before the peer has
+ * sent its certificates, and if `conn` is NULL.
+ */
+size_t rustls_connection_get_peer_cert_count(const struct rustls_connection *conn);
+
 /**
  * Return the i-th certificate provided by the peer.
  * Index 0 is the end entity certificate. Higher indexes are certificates
  * in the chain. Requesting an index higher than what is available returns
  * NULL.
+ *
+ * The chain is copied out of rustls the first time any of the peer
+ * certificate functions finds it, and kept: the returned pointer is the
+ * same on every call, and remains valid until the connection is freed or
+ * reset with `rustls_connection_reset`.
  */
 const struct rustls_certificate *rustls_connection_get_peer_certificate(struct rustls_connection *conn,
                                                                         size_t i);
 
+/**
+ * Point `out` at the DER encoding of the i-th certificate provided by the
+ * peer, as numbered by `rustls_connection_get_peer_certificate`. This is
+ * the same as calling that function and then `rustls_certificate_get_der`.
+ * If there is no i-th certificate, including before the peer has sent its
+ * certificates, `out` is set to an empty slice and RUSTLS_RESULT_OK is
+ * returned.
+ *
+ * The slice borrows from the connection: it remains valid until the
+ * connection is freed or reset with `rustls_connection_reset`, and must
+ * not be used after that.
+ */
+enum rustls_result rustls_connection_get_peer_certificate_der(struct rustls_connection *conn,
+                                                              size_t i,
+                                                              struct rustls_slice_bytes *out);
+
+/**
+ * Copy the DER encoding of every certificate provided by the peer into
+ * `buf`, which can hold up to `len` bytes, and store the number of bytes
+ * written in `out_n`.
+ *
+ * The output uses the same framing as the `certificate_list` of a TLS 1.2
+ * Certificate message (RFC 5246, section 7.4.2): each certificate is
+ * preceded by its length as a 3-byte big-endian integer, and the
+ * certificates follow one another with no padding. The end entity
+ * certificate comes first, followed by the rest of the chain in the order
+ * the peer sent it. There is no overall length prefix; the total length
+ * is `*out_n`.
+ *
+ * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if `buf` is too small; in that
+ * case, and as an exception to the usual convention, the number of bytes
+ * needed is stored in `out_n` so the caller can retry with a larger buffer.
+ * Returns Ok with *out_n == 0 if the peer has not provided any certificates
+ * (yet).
+ */
+enum rustls_result rustls_connection_get_peer_certs_der_concat(const struct rustls_connection *conn,
+                                                               uint8_t *buf,
+                                                               size_t len,
+                                                               size_t *out_n);
+
+/**
+ * Write the SHA-256 hash of the SubjectPublicKeyInfo of the peer's end
+ * entity certificate to `buf`, which can hold up to `len` bytes, and store
+ * the number of bytes written (always 32) in `out_n`. This is the value
+ * that `rustls_client_config_builder_dangerous_set_pinned_spki` compares
+ * against, and the one usually published as an SPKI pin (RFC 7469), before
+ * base64 encoding.
+ *
+ * Returns RUSTLS_RESULT_NO_CERTIFICATES_PRESENTED if the peer has not
+ * provided a certificate (yet), RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if
+ * the SubjectPublicKeyInfo can't be found in it, and
+ * RUSTLS_RESULT_INSUFFICIENT_SIZE if `len` is less than 32.
+ */
+enum rustls_result rustls_connection_get_peer_cert_spki_sha256(const struct rustls_connection *conn,
+                                                               uint8_t *buf,
+                                                               size_t len,
+                                                               size_t *out_n);
+
+/**
+ * Set the ALPN protocols for this connection, replacing the list from its
+ * config. `protocols` and `len` are as for
+ * `rustls_client_config_builder_set_protocols`, and the data is copied.
+ * A client offers these protocols instead of the config's; a server picks
+ * from these instead. An empty list disables ALPN for this connection.
+ *
+ * rustls only reads ALPN protocols from the config, and a client session
+ * builds its ClientHello as soon as it is created. So crustls implements
+ * this by recreating the connection's session from a copy of the config
+ * with the new list. Everything else about the config is kept, including
+ * any session storage, so resumption still works. This must be called
+ * before any TLS data is read or written and before any plaintext is
+ * written; otherwise it returns RUSTLS_RESULT_INVALID_PARAMETER and
+ * changes nothing.
+ */
+enum rustls_result rustls_connection_set_alpn_protocols(struct rustls_connection *conn,
+                                                        const struct rustls_slice_bytes *protocols,
+                                                        size_t len);
+
+/**
+ * Make a client connection perform a full handshake, rather than resume a
+ * session stored in its config, while leaving the config's stored sessions
+ * for other connections to use. The session this connection establishes
+ * is stored as usual. To stop every connection from a config resuming, see
+ * `rustls_client_config_clear_session_cache`.
+ *
+ * A client session looks up the session to resume as soon as it is
+ * created, so like `rustls_connection_set_alpn_protocols` this recreates
+ * it, and must be called before any TLS data is read or written and before
+ * any plaintext is written. It is kept across `rustls_connection_reset`.
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER, changing nothing, if called too
+ * late or on a server connection.
+ */
+enum rustls_result rustls_connection_forget_resumption(struct rustls_connection *conn);
+
 /**
  * Get the ALPN protocol that was negotiated, if any. Stores a pointer to a
  * borrowed buffer of bytes, and that buffer's len, in the output parameters.
@@ -923,10 +2419,43 @@ void rustls_connection_get_alpn_protocol(const struct rustls_connection *conn,
                                          const uint8_t **protocol_out,
                                          size_t *protocol_out_len);
 
+/**
+ * Get the ALPN protocol that was negotiated, or `default_protocol` if none
+ * was. This is for applications, such as HTTP servers, that fall back to a
+ * fixed protocol like "http/1.1" when the peer doesn't use ALPN.
+ *
+ * Stores a borrowed slice in `out`. Where it is borrowed from depends on
+ * the outcome:
+ *
+ * - If a protocol was negotiated, the slice points into the connection and
+ *   lives as long as the connection.
+ * - Otherwise, including while the connection is still handshaking, the
+ *   slice is `default_protocol` and `default_protocol_len` exactly as
+ *   passed in. That memory belongs to the caller, who must keep it alive
+ *   for as long as they use `out`.
+ *
+ * `default_protocol` may be NULL if `default_protocol_len` is 0, in which
+ * case `out` is NULL and 0 when no protocol was negotiated, as with
+ * rustls_connection_get_alpn_protocol.
+ */
+void rustls_connection_get_alpn_protocol_or(const struct rustls_connection *conn,
+                                            const uint8_t *default_protocol,
+                                            size_t default_protocol_len,
+                                            struct rustls_slice_bytes *out);
+
 /**
  * Return the TLS protocol version that has been negotiated. Before this
  * has been decided during the handshake, this will return 0. Otherwise,
  * the u16 version number as defined in the relevant RFC is returned.
+ *
+ * The version is known well before the handshake completes. A server
+ * decides it while processing the ClientHello, so it is non-zero after the
+ * `rustls_connection_process_new_packets` call that handles the
+ * ClientHello, before the server has sent anything. A client learns it
+ * from the ServerHello (or a TLS 1.3 HelloRetryRequest), so it is non-zero
+ * after the call that processes that message, while the rest of the
+ * server's first flight may still be outstanding. It does not change once
+ * set.
  * https://docs.rs/rustls/0.19.1/rustls/trait.Session.html#tymethod.get_protocol_version
  * https://docs.rs/rustls/0.19.1/rustls/internal/msgs/enums/enum.ProtocolVersion.html
  */
@@ -935,16 +2464,130 @@ uint16_t rustls_connection_get_protocol_version(const struct rustls_connection *
 /**
  * Retrieves the cipher suite agreed with the peer.
  * This returns NULL until the ciphersuite is agreed.
+ * If you only need the suite's u16 value, use
+ * `rustls_connection_get_negotiated_ciphersuite_id`.
  * https://docs.rs/rustls/0.19.0/rustls/trait.Session.html#tymethod.get_negotiated_ciphersuite
  */
 const struct rustls_supported_ciphersuite *rustls_connection_get_negotiated_ciphersuite(const struct rustls_connection *conn);
 
+/**
+ * Return the IANA-assigned u16 value of the cipher suite agreed with the
+ * peer, or 0 until the cipher suite is agreed. This is the same as calling
+ * `rustls_supported_ciphersuite_get_suite` on the result of
+ * `rustls_connection_get_negotiated_ciphersuite`, without the NULL check.
+ * https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-4
+ */
+uint16_t rustls_connection_get_negotiated_ciphersuite_id(const struct rustls_connection *conn);
+
+/**
+ * Fill in `out` with what the connection has negotiated so far, in a single
+ * call. See `rustls_handshake_info` for the meaning of each field.
+ */
+enum rustls_result rustls_connection_get_handshake_info(const struct rustls_connection *conn,
+                                                        struct rustls_handshake_info *out);
+
+/**
+ * Write the name of the negotiated key exchange group, such as "X25519"
+ * or "secp256r1", into `buf`, which can hold up to `len` bytes, and store
+ * the number of bytes written in `out_n`. The name is UTF-8 and is not
+ * NUL-terminated. A group crustls doesn't know is written as "Unknown("
+ * followed by its value in hex and ")", such as "Unknown(0x1234)". Until
+ * the group is known, and for resumed TLS 1.2 sessions, which don't perform
+ * a key exchange, nothing is written and `out_n` is 0. The group's value
+ * is the `kx_group` field of `rustls_handshake_info`.
+ *
+ * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if `buf` is
+ * too small; in that case the number of bytes needed is stored in `out_n`.
+ */
+enum rustls_result rustls_connection_get_negotiated_group_name(const struct rustls_connection *conn,
+                                                               uint8_t *buf,
+                                                               size_t len,
+                                                               size_t *out_n);
+
+/**
+ * Return true if the connection completed a TLS 1.2 handshake that
+ * negotiated the Extended Master Secret extension (RFC 7627), which binds
+ * the session's keys to the whole handshake. Returns false for TLS 1.3,
+ * where the extension doesn't apply because the key schedule always does
+ * this, while the handshake is in progress, and if `conn` is NULL.
+ */
+bool rustls_connection_get_used_extended_master_secret(const struct rustls_connection *conn);
+
+/**
+ * Return the most early (0-RTT) data, in bytes, that the server allows a
+ * client connection to send: the limit in the resumption ticket it
+ * offered, if its config enables early data. Returns 0 if early data isn't
+ * available, which includes once the server has rejected it, and if `conn`
+ * is NULL.
+ *
+ * Always 0 for server connections: rustls 0.19 servers never accept early
+ * data over TLS, so there is no configured limit to report.
+ */
+uint32_t rustls_connection_get_max_early_data_size(const struct rustls_connection *conn);
+
+/**
+ * Fill in `out` with the connection's counters. See
+ * `rustls_connection_stats` for the meaning of each one.
+ */
+enum rustls_result rustls_connection_get_stats(const struct rustls_connection *conn,
+                                               struct rustls_connection_stats *out);
+
+/**
+ * Store the number of TLS records this connection has processed from the
+ * peer in `out_read`, and the number it has sent to the peer in
+ * `out_write`. This is for debugging: comparing the counts at each end can
+ * show a middlebox that injects or drops records.
+ *
+ * rustls doesn't expose the record layer's real sequence numbers, so these
+ * are counts rather than sequence numbers, and they differ in two ways:
+ *
+ * - They count every record since the connection was created, including
+ *   unencrypted handshake and ChangeCipherSpec records. TLS sequence
+ *   numbers start again from zero whenever new keys are installed (at
+ *   ChangeCipherSpec in TLS 1.2, and at each stage of the handshake and on
+ *   KeyUpdate in TLS 1.3), but these counts never do.
+ * - A record counts towards `out_read` once
+ *   rustls_connection_process_new_packets has processed it without error,
+ *   as for `records_processed` in rustls_connection_stats, and towards
+ *   `out_write` once all of its bytes have been passed to the write
+ *   callback of rustls_connection_write_tls or
+ *   rustls_connection_write_tls_vectored.
+ */
+enum rustls_result rustls_connection_get_sequence_numbers(const struct rustls_connection *conn,
+                                                          uint64_t *out_read,
+                                                          uint64_t *out_write);
+
+/**
+ * Store the number of TLS bytes this connection has sent while
+ * handshaking in `out_sent`, and the number it has received in
+ * `out_received`. Together with `rustls_connection_get_stats` this shows
+ * how much of a connection's traffic was handshake overhead.
+ *
+ * Bytes are attributed a call at a time: everything a
+ * `rustls_connection_read_tls` call reads counts if the handshake was still
+ * in progress when it was called, and everything a
+ * `rustls_connection_write_tls` or `rustls_connection_write_tls_vectored`
+ * call writes counts until the last handshake messages have been written.
+ * So application data that arrives in the same read as the end of the
+ * handshake, and plaintext written before the handshake completed, which
+ * is sent along with the last handshake messages, are counted too. A TLS
+ * 1.3 server sends its session tickets as its handshake completes, so they
+ * count for the server, but not for the client, which receives them after
+ * its handshake has completed.
+ */
+enum rustls_result rustls_connection_get_handshake_bytes(const struct rustls_connection *conn,
+                                                         uint64_t *out_sent,
+                                                         uint64_t *out_received);
+
 /**
  * Write up to `count` plaintext bytes from `buf` into the `rustls_connection`.
  * This will increase the number of output bytes available to
  * `rustls_connection_write_tls`.
- * On success, store the number of bytes actually written in *out_n
- * (this may be less than `count`).
+ * Store the number of bytes actually written in *out_n. That is less than
+ * `count` only when the limit set with `rustls_connection_set_buffer_limit`
+ * was reached, and then RUSTLS_RESULT_WRITE_WOULD_BLOCK is returned
+ * instead of RUSTLS_RESULT_OK: write out TLS bytes with
+ * `rustls_connection_write_tls`, then write the rest of `buf`.
  */
 enum rustls_result rustls_connection_write(struct rustls_connection *conn,
                                            const uint8_t *buf,
@@ -957,7 +2600,18 @@ enum rustls_result rustls_connection_write(struct rustls_connection *conn,
  * than `count`). A success with *out_n set to 0 means "all bytes currently
  * available have been read, but more bytes may become available after
  * subsequent calls to rustls_connection_read_tls and
- * rustls_connection_process_new_packets."
+ * rustls_connection_process_new_packets." It is the equivalent of
+ * EWOULDBLOCK: calling this again without reading more TLS data will keep
+ * returning 0, so a read loop should stop there and wait for the socket to
+ * become readable. `rustls_connection_read_has_more` tells whether a call
+ * would return any bytes, without reading them.
+ *
+ * The end of the stream is never signalled by *out_n == 0. A clean close
+ * by the peer returns RUSTLS_RESULT_ALERT_CLOSE_NOTIFY once all plaintext
+ * sent before it has been read; see also `rustls_connection_is_closed`. If
+ * the socket closes without a close_notify, the next call to
+ * `rustls_connection_process_new_packets` after that plaintext has been read
+ * returns RUSTLS_RESULT_UNEXPECTED_EOF.
  *
  * Subtle note: Even though this function only writes to `buf` and does not
  * read from it, the memory in `buf` must be initialized before the call (for
@@ -969,9 +2623,118 @@ enum rustls_result rustls_connection_read(struct rustls_connection *conn,
                                           size_t count,
                                           size_t *out_n);
 
+/**
+ * Read plaintext like `rustls_connection_read`, but stop at the end of a
+ * TLS record: the bytes read never span the end of one record and the
+ * start of the next. *out_at_boundary is set to true if they end exactly
+ * where a record ended, and false if more of that record remains, or if
+ * nothing was read.
+ *
+ * Record framing is up to the peer's TLS implementation, which may split
+ * one write over several records or combine several writes into one, so
+ * it is not a reliable message delimiter. It is only useful for protocols
+ * whose peers are known to send each message in records of its own.
+ *
+ * The end of each record is known exactly for TLS 1.2. TLS 1.3 encrypts
+ * records' real content types, so if records processed together include
+ * a padded record, or one carrying a handshake message or alert, only the
+ * end of all their plaintext is known to be a record boundary.
+ *
+ * Record boundaries are only tracked for connections that use this
+ * function, since it costs an extra copy of all plaintext received. The
+ * first call turns tracking on, and all plaintext processed before it is
+ * treated as one record, so to know every boundary, call this once with a
+ * `count` of 0 before the first call to
+ * rustls_connection_process_new_packets. Tracking stays on for the
+ * connection's lifetime, including after rustls_connection_reset.
+ */
+enum rustls_result rustls_connection_read_until_boundary(struct rustls_connection *conn,
+                                                         uint8_t *buf,
+                                                         size_t count,
+                                                         size_t *out_n,
+                                                         bool *out_at_boundary);
+
+/**
+ * Return the errno of the last I/O error that a function on `conn`
+ * reported as RUSTLS_RESULT_IO, such as EPIPE, or 0 if there hasn't been
+ * one since the connection was created or reset. Errors without an errno
+ * are reported as EIO. `rustls_connection_read_tls` and
+ * `rustls_connection_write_tls` return their errors directly, and don't
+ * change this. Returns EINVAL if `conn` is NULL.
+ */
+rustls_io_result rustls_connection_last_io_error(const struct rustls_connection *conn);
+
+/**
+ * Borrow the plaintext received from the peer that hasn't been read yet,
+ * storing a view of it in `out`, without copying it into a buffer of the
+ * caller's. Once the caller has used some or all of it, they must call
+ * `rustls_connection_consume_plaintext` to move past those bytes; until
+ * then, each call returns the same bytes again. This suits proxies that
+ * forward plaintext straight on to another socket.
+ *
+ * The view is borrowed from the connection and is invalidated by the next
+ * call to rustls_connection_read_tls, rustls_connection_process_new_packets,
+ * rustls_connection_read, rustls_connection_consume_plaintext or this
+ * function, and by freeing the connection. Copy anything needed beyond that.
+ *
+ * The results match those of `rustls_connection_read`: an empty view (`len`
+ * 0) means no plaintext is available until more TLS data is read and
+ * processed, RUSTLS_RESULT_ALERT_CLOSE_NOTIFY means the peer closed the
+ * connection cleanly and all plaintext sent before that has been consumed,
+ * and the view is capped by `rustls_connection_set_max_plaintext_read`.
+ *
+ * The version of rustls used by crustls doesn't give access to its buffer
+ * of decrypted plaintext, so processing new packets still copies it once,
+ * into a buffer inside the connection. This saves the
+ * caller's own copy, and the other one will go away when crustls moves to a
+ * rustls release that exposes the buffer.
+ */
+enum rustls_result rustls_connection_take_received_plaintext(struct rustls_connection *conn,
+                                                             struct rustls_slice_bytes *out);
+
+/**
+ * Mark the first `n` bytes of the view returned by
+ * `rustls_connection_take_received_plaintext` as used, so that they aren't
+ * returned again. Returns RUSTLS_RESULT_INVALID_PARAMETER, and consumes
+ * nothing, if `n` is more than the bytes taken and not yet consumed.
+ */
+enum rustls_result rustls_connection_consume_plaintext(struct rustls_connection *conn, size_t n);
+
+/**
+ * Return a connection to the state it was in when created, so that it
+ * can be reused for a new TLS session with the same peer, such as by a
+ * connection pool. The new session uses the same config and, for a
+ * client, the same hostname. Any TLS bytes or plaintext still queued are
+ * discarded, and everything learned from the old session (peer
+ * certificates, negotiated parameters, statistics, close state) is
+ * forgotten.
+ *
+ * Settings made on the connection are kept: the buffer limit, the
+ * maximum plaintext read, the protocols set with
+ * rustls_connection_set_alpn_protocols, and the log and new ticket
+ * callbacks. The userdata pointer is cleared, so set it again before
+ * processing packets if callbacks need it. So is the handshake deadline,
+ * which was for the old handshake: set a new one with
+ * rustls_connection_set_handshake_deadline if needed.
+ *
+ * The version of rustls used by crustls can't reset its session in place,
+ * so the rustls session is allocated afresh; buffers owned by crustls keep
+ * their capacity. Calling with NULL does nothing.
+ *
+ * crustls doesn't offer a pool of connections. The fresh rustls session is
+ * where a handshake's allocations happen (record buffers, the handshake
+ * hash, the peer's certificate chain), so a pool could only recycle the
+ * outer connection and a few small buffers, which this function already
+ * does for a connection the application keeps.
+ */
+void rustls_connection_reset(struct rustls_connection *conn);
+
 /**
  * Free a rustls_connection. Calling with NULL is fine.
  * Must not be called twice with the same value.
+ * This never performs I/O or invokes callbacks: any TLS bytes still queued,
+ * such as a close_notify alert from `rustls_connection_send_close_notify`,
+ * are discarded. Flush them first if the peer should receive them.
  */
 void rustls_connection_free(struct rustls_connection *conn);
 
@@ -985,11 +2748,83 @@ void rustls_error(enum rustls_result result, char *buf, size_t len, size_t *out_
 
 bool rustls_result_is_cert_error(enum rustls_result result);
 
+/**
+ * Return true if `result` reports a transient condition, so that the same
+ * call may succeed later without anything being changed but time and
+ * further I/O: RUSTLS_RESULT_WRITE_WOULD_BLOCK, once TLS data has been
+ * written out, and RUSTLS_RESULT_HANDSHAKE_NOT_COMPLETE, once the handshake
+ * has completed. Every other result, including RUSTLS_RESULT_OK, returns
+ * false; certificate and protocol errors, and alerts from the peer, are
+ * fatal to the connection.
+ *
+ * A would-block error from the read or write callback of
+ * `rustls_connection_read_tls` or `rustls_connection_write_tls` is
+ * returned as its errno, such as EAGAIN, rather than as a rustls_result.
+ * RUSTLS_RESULT_IO on its own doesn't say whether the underlying error is
+ * transient; `rustls_connection_last_io_error` gives its errno.
+ */
+bool rustls_result_is_retryable(enum rustls_result result);
+
+/**
+ * Install a process-wide callback for the log messages emitted by rustls
+ * and crustls, such as handshake diagnostics, at `level` and more severe
+ * levels (1 is ERROR, through to 5 for TRACE). Unlike a callback set with
+ * rustls_connection_set_log_callback, this also receives messages logged
+ * outside of any connection, and it is invoked with `userdata` rather than
+ * a connection's userdata. Messages logged while processing a connection
+ * that has its own log callback go to both.
+ *
+ * This can be called only once, and should be called before any
+ * connections are created; `userdata` must remain valid for the rest of
+ * the process. The callback may be invoked from any thread that uses
+ * crustls, so it must be thread-safe.
+ *
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER if `level` is out of range. A
+ * second call also returns RUSTLS_RESULT_INVALID_PARAMETER, and the first
+ * callback, userdata and level stay installed.
+ */
+enum rustls_result rustls_set_log_callback(rustls_log_callback cb,
+                                           void *userdata,
+                                           rustls_log_level level);
+
 /**
  * Return a rustls_str containing the stringified version of a log level.
  */
 struct rustls_str rustls_log_level_str(rustls_log_level level);
 
+/**
+ * Write the message of the most recent Rust panic caught on the calling
+ * thread into `buf`, which can hold up to `len` bytes, and store its
+ * length in `out_n`. The message is UTF-8 and is not NUL-terminated.
+ *
+ * crustls never lets a panic unwind into C. Instead the function that
+ * panicked returns RUSTLS_RESULT_PANIC, or if it doesn't return a
+ * rustls_result, false, 0 or NULL. Since those may also be ordinary
+ * results, the message can be used to find out whether, and why, a panic
+ * happened. A panic is always a bug in crustls or rustls, so the message is
+ * worth logging and reporting.
+ *
+ * The message is kept per thread until the next panic on that thread
+ * replaces it. Returns RUSTLS_RESULT_NOT_FOUND if there has been no panic
+ * on this thread, and RUSTLS_RESULT_INSUFFICIENT_SIZE, with the required
+ * length in `out_n`, if `len` is too small.
+ */
+enum rustls_result rustls_last_panic_message(uint8_t *buf, size_t len, size_t *out_n);
+
+/**
+ * Copy the bytes viewed by `src` into `dst`, which can hold up to `dst_len`
+ * bytes, so they can be used after `src` is no longer valid. The number of
+ * bytes in `src` is always stored in `out_n`.
+ *
+ * If `dst` is too small, copies the first `dst_len` bytes and returns
+ * RUSTLS_RESULT_INSUFFICIENT_SIZE; the caller can retry with a buffer of
+ * `*out_n` bytes. `dst` may be NULL if `dst_len` is 0, to ask for the size.
+ */
+enum rustls_result rustls_slice_bytes_copy(const struct rustls_slice_bytes *src,
+                                           uint8_t *dst,
+                                           size_t dst_len,
+                                           size_t *out_n);
+
 /**
  * Return the length of the outer slice. If the input pointer is NULL,
  * returns 0.
@@ -1059,6 +2894,39 @@ void rustls_server_config_builder_free(struct rustls_server_config_builder *conf
  */
 struct rustls_server_config_builder *rustls_server_config_builder_from_config(const struct rustls_server_config *config);
 
+/**
+ * Reject clients whose end-entity certificate has an RSA key of fewer than
+ * `bits` bits, for example to enforce a 2048-bit minimum. Keys of other
+ * types are not affected, and neither are clients that send no
+ * certificate where that is allowed. rustls_connection_process_new_packets
+ * returns `RUSTLS_RESULT_WEAK_KEY` for a rejected certificate. By default
+ * any size that webpki accepts is allowed.
+ *
+ * The minimum is applied to whichever client cert verifier the builder
+ * has when rustls_server_config_builder_build is called. Calling this more
+ * than once doesn't relax a minimum set earlier.
+ */
+enum rustls_result rustls_server_config_builder_set_min_rsa_key_bits(struct rustls_server_config_builder *builder,
+                                                                     uint32_t bits);
+
+/**
+ * Restrict the signature schemes used for client authentication to the
+ * `count` IANA-assigned values at `schemes`: only these are requested from
+ * the client, and the client's handshake signature must use one of them.
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER if the list is empty or contains
+ * a scheme rustls doesn't support.
+ *
+ * The server's own handshake signature uses a scheme the client offers,
+ * so it isn't affected. Like `rustls_server_config_builder_set_min_rsa_key_bits`,
+ * this is applied to the builder's client cert verifier when the config is
+ * built, and calling it again can only narrow the list further. If the
+ * narrowed list would be empty, this returns RUSTLS_RESULT_INVALID_PARAMETER
+ * and keeps the earlier list.
+ */
+enum rustls_result rustls_server_config_builder_set_signature_schemes(struct rustls_server_config_builder *builder,
+                                                                      const uint16_t *schemes,
+                                                                      size_t count);
+
 /**
  * Set the TLS protocol versions to use when negotiating a TLS session.
  *
@@ -1074,14 +2942,64 @@ enum rustls_result rustls_server_config_builder_set_versions(struct rustls_serve
                                                              size_t len);
 
 /**
- * With `ignore` != 0, the server will ignore the client ordering of cipher
- * suites, aka preference, during handshake and respect its own ordering
- * as configured.
+ * With `ignore` true, the server chooses the first cipher suite in its own
+ * list (see rustls_server_config_builder_set_ciphersuites) that the client
+ * also supports, rather than the client's most preferred suite that the
+ * server supports. The default is false: rustls honors the client's
+ * preference. Either way, only suites on both lists are considered, and
+ * this has no effect on the choice of protocol version or key exchange
+ * group. Use this when server-side ordering is required, e.g. for
+ * compliance.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html#fields
  */
 enum rustls_result rustls_server_config_builder_set_ignore_client_order(struct rustls_server_config_builder *builder,
                                                                         bool ignore);
 
+/**
+ * Enable or disable TLS certificate compression (RFC 8879). When disabled,
+ * the server will not compress its certificate chain even if the client
+ * offers compression algorithms.
+ *
+ * The version of rustls used by crustls has no support for RFC 8879, so
+ * certificate compression is never negotiated and this call has no
+ * effect. Passing `false` matches that and returns RUSTLS_RESULT_OK;
+ * passing `true` returns RUSTLS_RESULT_UNIMPLEMENTED, so callers aren't
+ * told compression is on. Once crustls moves to a rustls release that
+ * supports compression, `true` will enable it.
+ */
+enum rustls_result rustls_server_config_builder_set_cert_compression(struct rustls_server_config_builder *builder,
+                                                                     bool enabled);
+
+/**
+ * Enable or disable TLS 1.3 middlebox compatibility mode (RFC 8446 appendix
+ * D.4), in which the server echoes the client's legacy session ID and sends a
+ * dummy ChangeCipherSpec record so the handshake looks more like TLS 1.2
+ * resumption to middleboxes that would otherwise interfere with it.
+ *
+ * The version of rustls used by crustls always sends the compatibility
+ * messages in TLS 1.3 handshakes and cannot turn them off, so this changes
+ * nothing: compatibility mode is always enabled. Passing `true` returns
+ * RUSTLS_RESULT_OK; passing `false` returns RUSTLS_RESULT_UNSUPPORTED.
+ * Returns RUSTLS_RESULT_NULL_PARAMETER if `builder` is NULL.
+ */
+enum rustls_result rustls_server_config_builder_set_tls13_compat_mode(struct rustls_server_config_builder *builder,
+                                                                      bool enabled);
+
+/**
+ * Set the largest plaintext fragment that will be put in one outgoing TLS
+ * record, for example to keep records within a path MTU. Larger writes are
+ * split across several records. `max_fragment_size` must be between 32 and
+ * 16384 (the TLS maximum and the default), or 0 to restore the default.
+ * This only affects what is sent; it does not negotiate the max_fragment_length
+ * extension with the peer.
+ *
+ * Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `max_fragment_size` is out
+ * of range.
+ * https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html#structfield.mtu
+ */
+enum rustls_result rustls_server_config_builder_set_max_fragment_size(struct rustls_server_config_builder *builder,
+                                                                      size_t max_fragment_size);
+
 /**
  * Set the ALPN protocol list to the given protocols. `protocols` must point
  * to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
@@ -1127,12 +3045,50 @@ enum rustls_result rustls_server_config_builder_set_certified_keys(struct rustls
                                                                    const struct rustls_certified_key *const *certified_keys,
                                                                    size_t certified_keys_len);
 
+/**
+ * Serve a single certificate chain and private key, in the same formats
+ * as for `rustls_certified_key_build`. This does the work of building a
+ * `rustls_certified_key` with that function, passing it to
+ * `rustls_server_config_builder_set_certified_keys` and freeing it, in
+ * one call, and returns the same errors, such as
+ * RUSTLS_RESULT_PRIVATE_KEY_PARSE_ERROR. On error, the builder is
+ * unchanged.
+ */
+enum rustls_result rustls_server_config_builder_set_single_cert(struct rustls_server_config_builder *builder,
+                                                                const uint8_t *cert_chain,
+                                                                size_t cert_chain_len,
+                                                                const uint8_t *private_key,
+                                                                size_t private_key_len);
+
 /**
  * Turn a *rustls_server_config_builder (mutable) into a *rustls_server_config
  * (read-only).
  */
 const struct rustls_server_config *rustls_server_config_builder_build(struct rustls_server_config_builder *builder);
 
+/**
+ * Replace the certificate and key served by a config that is already in
+ * use, for example to rotate a certificate before it expires without
+ * building a new config. Handshakes that choose their certificate after
+ * this returns serve `new_key`; connections that already chose one,
+ * including ones still handshaking, keep it. The swap is guarded by a
+ * lock, so it is safe to call while other threads are creating or
+ * driving connections that use `config`, and every thread sees the new
+ * key once this returns. Resumed sessions keep the certificate of the
+ * session they resume.
+ *
+ * `new_key` replaces the keys set with
+ * rustls_server_config_builder_set_certified_keys, and the key returned
+ * by a hello callback, which is still called. Like those keys, it is only
+ * served to clients that support one of its signature schemes. The config
+ * keeps its own reference to `new_key`, so the caller may free it
+ * afterwards. A config made from this one with
+ * rustls_server_config_builder_from_config starts with `new_key`, but is
+ * not affected by later calls on this config.
+ */
+enum rustls_result rustls_server_config_set_certified_key_atomic(const struct rustls_server_config *config,
+                                                                 const struct rustls_certified_key *new_key);
+
 /**
  * "Free" a server_config previously returned from
  * rustls_server_config_builder_build. Since server_config is actually an
@@ -1161,6 +3117,13 @@ enum rustls_result rustls_server_connection_new(const struct rustls_server_confi
  * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if the SNI hostname is longer than `count`.
  * Returns Ok with *out_n == 0 if there is no SNI hostname available on this session
  * because it hasn't been processed yet, or because the client did not send SNI.
+ *
+ * rustls rejects a ClientHello whose SNI isn't a valid DNS name, so the
+ * handshake fails and no hostname is ever available for it. Any name that
+ * is returned is therefore printable ASCII, and is passed through as the
+ * client sent it apart from being lowercased. As a safeguard, a name that
+ * somehow isn't a valid DNS name is never copied out:
+ * RUSTLS_RESULT_INVALID_DNS_NAME_ERROR is returned with *out_n == 0 instead.
  * https://docs.rs/rustls/0.19.0/rustls/struct.ServerSession.html#method.get_sni_hostname
  */
 enum rustls_result rustls_server_connection_get_sni_hostname(const struct rustls_connection *conn,
@@ -1168,6 +3131,23 @@ enum rustls_result rustls_server_connection_get_sni_hostname(const struct rustls
                                                              size_t count,
                                                              size_t *out_n);
 
+/**
+ * Check that the certificate this server connection sent is valid for the
+ * SNI hostname the client sent, using the same name matching rules as
+ * certificate verification. This is a self-check for configurations that
+ * choose certificates by SNI, such as with
+ * `rustls_server_config_builder_set_hello_callback`.
+ *
+ * Returns RUSTLS_RESULT_OK if the certificate matches, and also if the
+ * client sent no SNI, since there is then nothing to check; use
+ * `rustls_server_connection_get_sni_hostname` to tell these apart.
+ * Returns RUSTLS_RESULT_CERT_NOT_VALID_FOR_NAME if it doesn't match,
+ * RUSTLS_RESULT_NOT_FOUND if no certificate has been chosen, either
+ * because the handshake hasn't got that far or because the session was
+ * resumed, and RUSTLS_RESULT_INVALID_PARAMETER for a client connection.
+ */
+enum rustls_result rustls_connection_served_cert_matches_sni(const struct rustls_connection *conn);
+
 /**
  * Register a callback to be invoked when a session created from this config
  * is seeing a TLS ClientHello message. If `userdata` has been set with
@@ -1187,6 +3167,44 @@ enum rustls_result rustls_server_connection_get_sni_hostname(const struct rustls
 enum rustls_result rustls_server_config_builder_set_hello_callback(struct rustls_server_config_builder *builder,
                                                                    rustls_client_hello_callback callback);
 
+/**
+ * Register a callback that chooses the ALPN protocol for each connection
+ * from the protocols the client offers, instead of using the list set
+ * with rustls_server_config_builder_set_protocols. For example, it can
+ * prefer h2 for some clients and http/1.1 for others, based on state
+ * reachable from the connection's userdata. The callback is not called if
+ * the client offers no protocols. It is called after the certificate has
+ * been chosen, so register it once, after
+ * rustls_server_config_builder_set_certified_keys or
+ * rustls_server_config_builder_set_hello_callback: those replace the ALPN
+ * callback. It is not permitted to pass a NULL value for `callback`.
+ *
+ * If the callback declines, rustls_connection_process_new_packets returns
+ * `RUSTLS_RESULT_NO_APPLICATION_PROTOCOL` and the connection fails. The
+ * version of rustls used by crustls can't send the no_application_protocol
+ * alert in that case, so the client sees the connection close instead.
+ *
+ * rustls 0.19 can only choose from a fixed list, so after the callback
+ * runs the connection processes the ClientHello a second time, with the
+ * chosen protocol and the same certificate. Callbacks are not run again.
+ */
+enum rustls_result rustls_server_config_builder_set_alpn_select_callback(struct rustls_server_config_builder *builder,
+                                                                         rustls_alpn_select_callback callback);
+
+/**
+ * Register a callback that looks up the external pre-shared keys (RFC 8446
+ * section 2.2) offered by TLS 1.3 clients, so that clients can
+ * authenticate with a key set up out of band instead of a certificate. See
+ * rustls_client_config_builder_add_external_psk for the client side. It
+ * is not permitted to pass a NULL value for `callback`.
+ *
+ * PLACEHOLDER: rustls 0.19 only supports pre-shared keys for session
+ * resumption, so this returns RUSTLS_RESULT_UNIMPLEMENTED and leaves the
+ * config unchanged.
+ */
+enum rustls_result rustls_server_config_builder_set_external_psk_resolver(struct rustls_server_config_builder *builder,
+                                                                          rustls_psk_resolver_callback callback);
+
 /**
  * Select a `rustls_certified_key` from the list that matches the cryptographic
  * parameters of a TLS client hello. Note that this does not do any SNI matching.
@@ -1221,4 +3239,23 @@ enum rustls_result rustls_server_config_builder_set_persistence(struct rustls_se
                                                                 rustls_session_store_get_callback get_cb,
                                                                 rustls_session_store_put_callback put_cb);
 
+/**
+ * Keep up to `size` sessions in rustls's built-in in-memory session cache,
+ * for resumption by session ID (TLS 1.2) or by stateful ticket (TLS 1.3).
+ * Each cached session holds its secrets and, if the client authenticated,
+ * its certificate chain, so this bounds the memory a busy server spends on
+ * resumption. A `size` of 0 disables the cache, so sessions are never
+ * resumed unless rustls_server_config_builder_set_persistence is used.
+ *
+ * The default is a cache of 256 sessions. When the cache is full, storing a
+ * new session evicts an arbitrary existing one: the cache does not track
+ * use, so this is not least-recently-used eviction.
+ *
+ * This replaces any callbacks set with
+ * rustls_server_config_builder_set_persistence, and vice versa: whichever
+ * is called last wins.
+ */
+enum rustls_result rustls_server_config_builder_set_session_cache_size(struct rustls_server_config_builder *builder,
+                                                                       size_t size);
+
 #endif /* CRUSTLS_H */
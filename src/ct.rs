@@ -0,0 +1,272 @@
+// Checking of Signed Certificate Timestamps (RFC 6962) embedded in
+// certificates, for the client's Certificate Transparency policy. rustls
+// 0.19 only checks SCTs sent in the TLS handshake, and only when they are
+// present, and the sct crate can't verify embedded SCTs, which are signed
+// over the precertificate. So we rebuild the precertificate's
+// TBSCertificate with our own DER reader and check the signatures with
+// ring.
+//
+// Only SCTs signed with SHA-256 and ECDSA (P-256) or RSA are accepted, as
+// RFC 6962 requires.
+
+use ring::{digest, signature};
+
+use crate::der::{self, BadDer};
+use crate::x509;
+
+/// The X.509 extension holding embedded SCTs, 1.3.6.1.4.1.11129.2.4.2.
+const SCT_LIST_EXTENSION: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+
+/// Version v1 of the SCT structure.
+const SCT_V1: u8 = 0;
+/// SignatureType.certificate_timestamp.
+const CERTIFICATE_TIMESTAMP: u8 = 0;
+/// LogEntryType.precert_entry.
+const PRECERT_ENTRY: u16 = 1;
+/// HashAlgorithm.sha256 and SignatureAlgorithm.rsa and .ecdsa, from RFC
+/// 5246 section 7.4.1.4.1.
+const SHA256: u8 = 4;
+const RSA: u8 = 1;
+const ECDSA: u8 = 3;
+
+/// A CT log, identified by its public key.
+#[derive(Clone)]
+pub(crate) struct CtLog {
+    /// The SHA-256 hash of the log's SubjectPublicKeyInfo.
+    id: [u8; 32],
+    /// The TLS SignatureAlgorithm of the log's signatures.
+    signature_algorithm: u8,
+    verification_algorithm: &'static dyn signature::VerificationAlgorithm,
+    /// The key bits of the log's subjectPublicKey.
+    key: Vec<u8>,
+}
+
+impl CtLog {
+    /// Make a log from its DER-encoded SubjectPublicKeyInfo, as published
+    /// in CT log lists. Returns None unless it is an ECDSA P-256 or RSA key.
+    pub(crate) fn new(spki: &[u8]) -> Option<CtLog> {
+        let (signature_algorithm, verification_algorithm): (
+            u8,
+            &'static dyn signature::VerificationAlgorithm,
+        ) = match x509::key_algorithm_name(spki).ok()?.as_str() {
+            "ECDSA P-256" => (ECDSA, &signature::ECDSA_P256_SHA256_ASN1),
+            "RSA" => (RSA, &signature::RSA_PKCS1_2048_8192_SHA256),
+            _ => return None,
+        };
+        let mut reader = der::Reader::new(der::expect_only(spki, der::SEQUENCE).ok()?);
+        reader.expect(der::SEQUENCE).ok()?;
+        let key = match reader.expect(der::BIT_STRING).ok()?.split_first() {
+            Some((0, key)) => key.to_vec(),
+            _ => return None,
+        };
+        let mut id = [0u8; 32];
+        id.copy_from_slice(digest::digest(&digest::SHA256, spki).as_ref());
+        Some(CtLog {
+            id,
+            signature_algorithm,
+            verification_algorithm,
+            key,
+        })
+    }
+}
+
+/// Return how many of `logs` have a valid SCT embedded in `cert`, which was
+/// issued by the holder of the DER-encoded SubjectPublicKeyInfo
+/// `issuer_spki`. SCTs timestamped after `now` don't count, and neither do
+/// further SCTs from a log that has already been counted.
+pub(crate) fn count_valid_scts(
+    cert: &[u8],
+    issuer_spki: &[u8],
+    logs: &[CtLog],
+    now: webpki::Time,
+) -> usize {
+    let embedded = match embedded_scts(cert) {
+        Ok(Some(embedded)) => embedded,
+        _ => return 0,
+    };
+    let issuer_key_hash = digest::digest(&digest::SHA256, issuer_spki);
+    let mut signed_by: Vec<&[u8; 32]> = Vec::new();
+    let mut list = embedded.scts;
+    while !list.is_empty() {
+        let sct = match take_u16_prefixed(&mut list) {
+            Some(sct) => sct,
+            None => break,
+        };
+        if let Some(log) = verify_sct(
+            sct,
+            &embedded.precert_tbs,
+            issuer_key_hash.as_ref(),
+            logs,
+            now,
+        ) {
+            if !signed_by.contains(&&log.id) {
+                signed_by.push(&log.id);
+            }
+        }
+    }
+    signed_by.len()
+}
+
+/// The SCTs embedded in a certificate.
+struct EmbeddedScts<'a> {
+    /// The SCT list, without its length prefix.
+    scts: &'a [u8],
+    /// The certificate's TBSCertificate with the SCT list extension
+    /// removed, which is what the logs signed.
+    precert_tbs: Vec<u8>,
+}
+
+/// Return the SCTs embedded in `cert`, or None if there are none.
+fn embedded_scts(cert: &[u8]) -> Result<Option<EmbeddedScts<'_>>, BadDer> {
+    let mut outer = der::Reader::new(der::expect_only(cert, der::SEQUENCE)?);
+    let mut tbs = der::Reader::new(outer.expect(der::SEQUENCE)?);
+    let mut precert_tbs = Vec::new();
+    let mut scts = None;
+    while !tbs.is_empty() {
+        let (tag, element) = tbs.read_element()?;
+        if tag != der::CONTEXT_SPECIFIC_CONSTRUCTED_3 {
+            precert_tbs.extend_from_slice(element);
+            continue;
+        }
+        let extensions = der::expect_only(element, der::CONTEXT_SPECIFIC_CONSTRUCTED_3)?;
+        let mut extensions = der::Reader::new(der::expect_only(extensions, der::SEQUENCE)?);
+        let mut kept = Vec::new();
+        while !extensions.is_empty() {
+            let (_, extension) = extensions.read_element()?;
+            let mut fields = der::Reader::new(der::expect_only(extension, der::SEQUENCE)?);
+            if fields.expect(der::OID)? != SCT_LIST_EXTENSION {
+                kept.extend_from_slice(extension);
+                continue;
+            }
+            if fields.peek_tag() == Some(der::BOOLEAN) {
+                fields.read()?;
+            }
+            let value = fields.expect(der::OCTET_STRING)?;
+            let mut list = der::expect_only(value, der::OCTET_STRING)?;
+            scts = Some(take_u16_prefixed(&mut list).ok_or(BadDer {})?);
+        }
+        if !kept.is_empty() {
+            let mut sequence = Vec::new();
+            der::write(&mut sequence, der::SEQUENCE, &kept);
+            der::write(
+                &mut precert_tbs,
+                der::CONTEXT_SPECIFIC_CONSTRUCTED_3,
+                &sequence,
+            );
+        }
+    }
+    Ok(scts.map(|scts| {
+        let mut encoded = Vec::new();
+        der::write(&mut encoded, der::SEQUENCE, &precert_tbs);
+        EmbeddedScts {
+            scts,
+            precert_tbs: encoded,
+        }
+    }))
+}
+
+/// Check one SCT, returning the log in `logs` that signed it.
+fn verify_sct<'a>(
+    mut sct: &[u8],
+    precert_tbs: &[u8],
+    issuer_key_hash: &[u8],
+    logs: &'a [CtLog],
+    now: webpki::Time,
+) -> Option<&'a CtLog> {
+    if take(&mut sct, 1)? != [SCT_V1] {
+        return None;
+    }
+    let log_id = take(&mut sct, 32)?;
+    let timestamp = take(&mut sct, 8)?;
+    let extensions = take_u16_prefixed(&mut sct)?;
+    let algorithms = take(&mut sct, 2)?;
+    let sig = take_u16_prefixed(&mut sct)?;
+    if !sct.is_empty() || precert_tbs.len() >= 1 << 24 {
+        return None;
+    }
+    let log = logs.iter().find(|log| log.id == log_id)?;
+    if algorithms != [SHA256, log.signature_algorithm] {
+        return None;
+    }
+    let mut timestamp_bytes = [0u8; 8];
+    timestamp_bytes.copy_from_slice(timestamp);
+    let seconds = u64::from_be_bytes(timestamp_bytes) / 1000;
+    if webpki::Time::from_seconds_since_unix_epoch(seconds) > now {
+        return None;
+    }
+
+    let mut message = vec![SCT_V1, CERTIFICATE_TIMESTAMP];
+    message.extend_from_slice(timestamp);
+    message.extend_from_slice(&PRECERT_ENTRY.to_be_bytes());
+    message.extend_from_slice(issuer_key_hash);
+    message.extend_from_slice(&(precert_tbs.len() as u32).to_be_bytes()[1..]);
+    message.extend_from_slice(precert_tbs);
+    message.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    message.extend_from_slice(extensions);
+    signature::UnparsedPublicKey::new(log.verification_algorithm, &log.key)
+        .verify(&message, sig)
+        .ok()?;
+    Some(log)
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Some(head)
+}
+
+fn take_u16_prefixed<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = take(input, 2)?;
+    take(input, u16::from_be_bytes([len[0], len[1]]) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// 2026-10-01T00:00:00Z, when the test SCTs were made.
+    const SCT_TIME: u64 = 1790812800;
+
+    fn count(cert_pem: &[u8], logs: &[&[u8]], unix_seconds: u64) -> usize {
+        let cert = testutil::certs(cert_pem).remove(0);
+        let issuer = testutil::certs(testutil::CA_PEM).remove(0);
+        let issuer_spki = x509::subject_public_key_info(&issuer.0).unwrap();
+        let logs: Vec<CtLog> = logs.iter().map(|l| CtLog::new(l).unwrap()).collect();
+        let now = webpki::Time::from_seconds_since_unix_epoch(unix_seconds);
+        count_valid_scts(&cert.0, issuer_spki, &logs, now)
+    }
+
+    #[test]
+    fn test_count_valid_scts() {
+        let all: &[&[u8]] = &[testutil::CT_LOG1, testutil::CT_LOG2, testutil::CT_LOG3];
+        assert_eq!(count(testutil::LOCALHOST_SCT_PEM, all, SCT_TIME), 2);
+        assert_eq!(count(testutil::LOCALHOST_SCT_PEM, &all[1..], SCT_TIME), 1);
+        assert_eq!(
+            count(
+                testutil::LOCALHOST_SCT_PEM,
+                &[testutil::CT_LOG1; 2],
+                SCT_TIME
+            ),
+            1
+        );
+        // Timestamped in the future.
+        assert_eq!(count(testutil::LOCALHOST_SCT_PEM, all, SCT_TIME - 1), 0);
+        assert_eq!(count(testutil::LOCALHOST_PEM, all, SCT_TIME), 0);
+
+        // Signed for a different certificate.
+        let mut cert = testutil::certs(testutil::LOCALHOST_SCT_PEM).remove(0).0;
+        let issuer = testutil::certs(testutil::CA_PEM).remove(0);
+        let issuer_spki = x509::subject_public_key_info(&issuer.0).unwrap();
+        let logs: Vec<CtLog> = all.iter().map(|l| CtLog::new(l).unwrap()).collect();
+        let serial = x509::serial_number(&cert).unwrap().as_ptr() as usize - cert.as_ptr() as usize;
+        cert[serial] ^= 1;
+        let now = webpki::Time::from_seconds_since_unix_epoch(SCT_TIME);
+        assert_eq!(count_valid_scts(&cert, issuer_spki, &logs, now), 0);
+
+        assert!(CtLog::new(b"not a key").is_none());
+    }
+}
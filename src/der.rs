@@ -0,0 +1,194 @@
+// A minimal reader and writer for the subset of DER that crustls needs in
+// order to inspect keys and certificates. This is not a general ASN.1
+// implementation: it only understands single-byte tags and definite
+// lengths, which is all that DER permits for the structures we look at.
+
+pub(crate) const BOOLEAN: u8 = 0x01;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const BIT_STRING: u8 = 0x03;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const OID: u8 = 0x06;
+pub(crate) const ENUMERATED: u8 = 0x0a;
+pub(crate) const SEQUENCE: u8 = 0x30;
+pub(crate) const CONTEXT_SPECIFIC_CONSTRUCTED_0: u8 = 0xa0;
+/// The [3] EXPLICIT wrapper around a TBSCertificate's extensions.
+pub(crate) const CONTEXT_SPECIFIC_CONSTRUCTED_3: u8 = 0xa3;
+
+/// BadDer represents any failure to parse DER input.
+#[derive(Debug, PartialEq)]
+pub(crate) struct BadDer {}
+
+/// A cursor over a DER-encoded byte slice. Each read consumes one complete
+/// TLV element from the front of the input.
+pub(crate) struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(input: &'a [u8]) -> Self {
+        Reader { input }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Return the tag of the next element without consuming it.
+    pub(crate) fn peek_tag(&self) -> Option<u8> {
+        self.input.first().copied()
+    }
+
+    /// Read the next element, returning its tag and value.
+    pub(crate) fn read(&mut self) -> Result<(u8, &'a [u8]), BadDer> {
+        let (&tag, rest) = self.input.split_first().ok_or(BadDer {})?;
+        let (&first, mut rest) = rest.split_first().ok_or(BadDer {})?;
+        let len: usize = match first {
+            n if n < 0x80 => n as usize,
+            0x81..=0x84 => {
+                let count = (first & 0x7f) as usize;
+                if rest.len() < count {
+                    return Err(BadDer {});
+                }
+                let (len_bytes, after) = rest.split_at(count);
+                rest = after;
+                // DER requires the shortest possible length encoding.
+                if len_bytes[0] == 0 {
+                    return Err(BadDer {});
+                }
+                let len = len_bytes
+                    .iter()
+                    .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+                if len < 0x80 {
+                    return Err(BadDer {});
+                }
+                len
+            }
+            // Indefinite lengths (0x80) are not valid DER, and lengths
+            // longer than four bytes are not plausible for our inputs.
+            _ => return Err(BadDer {}),
+        };
+        if rest.len() < len {
+            return Err(BadDer {});
+        }
+        let (value, rest) = rest.split_at(len);
+        self.input = rest;
+        Ok((tag, value))
+    }
+
+    /// Read the next element, returning its tag and complete encoding,
+    /// including the tag and length bytes.
+    pub(crate) fn read_element(&mut self) -> Result<(u8, &'a [u8]), BadDer> {
+        let start = self.input;
+        let (tag, _) = self.read()?;
+        let consumed = start.len() - self.input.len();
+        Ok((tag, &start[..consumed]))
+    }
+
+    /// Read the next element, requiring that it have the given tag.
+    pub(crate) fn expect(&mut self, tag: u8) -> Result<&'a [u8], BadDer> {
+        match self.read()? {
+            (t, value) if t == tag => Ok(value),
+            _ => Err(BadDer {}),
+        }
+    }
+}
+
+/// Parse `input` as exactly one element with the given tag, returning its
+/// value. Trailing data is an error.
+pub(crate) fn expect_only(input: &[u8], tag: u8) -> Result<&[u8], BadDer> {
+    let mut reader = Reader::new(input);
+    let value = reader.expect(tag)?;
+    if !reader.is_empty() {
+        return Err(BadDer {});
+    }
+    Ok(value)
+}
+
+/// Format the contents of an OBJECT IDENTIFIER in dotted decimal, such as
+/// "1.2.840.10045.2.1".
+pub(crate) fn oid_to_string(oid: &[u8]) -> Result<String, BadDer> {
+    let mut arcs: Vec<u64> = Vec::new();
+    let mut arc: u64 = 0;
+    for (i, &b) in oid.iter().enumerate() {
+        // A leading 0x80 would be a non-minimal encoding.
+        if (arc == 0 && b == 0x80) || arc > u64::MAX >> 7 {
+            return Err(BadDer {});
+        }
+        arc = (arc << 7) | (b & 0x7f) as u64;
+        if b & 0x80 != 0 {
+            if i == oid.len() - 1 {
+                return Err(BadDer {});
+            }
+            continue;
+        }
+        if arcs.is_empty() {
+            // The first subidentifier combines the first two arcs.
+            let first = (arc / 40).min(2);
+            arcs.push(first);
+            arcs.push(arc - first * 40);
+        } else {
+            arcs.push(arc);
+        }
+        arc = 0;
+    }
+    if arcs.is_empty() {
+        return Err(BadDer {});
+    }
+    let arcs: Vec<String> = arcs.iter().map(|a| a.to_string()).collect();
+    Ok(arcs.join("."))
+}
+
+/// Append a DER element with the given tag and value to `out`.
+pub(crate) fn write(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    let len = value.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes = (len as u32).to_be_bytes();
+        let skip = len_bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+    out.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for &len in &[0usize, 1, 0x7f, 0x80, 0xff, 0x100, 0x10000] {
+            let value = vec![0x5a; len];
+            let mut encoded = Vec::new();
+            write(&mut encoded, OCTET_STRING, &value);
+            assert_eq!(expect_only(&encoded, OCTET_STRING), Ok(&value[..]));
+        }
+    }
+
+    #[test]
+    fn test_oid_to_string() {
+        let id_ec_public_key = [0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+        assert_eq!(
+            oid_to_string(&id_ec_public_key),
+            Ok("1.2.840.10045.2.1".to_string())
+        );
+        assert_eq!(oid_to_string(&[0x88, 0x37]), Ok("2.999".to_string()));
+        assert!(oid_to_string(&[]).is_err());
+        assert!(oid_to_string(&[0x2a, 0x86]).is_err());
+        assert!(oid_to_string(&[0x2a, 0x80, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_lengths() {
+        // Truncated value.
+        assert!(Reader::new(&[0x04, 0x02, 0x00]).read().is_err());
+        // Indefinite length.
+        assert!(Reader::new(&[0x30, 0x80, 0x00, 0x00]).read().is_err());
+        // Long form used for a length that fits in the short form.
+        assert!(Reader::new(&[0x04, 0x81, 0x01, 0x00]).read().is_err());
+        // Trailing data.
+        assert!(expect_only(&[0x05, 0x00, 0x05, 0x00], 0x05).is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use rustls::internal::msgs::enums::NamedGroup;
+use rustls::SignatureScheme;
+
 #[repr(C)]
 #[allow(dead_code)]
 /// Definitions of known TLS protocol versions.
@@ -10,6 +13,47 @@ pub enum rustls_tls_version {
     Tlsv1_3 = 0x0304,
 }
 
+/// A hash algorithm, by its value in the TLS HashAlgorithm registry. An
+/// external pre-shared key can only be used with cipher suites that use its
+/// hash algorithm.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum rustls_hash_algorithm {
+    Sha256 = 4,
+    Sha384 = 5,
+}
+
+/// What a client does with the OCSP response a server staples to its
+/// certificate. See rustls_client_config_builder_set_ocsp_policy.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum rustls_ocsp_policy {
+    /// Don't look at stapled responses. This is the default.
+    #[default]
+    Ignore = 0,
+    /// Reject the certificate if a usable stapled response says it is
+    /// revoked. Accept it if there is no response, or it can't be used.
+    SoftFail = 1,
+    /// Accept the certificate only if a usable stapled response says it is
+    /// good.
+    HardFail = 2,
+}
+
+/// Which side of a TLS session a connection is. See
+/// rustls_connection_get_side.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum rustls_connection_side {
+    /// Returned for a NULL connection.
+    #[default]
+    Unknown = 0,
+    Client = 1,
+    Server = 2,
+}
+
 pub(crate) fn rustls_tls_version_from_u16(version_num: u16) -> rustls::ProtocolVersion {
     match version_num {
         // This is very bad and will be fixes in rustls by
@@ -28,3 +72,81 @@ pub(crate) fn rustls_tls_version_from_u16(version_num: u16) -> rustls::ProtocolV
         n => rustls::ProtocolVersion::Unknown(n),
     }
 }
+
+/// Every signature scheme rustls can sign or verify with, in its order of
+/// preference.
+pub(crate) static ALL_SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ED25519,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+/// The signature scheme with the IANA-assigned value `scheme`, if it is one
+/// rustls supports.
+pub(crate) fn signature_scheme_from_u16(scheme: u16) -> Option<SignatureScheme> {
+    ALL_SIGNATURE_SCHEMES
+        .iter()
+        .copied()
+        .find(|s| s.get_u16() == scheme)
+}
+
+/// Map IANA-assigned values to the signature schemes they name, or return
+/// None if `input` is empty or names a scheme rustls doesn't support.
+pub(crate) fn signature_schemes_from_u16s(input: &[u16]) -> Option<Vec<SignatureScheme>> {
+    if input.is_empty() {
+        return None;
+    }
+    input
+        .iter()
+        .map(|&s| signature_scheme_from_u16(s))
+        .collect()
+}
+
+/// The key exchange groups rustls supports, in its order of preference.
+pub(crate) static ALL_KX_GROUPS: &[NamedGroup] = &[
+    NamedGroup::X25519,
+    NamedGroup::secp384r1,
+    NamedGroup::secp256r1,
+];
+
+/// Map IANA-assigned NamedGroup values to the groups they name, or return
+/// None if `input` is empty or names a group rustls doesn't support.
+pub(crate) fn kx_groups_from_u16s(input: &[u16]) -> Option<Vec<NamedGroup>> {
+    if input.is_empty() {
+        return None;
+    }
+    input
+        .iter()
+        .map(|&n| ALL_KX_GROUPS.iter().copied().find(|g| g.get_u16() == n))
+        .collect()
+}
+
+/// Names of the NamedGroups a peer might negotiate, from
+/// https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-8.
+static NAMED_GROUP_NAMES: &[(u16, &str)] = &[
+    (0x0017, "secp256r1"),
+    (0x0018, "secp384r1"),
+    (0x0019, "secp521r1"),
+    (0x001d, "X25519"),
+    (0x001e, "X448"),
+    (0x0100, "ffdhe2048"),
+    (0x0101, "ffdhe3072"),
+    (0x0102, "ffdhe4096"),
+    (0x0103, "ffdhe6144"),
+    (0x0104, "ffdhe8192"),
+];
+
+/// The IANA name of the NamedGroup `group`, or "Unknown(0x....)" with its
+/// value in hex if it isn't one we know.
+pub(crate) fn named_group_name(group: u16) -> String {
+    match NAMED_GROUP_NAMES.iter().find(|(value, _)| *value == group) {
+        Some((_, name)) => name.to_string(),
+        None => format!("Unknown(0x{:04x})", group),
+    }
+}
@@ -30,7 +30,7 @@ pub extern "C" fn rustls_error(
             if buf.is_null() {
                 return;
             }
-            slice::from_raw_parts_mut(buf as *mut u8, len as usize)
+            slice::from_raw_parts_mut(buf as *mut u8, len)
         };
         let error_str = result.to_string();
         let len: usize = min(write_buf.len() - 1, error_str.len());
@@ -43,15 +43,40 @@ pub extern "C" fn rustls_error(
 
 #[no_mangle]
 pub extern "C" fn rustls_result_is_cert_error(result: rustls_result) -> bool {
-    match result_to_tlserror(&result) {
-        Either::TLSError(TLSError::WebPKIError(_)) => true,
-        Either::TLSError(TLSError::InvalidSCT(_)) => true,
-        _ => false,
-    }
+    result == rustls_result::CertificateRevoked
+        || result == rustls_result::WeakKey
+        || result == rustls_result::CertificateTransparencyRequired
+        || result == rustls_result::CertificatePolicyMissing
+        || matches!(
+            result_to_tlserror(&result),
+            Either::TLSError(TLSError::WebPKIError(_)) | Either::TLSError(TLSError::InvalidSCT(_))
+        )
+}
+
+/// Return true if `result` reports a transient condition, so that the same
+/// call may succeed later without anything being changed but time and
+/// further I/O: RUSTLS_RESULT_WRITE_WOULD_BLOCK, once TLS data has been
+/// written out, and RUSTLS_RESULT_HANDSHAKE_NOT_COMPLETE, once the handshake
+/// has completed. Every other result, including RUSTLS_RESULT_OK, returns
+/// false; certificate and protocol errors, and alerts from the peer, are
+/// fatal to the connection.
+///
+/// A would-block error from the read or write callback of
+/// `rustls_connection_read_tls` or `rustls_connection_write_tls` is
+/// returned as its errno, such as EAGAIN, rather than as a rustls_result.
+/// RUSTLS_RESULT_IO on its own doesn't say whether the underlying error is
+/// transient; `rustls_connection_last_io_error` gives its errno.
+#[no_mangle]
+pub extern "C" fn rustls_result_is_retryable(result: rustls_result) -> bool {
+    matches!(
+        result,
+        rustls_result::WriteWouldBlock | rustls_result::HandshakeNotComplete
+    )
 }
 
 #[allow(dead_code)]
 #[repr(C)]
+#[derive(Debug, PartialEq)]
 pub enum rustls_result {
     Ok = 7000,
     Io = 7001,
@@ -63,6 +88,19 @@ pub enum rustls_result {
     InsufficientSize = 7007,
     NotFound = 7008,
     InvalidParameter = 7009,
+    Unsupported = 7010,
+    CertificateRevoked = 7011,
+    WeakKey = 7012,
+    UnexpectedEof = 7013,
+    RandomUnavailable = 7014,
+    CertificateTransparencyRequired = 7015,
+    NoVerifierConfigured = 7016,
+    WriteWouldBlock = 7017,
+    HandshakeTimeout = 7018,
+    CannotBuildChain = 7019,
+    CertificatePolicyMissing = 7020,
+    Unimplemented = 7021,
+    FileReadError = 7022,
 
     // From https://docs.rs/rustls/0.19.0/rustls/enum.TLSError.html
     CorruptMessage = 7100,
@@ -281,6 +319,32 @@ pub(crate) fn result_to_tlserror(input: &rustls_result) -> Either {
         InsufficientSize => return Either::String("provided buffer is of insufficient size".to_string()),
         NotFound => return Either::String("the item was not found".to_string()),
         InvalidParameter => return Either::String("a parameter had an invalid value".to_string()),
+        Unsupported => return Either::String(
+            "the operation is not supported by this version of rustls".to_string()),
+        CertificateRevoked => return Either::String(
+            "the server's certificate is revoked, or the OCSP policy required proof that it isn't".to_string()),
+        WeakKey => return Either::String(
+            "the peer's certificate has an RSA key smaller than the configured minimum".to_string()),
+        UnexpectedEof => return Either::String(
+            "the peer closed the connection without sending close_notify".to_string()),
+        RandomUnavailable => return Either::String(
+            "the system's random number generator is unavailable".to_string()),
+        CertificateTransparencyRequired => return Either::String(
+            "the server's certificate has too few valid SCTs for the Certificate Transparency policy".to_string()),
+        NoVerifierConfigured => return Either::String(
+            "the client config needs a custom certificate verifier, and none was set".to_string()),
+        WriteWouldBlock => return Either::String(
+            "the connection's buffer limit was reached, so not all the plaintext was accepted".to_string()),
+        HandshakeTimeout => return Either::String(
+            "the handshake did not complete before the connection's deadline".to_string()),
+        CannotBuildChain => return Either::String(
+            "the certificates don't form a chain from one that matches the private key".to_string()),
+        CertificatePolicyMissing => return Either::String(
+            "the server's certificate doesn't assert a certificate policy the client requires".to_string()),
+        Unimplemented => return Either::String(
+            "the operation is a placeholder that crustls doesn't implement yet".to_string()),
+        FileReadError => return Either::String(
+            "a file could not be read".to_string()),
 
         // These variants correspond to a TLSError variant with a field,
         // where generating an arbitrary field would produce a confusing error
@@ -306,6 +370,19 @@ pub(crate) fn result_to_tlserror(input: &rustls_result) -> Either {
         InsufficientSize => unreachable!(),
         NotFound => unreachable!(),
         InvalidParameter => unreachable!(),
+        Unsupported => unreachable!(),
+        CertificateRevoked => unreachable!(),
+        WeakKey => unreachable!(),
+        UnexpectedEof => unreachable!(),
+        RandomUnavailable => unreachable!(),
+        CertificateTransparencyRequired => unreachable!(),
+        NoVerifierConfigured => unreachable!(),
+        WriteWouldBlock => unreachable!(),
+        HandshakeTimeout => unreachable!(),
+        CannotBuildChain => unreachable!(),
+        CertificatePolicyMissing => unreachable!(),
+        Unimplemented => unreachable!(),
+        FileReadError => unreachable!(),
 
         InappropriateMessage => unreachable!(),
         InappropriateHandshakeMessage => unreachable!(),
@@ -396,3 +473,130 @@ pub(crate) fn result_to_tlserror(input: &rustls_result) -> Either {
     };
     Either::TLSError(e)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Define `all_results`, returning each of the listed results. The
+    /// exhaustive match stops this compiling if a variant is left out.
+    macro_rules! all_results {
+        ( $( $variant:ident ),* $(,)? ) => {
+            fn all_results() -> Vec<rustls_result> {
+                let _exhaustive = |result: rustls_result| match result {
+                    $( rustls_result::$variant => (), )*
+                };
+                vec![ $( rustls_result::$variant, )* ]
+            }
+        };
+    }
+
+    all_results!(
+        Ok,
+        Io,
+        NullParameter,
+        InvalidDnsNameError,
+        Panic,
+        CertificateParseError,
+        PrivateKeyParseError,
+        InsufficientSize,
+        NotFound,
+        InvalidParameter,
+        Unsupported,
+        CertificateRevoked,
+        WeakKey,
+        UnexpectedEof,
+        RandomUnavailable,
+        CertificateTransparencyRequired,
+        NoVerifierConfigured,
+        WriteWouldBlock,
+        HandshakeTimeout,
+        CannotBuildChain,
+        CertificatePolicyMissing,
+        Unimplemented,
+        FileReadError,
+        CorruptMessage,
+        NoCertificatesPresented,
+        DecryptError,
+        FailedToGetCurrentTime,
+        HandshakeNotComplete,
+        PeerSentOversizedRecord,
+        NoApplicationProtocol,
+        PeerIncompatibleError,
+        PeerMisbehavedError,
+        InappropriateMessage,
+        InappropriateHandshakeMessage,
+        CorruptMessagePayload,
+        General,
+        AlertCloseNotify,
+        AlertUnexpectedMessage,
+        AlertBadRecordMac,
+        AlertDecryptionFailed,
+        AlertRecordOverflow,
+        AlertDecompressionFailure,
+        AlertHandshakeFailure,
+        AlertNoCertificate,
+        AlertBadCertificate,
+        AlertUnsupportedCertificate,
+        AlertCertificateRevoked,
+        AlertCertificateExpired,
+        AlertCertificateUnknown,
+        AlertIllegalParameter,
+        AlertUnknownCA,
+        AlertAccessDenied,
+        AlertDecodeError,
+        AlertDecryptError,
+        AlertExportRestriction,
+        AlertProtocolVersion,
+        AlertInsufficientSecurity,
+        AlertInternalError,
+        AlertInappropriateFallback,
+        AlertUserCanceled,
+        AlertNoRenegotiation,
+        AlertMissingExtension,
+        AlertUnsupportedExtension,
+        AlertCertificateUnobtainable,
+        AlertUnrecognisedName,
+        AlertBadCertificateStatusResponse,
+        AlertBadCertificateHashValue,
+        AlertUnknownPSKIdentity,
+        AlertCertificateRequired,
+        AlertNoApplicationProtocol,
+        AlertUnknown,
+        CertBadDER,
+        CertBadDERTime,
+        CertCAUsedAsEndEntity,
+        CertExpired,
+        CertNotValidForName,
+        CertNotValidYet,
+        CertEndEntityUsedAsCA,
+        CertExtensionValueInvalid,
+        CertInvalidCertValidity,
+        CertInvalidSignatureForPublicKey,
+        CertNameConstraintViolation,
+        CertPathLenConstraintViolated,
+        CertSignatureAlgorithmMismatch,
+        CertRequiredEKUNotFound,
+        CertUnknownIssuer,
+        CertUnsupportedCertVersion,
+        CertUnsupportedCriticalExtension,
+        CertUnsupportedSignatureAlgorithmForPublicKey,
+        CertUnsupportedSignatureAlgorithm,
+        CertSCTMalformed,
+        CertSCTInvalidSignature,
+        CertSCTTimestampInFuture,
+        CertSCTUnsupportedVersion,
+        CertSCTUnknownLog,
+    );
+
+    #[test]
+    fn test_result_is_retryable() {
+        for result in all_results() {
+            let name = format!("{:?}", result);
+            // result_to_tlserror has a message for every result.
+            assert!(!result.to_string().is_empty(), "{}", name);
+            let retryable = ["WriteWouldBlock", "HandshakeNotComplete"].contains(&name.as_str());
+            assert_eq!(rustls_result_is_retryable(result), retryable, "{}", name);
+        }
+    }
+}
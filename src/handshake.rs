@@ -0,0 +1,315 @@
+// rustls 0.19 doesn't expose some details of the handshake that callers want
+// to log or audit, such as the key exchange group or whether the session was
+// resumed. The handshake messages that carry them are sent in the clear, so
+// we recover them by watching the TLS records that pass through read_tls and
+// write_tls. This only needs to understand enough of the record and
+// handshake layers to find those messages; rustls does the real validation,
+// so anything malformed is simply ignored here.
+
+use std::io::{IoSlice, Read, Result, Write};
+
+const CONTENT_CHANGE_CIPHER_SPEC: u8 = 20;
+const CONTENT_ALERT: u8 = 21;
+const CONTENT_HANDSHAKE: u8 = 22;
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const SERVER_KEY_EXCHANGE: u8 = 12;
+
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_EXTENDED_MASTER_SECRET: u16 = 23;
+const EXT_PRE_SHARED_KEY: u16 = 41;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_KEY_SHARE: u16 = 51;
+
+/// ECCurveType.named_curve, RFC 8422 section 5.4.
+const NAMED_CURVE: u8 = 3;
+
+/// rustls refuses handshake messages larger than this, so there's no point
+/// buffering more while waiting for one to complete.
+const MAX_HANDSHAKE_SIZE: usize = 0xffff;
+
+/// The most received bytes kept for replay: room for the largest
+/// ClientHello, split over records of any size.
+const MAX_RETAINED: usize = 2 * (MAX_HANDSHAKE_SIZE + 1);
+
+/// A ServerHello with this random value is really a HelloRetryRequest.
+/// RFC 8446 section 4.1.3.
+const HELLO_RETRY_REQUEST_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// The handshake details gathered so far. Anything not yet seen is zero or
+/// false.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct HandshakeDetails {
+    /// The IANA-assigned NamedGroup used for key exchange. Not known for
+    /// resumed TLS 1.2 sessions, which don't perform a key exchange.
+    pub(crate) kx_group: u16,
+    pub(crate) resumed: bool,
+    pub(crate) extended_master_secret: bool,
+}
+
+/// Watches the records of one connection, in both directions.
+#[derive(Default)]
+pub(crate) struct HandshakeObserver {
+    sent: RecordStream,
+    received: RecordStream,
+    client_session_id: Option<Vec<u8>>,
+    /// The signature_algorithms offered in a ClientHello received from the
+    /// peer, until taken.
+    client_signature_schemes: Option<Vec<u16>>,
+    server_hello_seen: bool,
+    details: HandshakeDetails,
+    /// Everything received, if asked to keep it, until the first bytes are
+    /// sent.
+    retained: Option<Vec<u8>>,
+}
+
+impl HandshakeObserver {
+    pub(crate) fn details(&self) -> &HandshakeDetails {
+        &self.details
+    }
+
+    /// Return the signature schemes a client peer offered, if a ClientHello
+    /// has been received since the last call.
+    pub(crate) fn take_client_signature_schemes(&mut self) -> Option<Vec<u16>> {
+        self.client_signature_schemes.take()
+    }
+
+    /// Keep the bytes received from now until the first bytes are sent, so
+    /// that they can be replayed into a new session.
+    pub(crate) fn retain_received(&mut self) {
+        self.retained = Some(Vec::new());
+    }
+
+    /// The bytes kept by `retain_received`, or None if something has been
+    /// sent or too much has been received.
+    pub(crate) fn retained_received(&self) -> Option<&[u8]> {
+        self.retained.as_deref()
+    }
+
+    /// Observe bytes written to the peer.
+    pub(crate) fn sent(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.retained = None;
+        }
+        let mut stream = std::mem::take(&mut self.sent);
+        stream.push(data, self, false);
+        self.sent = stream;
+    }
+
+    /// Observe bytes read from the peer.
+    pub(crate) fn received(&mut self, data: &[u8]) {
+        if let Some(retained) = &mut self.retained {
+            retained.extend_from_slice(data);
+            if retained.len() > MAX_RETAINED {
+                self.retained = None;
+            }
+        }
+        let mut stream = std::mem::take(&mut self.received);
+        stream.push(data, self, true);
+        self.received = stream;
+    }
+
+    fn handle_message(&mut self, typ: u8, body: &[u8], from_peer: bool) {
+        let _ = match typ {
+            CLIENT_HELLO => self.client_hello(body, from_peer),
+            SERVER_HELLO => self.server_hello(body),
+            SERVER_KEY_EXCHANGE => self.server_key_exchange(body),
+            _ => None,
+        };
+    }
+
+    fn client_hello(&mut self, mut body: &[u8], from_peer: bool) -> Option<()> {
+        take(&mut body, 2 + 32)?;
+        self.client_session_id = Some(take_u8_prefixed(&mut body)?.to_vec());
+        if !from_peer {
+            return Some(());
+        }
+        take_u16_prefixed(&mut body)?;
+        take_u8_prefixed(&mut body)?;
+        let mut extensions = take_u16_prefixed(&mut body)?;
+        while !extensions.is_empty() {
+            let typ = take_u16(&mut extensions)?;
+            let mut data = take_u16_prefixed(&mut extensions)?;
+            if typ == EXT_SIGNATURE_ALGORITHMS {
+                let mut list = take_u16_prefixed(&mut data)?;
+                let mut schemes = Vec::new();
+                while !list.is_empty() {
+                    schemes.push(take_u16(&mut list)?);
+                }
+                self.client_signature_schemes = Some(schemes);
+            }
+        }
+        Some(())
+    }
+
+    fn server_hello(&mut self, mut body: &[u8]) -> Option<()> {
+        take(&mut body, 2)?;
+        if take(&mut body, 32)? == HELLO_RETRY_REQUEST_RANDOM {
+            return None;
+        }
+        let session_id = take_u8_prefixed(&mut body)?;
+        take(&mut body, 2 + 1)?;
+        let mut extensions = if body.is_empty() {
+            body
+        } else {
+            take_u16_prefixed(&mut body)?
+        };
+
+        let mut details = HandshakeDetails::default();
+        let mut tls13 = false;
+        while !extensions.is_empty() {
+            let typ = take_u16(&mut extensions)?;
+            let mut data = take_u16_prefixed(&mut extensions)?;
+            match typ {
+                EXT_EXTENDED_MASTER_SECRET => details.extended_master_secret = true,
+                EXT_PRE_SHARED_KEY => details.resumed = true,
+                EXT_SUPPORTED_VERSIONS => tls13 = true,
+                EXT_KEY_SHARE => details.kx_group = take_u16(&mut data)?,
+                _ => {}
+            }
+        }
+        if !tls13 {
+            // A TLS 1.2 server resumes by echoing the client's session ID.
+            details.resumed =
+                !session_id.is_empty() && self.client_session_id.as_deref() == Some(session_id);
+        }
+        self.details = details;
+        self.server_hello_seen = true;
+        Some(())
+    }
+
+    fn server_key_exchange(&mut self, mut body: &[u8]) -> Option<()> {
+        if take(&mut body, 1)? == [NAMED_CURVE] {
+            self.details.kx_group = take_u16(&mut body)?;
+        }
+        Some(())
+    }
+}
+
+/// Reassembles plaintext handshake messages from the records sent in one
+/// direction. Once encryption starts we stop looking: that's at the first
+/// ChangeCipherSpec after the ServerHello (TLS 1.2), or the first
+/// application data record (TLS 1.3, which may send ChangeCipherSpec early
+/// for middlebox compatibility).
+#[derive(Default)]
+struct RecordStream {
+    records: Vec<u8>,
+    messages: Vec<u8>,
+    done: bool,
+}
+
+impl RecordStream {
+    /// Add `data` to the stream, passing any handshake messages it completes
+    /// to `observer`.
+    fn push(&mut self, data: &[u8], observer: &mut HandshakeObserver, from_peer: bool) {
+        if self.done {
+            return;
+        }
+        self.records.extend_from_slice(data);
+        while self.records.len() >= 5 {
+            let len = u16::from_be_bytes([self.records[3], self.records[4]]) as usize;
+            if self.records.len() < 5 + len {
+                break;
+            }
+            let record: Vec<u8> = self.records.drain(..5 + len).collect();
+            match record[0] {
+                CONTENT_HANDSHAKE => self.messages.extend_from_slice(&record[5..]),
+                CONTENT_ALERT => continue,
+                CONTENT_CHANGE_CIPHER_SPEC if !observer.server_hello_seen => continue,
+                _ => return self.finish(),
+            }
+            while self.messages.len() >= 4 {
+                let header = [0, self.messages[1], self.messages[2], self.messages[3]];
+                let len = u32::from_be_bytes(header) as usize;
+                if self.messages.len() < 4 + len {
+                    break;
+                }
+                let message: Vec<u8> = self.messages.drain(..4 + len).collect();
+                observer.handle_message(message[0], &message[4..], from_peer);
+            }
+            if self.messages.len() > 4 + MAX_HANDSHAKE_SIZE {
+                return self.finish();
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        self.records = Vec::new();
+        self.messages = Vec::new();
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Some(head)
+}
+
+fn take_u16(input: &mut &[u8]) -> Option<u16> {
+    take(input, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn take_u8_prefixed<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = take(input, 1)?[0] as usize;
+    take(input, len)
+}
+
+fn take_u16_prefixed<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = take_u16(input)? as usize;
+    take(input, len)
+}
+
+/// A reader that shows everything read through it to a `HandshakeObserver`.
+pub(crate) struct ObservedReader<'a, R: Read + ?Sized> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) observer: &'a mut HandshakeObserver,
+}
+
+impl<R: Read + ?Sized> Read for ObservedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.observer.received(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// A writer that shows everything written through it to a
+/// `HandshakeObserver`.
+pub(crate) struct ObservedWriter<'a, W: Write + ?Sized> {
+    pub(crate) inner: &'a mut W,
+    pub(crate) observer: &'a mut HandshakeObserver,
+}
+
+impl<W: Write + ?Sized> Write for ObservedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.observer.sent(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        let mut remaining = n;
+        for buf in bufs {
+            let len = remaining.min(buf.len());
+            self.observer.sent(&buf[..len]);
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,67 @@
+// Certificate verifiers, resolvers and session stores belong to a config,
+// and are shared by every connection made from it, but some of what they
+// learn is specific to the connection being processed. They report it through a thread-local, in
+// the same way callbacks find their userdata, and the connection collects it
+// after rustls returns. It's a stack because a C callback might process
+// packets on another connection.
+
+use std::cell::RefCell;
+
+use rustls::sign::CertifiedKey;
+use rustls::Certificate;
+
+/// What config-level hooks reported while processing a connection's
+/// packets. Fields are None or empty unless the corresponding hook ran.
+#[derive(Clone, Default)]
+pub(crate) struct HookReport {
+    pub(crate) peer_cert_expired: Option<bool>,
+    /// The server certificate was rejected under the client's OCSP policy.
+    pub(crate) ocsp_rejected: bool,
+    /// The peer's certificate was rejected for having too small an RSA key.
+    pub(crate) weak_key: bool,
+    /// The server certificate had too few valid SCTs for the client's
+    /// Certificate Transparency policy.
+    pub(crate) ct_rejected: bool,
+    /// The server certificate lacked a certificate policy the client
+    /// requires.
+    pub(crate) policy_missing: bool,
+    pub(crate) peer_signature_schemes: Option<Vec<u16>>,
+    /// Identifiers of the session tickets received, in order.
+    pub(crate) new_tickets: Vec<Vec<u8>>,
+    /// The end-entity certificate a server chose to send.
+    pub(crate) served_cert: Option<Certificate>,
+    /// A client chose a certificate to send in reply to a
+    /// CertificateRequest.
+    pub(crate) client_cert_sent: bool,
+    pub(crate) alpn_selection: Option<AlpnSelection>,
+}
+
+/// What a server's ALPN select callback chose, and the certificate chosen
+/// for the same ClientHello, so the handshake can be replayed with them.
+#[derive(Clone)]
+pub(crate) struct AlpnSelection {
+    /// None if the callback declined all the offered protocols.
+    pub(crate) protocol: Option<Vec<u8>>,
+    pub(crate) key: CertifiedKey,
+}
+
+thread_local! {
+    static REPORTS: RefCell<Vec<HookReport>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run `f`, and return what any hooks reported during it.
+pub(crate) fn capture<T>(f: impl FnOnce() -> T) -> (T, HookReport) {
+    REPORTS.with(|r| r.borrow_mut().push(HookReport::default()));
+    let result = f();
+    let report = REPORTS.with(|r| r.borrow_mut().pop()).unwrap_or_default();
+    (result, report)
+}
+
+/// Update the report for the connection currently being processed, if any.
+pub(crate) fn report(f: impl FnOnce(&mut HookReport)) {
+    REPORTS.with(|r| {
+        if let Some(top) = r.borrow_mut().last_mut() {
+            f(top);
+        }
+    });
+}
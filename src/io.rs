@@ -18,6 +18,11 @@ use std::ops::Deref;
 /// `userdata` is set to the value provided to `rustls_*_session_set_userdata`. In most
 /// cases that should be a struct that contains, at a minimum, a file descriptor.
 /// The buf and out_n pointers are borrowed and should not be retained across calls.
+///
+/// Returning 0 with out_n set to 0 means the end of the stream (EOF), as
+/// for read(2). A non-blocking callback that has nothing to read yet must
+/// return an error such as EAGAIN instead. The callback is never called
+/// with `n` of 0.
 pub type rustls_read_callback = Option<
     unsafe extern "C" fn(
         userdata: *mut c_void,
@@ -51,6 +56,28 @@ impl Read for CallbackReader {
     }
 }
 
+/// A reader that notes whether its inner reader reported EOF, by reading
+/// nothing into a buffer with room. rustls reads into an empty buffer once it
+/// has buffered as much of a malformed record as it will; those reads return
+/// 0 without reaching the inner reader, and aren't EOF.
+pub(crate) struct EofReader<'a> {
+    pub(crate) inner: &'a mut dyn Read,
+    pub(crate) eof: bool,
+}
+
+impl Read for EofReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = self.inner.read(buf)?;
+        if n == 0 {
+            self.eof = true;
+        }
+        Ok(n)
+    }
+}
+
 /// A callback for rustls_server_session_write_tls or rustls_client_session_write_tls.
 /// An implementation of this callback should attempt to write the `n` bytes in buf
 /// to the network. If any bytes were written, the implementation should
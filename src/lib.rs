@@ -2,24 +2,36 @@
 #![allow(non_camel_case_types)]
 use libc::{c_char, c_void, size_t};
 use std::cell::RefCell;
+use std::cmp::min;
 use std::io::Error;
 use std::io::ErrorKind::ConnectionAborted;
-use std::sync::Arc;
-use std::{cmp::min, thread::AccessError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
 use std::{mem, slice};
 
 mod cipher;
 mod client;
 mod connection;
+mod ct;
+mod der;
 mod enums;
 mod error;
+mod handshake;
+mod hooks;
 mod io;
 mod log;
+mod ocsp;
 mod panic;
 mod rslice;
+mod serialize;
 mod server;
 mod session;
+#[cfg(test)]
+mod testutil;
+mod verify;
+mod x509;
 
+use crate::error::rustls_result;
 use crate::log::rustls_log_callback;
 use crate::panic::PanicOrDefault;
 
@@ -34,7 +46,7 @@ use crate::panic::PanicOrDefault;
 // Rust code, we model these thread locals as a stack, so we can always
 // restore the previous version.
 thread_local! {
-    pub static USERDATA: RefCell<Vec<Userdata>> = RefCell::new(Vec::new());
+    pub static USERDATA: RefCell<Vec<Userdata>> = const { RefCell::new(Vec::new()) };
 }
 
 pub struct Userdata {
@@ -45,9 +57,10 @@ pub struct Userdata {
 /// UserdataGuard pops an entry off the USERDATA stack, restoring the
 /// thread-local state to its value previous to the creation of the UserdataGuard.
 /// Invariants: As long as a UserdataGuard is live:
-//// - The stack of userdata items for this thread must have at least one item.
+///  - The stack of userdata items for this thread must have at least one item.
 ///  - The top item on that stack must be the one this guard was built with.
 ///  - The `data` field must not be None.
+///
 /// If any of these invariants fails, try_drop will return an error.
 pub struct UserdataGuard {
     // Keep a copy of the data we expect to be popping off the stack. This allows
@@ -95,7 +108,7 @@ impl UserdataGuard {
                     },
                 )
             })
-            .unwrap_or_else(|_: AccessError| Err(UserdataError::AccessError))
+            .unwrap_or(Err(UserdataError::AccessError))
     }
 }
 
@@ -171,10 +184,33 @@ pub fn log_callback_get() -> Result<(rustls_log_callback, *mut c_void), Userdata
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module, clippy::redundant_pattern_matching)]
 mod tests {
     use super::*;
     use std::thread;
 
+    #[test]
+    fn test_crypto_backend_name() {
+        let mut buf = [0u8; 16];
+        let mut n: size_t = 0;
+        let result = rustls_crypto_backend_name(buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!(result, rustls_result::Ok);
+        assert_eq!(&buf[..n], b"ring");
+
+        let result = rustls_crypto_backend_name(buf.as_mut_ptr(), 2, &mut n);
+        assert_eq!(result, rustls_result::InsufficientSize);
+        assert_eq!(n, 4);
+    }
+
+    #[test]
+    fn test_init() {
+        let threads: Vec<_> = (0..4).map(|_| thread::spawn(|| rustls_init())).collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), rustls_result::Ok);
+        }
+        assert_eq!(rustls_init(), rustls_result::Ok);
+    }
+
     #[test]
     fn guard_try_pop() {
         let data = "hello";
@@ -298,24 +334,28 @@ pub(crate) trait CastPtr {
 
 #[macro_export]
 macro_rules! try_slice {
-    ( $ptr:expr, $count:expr ) => {
-        if $ptr.is_null() {
-            return crate::panic::NullParameterOrDefault::value();
+    ( $ptr:expr, $count:expr ) => {{
+        let ptr = $ptr;
+        let count = $count as usize;
+        if ptr.is_null() {
+            return $crate::panic::NullParameterOrDefault::value();
         } else {
-            unsafe { slice::from_raw_parts($ptr, $count as usize) }
+            unsafe { slice::from_raw_parts(ptr, count) }
         }
-    };
+    }};
 }
 
 #[macro_export]
 macro_rules! try_mut_slice {
-    ( $ptr:expr, $count:expr ) => {
-        if $ptr.is_null() {
-            return crate::panic::NullParameterOrDefault::value();
+    ( $ptr:expr, $count:expr ) => {{
+        let ptr = $ptr;
+        let count = $count as usize;
+        if ptr.is_null() {
+            return $crate::panic::NullParameterOrDefault::value();
         } else {
-            unsafe { slice::from_raw_parts_mut($ptr, $count as usize) }
+            unsafe { slice::from_raw_parts_mut(ptr, count) }
         }
-    };
+    }};
 }
 
 /// Turn a raw const pointer into a reference. This is a generic function
@@ -341,6 +381,14 @@ impl CastPtr for size_t {
     type RustType = size_t;
 }
 
+impl CastPtr for bool {
+    type RustType = bool;
+}
+
+impl CastPtr for u8 {
+    type RustType = u8;
+}
+
 impl CastPtr for *const u8 {
     type RustType = *const u8;
 }
@@ -353,9 +401,9 @@ impl CastPtr for *const u8 {
 #[macro_export]
 macro_rules! try_ref_from_ptr {
     ( $var:ident ) => {
-        match crate::try_from($var) {
+        match $crate::try_from($var) {
             Some(c) => c,
-            None => return crate::panic::NullParameterOrDefault::value(),
+            None => return $crate::panic::NullParameterOrDefault::value(),
         }
     };
 }
@@ -363,9 +411,9 @@ macro_rules! try_ref_from_ptr {
 #[macro_export]
 macro_rules! try_mut_from_ptr {
     ( $var:ident ) => {
-        match crate::try_from_mut($var) {
+        match $crate::try_from_mut($var) {
             Some(c) => c,
-            None => return crate::panic::NullParameterOrDefault::value(),
+            None => return $crate::panic::NullParameterOrDefault::value(),
         }
     };
 }
@@ -375,7 +423,7 @@ macro_rules! try_callback {
     ( $var:ident ) => {
         match $var {
             Some(c) => c,
-            None => return crate::panic::NullParameterOrDefault::value(),
+            None => return $crate::panic::NullParameterOrDefault::value(),
         }
     };
 }
@@ -389,7 +437,7 @@ pub extern "C" fn rustls_version(buf: *mut c_char, len: size_t) -> size_t {
             if buf.is_null() {
                 return 0;
             }
-            slice::from_raw_parts_mut(buf as *mut u8, len as usize)
+            slice::from_raw_parts_mut(buf as *mut u8, len)
         };
         let version: String = format!(
             "crustls/{}/rustls/{}",
@@ -404,6 +452,73 @@ pub extern "C" fn rustls_version(buf: *mut c_char, len: size_t) -> size_t {
     }
 }
 
+/// The cryptography library that rustls, and so crustls, uses.
+const CRYPTO_BACKEND: &str = "ring";
+
+/// Write the name of the cryptography library crustls was built against,
+/// such as "ring", into `buf`, which can hold up to `len` bytes, and store
+/// the number of bytes written in `out_n`. The name is UTF-8 and is not
+/// NUL-terminated. Applications with requirements such as FIPS validation
+/// can check this at startup.
+///
+/// The version of rustls used by crustls only supports ring, so this
+/// always reports "ring" for now. Once crustls moves to a rustls release
+/// with pluggable crypto providers, it will gain a build-time option to use
+/// aws-lc-rs instead, and this will report "aws-lc-rs" when it is used.
+///
+/// Returns RUSTLS_RESULT_INSUFFICIENT_SIZE, writing nothing, if `buf` is
+/// too small; in that case the number of bytes needed is stored in `out_n`.
+#[no_mangle]
+pub extern "C" fn rustls_crypto_backend_name(
+    buf: *mut u8,
+    len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let write_buf: &mut [u8] = try_mut_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        let name: &[u8] = CRYPTO_BACKEND.as_bytes();
+        *out_n = name.len();
+        if name.len() > write_buf.len() {
+            return rustls_result::InsufficientSize;
+        }
+        write_buf[..name.len()].copy_from_slice(name);
+        rustls_result::Ok
+    }
+}
+
+static INIT: Once = Once::new();
+/// Whether the checks made by the first call to rustls_init passed.
+static INIT_OK: AtomicBool = AtomicBool::new(false);
+
+/// Do crustls' one-time setup, and check that the environment can support
+/// TLS. Calling this is optional but recommended: call it once at startup,
+/// so that problems show up there rather than in the first handshake.
+///
+/// This detects the CPU features the cryptography library can use, and
+/// checks that the system's random number generator works, returning
+/// RUSTLS_RESULT_RANDOM_UNAVAILABLE if it doesn't. The work is done only
+/// by the first call; it is safe to call this any number of times, from
+/// any thread, and every call returns the same result.
+#[no_mangle]
+pub extern "C" fn rustls_init() -> rustls_result {
+    ffi_panic_boundary! {
+        INIT.call_once(|| {
+            // ring detects CPU features the first time it needs them.
+            ring::digest::digest(&ring::digest::SHA256, b"");
+            let mut buf = [0u8; 16];
+            let rng = ring::rand::SystemRandom::new();
+            let ok = ring::rand::SecureRandom::fill(&rng, &mut buf).is_ok();
+            INIT_OK.store(ok, Ordering::SeqCst);
+        });
+        if INIT_OK.load(Ordering::SeqCst) {
+            rustls_result::Ok
+        } else {
+            rustls_result::RandomUnavailable
+        }
+    }
+}
+
 /// In rustls_server_config_builder_build, and rustls_client_config_builder_build,
 /// we create an Arc, then call `into_raw` and return the resulting raw pointer
 /// to C. C can then call rustls_server_session_new multiple times using that
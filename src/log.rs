@@ -1,30 +1,58 @@
 use std::convert::TryInto;
+use std::sync::Mutex;
 
 use libc::c_void;
 use log::Level;
 
-use crate::{log_callback_get, rslice::rustls_str};
+use crate::error::rustls_result;
+use crate::{ffi_panic_boundary, log_callback_get, rslice::rustls_str, try_callback};
 
 struct Logger {}
 
+/// The callback installed by rustls_set_log_callback. The userdata pointer
+/// is stored as a usize so the static is Sync; it's only ever handed back
+/// to C.
+struct GlobalLogCallback {
+    callback: LogCallback,
+    userdata: usize,
+    level: Level,
+}
+
+static GLOBAL_LOG_CALLBACK: Mutex<Option<GlobalLogCallback>> = Mutex::new(None);
+
+type LogCallback = unsafe extern "C" fn(userdata: *mut c_void, params: *const rustls_log_params);
+
 impl log::Log for Logger {
     fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
         true
     }
     fn log(&self, record: &log::Record<'_>) {
-        if let Ok((Some(cb), userdata)) = log_callback_get() {
-            let message = format!("{} {}", record.target(), record.args());
-            if let Ok(message) = message.as_str().try_into() {
-                unsafe {
-                    cb(
-                        userdata,
-                        &rustls_log_params {
-                            level: record.level() as rustls_log_level,
-                            message,
-                        },
-                    );
-                }
-            }
+        let connection = match log_callback_get() {
+            Ok((Some(cb), userdata)) => Some((cb, userdata)),
+            _ => None,
+        };
+        let global = match GLOBAL_LOG_CALLBACK.lock() {
+            Ok(global) => global
+                .as_ref()
+                .filter(|g| record.level() <= g.level)
+                .map(|g| (g.callback, g.userdata as *mut c_void)),
+            Err(_) => None,
+        };
+        if connection.is_none() && global.is_none() {
+            return;
+        }
+        let message = format!("{} {}", record.target(), record.args());
+        let (message, target) = match (message.as_str().try_into(), record.target().try_into()) {
+            (Ok(message), Ok(target)) => (message, target),
+            _ => return,
+        };
+        let params = rustls_log_params {
+            level: record.level() as rustls_log_level,
+            message,
+            target,
+        };
+        for (cb, userdata) in connection.into_iter().chain(global) {
+            unsafe { cb(userdata, &params) };
         }
     }
     fn flush(&self) {}
@@ -32,7 +60,61 @@ impl log::Log for Logger {
 
 pub(crate) fn ensure_log_registered() {
     log::set_logger(&Logger {}).ok();
-    log::set_max_level(log::LevelFilter::Debug)
+    if log::max_level() < log::LevelFilter::Debug {
+        log::set_max_level(log::LevelFilter::Debug)
+    }
+}
+
+/// Install a process-wide callback for the log messages emitted by rustls
+/// and crustls, such as handshake diagnostics, at `level` and more severe
+/// levels (1 is ERROR, through to 5 for TRACE). Unlike a callback set with
+/// rustls_connection_set_log_callback, this also receives messages logged
+/// outside of any connection, and it is invoked with `userdata` rather than
+/// a connection's userdata. Messages logged while processing a connection
+/// that has its own log callback go to both.
+///
+/// This can be called only once, and should be called before any
+/// connections are created; `userdata` must remain valid for the rest of
+/// the process. The callback may be invoked from any thread that uses
+/// crustls, so it must be thread-safe.
+///
+/// Returns RUSTLS_RESULT_INVALID_PARAMETER if `level` is out of range. A
+/// second call also returns RUSTLS_RESULT_INVALID_PARAMETER, and the first
+/// callback, userdata and level stay installed.
+#[no_mangle]
+pub extern "C" fn rustls_set_log_callback(
+    cb: rustls_log_callback,
+    userdata: *mut c_void,
+    level: rustls_log_level,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let callback: LogCallback = try_callback!(cb);
+        let level: Level = match level {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            5 => Level::Trace,
+            _ => return rustls_result::InvalidParameter,
+        };
+        let mut global = match GLOBAL_LOG_CALLBACK.lock() {
+            Ok(global) => global,
+            Err(_) => return rustls_result::Panic,
+        };
+        if global.is_some() {
+            return rustls_result::InvalidParameter;
+        }
+        *global = Some(GlobalLogCallback {
+            callback,
+            userdata: userdata as usize,
+            level,
+        });
+        ensure_log_registered();
+        if log::max_level() < level.to_level_filter() {
+            log::set_max_level(level.to_level_filter());
+        }
+        rustls_result::Ok
+    }
 }
 
 type rustls_log_level = usize;
@@ -51,12 +133,55 @@ pub extern "C" fn rustls_log_level_str(level: rustls_log_level) -> rustls_str<'s
     rustls_str::from_str_unchecked(s)
 }
 
+/// A log message, as passed to a rustls_log_callback. The message is
+/// prefixed with its target, the Rust module that logged it, which is also
+/// given separately.
+///
+/// crustls allocates this struct, and new fields are only ever appended, so
+/// a callback built against an older header still reads `level` and
+/// `message` correctly. `target` was added in 0.8.0.
 #[repr(C)]
 pub struct rustls_log_params<'a> {
     level: rustls_log_level,
     message: rustls_str<'a>,
+    target: rustls_str<'a>,
 }
 
 #[allow(non_camel_case_types)]
 pub type rustls_log_callback =
     Option<unsafe extern "C" fn(userdata: *mut c_void, params: *const rustls_log_params)>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MESSAGES: Mutex<Vec<(rustls_log_level, String)>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn record(_userdata: *mut c_void, params: *const rustls_log_params) {
+        let params = &*params;
+        let target = std::slice::from_raw_parts(params.target.data as *const u8, params.target.len);
+        if target == b"crustls_log_test" {
+            let message =
+                std::slice::from_raw_parts(params.message.data as *const u8, params.message.len);
+            let message = String::from_utf8(message.to_vec()).unwrap();
+            MESSAGES.lock().unwrap().push((params.level, message));
+        }
+    }
+
+    #[test]
+    fn test_set_log_callback() {
+        let result = rustls_set_log_callback(Some(record), std::ptr::null_mut(), 0);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        let result = rustls_set_log_callback(Some(record), std::ptr::null_mut(), 3);
+        assert_eq!(result, rustls_result::Ok);
+        let result = rustls_set_log_callback(Some(record), std::ptr::null_mut(), 3);
+        assert_eq!(result, rustls_result::InvalidParameter);
+
+        log::info!(target: "crustls_log_test", "hello");
+        log::debug!(target: "crustls_log_test", "too verbose");
+        assert_eq!(
+            *MESSAGES.lock().unwrap(),
+            vec![(3, "crustls_log_test hello".to_string())]
+        );
+    }
+}
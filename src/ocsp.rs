@@ -0,0 +1,265 @@
+// Checking of stapled OCSP responses (RFC 6960) for the client's OCSP
+// policy. rustls 0.19 hands the stapled response to the certificate
+// verifier without looking at it, and webpki doesn't understand OCSP, so we
+// read the response with our own DER reader and let webpki check its
+// signature.
+//
+// Only responses signed by the certificate's issuer itself are accepted.
+// Responses signed by a delegated OCSP responder, or that use hash or
+// signature algorithms not listed here, are treated as unusable.
+
+use std::convert::TryFrom;
+
+use ring::digest;
+
+use crate::der::{self, BadDer};
+use crate::x509;
+
+const GENERALIZED_TIME: u8 = 0x18;
+const CONTEXT_SPECIFIC_CONSTRUCTED_0: u8 = der::CONTEXT_SPECIFIC_CONSTRUCTED_0;
+
+/// The CertStatus choices: good [0] and unknown [2] are IMPLICIT NULL,
+/// revoked [1] is an IMPLICIT RevokedInfo SEQUENCE.
+const CERT_STATUS_GOOD: u8 = 0x80;
+const CERT_STATUS_REVOKED: u8 = 0xa1;
+const CERT_STATUS_UNKNOWN: u8 = 0x82;
+
+/// OCSPResponseStatus successful.
+const SUCCESSFUL: &[u8] = &[0];
+
+/// id-pkix-ocsp-basic, 1.3.6.1.5.5.7.48.1.1.
+const ID_PKIX_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+/// id-sha1, 1.3.14.3.2.26.
+const ID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+/// id-sha256, 2.16.840.1.101.3.4.2.1.
+const ID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// Signature algorithm OIDs, with the webpki algorithms that may verify
+/// each. ECDSA OIDs name only the hash, so the curve comes from the key.
+static SIGNATURE_ALGORITHMS: &[(&[u8], &[&webpki::SignatureAlgorithm])] = &[
+    // ecdsa-with-SHA256, 1.2.840.10045.4.3.2.
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02],
+        &[&webpki::ECDSA_P256_SHA256, &webpki::ECDSA_P384_SHA256],
+    ),
+    // ecdsa-with-SHA384, 1.2.840.10045.4.3.3.
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03],
+        &[&webpki::ECDSA_P256_SHA384, &webpki::ECDSA_P384_SHA384],
+    ),
+    // sha256WithRSAEncryption, 1.2.840.113549.1.1.11.
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b],
+        &[&webpki::RSA_PKCS1_2048_8192_SHA256],
+    ),
+    // sha384WithRSAEncryption, 1.2.840.113549.1.1.12.
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c],
+        &[&webpki::RSA_PKCS1_2048_8192_SHA384],
+    ),
+    // sha512WithRSAEncryption, 1.2.840.113549.1.1.13.
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d],
+        &[&webpki::RSA_PKCS1_2048_8192_SHA512],
+    ),
+    // id-Ed25519, 1.3.101.112.
+    (&[0x2b, 0x65, 0x70], &[&webpki::ED25519]),
+];
+
+/// The status an OCSP response gives for a certificate.
+#[derive(Debug, PartialEq)]
+pub(crate) enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// BadResponse represents any reason a stapled response can't be relied
+/// on: it is malformed, not correctly signed by the issuer, has no entry
+/// for the certificate, or is not current.
+#[derive(Debug, PartialEq)]
+pub(crate) struct BadResponse {}
+
+impl From<BadDer> for BadResponse {
+    fn from(_: BadDer) -> Self {
+        BadResponse {}
+    }
+}
+
+/// Return the status that the DER-encoded OCSPResponse `response` gives
+/// for `cert`, which was issued by `issuer`, as of `now`.
+///
+/// A good status only counts while the response is current. A revoked
+/// status is returned however old the response is, since revocation is
+/// permanent.
+pub(crate) fn check(
+    response: &[u8],
+    cert: &[u8],
+    issuer: &[u8],
+    now: webpki::Time,
+) -> Result<CertStatus, BadResponse> {
+    let mut basic = der::Reader::new(der::expect_only(basic_response(response)?, der::SEQUENCE)?);
+    let tbs = match basic.read_element()? {
+        (der::SEQUENCE, tbs) => tbs,
+        _ => return Err(BadResponse {}),
+    };
+    let algorithm = der::Reader::new(basic.expect(der::SEQUENCE)?).expect(der::OID)?;
+    let signature = match basic.expect(der::BIT_STRING)?.split_first() {
+        Some((0, signature)) => signature,
+        _ => return Err(BadResponse {}),
+    };
+    verify_signature(issuer, algorithm, tbs, signature)?;
+
+    let mut data = der::Reader::new(der::expect_only(tbs, der::SEQUENCE)?);
+    if data.peek_tag() == Some(CONTEXT_SPECIFIC_CONSTRUCTED_0) {
+        data.read()?;
+    }
+    // responderID and producedAt.
+    data.read()?;
+    data.expect(GENERALIZED_TIME)?;
+    let mut responses = der::Reader::new(data.expect(der::SEQUENCE)?);
+    while !responses.is_empty() {
+        let mut single = der::Reader::new(responses.expect(der::SEQUENCE)?);
+        if !cert_id_matches(single.expect(der::SEQUENCE)?, cert, issuer)? {
+            continue;
+        }
+        let (status, _) = single.read()?;
+        let this_update = x509::read_time(&mut single)?;
+        let next_update = match single.peek_tag() {
+            Some(CONTEXT_SPECIFIC_CONSTRUCTED_0) => Some(x509::read_time(&mut der::Reader::new(
+                single.expect(CONTEXT_SPECIFIC_CONSTRUCTED_0)?,
+            ))?),
+            _ => None,
+        };
+        return match status {
+            CERT_STATUS_REVOKED => Ok(CertStatus::Revoked),
+            CERT_STATUS_GOOD | CERT_STATUS_UNKNOWN => {
+                if time(this_update)? > now {
+                    return Err(BadResponse {});
+                }
+                if let Some(next_update) = next_update {
+                    if time(next_update)? < now {
+                        return Err(BadResponse {});
+                    }
+                }
+                if status == CERT_STATUS_GOOD {
+                    Ok(CertStatus::Good)
+                } else {
+                    Ok(CertStatus::Unknown)
+                }
+            }
+            _ => Err(BadResponse {}),
+        };
+    }
+    Err(BadResponse {})
+}
+
+/// Return the BasicOCSPResponse carried by a successful OCSPResponse.
+fn basic_response(response: &[u8]) -> Result<&[u8], BadResponse> {
+    let mut outer = der::Reader::new(der::expect_only(response, der::SEQUENCE)?);
+    if outer.expect(der::ENUMERATED)? != SUCCESSFUL {
+        return Err(BadResponse {});
+    }
+    let response_bytes = outer.expect(CONTEXT_SPECIFIC_CONSTRUCTED_0)?;
+    let mut response_bytes = der::Reader::new(der::expect_only(response_bytes, der::SEQUENCE)?);
+    if response_bytes.expect(der::OID)? != ID_PKIX_OCSP_BASIC {
+        return Err(BadResponse {});
+    }
+    Ok(response_bytes.expect(der::OCTET_STRING)?)
+}
+
+/// Check that `signature` over `tbs` was made by `issuer`'s key with the
+/// algorithm identified by the OID `algorithm`.
+fn verify_signature(
+    issuer: &[u8],
+    algorithm: &[u8],
+    tbs: &[u8],
+    signature: &[u8],
+) -> Result<(), BadResponse> {
+    let issuer = webpki::EndEntityCert::from(issuer).map_err(|_| BadResponse {})?;
+    let candidates = match SIGNATURE_ALGORITHMS
+        .iter()
+        .find(|(oid, _)| *oid == algorithm)
+    {
+        Some((_, candidates)) => candidates,
+        None => return Err(BadResponse {}),
+    };
+    if candidates
+        .iter()
+        .any(|alg| issuer.verify_signature(alg, tbs, signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err(BadResponse {})
+    }
+}
+
+/// Check whether a CertID identifies `cert`, issued by `issuer`.
+fn cert_id_matches(cert_id: &[u8], cert: &[u8], issuer: &[u8]) -> Result<bool, BadResponse> {
+    let mut cert_id = der::Reader::new(cert_id);
+    let hash = der::Reader::new(cert_id.expect(der::SEQUENCE)?).expect(der::OID)?;
+    let issuer_name_hash = cert_id.expect(der::OCTET_STRING)?;
+    let issuer_key_hash = cert_id.expect(der::OCTET_STRING)?;
+    let serial_number = cert_id.expect(der::INTEGER)?;
+    let hash: &'static digest::Algorithm = if hash == ID_SHA1 {
+        &digest::SHA1_FOR_LEGACY_USE_ONLY
+    } else if hash == ID_SHA256 {
+        &digest::SHA256
+    } else {
+        return Ok(false);
+    };
+    Ok(serial_number == x509::serial_number(cert)?
+        && issuer_name_hash == digest::digest(hash, x509::issuer(cert)?).as_ref()
+        && issuer_key_hash == digest::digest(hash, x509::subject_public_key(issuer)?).as_ref())
+}
+
+fn time(seconds: i64) -> Result<webpki::Time, BadResponse> {
+    let seconds = u64::try_from(seconds).map_err(|_| BadResponse {})?;
+    Ok(webpki::Time::from_seconds_since_unix_epoch(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn status(response: &[u8], unix_seconds: u64) -> Result<CertStatus, BadResponse> {
+        let leaf = testutil::certs(testutil::LOCALHOST_VIA_INTERMEDIATE_PEM).remove(0);
+        let issuer = testutil::certs(testutil::INTERMEDIATE_PEM).remove(0);
+        let now = webpki::Time::from_seconds_since_unix_epoch(unix_seconds);
+        check(response, &leaf.0, &issuer.0, now)
+    }
+
+    #[test]
+    fn test_check() {
+        // The responses were produced in October 2026 and are valid for a
+        // century.
+        const YEAR_2030: u64 = 1893456000;
+        const YEAR_2025: u64 = 1735689600;
+        assert_eq!(status(testutil::OCSP_GOOD, YEAR_2030), Ok(CertStatus::Good));
+        assert_eq!(status(testutil::OCSP_GOOD, YEAR_2025), Err(BadResponse {}));
+        assert_eq!(
+            status(testutil::OCSP_REVOKED, YEAR_2030),
+            Ok(CertStatus::Revoked)
+        );
+        assert_eq!(
+            status(testutil::OCSP_REVOKED, YEAR_2025),
+            Ok(CertStatus::Revoked)
+        );
+        // Signed by the test CA rather than the intermediate.
+        assert_eq!(
+            status(testutil::OCSP_WRONG_SIGNER, YEAR_2030),
+            Err(BadResponse {})
+        );
+        assert_eq!(status(b"", YEAR_2030), Err(BadResponse {}));
+
+        // A response for some other certificate.
+        let other = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        let issuer = testutil::certs(testutil::INTERMEDIATE_PEM).remove(0);
+        let now = webpki::Time::from_seconds_since_unix_epoch(YEAR_2030);
+        assert_eq!(
+            check(testutil::OCSP_GOOD, &other.0, &issuer.0, now),
+            Err(BadResponse {})
+        );
+    }
+}
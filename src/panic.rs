@@ -1,8 +1,13 @@
-use libc::EINVAL;
+use libc::{size_t, EINVAL};
 
+use crate::enums::rustls_connection_side;
 use crate::error::{rustls_io_result, rustls_result};
+use crate::{try_mut_from_ptr, try_mut_slice};
 
+use std::any::Any;
+use std::cell::RefCell;
 use std::ptr::{null, null_mut};
+use std::slice;
 
 // We wrap all function calls in an ffi_panic_boundary! macro, which catches
 // panics and early-returns from the function. For functions that return
@@ -27,8 +32,10 @@ pub(crate) trait NullParameterOrDefault {
 pub(crate) trait Defaultable: Default {}
 
 impl Defaultable for u16 {}
+impl Defaultable for u32 {}
 impl Defaultable for usize {}
 impl Defaultable for bool {}
+impl Defaultable for rustls_connection_side {}
 impl Defaultable for () {}
 impl<T> Defaultable for Option<T> {}
 
@@ -99,7 +106,111 @@ macro_rules! ffi_panic_boundary {
             $($tt)*
         }) {
             Ok(ret) => ret,
-            Err(_) => return crate::PanicOrDefault::value(),
+            Err(payload) => {
+                $crate::panic::record_panic(payload.as_ref());
+                return $crate::PanicOrDefault::value();
+            }
         }
     }
 }
+
+thread_local! {
+    /// The message of the last panic caught on this thread.
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Remember the message of a panic caught by ffi_panic_boundary!, for
+/// rustls_last_panic_message.
+pub(crate) fn record_panic(payload: &(dyn Any + Send)) {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panic with a non-string payload".to_string()
+    };
+    LAST_PANIC.with(|p| *p.borrow_mut() = Some(message));
+}
+
+/// Write the message of the most recent Rust panic caught on the calling
+/// thread into `buf`, which can hold up to `len` bytes, and store its
+/// length in `out_n`. The message is UTF-8 and is not NUL-terminated.
+///
+/// crustls never lets a panic unwind into C. Instead the function that
+/// panicked returns RUSTLS_RESULT_PANIC, or if it doesn't return a
+/// rustls_result, false, 0 or NULL. Since those may also be ordinary
+/// results, the message can be used to find out whether, and why, a panic
+/// happened. A panic is always a bug in crustls or rustls, so the message is
+/// worth logging and reporting.
+///
+/// The message is kept per thread until the next panic on that thread
+/// replaces it. Returns RUSTLS_RESULT_NOT_FOUND if there has been no panic
+/// on this thread, and RUSTLS_RESULT_INSUFFICIENT_SIZE, with the required
+/// length in `out_n`, if `len` is too small.
+#[no_mangle]
+pub extern "C" fn rustls_last_panic_message(
+    buf: *mut u8,
+    len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let write_buf: &mut [u8] = try_mut_slice!(buf, len);
+        let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        LAST_PANIC.with(|p| match p.borrow().as_deref() {
+            None => rustls_result::NotFound,
+            Some(message) if message.len() > write_buf.len() => {
+                *out_n = message.len();
+                rustls_result::InsufficientSize
+            }
+            Some(message) => {
+                write_buf[..message.len()].copy_from_slice(message.as_bytes());
+                *out_n = message.len();
+                rustls_result::Ok
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panics(message: &str) -> bool {
+        ffi_panic_boundary! {
+            if !message.is_empty() {
+                panic!("{}", message);
+            }
+            true
+        }
+    }
+
+    fn last_panic_message() -> (rustls_result, String) {
+        let mut buf = [0u8; 64];
+        let mut n = 0;
+        let result = rustls_last_panic_message(buf.as_mut_ptr(), buf.len(), &mut n);
+        (result, String::from_utf8(buf[..n].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_last_panic_message() {
+        // Each test runs on a thread of its own.
+        assert_eq!(last_panic_message().0, rustls_result::NotFound);
+        assert!(panics(""));
+        assert_eq!(last_panic_message().0, rustls_result::NotFound);
+        assert!(!panics("first"));
+        assert_eq!(
+            last_panic_message(),
+            (rustls_result::Ok, "first".to_string())
+        );
+        assert!(!panics("second"));
+        assert_eq!(
+            last_panic_message(),
+            (rustls_result::Ok, "second".to_string())
+        );
+
+        let mut buf = [0u8; 3];
+        let mut n = 0;
+        let result = rustls_last_panic_message(buf.as_mut_ptr(), buf.len(), &mut n);
+        assert_eq!((result, n), (rustls_result::InsufficientSize, 6));
+    }
+}
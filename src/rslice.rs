@@ -1,10 +1,14 @@
 use libc::{c_char, size_t};
+use std::cmp::min;
 use std::marker::PhantomData;
 use std::{
     convert::{TryFrom, TryInto},
     ptr::null,
 };
 
+use crate::error::rustls_result::{self, NullParameter};
+use crate::{ffi_panic_boundary, CastPtr};
+
 /// A read-only view on a Rust byte slice.
 ///
 /// This is used to pass data from crustls to callback functions provided
@@ -15,7 +19,8 @@ use std::{
 /// using this in its signature. For instance, when this is a parameter to a
 /// callback, the lifetime will usually be the duration of the callback.
 /// Functions that receive one of these must not dereference the data pointer
-/// beyond the allowed lifetime.
+/// beyond the allowed lifetime. To keep the bytes for longer, copy them into
+/// memory you own, for instance with rustls_slice_bytes_copy.
 #[repr(C)]
 pub struct rustls_slice_bytes<'a> {
     pub data: *const u8,
@@ -23,6 +28,10 @@ pub struct rustls_slice_bytes<'a> {
     phantom: PhantomData<&'a [u8]>,
 }
 
+impl<'a> CastPtr for rustls_slice_bytes<'a> {
+    type RustType = rustls_slice_bytes<'a>;
+}
+
 impl<'a> From<&'a [u8]> for rustls_slice_bytes<'a> {
     fn from(s: &[u8]) -> Self {
         rustls_slice_bytes {
@@ -44,6 +53,65 @@ fn test_rustls_slice_bytes() {
     }
 }
 
+/// Copy the bytes viewed by `src` into `dst`, which can hold up to `dst_len`
+/// bytes, so they can be used after `src` is no longer valid. The number of
+/// bytes in `src` is always stored in `out_n`.
+///
+/// If `dst` is too small, copies the first `dst_len` bytes and returns
+/// RUSTLS_RESULT_INSUFFICIENT_SIZE; the caller can retry with a buffer of
+/// `*out_n` bytes. `dst` may be NULL if `dst_len` is 0, to ask for the size.
+#[no_mangle]
+pub extern "C" fn rustls_slice_bytes_copy(
+    src: *const rustls_slice_bytes,
+    dst: *mut u8,
+    dst_len: size_t,
+    out_n: *mut size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let src: &rustls_slice_bytes = match unsafe { src.as_ref() } {
+            Some(s) => s,
+            None => return NullParameter,
+        };
+        let out_n: &mut size_t = match unsafe { out_n.as_mut() } {
+            Some(n) => n,
+            None => return NullParameter,
+        };
+        if (src.data.is_null() && src.len > 0) || (dst.is_null() && dst_len > 0) {
+            return NullParameter;
+        }
+        *out_n = src.len;
+        let n = min(src.len, dst_len);
+        if n > 0 {
+            unsafe { std::ptr::copy_nonoverlapping(src.data, dst, n) };
+        }
+        if n < src.len {
+            return rustls_result::InsufficientSize;
+        }
+        rustls_result::Ok
+    }
+}
+
+#[test]
+fn test_rustls_slice_bytes_copy() {
+    let bytes = b"abcd";
+    let rsb: rustls_slice_bytes = bytes.as_ref().into();
+    let mut buf = [0u8; 8];
+    let mut n = 0;
+    let result = rustls_slice_bytes_copy(&rsb, buf.as_mut_ptr(), buf.len(), &mut n);
+    assert_eq!(result, rustls_result::Ok);
+    assert_eq!(&buf[..n], b"abcd");
+
+    let mut buf = [0u8; 3];
+    let result = rustls_slice_bytes_copy(&rsb, buf.as_mut_ptr(), buf.len(), &mut n);
+    assert_eq!(result, rustls_result::InsufficientSize);
+    assert_eq!(n, 4);
+    assert_eq!(&buf, b"abc");
+
+    let result = rustls_slice_bytes_copy(&rsb, std::ptr::null_mut(), 0, &mut n);
+    assert_eq!(result, rustls_result::InsufficientSize);
+    assert_eq!(n, 4);
+}
+
 /// A read-only view of a slice of Rust byte slices.
 ///
 /// This is used to pass data from crustls to callback functions provided
@@ -135,7 +203,9 @@ fn test_rustls_slice_slice_bytes() {
 /// using this in its signature. For instance, when this is a parameter to a
 /// callback, the lifetime will usually be the duration of the callback.
 /// Functions that receive one of these must not dereference the data pointer
-/// beyond the allowed lifetime.
+/// beyond the allowed lifetime. To keep the string for longer, copy `len`
+/// bytes from `data` into memory you own (and NUL-terminate the copy if you
+/// want a C string).
 #[repr(C)]
 pub struct rustls_str<'a> {
     pub data: *const c_char,
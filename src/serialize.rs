@@ -0,0 +1,205 @@
+// A compact binary form of the settings of a client config that are slow to
+// rebuild or commonly set, so that short-lived processes can save a config
+// once and load it quickly: trust anchors are stored already extracted from
+// their certificates, so loading them parses nothing.
+//
+// The format is the magic bytes "CRCC" and a version byte, then:
+//
+//   versions:     u8 count, then each ProtocolVersion as a u16
+//   ciphersuites: u8 count, then each suite's IANA value as a u16
+//   ALPN:         u16 count, then each protocol name with a u8 length
+//   roots:        u32 count, then each trust anchor's subject and
+//                 SubjectPublicKeyInfo with u16 lengths, and its name
+//                 constraints as a u8 presence flag and a u16 length
+//
+// All integers are big-endian. Nothing secret is stored, and neither is
+// anything that can't be reproduced from bytes, such as callbacks.
+
+use rustls::{ClientConfig, OwnedTrustAnchor, ALL_CIPHERSUITES};
+
+use crate::enums::rustls_tls_version_from_u16;
+
+const MAGIC: &[u8] = b"CRCC";
+const FORMAT_VERSION: u8 = 1;
+
+/// BadFormat represents any failure to load a serialized config: the input
+/// is truncated, has trailing data, is from another format version, or
+/// names a protocol version or cipher suite rustls doesn't support.
+#[derive(Debug, PartialEq)]
+pub(crate) struct BadFormat {}
+
+/// Serialize the protocol versions, cipher suites, ALPN protocols and
+/// trusted roots of `config`. Returns None if one of them is too large
+/// for the format.
+pub(crate) fn encode(config: &ClientConfig) -> Option<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    out.push(FORMAT_VERSION);
+
+    push_count(&mut out, config.versions.len(), 1)?;
+    for version in &config.versions {
+        out.extend_from_slice(&version.get_u16().to_be_bytes());
+    }
+    push_count(&mut out, config.ciphersuites.len(), 1)?;
+    for suite in &config.ciphersuites {
+        out.extend_from_slice(&suite.suite.get_u16().to_be_bytes());
+    }
+    push_count(&mut out, config.alpn_protocols.len(), 2)?;
+    for protocol in &config.alpn_protocols {
+        push_prefixed(&mut out, protocol, 1)?;
+    }
+    push_count(&mut out, config.root_store.roots.len(), 4)?;
+    for root in &config.root_store.roots {
+        let anchor = root.to_trust_anchor();
+        push_prefixed(&mut out, anchor.subject, 2)?;
+        push_prefixed(&mut out, anchor.spki, 2)?;
+        match anchor.name_constraints {
+            Some(constraints) => {
+                out.push(1);
+                push_prefixed(&mut out, constraints, 2)?;
+            }
+            None => out.push(0),
+        }
+    }
+    Some(out)
+}
+
+/// Replace the protocol versions, cipher suites, ALPN protocols and
+/// trusted roots of `config` with those serialized in `input`. On error,
+/// `config` is unchanged.
+pub(crate) fn decode(mut input: &[u8], config: &mut ClientConfig) -> Result<(), BadFormat> {
+    if take(&mut input, MAGIC.len())? != MAGIC || take(&mut input, 1)? != [FORMAT_VERSION] {
+        return Err(BadFormat {});
+    }
+
+    let mut versions = Vec::new();
+    for _ in 0..take_count(&mut input, 1)? {
+        match rustls_tls_version_from_u16(take_u16(&mut input)?) {
+            v @ rustls::ProtocolVersion::TLSv1_2 | v @ rustls::ProtocolVersion::TLSv1_3 => {
+                versions.push(v)
+            }
+            _ => return Err(BadFormat {}),
+        }
+    }
+    let mut ciphersuites = Vec::new();
+    for _ in 0..take_count(&mut input, 1)? {
+        let value = take_u16(&mut input)?;
+        match ALL_CIPHERSUITES
+            .iter()
+            .find(|cs| cs.suite.get_u16() == value)
+        {
+            Some(&suite) => ciphersuites.push(suite),
+            None => return Err(BadFormat {}),
+        }
+    }
+    let mut alpn_protocols = Vec::new();
+    for _ in 0..take_count(&mut input, 2)? {
+        alpn_protocols.push(take_prefixed(&mut input, 1)?.to_vec());
+    }
+    let mut roots = Vec::new();
+    for _ in 0..take_count(&mut input, 4)? {
+        let subject = take_prefixed(&mut input, 2)?;
+        let spki = take_prefixed(&mut input, 2)?;
+        let name_constraints = match take(&mut input, 1)? {
+            [0] => None,
+            [1] => Some(take_prefixed(&mut input, 2)?),
+            _ => return Err(BadFormat {}),
+        };
+        roots.push(OwnedTrustAnchor::from_trust_anchor(&webpki::TrustAnchor {
+            subject,
+            spki,
+            name_constraints,
+        }));
+    }
+    if !input.is_empty() {
+        return Err(BadFormat {});
+    }
+
+    config.versions = versions;
+    config.ciphersuites = ciphersuites;
+    config.alpn_protocols = alpn_protocols;
+    config.root_store.roots = roots;
+    Ok(())
+}
+
+/// Append `count` as a big-endian integer of `size` bytes.
+fn push_count(out: &mut Vec<u8>, count: usize, size: usize) -> Option<()> {
+    if size < 8 && count >> (8 * size) != 0 {
+        return None;
+    }
+    out.extend_from_slice(&(count as u64).to_be_bytes()[8 - size..]);
+    Some(())
+}
+
+/// Append `data` with its length as a big-endian integer of `size` bytes.
+fn push_prefixed(out: &mut Vec<u8>, data: &[u8], size: usize) -> Option<()> {
+    push_count(out, data.len(), size)?;
+    out.extend_from_slice(data);
+    Some(())
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], BadFormat> {
+    if input.len() < n {
+        return Err(BadFormat {});
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+fn take_u16(input: &mut &[u8]) -> Result<u16, BadFormat> {
+    Ok(take_count(input, 2)? as u16)
+}
+
+/// Read a big-endian integer of `size` bytes.
+fn take_count(input: &mut &[u8], size: usize) -> Result<usize, BadFormat> {
+    Ok(take(input, size)?
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 8) | b as usize))
+}
+
+fn take_prefixed<'a>(input: &mut &'a [u8], size: usize) -> Result<&'a [u8], BadFormat> {
+    let len = take_count(input, size)?;
+    take(input, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use rustls::ProtocolVersion;
+
+    #[test]
+    fn test_round_trip() {
+        let mut config = testutil::client_config();
+        config.versions = vec![ProtocolVersion::TLSv1_2];
+        config.ciphersuites = ALL_CIPHERSUITES[3..5].to_vec();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let encoded = encode(&config).unwrap();
+
+        let mut decoded = ClientConfig::new();
+        assert_eq!(decode(&encoded, &mut decoded), Ok(()));
+        assert_eq!(decoded.versions, config.versions);
+        let suites = |c: &ClientConfig| -> Vec<u16> {
+            c.ciphersuites.iter().map(|cs| cs.suite.get_u16()).collect()
+        };
+        assert_eq!(suites(&decoded), suites(&config));
+        assert_eq!(decoded.alpn_protocols, config.alpn_protocols);
+        assert_eq!(decoded.root_store.len(), 1);
+        assert_eq!(encode(&decoded), Some(encoded.clone()));
+
+        // Every prefix is truncated, and trailing data is rejected.
+        for len in 0..encoded.len() {
+            let mut untouched = ClientConfig::new();
+            assert_eq!(decode(&encoded[..len], &mut untouched), Err(BadFormat {}));
+            assert!(untouched.root_store.is_empty());
+        }
+        let mut longer = encoded.clone();
+        longer.push(0);
+        assert_eq!(decode(&longer, &mut decoded), Err(BadFormat {}));
+
+        // An unsupported protocol version.
+        let mut tls11 = encoded;
+        tls11[6..8].copy_from_slice(&[0x03, 0x02]);
+        assert_eq!(decode(&tls11, &mut decoded), Err(BadFormat {}));
+    }
+}
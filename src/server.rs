@@ -1,31 +1,37 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::ffi::c_void;
 use std::ptr::null_mut;
 use std::slice;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-use libc::size_t;
+use libc::{c_int, size_t};
 use rustls::sign::CertifiedKey;
 use rustls::{
     AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, NoClientAuth,
-    ServerConfig, ServerSession,
+    ServerConfig,
+};
+use rustls::{
+    NoServerSessionStorage, ResolvesServerCert, ServerSessionMemoryCache, ALL_CIPHERSUITES,
 };
-use rustls::{ResolvesServerCert, ALL_CIPHERSUITES};
 use rustls::{SignatureScheme, SupportedCipherSuite};
 
 use crate::cipher::{
-    rustls_certified_key, rustls_client_cert_verifier, rustls_client_cert_verifier_optional,
-    rustls_supported_ciphersuite,
+    certified_key_build, rustls_certified_key, rustls_client_cert_verifier,
+    rustls_client_cert_verifier_optional, rustls_supported_ciphersuite,
 };
 use crate::connection::{rustls_connection, Connection};
-use crate::enums::rustls_tls_version_from_u16;
+use crate::enums::{
+    rustls_hash_algorithm, rustls_tls_version_from_u16, signature_schemes_from_u16s,
+};
 use crate::error::rustls_result;
 use crate::error::rustls_result::{InvalidParameter, NullParameter};
+use crate::hooks::{self, AlpnSelection};
 use crate::rslice::{rustls_slice_bytes, rustls_slice_slice_bytes, rustls_slice_u16, rustls_str};
 use crate::session::{
     rustls_session_store_get_callback, rustls_session_store_put_callback, SessionStoreBroker,
     SessionStoreGetCallback, SessionStorePutCallback,
 };
+use crate::verify::ClientCertPolicy;
 use crate::{
     arc_with_incref_from_raw, ffi_panic_boundary, try_mut_from_ptr, try_mut_slice,
     try_ref_from_ptr, try_slice, userdata_get, CastPtr,
@@ -36,7 +42,7 @@ use crate::{
 /// done configuring settings, call rustls_server_config_builder_build
 /// to turn it into a *rustls_server_config. This object is not safe
 /// for concurrent mutation. Under the hood, it corresponds to a
-/// Box<ServerConfig>.
+/// Box<ServerConfigBuilder>.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html
 pub struct rustls_server_config_builder {
     // We use the opaque struct pattern to tell C about our types without
@@ -46,11 +52,55 @@ pub struct rustls_server_config_builder {
 }
 
 impl CastPtr for rustls_server_config_builder {
-    type RustType = ServerConfig;
+    type RustType = ServerConfigBuilder;
+}
+
+pub(crate) struct ServerConfigBuilder {
+    config: ServerConfig,
+    /// Set with rustls_server_config_builder_set_min_rsa_key_bits. On build,
+    /// a nonzero minimum is added to the config's client cert verifier.
+    min_rsa_key_bits: u32,
+    /// Set with rustls_server_config_builder_set_signature_schemes, and
+    /// likewise added to the client cert verifier on build.
+    signature_schemes: Option<Vec<SignatureScheme>>,
+    /// The cert resolver is an AlpnSelector, so server connections must
+    /// keep the ClientHello to replay it.
+    selects_alpn: bool,
+}
+
+impl ServerConfigBuilder {
+    pub(crate) fn new(config: ServerConfig) -> Self {
+        ServerConfigBuilder {
+            config,
+            min_rsa_key_bits: 0,
+            signature_schemes: None,
+            selects_alpn: false,
+        }
+    }
+
+    pub(crate) fn build(self) -> BuiltServerConfig {
+        let mut config = self.config;
+        if self.min_rsa_key_bits > 0 || self.signature_schemes.is_some() {
+            let policy = ClientCertPolicy {
+                config: config.clone(),
+                min_rsa_key_bits: self.min_rsa_key_bits,
+                signature_schemes: self.signature_schemes,
+            };
+            config.set_client_certificate_verifier(Arc::new(policy));
+        }
+        let cert_reporter = Arc::new(CertReporter::new(config.cert_resolver.clone()));
+        config.cert_resolver = cert_reporter.clone();
+        BuiltServerConfig {
+            config: Arc::new(config),
+            cert_reporter,
+            selects_alpn: self.selects_alpn,
+        }
+    }
 }
 
 /// A server config that is done being constructed and is now read-only.
-/// Under the hood, this object corresponds to an Arc<ServerConfig>.
+/// Under the hood, this object corresponds to an Arc<BuiltServerConfig>,
+/// which holds an Arc<ServerConfig>.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html
 pub struct rustls_server_config {
     // We use the opaque struct pattern to tell C about our types without
@@ -60,7 +110,15 @@ pub struct rustls_server_config {
 }
 
 impl CastPtr for rustls_server_config {
-    type RustType = ServerConfig;
+    type RustType = BuiltServerConfig;
+}
+
+pub(crate) struct BuiltServerConfig {
+    config: Arc<ServerConfig>,
+    /// The config's cert resolver, which rustls only lets us see as a
+    /// ResolvesServerCert.
+    cert_reporter: Arc<CertReporter>,
+    selects_alpn: bool,
 }
 
 /// Create a rustls_server_config_builder. Caller owns the memory and must
@@ -71,7 +129,7 @@ impl CastPtr for rustls_server_config {
 pub extern "C" fn rustls_server_config_builder_new() -> *mut rustls_server_config_builder {
     ffi_panic_boundary! {
         let config = rustls::ServerConfig::new(Arc::new(NoClientAuth));
-        let b = Box::new(config);
+        let b = Box::new(ServerConfigBuilder::new(config));
         Box::into_raw(b) as *mut _
     }
 }
@@ -93,7 +151,7 @@ pub extern "C" fn rustls_server_config_builder_with_client_verifier(
             }
         };
         let config = rustls::ServerConfig::new(verifier);
-        let b = Box::new(config);
+        let b = Box::new(ServerConfigBuilder::new(config));
         Box::into_raw(b) as *mut rustls_server_config_builder
     }
 }
@@ -115,7 +173,7 @@ pub extern "C" fn rustls_server_config_builder_with_client_verifier_optional(
             }
         };
         let config = rustls::ServerConfig::new(verifier);
-        let b = Box::new(config);
+        let b = Box::new(ServerConfigBuilder::new(config));
         Box::into_raw(b) as *mut rustls_server_config_builder
     }
 }
@@ -128,9 +186,9 @@ pub extern "C" fn rustls_server_config_builder_with_client_verifier_optional(
 #[no_mangle]
 pub extern "C" fn rustls_server_config_builder_free(config: *mut rustls_server_config_builder) {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(config);
+        let config: &mut ServerConfigBuilder = try_mut_from_ptr!(config);
         // Convert the pointer to a Box and drop it.
-        unsafe { Box::from_raw(config); }
+        unsafe { drop(Box::from_raw(config)) }
     }
 }
 
@@ -142,8 +200,78 @@ pub extern "C" fn rustls_server_config_builder_from_config(
     config: *const rustls_server_config,
 ) -> *mut rustls_server_config_builder {
     ffi_panic_boundary! {
-        let config: &ServerConfig = try_ref_from_ptr!(config);
-        Box::into_raw(Box::new(config.clone())) as *mut _
+        let config: &BuiltServerConfig = try_ref_from_ptr!(config);
+        let mut builder = config.config.as_ref().clone();
+        // Start from the current key, but don't follow later changes to it.
+        let reporter = &config.cert_reporter;
+        builder.cert_resolver = match reporter.current_key() {
+            Some(key) => Arc::new(CertReporter {
+                inner: reporter.inner.clone(),
+                key: RwLock::new(Some(key)),
+            }),
+            None => reporter.inner.clone(),
+        };
+        let mut builder = ServerConfigBuilder::new(builder);
+        builder.selects_alpn = config.selects_alpn;
+        Box::into_raw(Box::new(builder)) as *mut _
+    }
+}
+
+/// Reject clients whose end-entity certificate has an RSA key of fewer than
+/// `bits` bits, for example to enforce a 2048-bit minimum. Keys of other
+/// types are not affected, and neither are clients that send no
+/// certificate where that is allowed. rustls_connection_process_new_packets
+/// returns `RUSTLS_RESULT_WEAK_KEY` for a rejected certificate. By default
+/// any size that webpki accepts is allowed.
+///
+/// The minimum is applied to whichever client cert verifier the builder
+/// has when rustls_server_config_builder_build is called. Calling this more
+/// than once doesn't relax a minimum set earlier.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_min_rsa_key_bits(
+    builder: *mut rustls_server_config_builder,
+    bits: u32,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        builder.min_rsa_key_bits = builder.min_rsa_key_bits.max(bits);
+        rustls_result::Ok
+    }
+}
+
+/// Restrict the signature schemes used for client authentication to the
+/// `count` IANA-assigned values at `schemes`: only these are requested from
+/// the client, and the client's handshake signature must use one of them.
+/// Returns RUSTLS_RESULT_INVALID_PARAMETER if the list is empty or contains
+/// a scheme rustls doesn't support.
+///
+/// The server's own handshake signature uses a scheme the client offers,
+/// so it isn't affected. Like `rustls_server_config_builder_set_min_rsa_key_bits`,
+/// this is applied to the builder's client cert verifier when the config is
+/// built, and calling it again can only narrow the list further. If the
+/// narrowed list would be empty, this returns RUSTLS_RESULT_INVALID_PARAMETER
+/// and keeps the earlier list.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_signature_schemes(
+    builder: *mut rustls_server_config_builder,
+    schemes: *const u16,
+    count: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        let schemes = match signature_schemes_from_u16s(try_slice!(schemes, count)) {
+            Some(schemes) => schemes,
+            None => return rustls_result::InvalidParameter,
+        };
+        let schemes: Vec<SignatureScheme> = match &builder.signature_schemes {
+            Some(earlier) => earlier.iter().filter(|s| schemes.contains(s)).cloned().collect(),
+            None => schemes,
+        };
+        if schemes.is_empty() {
+            return rustls_result::InvalidParameter;
+        }
+        builder.signature_schemes = Some(schemes);
+        rustls_result::Ok
     }
 }
 
@@ -162,7 +290,7 @@ pub extern "C" fn rustls_server_config_builder_set_versions(
     len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
         let tls_versions: &[u16] = try_slice!(tls_versions, len);
         config.versions.clear();
 
@@ -176,9 +304,14 @@ pub extern "C" fn rustls_server_config_builder_set_versions(
     }
 }
 
-/// With `ignore` != 0, the server will ignore the client ordering of cipher
-/// suites, aka preference, during handshake and respect its own ordering
-/// as configured.
+/// With `ignore` true, the server chooses the first cipher suite in its own
+/// list (see rustls_server_config_builder_set_ciphersuites) that the client
+/// also supports, rather than the client's most preferred suite that the
+/// server supports. The default is false: rustls honors the client's
+/// preference. Either way, only suites on both lists are considered, and
+/// this has no effect on the choice of protocol version or key exchange
+/// group. Use this when server-side ordering is required, e.g. for
+/// compliance.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html#fields
 #[no_mangle]
 pub extern "C" fn rustls_server_config_builder_set_ignore_client_order(
@@ -186,12 +319,86 @@ pub extern "C" fn rustls_server_config_builder_set_ignore_client_order(
     ignore: bool,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
         config.ignore_client_order = ignore;
         rustls_result::Ok
     }
 }
 
+/// Enable or disable TLS certificate compression (RFC 8879). When disabled,
+/// the server will not compress its certificate chain even if the client
+/// offers compression algorithms.
+///
+/// The version of rustls used by crustls has no support for RFC 8879, so
+/// certificate compression is never negotiated and this call has no
+/// effect. Passing `false` matches that and returns RUSTLS_RESULT_OK;
+/// passing `true` returns RUSTLS_RESULT_UNIMPLEMENTED, so callers aren't
+/// told compression is on. Once crustls moves to a rustls release that
+/// supports compression, `true` will enable it.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_cert_compression(
+    builder: *mut rustls_server_config_builder,
+    enabled: bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        if enabled {
+            return rustls_result::Unimplemented;
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Enable or disable TLS 1.3 middlebox compatibility mode (RFC 8446 appendix
+/// D.4), in which the server echoes the client's legacy session ID and sends a
+/// dummy ChangeCipherSpec record so the handshake looks more like TLS 1.2
+/// resumption to middleboxes that would otherwise interfere with it.
+///
+/// The version of rustls used by crustls always sends the compatibility
+/// messages in TLS 1.3 handshakes and cannot turn them off, so this changes
+/// nothing: compatibility mode is always enabled. Passing `true` returns
+/// RUSTLS_RESULT_OK; passing `false` returns RUSTLS_RESULT_UNSUPPORTED.
+/// Returns RUSTLS_RESULT_NULL_PARAMETER if `builder` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_tls13_compat_mode(
+    builder: *mut rustls_server_config_builder,
+    enabled: bool,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        if !enabled {
+            return rustls_result::Unsupported;
+        }
+        rustls_result::Ok
+    }
+}
+
+/// Set the largest plaintext fragment that will be put in one outgoing TLS
+/// record, for example to keep records within a path MTU. Larger writes are
+/// split across several records. `max_fragment_size` must be between 32 and
+/// 16384 (the TLS maximum and the default), or 0 to restore the default.
+/// This only affects what is sent; it does not negotiate the max_fragment_length
+/// extension with the peer.
+///
+/// Returns `RUSTLS_RESULT_INVALID_PARAMETER` if `max_fragment_size` is out
+/// of range.
+/// https://docs.rs/rustls/0.19.0/rustls/struct.ServerConfig.html#structfield.mtu
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_max_fragment_size(
+    builder: *mut rustls_server_config_builder,
+    max_fragment_size: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
+        config.mtu = match max_fragment_size {
+            0 => None,
+            32..=16384 => Some(max_fragment_size),
+            _ => return InvalidParameter,
+        };
+        rustls_result::Ok
+    }
+}
+
 /// Set the ALPN protocol list to the given protocols. `protocols` must point
 /// to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
 /// elements. Each element of the buffer must point to a slice of bytes that
@@ -209,7 +416,7 @@ pub extern "C" fn rustls_server_config_builder_set_protocols(
     len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
         let protocols: &[rustls_slice_bytes] = try_slice!(protocols, len);
 
         let mut vv: Vec<Vec<u8>> = Vec::new();
@@ -234,10 +441,10 @@ pub extern "C" fn rustls_server_config_builder_set_ciphersuites(
     len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
         let ciphersuites: &[*const rustls_supported_ciphersuite] = try_slice!(ciphersuites, len);
         let mut cs_vec: Vec<&'static SupportedCipherSuite> = Vec::new();
-        for &cs in ciphersuites.into_iter() {
+        for &cs in ciphersuites.iter() {
             let cs = try_ref_from_ptr!(cs);
             match ALL_CIPHERSUITES.iter().find(|&acs| cs.eq(acs)) {
                 Some(scs) => cs_vec.push(scs),
@@ -268,7 +475,7 @@ pub extern "C" fn rustls_server_config_builder_set_certified_keys(
     certified_keys_len: size_t,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
         let keys_ptrs: &[*const rustls_certified_key] = try_slice!(certified_keys, certified_keys_len);
         let mut keys: Vec<Arc<CertifiedKey>> = Vec::new();
         for &key_ptr in keys_ptrs {
@@ -281,7 +488,37 @@ pub extern "C" fn rustls_server_config_builder_set_certified_keys(
             };
             keys.push(certified_key);
         }
-        config.cert_resolver = Arc::new(ResolvesServerCertFromChoices::new(&keys));
+        builder.config.cert_resolver = Arc::new(ResolvesServerCertFromChoices::new(&keys));
+        builder.selects_alpn = false;
+        rustls_result::Ok
+    }
+}
+
+/// Serve a single certificate chain and private key, in the same formats
+/// as for `rustls_certified_key_build`. This does the work of building a
+/// `rustls_certified_key` with that function, passing it to
+/// `rustls_server_config_builder_set_certified_keys` and freeing it, in
+/// one call, and returns the same errors, such as
+/// RUSTLS_RESULT_PRIVATE_KEY_PARSE_ERROR. On error, the builder is
+/// unchanged.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_single_cert(
+    builder: *mut rustls_server_config_builder,
+    cert_chain: *const u8,
+    cert_chain_len: size_t,
+    private_key: *const u8,
+    private_key_len: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        let certified_key = match certified_key_build(
+            cert_chain, cert_chain_len, private_key, private_key_len) {
+            Ok(key) => Arc::new(key),
+            Err(rr) => return rr,
+        };
+        builder.config.cert_resolver =
+            Arc::new(ResolvesServerCertFromChoices::new(&[certified_key]));
+        builder.selects_alpn = false;
         rustls_result::Ok
     }
 }
@@ -293,9 +530,41 @@ pub extern "C" fn rustls_server_config_builder_build(
     builder: *mut rustls_server_config_builder,
 ) -> *const rustls_server_config {
     ffi_panic_boundary! {
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
-        let b = unsafe { Box::from_raw(config) };
-        Arc::into_raw(Arc::new(*b)) as *const _
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        let b = unsafe { Box::from_raw(builder) };
+        Arc::into_raw(Arc::new(b.build())) as *const _
+    }
+}
+
+/// Replace the certificate and key served by a config that is already in
+/// use, for example to rotate a certificate before it expires without
+/// building a new config. Handshakes that choose their certificate after
+/// this returns serve `new_key`; connections that already chose one,
+/// including ones still handshaking, keep it. The swap is guarded by a
+/// lock, so it is safe to call while other threads are creating or
+/// driving connections that use `config`, and every thread sees the new
+/// key once this returns. Resumed sessions keep the certificate of the
+/// session they resume.
+///
+/// `new_key` replaces the keys set with
+/// rustls_server_config_builder_set_certified_keys, and the key returned
+/// by a hello callback, which is still called. Like those keys, it is only
+/// served to clients that support one of its signature schemes. The config
+/// keeps its own reference to `new_key`, so the caller may free it
+/// afterwards. A config made from this one with
+/// rustls_server_config_builder_from_config starts with `new_key`, but is
+/// not affected by later calls on this config.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_set_certified_key_atomic(
+    config: *const rustls_server_config,
+    new_key: *const rustls_certified_key,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let config: &BuiltServerConfig = try_ref_from_ptr!(config);
+        let new_key: &CertifiedKey = try_ref_from_ptr!(new_key);
+        let new_key: Arc<CertifiedKey> = unsafe { arc_with_incref_from_raw(new_key) };
+        *config.cert_reporter.key.write().unwrap_or_else(|e| e.into_inner()) = Some(new_key);
+        rustls_result::Ok
     }
 }
 
@@ -308,10 +577,10 @@ pub extern "C" fn rustls_server_config_builder_build(
 #[no_mangle]
 pub extern "C" fn rustls_server_config_free(config: *const rustls_server_config) {
     ffi_panic_boundary! {
-        let config: &ServerConfig = try_ref_from_ptr!(config);
+        let config: &BuiltServerConfig = try_ref_from_ptr!(config);
         // To free the server_config, we reconstruct the Arc. It should have a refcount of 1,
         // representing the C code's copy. When it drops, that refcount will go down to 0
-        // and the inner ServerConfig will be dropped.
+        // and the inner ServerConfig will be dropped once no connection uses it.
         unsafe { drop(Arc::from_raw(config)) };
     }
 }
@@ -328,17 +597,12 @@ pub extern "C" fn rustls_server_connection_new(
     conn_out: *mut *mut rustls_connection,
 ) -> rustls_result {
     ffi_panic_boundary! {
-        let config: Arc<ServerConfig> = unsafe {
-            match (config as *const ServerConfig).as_ref() {
-                Some(c) => arc_with_incref_from_raw(c),
-                None => return NullParameter,
-            }
-        };
+        let config: &BuiltServerConfig = try_ref_from_ptr!(config);
 
         // We've succeeded. Put the server on the heap, and transfer ownership
         // to the caller. After this point, we must return CRUSTLS_OK so the
         // caller knows it is responsible for this memory.
-        let c = Connection::from_server(ServerSession::new(&config));
+        let c = Connection::from_server(&config.config, config.selects_alpn);
         unsafe {
             *conn_out = Box::into_raw(Box::new(c)) as *mut _;
         }
@@ -353,6 +617,13 @@ pub extern "C" fn rustls_server_connection_new(
 /// Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if the SNI hostname is longer than `count`.
 /// Returns Ok with *out_n == 0 if there is no SNI hostname available on this session
 /// because it hasn't been processed yet, or because the client did not send SNI.
+///
+/// rustls rejects a ClientHello whose SNI isn't a valid DNS name, so the
+/// handshake fails and no hostname is ever available for it. Any name that
+/// is returned is therefore printable ASCII, and is passed through as the
+/// client sent it apart from being lowercased. As a safeguard, a name that
+/// somehow isn't a valid DNS name is never copied out:
+/// RUSTLS_RESULT_INVALID_DNS_NAME_ERROR is returned with *out_n == 0 instead.
 /// https://docs.rs/rustls/0.19.0/rustls/struct.ServerSession.html#method.get_sni_hostname
 #[no_mangle]
 pub extern "C" fn rustls_server_connection_get_sni_hostname(
@@ -365,6 +636,7 @@ pub extern "C" fn rustls_server_connection_get_sni_hostname(
         let conn: &Connection = try_ref_from_ptr!(conn);
         let write_buf: &mut [u8] = try_mut_slice!(buf, count);
         let out_n: &mut size_t = try_mut_from_ptr!(out_n);
+        *out_n = 0;
         let server_session = match conn.as_server() {
             Some(s) => s,
             _ => return rustls_result::InvalidParameter,
@@ -375,16 +647,109 @@ pub extern "C" fn rustls_server_connection_get_sni_hostname(
                 return rustls_result::Ok
             },
         };
+        if webpki::DNSNameRef::try_from_ascii_str(sni_hostname).is_err() {
+            return rustls_result::InvalidDnsNameError;
+        }
         let len: usize = sni_hostname.len();
         if len > write_buf.len() {
             return rustls_result::InsufficientSize;
         }
-        write_buf[..len].copy_from_slice(&sni_hostname.as_bytes());
+        write_buf[..len].copy_from_slice(sni_hostname.as_bytes());
         *out_n = len;
         rustls_result::Ok
     }
 }
 
+/// Check that the certificate this server connection sent is valid for the
+/// SNI hostname the client sent, using the same name matching rules as
+/// certificate verification. This is a self-check for configurations that
+/// choose certificates by SNI, such as with
+/// `rustls_server_config_builder_set_hello_callback`.
+///
+/// Returns RUSTLS_RESULT_OK if the certificate matches, and also if the
+/// client sent no SNI, since there is then nothing to check; use
+/// `rustls_server_connection_get_sni_hostname` to tell these apart.
+/// Returns RUSTLS_RESULT_CERT_NOT_VALID_FOR_NAME if it doesn't match,
+/// RUSTLS_RESULT_NOT_FOUND if no certificate has been chosen, either
+/// because the handshake hasn't got that far or because the session was
+/// resumed, and RUSTLS_RESULT_INVALID_PARAMETER for a client connection.
+#[no_mangle]
+pub extern "C" fn rustls_connection_served_cert_matches_sni(
+    conn: *const rustls_connection,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let conn: &Connection = try_ref_from_ptr!(conn);
+        let server_session = match conn.as_server() {
+            Some(s) => s,
+            _ => return rustls_result::InvalidParameter,
+        };
+        let cert = match conn.served_cert() {
+            Some(cert) => cert,
+            None => return rustls_result::NotFound,
+        };
+        let sni_hostname = match server_session.get_sni_hostname() {
+            Some(sni_hostname) => sni_hostname,
+            None => return rustls_result::Ok,
+        };
+        let name = match webpki::DNSNameRef::try_from_ascii_str(sni_hostname) {
+            Ok(name) => name,
+            Err(_) => return rustls_result::InvalidDnsNameError,
+        };
+        let cert = match webpki::EndEntityCert::from(&cert.0) {
+            Ok(cert) => cert,
+            Err(_) => return rustls_result::CertificateParseError,
+        };
+        match cert.verify_is_valid_for_dns_name(name) {
+            Ok(()) => rustls_result::Ok,
+            Err(_) => rustls_result::CertNotValidForName,
+        }
+    }
+}
+
+/// Wraps the cert resolver of every config built by crustls, to record
+/// which certificate was chosen for each connection, for
+/// rustls_connection_served_cert_matches_sni, and to serve a key set with
+/// rustls_server_config_set_certified_key_atomic instead.
+struct CertReporter {
+    inner: Arc<dyn ResolvesServerCert>,
+    key: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl CertReporter {
+    fn new(inner: Arc<dyn ResolvesServerCert>) -> Self {
+        CertReporter {
+            inner,
+            key: RwLock::new(None),
+        }
+    }
+
+    fn current_key(&self) -> Option<Arc<CertifiedKey>> {
+        self.key.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl ResolvesServerCert for CertReporter {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let sigschemes = client_hello.sigschemes().to_vec();
+        // The inner resolver runs even when the key is replaced, so that
+        // the hello and ALPN select callbacks are still called.
+        let mut key = self.inner.resolve(client_hello)?;
+        if let Some(new_key) = self.current_key() {
+            new_key.key.choose_scheme(&sigschemes)?;
+            key = new_key.as_ref().clone();
+            hooks::report(|r| {
+                if let Some(selection) = &mut r.alpn_selection {
+                    selection.key = key.clone();
+                }
+            });
+        }
+        if let Ok(cert) = key.end_entity_cert() {
+            hooks::report(|r| r.served_cert = Some(cert.clone()));
+        }
+        Some(key)
+    }
+}
+
 /// Choose the server certificate to be used for a session based on certificate
 /// type. Will pick the first CertfiedKey available that is suitable for
 /// the SignatureSchemes supported by the client.
@@ -549,14 +914,178 @@ pub extern "C" fn rustls_server_config_builder_set_hello_callback(
             Some(cb) => cb,
             None => return rustls_result::NullParameter,
         };
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
-        config.cert_resolver = Arc::new(ClientHelloResolver::new(
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        builder.config.cert_resolver = Arc::new(ClientHelloResolver::new(
             callback
         ));
+        builder.selects_alpn = false;
+        rustls_result::Ok
+    }
+}
+
+/// Prototype of a callback that chooses the ALPN protocol for a server
+/// connection. `offered` points to `offered_len` protocol names, in the
+/// client's order of preference. The callback returns the index in
+/// `offered` of the protocol to use, or -1 to decline all of them.
+/// `userdata` will be set based on rustls_connection_set_userdata.
+///
+/// NOTE: `offered` and the protocol names are only available during the
+/// callback invocation. The callback must be thread-safe, unless there is
+/// only a single config and session where it is installed.
+pub type rustls_alpn_select_callback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        offered: *const rustls_slice_bytes,
+        offered_len: size_t,
+    ) -> c_int,
+>;
+
+type AlpnSelectCallback = unsafe extern "C" fn(
+    userdata: *mut c_void,
+    offered: *const rustls_slice_bytes,
+    offered_len: size_t,
+) -> c_int;
+
+/// Wraps a cert resolver, which is the only hook rustls calls with the
+/// ClientHello before choosing the ALPN protocol, to ask the callback for
+/// the protocol. rustls 0.19 only picks from the config's list, so the
+/// connection replays the ClientHello with a config listing just the
+/// chosen protocol; see `alpn_replay_config`.
+struct AlpnSelector {
+    inner: Arc<dyn ResolvesServerCert>,
+    callback: AlpnSelectCallback,
+}
+
+impl ResolvesServerCert for AlpnSelector {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        let offered: Vec<&[u8]> = client_hello.alpn().unwrap_or(&[]).to_vec();
+        let key = self.inner.resolve(client_hello)?;
+        if offered.is_empty() {
+            return Some(key);
+        }
+        let slices: Vec<rustls_slice_bytes> = offered.iter().map(|p| (*p).into()).collect();
+        let userdata = match userdata_get() {
+            Ok(u) => u,
+            Err(_) => return None,
+        };
+        let index = unsafe { (self.callback)(userdata, slices.as_ptr(), slices.len()) };
+        let protocol = usize::try_from(index)
+            .ok()
+            .and_then(|i| offered.get(i))
+            .map(|p| p.to_vec());
+        let selection = AlpnSelection {
+            protocol,
+            key: key.clone(),
+        };
+        hooks::report(|r| r.alpn_selection = Some(selection));
+        Some(key)
+    }
+}
+
+/// This struct can be considered thread safe, as long
+/// as the registered callbacks are thread safe. This is
+/// documented as a requirement in the API.
+unsafe impl Sync for AlpnSelector {}
+unsafe impl Send for AlpnSelector {}
+
+/// Register a callback that chooses the ALPN protocol for each connection
+/// from the protocols the client offers, instead of using the list set
+/// with rustls_server_config_builder_set_protocols. For example, it can
+/// prefer h2 for some clients and http/1.1 for others, based on state
+/// reachable from the connection's userdata. The callback is not called if
+/// the client offers no protocols. It is called after the certificate has
+/// been chosen, so register it once, after
+/// rustls_server_config_builder_set_certified_keys or
+/// rustls_server_config_builder_set_hello_callback: those replace the ALPN
+/// callback. It is not permitted to pass a NULL value for `callback`.
+///
+/// If the callback declines, rustls_connection_process_new_packets returns
+/// `RUSTLS_RESULT_NO_APPLICATION_PROTOCOL` and the connection fails. The
+/// version of rustls used by crustls can't send the no_application_protocol
+/// alert in that case, so the client sees the connection close instead.
+///
+/// rustls 0.19 can only choose from a fixed list, so after the callback
+/// runs the connection processes the ClientHello a second time, with the
+/// chosen protocol and the same certificate. Callbacks are not run again.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_alpn_select_callback(
+    builder: *mut rustls_server_config_builder,
+    callback: rustls_alpn_select_callback,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let callback: AlpnSelectCallback = match callback {
+            Some(cb) => cb,
+            None => return rustls_result::NullParameter,
+        };
+        let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        builder.config.cert_resolver = Arc::new(AlpnSelector {
+            inner: builder.config.cert_resolver.clone(),
+            callback,
+        });
+        builder.selects_alpn = true;
         rustls_result::Ok
     }
 }
 
+/// Prototype of a callback that looks up an external pre-shared key by the
+/// identity a client offers. If it knows the key, the callback writes it to
+/// `key_buf`, which can hold up to `key_buf_len` bytes, stores its length
+/// in `out_key_len` and its hash algorithm in `out_hash`, and returns
+/// RUSTLS_RESULT_OK. Otherwise it returns RUSTLS_RESULT_NOT_FOUND.
+/// `userdata` will be set based on rustls_connection_set_userdata.
+///
+/// NOTE: `identity` is only available during the callback invocation. The
+/// callback must be thread-safe, unless there is only a single config and
+/// session where it is installed.
+pub type rustls_psk_resolver_callback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        identity: *const rustls_slice_bytes,
+        key_buf: *mut u8,
+        key_buf_len: size_t,
+        out_key_len: *mut size_t,
+        out_hash: *mut rustls_hash_algorithm,
+    ) -> rustls_result,
+>;
+
+/// Register a callback that looks up the external pre-shared keys (RFC 8446
+/// section 2.2) offered by TLS 1.3 clients, so that clients can
+/// authenticate with a key set up out of band instead of a certificate. See
+/// rustls_client_config_builder_add_external_psk for the client side. It
+/// is not permitted to pass a NULL value for `callback`.
+///
+/// PLACEHOLDER: rustls 0.19 only supports pre-shared keys for session
+/// resumption, so this returns RUSTLS_RESULT_UNIMPLEMENTED and leaves the
+/// config unchanged.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_external_psk_resolver(
+    builder: *mut rustls_server_config_builder,
+    callback: rustls_psk_resolver_callback,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let _builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+        if callback.is_none() {
+            return rustls_result::NullParameter;
+        }
+        rustls_result::Unimplemented
+    }
+}
+
+/// A copy of `config` that negotiates `protocol` and serves `key`, for
+/// replaying a ClientHello after an ALPN select callback chose them.
+pub(crate) fn alpn_replay_config(
+    config: &ServerConfig,
+    protocol: Vec<u8>,
+    key: CertifiedKey,
+) -> ServerConfig {
+    let mut config = config.clone();
+    config.alpn_protocols = vec![protocol];
+    config.cert_resolver = Arc::new(CertReporter::new(Arc::new(
+        ResolvesServerCertFromChoices::new(&[Arc::new(key)]),
+    )));
+    config
+}
+
 // Turn a slice of u16's into a vec of SignatureScheme as needed by rustls.
 fn sigschemes(input: &[u16]) -> Vec<SignatureScheme> {
     use rustls::SignatureScheme::*;
@@ -646,10 +1175,502 @@ pub extern "C" fn rustls_server_config_builder_set_persistence(
             Some(cb) => cb,
             None => return rustls_result::NullParameter,
         };
-        let config: &mut ServerConfig = try_mut_from_ptr!(builder);
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
         config.set_persistence(Arc::new(SessionStoreBroker::new(
             get_cb, put_cb
         )));
         rustls_result::Ok
     }
 }
+
+/// Keep up to `size` sessions in rustls's built-in in-memory session cache,
+/// for resumption by session ID (TLS 1.2) or by stateful ticket (TLS 1.3).
+/// Each cached session holds its secrets and, if the client authenticated,
+/// its certificate chain, so this bounds the memory a busy server spends on
+/// resumption. A `size` of 0 disables the cache, so sessions are never
+/// resumed unless rustls_server_config_builder_set_persistence is used.
+///
+/// The default is a cache of 256 sessions. When the cache is full, storing a
+/// new session evicts an arbitrary existing one: the cache does not track
+/// use, so this is not least-recently-used eviction.
+///
+/// This replaces any callbacks set with
+/// rustls_server_config_builder_set_persistence, and vice versa: whichever
+/// is called last wins.
+#[no_mangle]
+pub extern "C" fn rustls_server_config_builder_set_session_cache_size(
+    builder: *mut rustls_server_config_builder,
+    size: size_t,
+) -> rustls_result {
+    ffi_panic_boundary! {
+        let config: &mut ServerConfig = &mut try_mut_from_ptr!(builder).config;
+        if size == 0 {
+            config.set_persistence(Arc::new(NoServerSessionStorage {}));
+        } else {
+            config.set_persistence(ServerSessionMemoryCache::new(size));
+        }
+        rustls_result::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::rustls_certified_key_free;
+    use crate::testutil;
+    use rustls::ciphersuite::{TLS13_AES_128_GCM_SHA256, TLS13_CHACHA20_POLY1305_SHA256};
+    use rustls::CipherSuite;
+    use rustls::{Certificate, ClientConfig, TLSError};
+
+    fn negotiated_suite(ignore_client_order: bool) -> CipherSuite {
+        let mut client_config = testutil::client_config();
+        client_config.ciphersuites =
+            vec![&TLS13_CHACHA20_POLY1305_SHA256, &TLS13_AES_128_GCM_SHA256];
+        let mut server_config = testutil::server_config();
+        server_config.ciphersuites =
+            vec![&TLS13_AES_128_GCM_SHA256, &TLS13_CHACHA20_POLY1305_SHA256];
+        let builder = new_builder(server_config);
+        let result =
+            rustls_server_config_builder_set_ignore_client_order(builder, ignore_client_order);
+        assert_eq!(result, rustls_result::Ok);
+
+        let (mut client, mut server) =
+            testutil::connection_pair(&Arc::new(client_config), &finish(builder));
+        testutil::handshake(&mut client, &mut server).unwrap();
+        server.as_ref().get_negotiated_ciphersuite().unwrap().suite
+    }
+
+    #[test]
+    fn test_ignore_client_order() {
+        assert_eq!(
+            negotiated_suite(false),
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+        );
+        assert_eq!(
+            negotiated_suite(true),
+            CipherSuite::TLS13_AES_128_GCM_SHA256
+        );
+    }
+
+    #[test]
+    fn test_set_cert_compression() {
+        let builder = new_builder(testutil::server_config());
+        let result = rustls_server_config_builder_set_cert_compression(builder, true);
+        assert_eq!(result, rustls_result::Unimplemented);
+        let result = rustls_server_config_builder_set_cert_compression(builder, false);
+        assert_eq!(result, rustls_result::Ok);
+        let result = rustls_server_config_builder_set_cert_compression(null_mut(), true);
+        assert_eq!(result, rustls_result::NullParameter);
+
+        let client_config = Arc::new(testutil::client_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &finish(builder));
+        testutil::handshake(&mut client, &mut server).unwrap();
+    }
+
+    fn sni_hostname(server: &Connection) -> (rustls_result, String) {
+        let mut buf = [0u8; 64];
+        let mut n = 12345;
+        let result = rustls_server_connection_get_sni_hostname(
+            server as *const Connection as *const rustls_connection,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        (result, String::from_utf8(buf[..n].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_min_rsa_key_bits() {
+        use rustls::RootCertStore;
+
+        let connect = |bits: u32, cert_pem: &[u8], key_pem: &[u8]| {
+            let mut roots = RootCertStore::empty();
+            roots.add(&testutil::certs(testutil::CA_PEM)[0]).unwrap();
+            let mut builder = ServerConfigBuilder::new(testutil::server_config());
+            let builder_ptr = &mut builder as *mut ServerConfigBuilder;
+            let result = rustls_server_config_builder_set_min_rsa_key_bits(
+                builder_ptr as *mut rustls_server_config_builder,
+                bits,
+            );
+            assert_eq!(result, rustls_result::Ok);
+            // The minimum applies to a verifier installed after it was set.
+            builder
+                .config
+                .set_client_certificate_verifier(AllowAnyAuthenticatedClient::new(roots));
+
+            let mut client_config = testutil::client_config();
+            client_config
+                .set_single_client_cert(testutil::certs(cert_pem), testutil::private_key(key_pem))
+                .unwrap();
+            let (mut client, mut server) =
+                testutil::connection_pair(&Arc::new(client_config), &builder.build().config);
+            let _ = testutil::handshake(&mut client, &mut server);
+            let server_ptr = &mut server as *mut Connection as *mut rustls_connection;
+            let result = crate::connection::rustls_connection_process_new_packets(server_ptr);
+            let mut alert = 0;
+            crate::connection::rustls_connection_get_pending_alert(server_ptr, &mut alert);
+            (result, alert)
+        };
+        let rsa = (
+            testutil::LOCALHOST_RSA2048_PEM,
+            testutil::LOCALHOST_RSA2048_KEY_PEM,
+        );
+        assert_eq!(connect(2048, rsa.0, rsa.1), (rustls_result::Ok, 0));
+        // A rejected client certificate gets handshake_failure.
+        assert_eq!(connect(3072, rsa.0, rsa.1), (rustls_result::WeakKey, 40));
+        let ecdsa = (testutil::LOCALHOST_PEM, testutil::LOCALHOST_KEY_PEM);
+        assert_eq!(connect(3072, ecdsa.0, ecdsa.1), (rustls_result::Ok, 0));
+    }
+
+    #[test]
+    fn test_set_signature_schemes() {
+        use rustls::RootCertStore;
+
+        let connect = |schemes: &[u16]| {
+            let mut roots = RootCertStore::empty();
+            roots.add(&testutil::certs(testutil::CA_PEM)[0]).unwrap();
+            let mut builder = ServerConfigBuilder::new(testutil::server_config());
+            let builder_ptr = &mut builder as *mut ServerConfigBuilder;
+            let result = rustls_server_config_builder_set_signature_schemes(
+                builder_ptr as *mut rustls_server_config_builder,
+                schemes.as_ptr(),
+                schemes.len(),
+            );
+            assert_eq!(result, rustls_result::Ok);
+            builder
+                .config
+                .set_client_certificate_verifier(AllowAnyAuthenticatedClient::new(roots));
+            let server_config = builder.build().config;
+            let requested: Vec<u16> = server_config
+                .get_verifier()
+                .supported_verify_schemes()
+                .iter()
+                .map(|s| s.get_u16())
+                .collect();
+
+            let mut client_config = testutil::client_config();
+            client_config
+                .set_single_client_cert(
+                    testutil::certs(testutil::LOCALHOST_PEM),
+                    testutil::private_key(testutil::LOCALHOST_KEY_PEM),
+                )
+                .unwrap();
+            let (mut client, mut server) =
+                testutil::connection_pair(&Arc::new(client_config), &server_config);
+            (testutil::handshake(&mut client, &mut server), requested)
+        };
+        let (result, requested) = connect(&[0x0403, 0x0804]);
+        assert!(result.is_ok());
+        assert_eq!(requested, vec![0x0403, 0x0804]);
+        // The client's ECDSA P-256 key can't sign with any allowed scheme,
+        // so it can't complete client authentication.
+        let (result, requested) = connect(&[0x0804]);
+        assert!(result.is_err());
+        assert_eq!(requested, vec![0x0804]);
+
+        // A second call narrows the list rather than replacing it.
+        let mut builder = ServerConfigBuilder::new(testutil::server_config());
+        let builder_ptr =
+            &mut builder as *mut ServerConfigBuilder as *mut rustls_server_config_builder;
+        for schemes in &[[0x0403, 0x0804], [0x0804, 0x0805]] {
+            let result = rustls_server_config_builder_set_signature_schemes(
+                builder_ptr,
+                schemes.as_ptr(),
+                2,
+            );
+            assert_eq!(result, rustls_result::Ok);
+        }
+        let result =
+            rustls_server_config_builder_set_signature_schemes(builder_ptr, [0].as_ptr(), 1);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        // A list disjoint from the earlier one is rejected without emptying it.
+        let result =
+            rustls_server_config_builder_set_signature_schemes(builder_ptr, [0x0403].as_ptr(), 1);
+        assert_eq!(result, rustls_result::InvalidParameter);
+        assert_eq!(
+            builder.signature_schemes,
+            Some(vec![SignatureScheme::RSA_PSS_SHA256])
+        );
+    }
+
+    unsafe extern "C" fn select_h2(
+        userdata: *mut c_void,
+        offered: *const rustls_slice_bytes,
+        offered_len: size_t,
+    ) -> c_int {
+        *(userdata as *mut usize) += 1;
+        let offered = slice::from_raw_parts(offered, offered_len);
+        match offered
+            .iter()
+            .position(|p| slice::from_raw_parts(p.data, p.len) == b"h2")
+        {
+            Some(i) => i as c_int,
+            None => -1,
+        }
+    }
+
+    fn connect_with_alpn_select(
+        version: rustls::ProtocolVersion,
+        offered: &[&[u8]],
+    ) -> (Result<(), TLSError>, Connection, Connection, usize) {
+        let mut server_config = testutil::server_config();
+        server_config.set_protocols(&[b"http/1.1".to_vec()]);
+        let builder = new_builder(server_config);
+        let result =
+            rustls_server_config_builder_set_alpn_select_callback(builder, Some(select_h2));
+        assert_eq!(result, rustls_result::Ok);
+        let mut client_config = testutil::client_config();
+        client_config.versions = vec![version];
+        let offered: Vec<Vec<u8>> = offered.iter().map(|p| p.to_vec()).collect();
+        client_config.set_protocols(&offered);
+
+        // The server connection comes from the built config, which knows it
+        // has an ALPN select callback.
+        let server_config = rustls_server_config_builder_build(builder);
+        let mut server_ptr: *mut rustls_connection = null_mut();
+        let result = rustls_server_connection_new(server_config, &mut server_ptr);
+        assert_eq!(result, rustls_result::Ok);
+        rustls_server_config_free(server_config);
+        let mut server = *unsafe { Box::from_raw(server_ptr as *mut Connection) };
+        let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut client = Connection::from_client(&Arc::new(client_config), name);
+        let mut calls: usize = 0;
+        let guard = crate::userdata_push(&mut calls as *mut usize as *mut c_void, None).unwrap();
+        let result = testutil::handshake(&mut client, &mut server);
+        guard.try_drop().unwrap();
+        (result, client, server, calls)
+    }
+
+    #[test]
+    fn test_alpn_select_callback() {
+        use rustls::ProtocolVersion::{TLSv1_2, TLSv1_3};
+
+        for &version in &[TLSv1_3, TLSv1_2] {
+            let offered: &[&[u8]] = &[b"http/1.1", b"h2"];
+            let (result, client, server, calls) = connect_with_alpn_select(version, offered);
+            result.unwrap();
+            assert_eq!(calls, 1);
+            assert_eq!(client.as_ref().get_alpn_protocol(), Some(&b"h2"[..]));
+            assert_eq!(server.as_ref().get_alpn_protocol(), Some(&b"h2"[..]));
+            assert!(server.served_cert().is_some());
+
+            // Declining fails the connection without sending anything more.
+            let (result, _, mut server, calls) = connect_with_alpn_select(version, &[b"http/1.1"]);
+            assert_eq!(result, Err(TLSError::NoApplicationProtocol));
+            assert_eq!(calls, 1);
+            assert!(!server.as_ref().wants_write());
+            assert_eq!(
+                server.process_new_packets(),
+                Err(TLSError::NoApplicationProtocol)
+            );
+
+            // The callback isn't consulted when nothing is offered.
+            let (result, client, _, calls) = connect_with_alpn_select(version, &[]);
+            result.unwrap();
+            assert_eq!(calls, 0);
+            assert_eq!(client.as_ref().get_alpn_protocol(), None);
+        }
+    }
+
+    #[test]
+    fn test_session_cache_size() {
+        let stored = |size: usize| {
+            let builder = rustls_server_config_builder_new();
+            let result = rustls_server_config_builder_set_session_cache_size(builder, size);
+            assert_eq!(result, rustls_result::Ok);
+            let config = rustls_server_config_builder_build(builder);
+            let config_ref: &BuiltServerConfig = crate::try_from(config).unwrap();
+            let storage = config_ref.config.session_storage.clone();
+            rustls_server_config_free(config);
+            let ids: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i]).collect();
+            for id in &ids {
+                storage.put(id.clone(), b"secret".to_vec());
+            }
+            ids.iter().filter(|id| storage.get(id).is_some()).count()
+        };
+        assert_eq!(stored(0), 0);
+        assert_eq!(stored(2), 2);
+        assert_eq!(stored(100), 4);
+    }
+
+    #[test]
+    fn test_sni_hostname() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let name = webpki::DNSNameRef::try_from_ascii_str("xn--bcher-kva.Example").unwrap();
+        let mut client = Connection::from_client(&client_config, name);
+        let mut server = Connection::from_server(&server_config, false);
+        assert_eq!(sni_hostname(&server), (rustls_result::Ok, String::new()));
+        testutil::transfer(&mut client, &mut server).unwrap();
+        assert_eq!(
+            sni_hostname(&server),
+            (rustls_result::Ok, "xn--bcher-kva.example".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sni_hostname_not_ascii() {
+        let client_config = Arc::new(testutil::client_config());
+        let server_config = Arc::new(testutil::server_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        let mut hello = Vec::new();
+        client.write_tls(&mut hello).unwrap();
+
+        // Swap "localhost" for a non-ASCII name of the same length.
+        let at = hello.windows(9).position(|w| w == b"localhost").unwrap();
+        hello[at..at + 9].copy_from_slice("localhös".as_bytes());
+        server.read_tls(&mut &hello[..]).unwrap();
+        assert!(server.process_new_packets().is_err());
+        assert_eq!(sni_hostname(&server), (rustls_result::Ok, String::new()));
+    }
+
+    #[test]
+    fn test_set_certified_key_atomic() {
+        let config_ptr = rustls_server_config_builder_build(new_builder(testutil::server_config()));
+        let server_config = unsafe { &*(config_ptr as *const BuiltServerConfig) }
+            .config
+            .clone();
+        // A new client each time, since resumed sessions keep their
+        // certificate.
+        let served = |server_config: &Arc<ServerConfig>| {
+            let client_config = Arc::new(testutil::client_config());
+            let (mut client, mut server) = testutil::connection_pair(&client_config, server_config);
+            testutil::handshake(&mut client, &mut server).unwrap();
+            client.as_ref().get_peer_certificates().unwrap().remove(0)
+        };
+        let set_chain = |config_ptr, chain: Vec<Certificate>| {
+            let key = testutil::private_key(testutil::LOCALHOST_KEY_PEM);
+            let key = rustls::sign::any_supported_type(&key).unwrap();
+            let key = CertifiedKey::new(chain, Arc::new(key));
+            let key_ptr = Arc::into_raw(Arc::new(key)) as *const rustls_certified_key;
+            let result = rustls_server_config_set_certified_key_atomic(config_ptr, key_ptr);
+            rustls_certified_key_free(key_ptr);
+            result
+        };
+        let localhost = testutil::certs(testutil::LOCALHOST_PEM);
+        let mut via_intermediate = testutil::certs(testutil::LOCALHOST_VIA_INTERMEDIATE_PEM);
+        via_intermediate.extend(testutil::certs(testutil::INTERMEDIATE_PEM));
+        assert_eq!(served(&server_config), localhost[0]);
+
+        // A connection that has already chosen its certificate keeps it.
+        let client_config = Arc::new(testutil::client_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::transfer(&mut client, &mut server).unwrap();
+
+        let result = set_chain(config_ptr, via_intermediate.clone());
+        assert_eq!(result, rustls_result::Ok);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(
+            client.as_ref().get_peer_certificates().unwrap()[0],
+            localhost[0]
+        );
+        assert_eq!(served(&server_config), via_intermediate[0]);
+
+        // A config made from this one starts with the new key, but doesn't
+        // follow later changes.
+        let derived = finish(rustls_server_config_builder_from_config(config_ptr));
+        assert_eq!(set_chain(config_ptr, localhost.clone()), rustls_result::Ok);
+        assert_eq!(served(&server_config), localhost[0]);
+        assert_eq!(served(&derived), via_intermediate[0]);
+
+        assert_eq!(
+            rustls_server_config_set_certified_key_atomic(config_ptr, std::ptr::null()),
+            rustls_result::NullParameter
+        );
+        rustls_server_config_free(config_ptr);
+    }
+
+    #[test]
+    fn test_set_single_cert() {
+        let set_single_cert = |chain: &[u8], key: &[u8]| {
+            let builder = rustls_server_config_builder_new();
+            let result = rustls_server_config_builder_set_single_cert(
+                builder,
+                chain.as_ptr(),
+                chain.len(),
+                key.as_ptr(),
+                key.len(),
+            );
+            (result, finish(builder))
+        };
+        let chain = [
+            testutil::LOCALHOST_VIA_INTERMEDIATE_PEM,
+            testutil::INTERMEDIATE_PEM,
+        ]
+        .concat();
+        let (result, server_config) = set_single_cert(&chain, testutil::LOCALHOST_KEY_PEM);
+        assert_eq!(result, rustls_result::Ok);
+        let client_config = Arc::new(testutil::client_config());
+        let (mut client, mut server) = testutil::connection_pair(&client_config, &server_config);
+        testutil::handshake(&mut client, &mut server).unwrap();
+        assert_eq!(
+            client.as_ref().get_peer_certificates().unwrap(),
+            testutil::certs(&chain)
+        );
+
+        let (result, _) = set_single_cert(&chain, b"not a key");
+        assert_eq!(result, rustls_result::PrivateKeyParseError);
+        let result = rustls_server_config_builder_set_single_cert(
+            std::ptr::null_mut(),
+            chain.as_ptr(),
+            chain.len(),
+            std::ptr::null(),
+            0,
+        );
+        assert_eq!(result, rustls_result::NullParameter);
+    }
+
+    fn new_builder(config: ServerConfig) -> *mut rustls_server_config_builder {
+        Box::into_raw(Box::new(ServerConfigBuilder::new(config))) as *mut _
+    }
+
+    fn finish(builder: *mut rustls_server_config_builder) -> Arc<ServerConfig> {
+        let config = rustls_server_config_builder_build(builder);
+        let built = unsafe { Arc::from_raw(config as *const BuiltServerConfig) };
+        built.config.clone()
+    }
+
+    /// Build `config` as rustls_server_config_builder_build would.
+    fn build(config: ServerConfig) -> Arc<ServerConfig> {
+        finish(new_builder(config))
+    }
+
+    fn served_cert_matches_sni(client_config: &Arc<ClientConfig>, name: &str) -> rustls_result {
+        let server_config = build(testutil::server_config());
+        let name = webpki::DNSNameRef::try_from_ascii_str(name).unwrap();
+        let mut client = Connection::from_client(client_config, name);
+        let mut server = Connection::from_server(&server_config, false);
+        let server_ptr = &server as *const Connection as *const rustls_connection;
+        assert_eq!(
+            rustls_connection_served_cert_matches_sni(server_ptr),
+            rustls_result::NotFound
+        );
+        testutil::transfer(&mut client, &mut server).unwrap();
+        let server_ptr = &server as *const Connection as *const rustls_connection;
+        rustls_connection_served_cert_matches_sni(server_ptr)
+    }
+
+    #[test]
+    fn test_served_cert_matches_sni() {
+        let client_config = Arc::new(testutil::client_config());
+        let check = |name| served_cert_matches_sni(&client_config, name);
+        assert_eq!(check("localhost"), rustls_result::Ok);
+        assert_eq!(check("example.com"), rustls_result::Ok);
+        assert_eq!(check("example.org"), rustls_result::CertNotValidForName);
+
+        let mut client_config = testutil::client_config();
+        client_config.enable_sni = false;
+        let client_config = Arc::new(client_config);
+        assert_eq!(
+            served_cert_matches_sni(&client_config, "example.org"),
+            rustls_result::Ok
+        );
+
+        let client = testutil::connection_pair(&client_config, &build(testutil::server_config())).0;
+        let client_ptr = &client as *const Connection as *const rustls_connection;
+        assert_eq!(
+            rustls_connection_served_cert_matches_sni(client_ptr),
+            rustls_result::InvalidParameter
+        );
+    }
+}
@@ -1,7 +1,15 @@
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use crate::error::rustls_result;
+use crate::hooks;
 use crate::rslice::rustls_slice_bytes;
 use crate::userdata_get;
 use libc::{c_int, c_void, size_t};
+use rustls::internal::msgs::codec::Codec;
+use rustls::internal::msgs::enums::NamedGroup;
+use rustls::internal::msgs::persist::ClientSessionValue;
 
 /// Any context information the callback will receive when invoked.
 pub type rustls_session_store_userdata = *mut c_void;
@@ -107,7 +115,7 @@ impl SessionStoreBroker {
             ) {
                 rustls_result::Ok => {
                     data.set_len(out_n);
-                    return Some(data);
+                    Some(data)
                 }
                 _ => None,
             }
@@ -122,12 +130,7 @@ impl SessionStoreBroker {
             Ok(u) => u,
             Err(_) => return false,
         };
-        unsafe {
-            match cb(userdata, &key, &value) {
-                rustls_result::Ok => true,
-                _ => false,
-            }
-        }
+        unsafe { matches!(cb(userdata, &key, &value), rustls_result::Ok) }
     }
 }
 
@@ -137,11 +140,11 @@ impl rustls::StoresServerSessions for SessionStoreBroker {
     }
 
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        return self.retrieve(key, false);
+        self.retrieve(key, false)
     }
 
     fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
-        return self.retrieve(key, true);
+        self.retrieve(key, true)
     }
 }
 
@@ -151,7 +154,7 @@ impl rustls::StoresClientSessions for SessionStoreBroker {
     }
 
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        return self.retrieve(key, false);
+        self.retrieve(key, false)
     }
 }
 
@@ -160,3 +163,150 @@ impl rustls::StoresClientSessions for SessionStoreBroker {
 /// documented as a requirement in the API.
 unsafe impl Sync for SessionStoreBroker {}
 unsafe impl Send for SessionStoreBroker {}
+
+/// A key exchange hint key that rustls never uses.
+const UNSTORED_KX_HINT_KEY: &[u8] = b"kx-hint-crustls-unstored";
+
+/// Where the store wrapped by a TicketReporter came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SessionStoreKind {
+    /// The in-memory cache rustls makes for a new config, which nothing
+    /// but crustls can reach.
+    Default,
+    /// The application's callbacks.
+    Callbacks,
+}
+
+/// Wraps a client config's session store to tell the connection being
+/// processed about each ticket the server sends, for
+/// rustls_connection_set_new_ticket_callback. rustls doesn't otherwise say
+/// when one arrives.
+pub(crate) struct TicketReporter {
+    inner: Arc<dyn rustls::StoresClientSessions>,
+    /// The key exchange groups to send a key share for, most preferred
+    /// first, or empty to leave the choice to rustls.
+    kx_groups: Vec<NamedGroup>,
+    kind: SessionStoreKind,
+    /// The number of times the store has been cleared. Each value is stored
+    /// behind the generation it was stored in, and only values of the
+    /// current generation are used. Shared with the TicketReporters derived
+    /// from this one.
+    generation: Arc<AtomicU64>,
+}
+
+impl TicketReporter {
+    pub(crate) fn new(
+        inner: Arc<dyn rustls::StoresClientSessions>,
+        kx_groups: Vec<NamedGroup>,
+        kind: SessionStoreKind,
+    ) -> Self {
+        TicketReporter {
+            inner,
+            kx_groups,
+            kind,
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// A TicketReporter for a config made from this one's config, which
+    /// uses the same store, and so clears it along with this one.
+    pub(crate) fn derive(&self, kx_groups: Vec<NamedGroup>) -> Self {
+        TicketReporter {
+            inner: self.inner.clone(),
+            kx_groups,
+            kind: self.kind,
+            generation: self.generation.clone(),
+        }
+    }
+
+    pub(crate) fn kx_groups(&self) -> &[NamedGroup] {
+        &self.kx_groups
+    }
+
+    /// Stop using everything stored so far. Values in rustls' cache are
+    /// overwritten with nothing, which rustls ignores, when they are next
+    /// looked up; the application's callbacks can't be reached outside a
+    /// connection, so what they hold is only hidden.
+    pub(crate) fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The value stored under `key` in the current generation.
+    fn current(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut value = self.inner.get(key)?;
+        let generation = self.generation.load(Ordering::SeqCst);
+        if value.len() < 8 || u64::from_be_bytes(value[..8].try_into().unwrap()) != generation {
+            if self.kind == SessionStoreKind::Default && !value.is_empty() {
+                self.inner.put(key.to_vec(), Vec::new());
+            }
+            return None;
+        }
+        Some(value.split_off(8))
+    }
+}
+
+impl rustls::StoresClientSessions for TicketReporter {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        // rustls also stores key exchange hints, under a different prefix.
+        if key.starts_with(b"session") {
+            if let Some(id) = ticket_id(&value) {
+                hooks::report(|r| r.new_tickets.push(id));
+            }
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        let mut stored = generation.to_be_bytes().to_vec();
+        stored.extend_from_slice(&value);
+        self.inner.put(key, stored)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let value = self.current(key);
+        if self.kx_groups.is_empty() || !key.starts_with(b"kx-hint") {
+            return value;
+        }
+        // rustls sends a TLS 1.3 key share only for the group hinted at
+        // here, which is the one the server chose last time, or X25519 if
+        // there is no hint. This relies on details of rustls 0.19.1
+        // (find_kx_hint in client/tls13.rs): the key is "kx-hint" followed
+        // by the length-prefixed server name, and the value is the group's
+        // encoding. test_kx_hint_format in client.rs checks both.
+        let group = match value.as_deref().and_then(NamedGroup::read_bytes) {
+            Some(hint) if self.kx_groups.contains(&hint) => hint,
+            _ => self.kx_groups[0],
+        };
+        Some(group.get_encoding())
+    }
+}
+
+/// Wraps a client config's session store for a connection that must not
+/// resume: it finds nothing stored, but still stores what it is given.
+pub(crate) struct IgnoreStoredSessions {
+    pub(crate) inner: Arc<dyn rustls::StoresClientSessions>,
+}
+
+impl rustls::StoresClientSessions for IgnoreStoredSessions {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.inner.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if key.starts_with(b"kx-hint") {
+            // A hint that was never stored, so that a TicketReporter still
+            // applies the config's key exchange groups, but not the one the
+            // server chose last time.
+            return self.inner.get(UNSTORED_KX_HINT_KEY);
+        }
+        None
+    }
+}
+
+/// Identify the resumption state in an encoded ClientSessionValue by the
+/// ticket the server issued or, for TLS 1.2 session ID resumption, by the
+/// session ID.
+fn ticket_id(value: &[u8]) -> Option<Vec<u8>> {
+    let value = ClientSessionValue::read_bytes(value)?;
+    if !value.ticket.0.is_empty() {
+        return Some(value.ticket.0);
+    }
+    Some(value.session_id.get_encoding().split_off(1))
+}
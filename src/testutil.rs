@@ -0,0 +1,134 @@
+// Helpers for tests that need a real handshake between a client and a server
+// connection, without involving any C callbacks or sockets.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use rustls::{ClientConfig, NoClientAuth, ServerConfig, TLSError};
+use rustls_pemfile::pkcs8_private_keys;
+
+use crate::connection::Connection;
+
+pub(crate) const CA_PEM: &[u8] = include_bytes!("../testdata/ca.pem");
+pub(crate) const LOCALHOST_PEM: &[u8] = include_bytes!("../testdata/localhost.pem");
+pub(crate) const LOCALHOST_KEY_PEM: &[u8] = include_bytes!("../testdata/localhost-key.pem");
+/// Like LOCALHOST_PEM, with the same key, but only valid during 2030.
+pub(crate) const LOCALHOST_2030_PEM: &[u8] = include_bytes!("../testdata/localhost-2030.pem");
+/// Like LOCALHOST_PEM, with the same key, but only valid during 2020.
+pub(crate) const LOCALHOST_EXPIRED_PEM: &[u8] = include_bytes!("../testdata/localhost-expired.pem");
+/// An intermediate CA issued by the test CA.
+pub(crate) const INTERMEDIATE_PEM: &[u8] = include_bytes!("../testdata/intermediate.pem");
+/// Like LOCALHOST_PEM, with the same key, but issued by INTERMEDIATE_PEM.
+pub(crate) const LOCALHOST_VIA_INTERMEDIATE_PEM: &[u8] =
+    include_bytes!("../testdata/localhost-via-intermediate.pem");
+/// A localhost certificate issued by the test CA for a 2048-bit RSA key.
+pub(crate) const LOCALHOST_RSA2048_PEM: &[u8] = include_bytes!("../testdata/localhost-rsa2048.pem");
+pub(crate) const LOCALHOST_RSA2048_KEY_PEM: &[u8] =
+    include_bytes!("../testdata/localhost-rsa2048-key.pem");
+/// Like LOCALHOST_PEM, with the same key, but with SCTs from CT_LOG1 and
+/// CT_LOG2 embedded, timestamped October 1, 2026.
+pub(crate) const LOCALHOST_SCT_PEM: &[u8] = include_bytes!("../testdata/localhost-sct.pem");
+/// Like LOCALHOST_PEM, with the same key, but asserting the certificate
+/// policies 1.3.6.1.4.1.55555.1.2 and 2.23.140.1.2.1.
+pub(crate) const LOCALHOST_POLICY_PEM: &[u8] = include_bytes!("../testdata/localhost-policy.pem");
+/// The SubjectPublicKeyInfos of three test CT logs. CT_LOG3 signed nothing.
+pub(crate) const CT_LOG1: &[u8] = include_bytes!("../testdata/ct-log1.der");
+pub(crate) const CT_LOG2: &[u8] = include_bytes!("../testdata/ct-log2.der");
+pub(crate) const CT_LOG3: &[u8] = include_bytes!("../testdata/ct-log3.der");
+/// OCSP responses for LOCALHOST_VIA_INTERMEDIATE_PEM, produced in October
+/// 2026 and valid until 2126. The first two are signed by the intermediate.
+pub(crate) const OCSP_GOOD: &[u8] = include_bytes!("../testdata/ocsp-good.der");
+pub(crate) const OCSP_REVOKED: &[u8] = include_bytes!("../testdata/ocsp-revoked.der");
+/// A good status, but signed by the test CA rather than the issuer.
+pub(crate) const OCSP_WRONG_SIGNER: &[u8] = include_bytes!("../testdata/ocsp-wrong-signer.der");
+
+/// Parse the PEM certificates in `pem`.
+pub(crate) fn certs(pem: &[u8]) -> Vec<rustls::Certificate> {
+    rustls_pemfile::certs(&mut Cursor::new(pem))
+        .unwrap()
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect()
+}
+
+/// A client config that trusts the test CA.
+pub(crate) fn client_config() -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_pem_file(&mut Cursor::new(CA_PEM))
+        .unwrap();
+    config
+}
+
+/// A server config that presents the test localhost certificate.
+pub(crate) fn server_config() -> ServerConfig {
+    server_config_with_cert(LOCALHOST_PEM)
+}
+
+/// A server config that presents `cert_pem`, which must be for the test
+/// localhost key.
+pub(crate) fn server_config_with_cert(cert_pem: &[u8]) -> ServerConfig {
+    server_config_with_ocsp(cert_pem, &[])
+}
+
+/// A server config that presents the certificate chain in `cert_pem`, whose
+/// first certificate must be for the test localhost key, and staples `ocsp`
+/// unless it is empty.
+pub(crate) fn server_config_with_ocsp(cert_pem: &[u8], ocsp: &[u8]) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    let chain = certs(cert_pem);
+    config
+        .set_single_cert_with_ocsp_and_sct(
+            chain,
+            private_key(LOCALHOST_KEY_PEM),
+            ocsp.to_vec(),
+            vec![],
+        )
+        .unwrap();
+    config
+}
+
+/// Parse the PKCS#8 private key in `pem`.
+pub(crate) fn private_key(pem: &[u8]) -> rustls::PrivateKey {
+    let key = pkcs8_private_keys(&mut Cursor::new(pem)).unwrap().remove(0);
+    rustls::PrivateKey(key)
+}
+
+/// Create a client connection to "localhost" and a server connection.
+pub(crate) fn connection_pair(
+    client_config: &Arc<ClientConfig>,
+    server_config: &Arc<ServerConfig>,
+) -> (Connection, Connection) {
+    let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    (
+        Connection::from_client(client_config, name),
+        Connection::from_server(server_config, false),
+    )
+}
+
+/// Move all pending TLS bytes from `from` to `to` and have `to` process
+/// them. Returns the number of bytes moved.
+pub(crate) fn transfer(from: &mut Connection, to: &mut Connection) -> Result<usize, TLSError> {
+    let mut buf = Vec::new();
+    while from.as_ref().wants_write() {
+        from.write_tls(&mut buf).unwrap();
+    }
+    let mut reader = Cursor::new(&buf[..]);
+    while (reader.position() as usize) < buf.len() {
+        to.read_tls(&mut reader).unwrap();
+        to.process_new_packets()?;
+    }
+    Ok(buf.len())
+}
+
+/// Run the handshake to completion, and deliver anything the server sends
+/// straight afterwards, such as TLS 1.3 session tickets.
+pub(crate) fn handshake(client: &mut Connection, server: &mut Connection) -> Result<(), TLSError> {
+    while client.as_ref().is_handshaking() || server.as_ref().is_handshaking() {
+        transfer(client, server)?;
+        transfer(server, client)?;
+    }
+    transfer(server, client)?;
+    Ok(())
+}
@@ -0,0 +1,492 @@
+// rustls 0.19's WebPKIVerifier can't be adjusted beyond its root store, so
+// the certificate verification options on the client config builder are
+// implemented by a verifier of our own. It performs the same checks as
+// WebPKIVerifier, with the adjustments described by the policy. On the
+// server side, options are added by wrapping the client cert verifier.
+
+use std::sync::Arc;
+
+use ring::digest;
+use rustls::internal::msgs::handshake::DigitallySignedStruct;
+use rustls::{
+    Certificate, ClientCertVerified, ClientCertVerifier, DistinguishedNames,
+    HandshakeSignatureValid, OwnedTrustAnchor, RootCertStore, ServerCertVerified, ServerConfig,
+    SignatureScheme, TLSError, WebPKIVerifier,
+};
+use webpki::{DNSName, DNSNameRef};
+
+use crate::ct::{self, CtLog};
+use crate::der;
+use crate::enums::rustls_ocsp_policy;
+use crate::error::{map_error, rustls_result};
+use crate::hooks::{self, HookReport};
+use crate::ocsp::{self, CertStatus};
+use crate::x509;
+
+/// The signature algorithms accepted in certificates. This matches the
+/// (private) list used by rustls' WebPKIVerifier.
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// The most times the intermediate fetcher is asked for more certificates
+/// while verifying one chain.
+const MAX_INTERMEDIATE_FETCHES: usize = 4;
+
+/// A source of intermediate certificates that the server didn't send.
+pub(crate) trait FetchIntermediates: Send + Sync {
+    /// Return certificates that may have issued `cert`.
+    fn fetch(&self, cert: &Certificate) -> Vec<Certificate>;
+}
+
+/// Told why a server certificate would have been rejected, when the policy
+/// only reports failures.
+pub(crate) trait ReportVerifyFailure: Send + Sync {
+    fn report(&self, result: rustls_result);
+}
+
+/// Adjustments to the default server certificate verification.
+#[derive(Clone, Default)]
+pub(crate) struct VerifyPolicy {
+    /// Validate certificates as of this time rather than the system clock.
+    pub(crate) time: Option<webpki::Time>,
+    /// Accept certificate chains that would be valid but for having expired.
+    pub(crate) accept_expired: bool,
+    /// SHA-256 hashes of SubjectPublicKeyInfos. If any are set, they replace
+    /// all other checks: a server is accepted if and only if its end-entity
+    /// certificate's key hashes to one of them.
+    pub(crate) pinned_spki: Vec<[u8; 32]>,
+    /// Asked for more intermediates when the presented chain doesn't reach
+    /// a trusted root.
+    pub(crate) fetch_intermediates: Option<Arc<dyn FetchIntermediates>>,
+    /// What to do with a stapled OCSP response. Not applied to pinned keys.
+    pub(crate) ocsp_policy: rustls_ocsp_policy,
+    /// Reject end-entity RSA keys smaller than this many bits.
+    pub(crate) min_rsa_key_bits: u32,
+    /// If set, the only signature schemes offered to the server and
+    /// accepted in its handshake signatures.
+    pub(crate) signature_schemes: Option<Vec<SignatureScheme>>,
+    /// Require SCTs from at least `min_scts` of these CT logs. Not applied
+    /// to pinned keys.
+    pub(crate) ct_logs: Vec<CtLog>,
+    pub(crate) min_scts: usize,
+    /// Certificate policy OIDs, without tag and length, that the
+    /// end-entity certificate must all assert. Not applied to pinned keys.
+    pub(crate) required_policies: Vec<Vec<u8>>,
+    /// If set, certificates that fail verification are reported here and
+    /// accepted anyway.
+    pub(crate) report_only: Option<Arc<dyn ReportVerifyFailure>>,
+}
+
+impl VerifyPolicy {
+    fn verify_pinned(&self, end_entity: &Certificate) -> Result<ServerCertVerified, TLSError> {
+        let spki = x509::subject_public_key_info(&end_entity.0)
+            .map_err(|_| TLSError::WebPKIError(webpki::Error::BadDER))?;
+        let hash = digest::digest(&digest::SHA256, spki);
+        if !self.pinned_spki.iter().any(|pin| pin[..] == *hash.as_ref()) {
+            return Err(TLSError::General(
+                "server's public key matches no pinned key".to_string(),
+            ));
+        }
+        hooks::report(|r| r.peer_cert_expired = Some(false));
+        Ok(ServerCertVerified::assertion())
+    }
+
+    /// Apply the OCSP policy to a chain that has otherwise been verified.
+    fn check_ocsp(&self, chain: &[Certificate], ocsp_response: &[u8]) -> Result<(), TLSError> {
+        if self.ocsp_policy == rustls_ocsp_policy::Ignore {
+            return Ok(());
+        }
+        let reject = |reason: &str| {
+            hooks::report(|r| r.ocsp_rejected = true);
+            Err(TLSError::General(reason.to_string()))
+        };
+        match (
+            ocsp_status(chain, ocsp_response, self.now()?),
+            self.ocsp_policy,
+        ) {
+            (Some(CertStatus::Revoked), _) => reject("server's certificate is revoked"),
+            (Some(CertStatus::Good), _) | (_, rustls_ocsp_policy::SoftFail) => Ok(()),
+            _ => reject("no usable OCSP response shows the server's certificate is good"),
+        }
+    }
+
+    /// Apply the CT policy to a chain that has otherwise been verified.
+    fn check_scts(&self, roots: &RootCertStore, chain: &[Certificate]) -> Result<(), TLSError> {
+        if self.min_scts == 0 {
+            return Ok(());
+        }
+        let count = match issuer_spki(roots, chain) {
+            Some(issuer_spki) => {
+                ct::count_valid_scts(&chain[0].0, &issuer_spki, &self.ct_logs, self.now()?)
+            }
+            None => 0,
+        };
+        if count < self.min_scts {
+            hooks::report(|r| r.ct_rejected = true);
+            return Err(TLSError::General(format!(
+                "server's certificate has valid SCTs from {} of the required {} CT logs",
+                count, self.min_scts
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that the end-entity certificate of a chain that has otherwise
+    /// been verified asserts every required certificate policy.
+    fn check_policies(&self, end_entity: &Certificate) -> Result<(), TLSError> {
+        if self.required_policies.is_empty() {
+            return Ok(());
+        }
+        let asserted = x509::certificate_policies(&end_entity.0)
+            .map_err(|_| TLSError::WebPKIError(webpki::Error::BadDER))?
+            .unwrap_or_default();
+        for required in &self.required_policies {
+            if !asserted.contains(&required.as_slice()) {
+                hooks::report(|r| r.policy_missing = true);
+                return Err(TLSError::General(format!(
+                    "server's certificate doesn't assert required policy {}",
+                    der::oid_to_string(required).unwrap_or_default()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn now(&self) -> Result<webpki::Time, TLSError> {
+        match self.time {
+            Some(time) => Ok(time),
+            None => webpki::Time::try_from(std::time::SystemTime::now())
+                .map_err(|_| TLSError::FailedToGetCurrentTime),
+        }
+    }
+}
+
+impl VerifyPolicy {
+    fn verify_chain(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: DNSNameRef<'_>,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let end_entity = match presented_certs.first() {
+            Some(end_entity) => end_entity,
+            None => return Err(TLSError::NoCertificatesPresented),
+        };
+        check_rsa_key_bits(end_entity, self.min_rsa_key_bits)?;
+        if !self.pinned_spki.is_empty() {
+            return self.verify_pinned(end_entity);
+        }
+        let cert = webpki::EndEntityCert::from(&end_entity.0).map_err(TLSError::WebPKIError)?;
+        let trust_roots: Vec<webpki::TrustAnchor> = roots
+            .roots
+            .iter()
+            .map(OwnedTrustAnchor::to_trust_anchor)
+            .collect();
+        let anchors = webpki::TLSServerTrustAnchors(&trust_roots);
+
+        let mut presented_certs: Vec<Certificate> = presented_certs.to_vec();
+        if let Some(fetcher) = &self.fetch_intermediates {
+            let now = self.now()?;
+            for _ in 0..MAX_INTERMEDIATE_FETCHES {
+                let chain: Vec<&[u8]> = presented_certs[1..].iter().map(|c| c.0.as_ref()).collect();
+                match cert.verify_is_valid_tls_server_cert(
+                    SUPPORTED_SIG_ALGS,
+                    &anchors,
+                    &chain,
+                    now,
+                ) {
+                    Err(webpki::Error::UnknownIssuer) => {}
+                    _ => break,
+                }
+                // The chain is presumed to be in order, so ask for the
+                // issuer of the last certificate in it.
+                let fetched = fetcher.fetch(presented_certs.last().unwrap());
+                if fetched.is_empty() {
+                    break;
+                }
+                presented_certs.extend(fetched);
+            }
+        }
+        let presented_certs: &[Certificate] = &presented_certs;
+
+        let chain: Vec<&[u8]> = presented_certs[1..].iter().map(|c| c.0.as_ref()).collect();
+        let verify_at =
+            |time| cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS, &anchors, &chain, time);
+
+        let mut expired = false;
+        match verify_at(self.now()?) {
+            Ok(()) => {}
+            Err(webpki::Error::CertExpired) if self.accept_expired => {
+                // Check the chain again as of the moment the first of its
+                // certificates expired. If it was valid then, expiry is the
+                // only problem.
+                let expired_at = match last_valid_time(presented_certs) {
+                    Some(t) => t,
+                    None => return Err(TLSError::WebPKIError(webpki::Error::CertExpired)),
+                };
+                verify_at(expired_at)
+                    .map_err(|_| TLSError::WebPKIError(webpki::Error::CertExpired))?;
+                expired = true;
+            }
+            Err(e) => return Err(TLSError::WebPKIError(e)),
+        }
+        cert.verify_is_valid_for_dns_name(dns_name)
+            .map_err(TLSError::WebPKIError)?;
+        self.check_policies(end_entity)?;
+        self.check_ocsp(presented_certs, ocsp_response)?;
+        self.check_scts(roots, presented_certs)?;
+        hooks::report(|r| r.peer_cert_expired = Some(expired));
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+impl rustls::ServerCertVerifier for VerifyPolicy {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: DNSNameRef<'_>,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let reporter = match &self.report_only {
+            Some(reporter) => reporter,
+            None => return self.verify_chain(roots, presented_certs, dns_name, ocsp_response),
+        };
+        // Keep the rejection from the connection, which would otherwise
+        // report it from rustls_connection_process_new_packets.
+        let (result, report) =
+            hooks::capture(|| self.verify_chain(roots, presented_certs, dns_name, ocsp_response));
+        hooks::report(|r| r.peer_cert_expired = report.peer_cert_expired);
+        if let Err(e) = result {
+            reporter.report(rejection_result(e, &report));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TLSError> {
+        check_scheme(&self.signature_schemes, dss)?;
+        WebPKIVerifier::new().verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TLSError> {
+        check_scheme(&self.signature_schemes, dss)?;
+        WebPKIVerifier::new().verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        restrict_schemes(
+            WebPKIVerifier::verification_schemes(),
+            &self.signature_schemes,
+        )
+    }
+}
+
+/// Wraps the client cert verifier of a server config to add the options set
+/// on the config builder. `config` is the config as it was before wrapping,
+/// which holds the verifier that does the real work.
+pub(crate) struct ClientCertPolicy {
+    pub(crate) config: ServerConfig,
+    pub(crate) min_rsa_key_bits: u32,
+    /// If set, the only signature schemes requested from the client and
+    /// accepted in its handshake signatures.
+    pub(crate) signature_schemes: Option<Vec<SignatureScheme>>,
+}
+
+impl ClientCertVerifier for ClientCertPolicy {
+    fn offer_client_auth(&self) -> bool {
+        self.config.get_verifier().offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self, sni: Option<&DNSName>) -> Option<bool> {
+        self.config.get_verifier().client_auth_mandatory(sni)
+    }
+
+    fn client_auth_root_subjects(&self, sni: Option<&DNSName>) -> Option<DistinguishedNames> {
+        self.config.get_verifier().client_auth_root_subjects(sni)
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        sni: Option<&DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        if let Some(end_entity) = presented_certs.first() {
+            check_rsa_key_bits(end_entity, self.min_rsa_key_bits)?;
+        }
+        self.config
+            .get_verifier()
+            .verify_client_cert(presented_certs, sni)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TLSError> {
+        check_scheme(&self.signature_schemes, dss)?;
+        self.config
+            .get_verifier()
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TLSError> {
+        check_scheme(&self.signature_schemes, dss)?;
+        self.config
+            .get_verifier()
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        restrict_schemes(
+            self.config.get_verifier().supported_verify_schemes(),
+            &self.signature_schemes,
+        )
+    }
+}
+
+/// Keep only the schemes in `allowed`, if it is set, preserving the order of
+/// `schemes`.
+fn restrict_schemes(
+    mut schemes: Vec<SignatureScheme>,
+    allowed: &Option<Vec<SignatureScheme>>,
+) -> Vec<SignatureScheme> {
+    if let Some(allowed) = allowed {
+        schemes.retain(|s| allowed.contains(s));
+    }
+    schemes
+}
+
+/// The result rustls_connection_process_new_packets returns for a server
+/// certificate rejected with `error`, given what the verifier reported.
+fn rejection_result(error: TLSError, report: &HookReport) -> rustls_result {
+    if report.ocsp_rejected {
+        rustls_result::CertificateRevoked
+    } else if report.weak_key {
+        rustls_result::WeakKey
+    } else if report.ct_rejected {
+        rustls_result::CertificateTransparencyRequired
+    } else if report.policy_missing {
+        rustls_result::CertificatePolicyMissing
+    } else {
+        map_error(error)
+    }
+}
+
+/// Reject a handshake signature made with a scheme outside `allowed`, if
+/// it is set. rustls doesn't check that the peer used one of the schemes
+/// offered to it.
+fn check_scheme(
+    allowed: &Option<Vec<SignatureScheme>>,
+    dss: &DigitallySignedStruct,
+) -> Result<(), TLSError> {
+    match allowed {
+        Some(allowed) if !allowed.contains(&dss.scheme) => Err(TLSError::PeerMisbehavedError(
+            format!("peer signed with disallowed scheme {:?}", dss.scheme),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Reject `end_entity` if it has an RSA key smaller than `min_bits`.
+fn check_rsa_key_bits(end_entity: &Certificate, min_bits: u32) -> Result<(), TLSError> {
+    if min_bits == 0 {
+        return Ok(());
+    }
+    let bits = x509::subject_public_key_info(&end_entity.0)
+        .and_then(x509::rsa_key_bits)
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::BadDER))?;
+    match bits {
+        Some(bits) if bits < min_bits as usize => {
+            hooks::report(|r| r.weak_key = true);
+            Err(TLSError::General(format!(
+                "peer's RSA key has {} bits, fewer than the required {}",
+                bits, min_bits
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The status that `ocsp_response` gives for the first certificate in
+/// `chain`, if it is a usable response signed by that certificate's issuer,
+/// which must be in the chain.
+fn ocsp_status(
+    chain: &[Certificate],
+    ocsp_response: &[u8],
+    now: webpki::Time,
+) -> Option<CertStatus> {
+    if ocsp_response.is_empty() {
+        return None;
+    }
+    let end_entity = &chain.first()?.0;
+    let issuer_name = x509::issuer(end_entity).ok()?;
+    let issuer = chain[1..]
+        .iter()
+        .find(|c| x509::subject(&c.0) == Ok(issuer_name))?;
+    ocsp::check(ocsp_response, end_entity, &issuer.0, now).ok()
+}
+
+/// The DER-encoded SubjectPublicKeyInfo of the issuer of the first
+/// certificate in `chain`, which must be later in the chain or in `roots`.
+fn issuer_spki(roots: &RootCertStore, chain: &[Certificate]) -> Option<Vec<u8>> {
+    let issuer_name = x509::issuer(&chain.first()?.0).ok()?;
+    if let Some(issuer) = chain[1..]
+        .iter()
+        .find(|c| x509::subject(&c.0) == Ok(issuer_name))
+    {
+        return x509::subject_public_key_info(&issuer.0)
+            .ok()
+            .map(<[u8]>::to_vec);
+    }
+    // Trust anchors keep the contents of the Name and SubjectPublicKeyInfo
+    // SEQUENCEs, without their tags and lengths.
+    let issuer_name = der::expect_only(issuer_name, der::SEQUENCE).ok()?;
+    let anchor = roots
+        .roots
+        .iter()
+        .map(OwnedTrustAnchor::to_trust_anchor)
+        .find(|anchor| anchor.subject == issuer_name)?;
+    let mut spki = Vec::new();
+    der::write(&mut spki, der::SEQUENCE, anchor.spki);
+    Some(spki)
+}
+
+/// The last moment at which every certificate in `certs` was unexpired.
+fn last_valid_time(certs: &[Certificate]) -> Option<webpki::Time> {
+    let mut earliest: Option<i64> = None;
+    for cert in certs {
+        let (_, not_after) = x509::validity(&cert.0).ok()?;
+        earliest = Some(earliest.map_or(not_after, |e| e.min(not_after)));
+    }
+    let seconds: u64 = std::convert::TryFrom::try_from(earliest?).ok()?;
+    Some(webpki::Time::from_seconds_since_unix_epoch(seconds))
+}
@@ -0,0 +1,406 @@
+// Accessors for the few fields of an X.509 certificate (RFC 5280) that
+// crustls needs to look at directly. webpki validates certificates but
+// doesn't expose their contents, so we read them with our own DER reader.
+// These don't validate the certificate; callers should only use them on
+// certificates that webpki has accepted, or for informational purposes.
+
+use crate::der::{self, BadDer};
+
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+/// The id-ecPublicKey algorithm OID, 1.2.840.10045.2.1, without tag and length.
+pub(crate) const ID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+/// rsaEncryption, 1.2.840.113549.1.1.1.
+const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// id-RSASSA-PSS, 1.2.840.113549.1.1.10.
+const ID_RSASSA_PSS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a];
+
+/// id-ce-extKeyUsage, 2.5.29.37.
+const EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+/// id-ce-certificatePolicies, 2.5.29.32.
+const CERTIFICATE_POLICIES: &[u8] = &[0x55, 0x1d, 0x20];
+/// id-kp-clientAuth, 1.3.6.1.5.5.7.3.2.
+pub(crate) const ID_KP_CLIENT_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+
+/// Public key algorithm OIDs, other than id-ecPublicKey, and their names.
+static KEY_ALGORITHMS: &[(&[u8], &str)] = &[
+    (RSA_ENCRYPTION, "RSA"),
+    (ID_RSASSA_PSS, "RSA-PSS"),
+    // id-Ed25519, 1.3.101.112.
+    (&[0x2b, 0x65, 0x70], "Ed25519"),
+    // id-Ed448, 1.3.101.113.
+    (&[0x2b, 0x65, 0x71], "Ed448"),
+];
+
+/// Named curve OIDs and their names.
+static CURVES: &[(&[u8], &str)] = &[
+    // secp256r1, 1.2.840.10045.3.1.7.
+    (&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07], "P-256"),
+    // secp384r1, 1.3.132.0.34.
+    (&[0x2b, 0x81, 0x04, 0x00, 0x22], "P-384"),
+    // secp521r1, 1.3.132.0.35.
+    (&[0x2b, 0x81, 0x04, 0x00, 0x23], "P-521"),
+];
+
+/// Skip the optional explicit `version` ([0]) at the start of the
+/// TBSCertificate. Returns a reader positioned at `serialNumber`.
+fn tbs_at_serial_number(cert: &[u8]) -> Result<der::Reader<'_>, BadDer> {
+    let mut outer = der::Reader::new(der::expect_only(cert, der::SEQUENCE)?);
+    let mut tbs = der::Reader::new(outer.expect(der::SEQUENCE)?);
+    if tbs.peek_tag() == Some(der::CONTEXT_SPECIFIC_CONSTRUCTED_0) {
+        tbs.read()?;
+    }
+    Ok(tbs)
+}
+
+/// Skip the TBSCertificate fields that precede `issuer`: an optional
+/// explicit `version` ([0]), `serialNumber` and `signature`. Returns a
+/// reader positioned at `issuer`.
+fn tbs_at_issuer(cert: &[u8]) -> Result<der::Reader<'_>, BadDer> {
+    let mut tbs = tbs_at_serial_number(cert)?;
+    tbs.expect(der::INTEGER)?;
+    tbs.expect(der::SEQUENCE)?;
+    Ok(tbs)
+}
+
+/// Skip the TBSCertificate fields up to and including `issuer`. Returns a
+/// reader positioned at `validity`.
+fn tbs_at_validity(cert: &[u8]) -> Result<der::Reader<'_>, BadDer> {
+    let mut tbs = tbs_at_issuer(cert)?;
+    tbs.expect(der::SEQUENCE)?;
+    Ok(tbs)
+}
+
+/// Skip the TBSCertificate fields up to and including `validity`. Returns
+/// a reader positioned at `subject`.
+fn tbs_at_subject(cert: &[u8]) -> Result<der::Reader<'_>, BadDer> {
+    let mut tbs = tbs_at_validity(cert)?;
+    tbs.expect(der::SEQUENCE)?;
+    Ok(tbs)
+}
+
+/// Return the contents of the certificate's serialNumber INTEGER.
+pub(crate) fn serial_number(cert: &[u8]) -> Result<&[u8], BadDer> {
+    tbs_at_serial_number(cert)?.expect(der::INTEGER)
+}
+
+/// Return the certificate's complete issuer Name element, including its
+/// tag and length.
+pub(crate) fn issuer(cert: &[u8]) -> Result<&[u8], BadDer> {
+    match tbs_at_issuer(cert)?.read_element()? {
+        (der::SEQUENCE, name) => Ok(name),
+        _ => Err(BadDer {}),
+    }
+}
+
+/// Return the certificate's complete subject Name element, including its
+/// tag and length.
+pub(crate) fn subject(cert: &[u8]) -> Result<&[u8], BadDer> {
+    match tbs_at_subject(cert)?.read_element()? {
+        (der::SEQUENCE, name) => Ok(name),
+        _ => Err(BadDer {}),
+    }
+}
+
+/// Return the certificate's notBefore and notAfter times, in seconds since
+/// the Unix epoch.
+pub(crate) fn validity(cert: &[u8]) -> Result<(i64, i64), BadDer> {
+    let mut tbs = tbs_at_validity(cert)?;
+    let mut validity = der::Reader::new(tbs.expect(der::SEQUENCE)?);
+    let not_before = read_time(&mut validity)?;
+    let not_after = read_time(&mut validity)?;
+    if !validity.is_empty() {
+        return Err(BadDer {});
+    }
+    Ok((not_before, not_after))
+}
+
+/// Return the certificate's complete SubjectPublicKeyInfo element,
+/// including its tag and length, as hashed for public key pinning.
+pub(crate) fn subject_public_key_info(cert: &[u8]) -> Result<&[u8], BadDer> {
+    let mut tbs = tbs_at_subject(cert)?;
+    tbs.expect(der::SEQUENCE)?;
+    match tbs.read_element()? {
+        (der::SEQUENCE, spki) => Ok(spki),
+        _ => Err(BadDer {}),
+    }
+}
+
+/// Return the contents of the extnValue OCTET STRING of the certificate's
+/// extension with the given OID, or None if it has no such extension.
+fn extension<'a>(cert: &'a [u8], oid: &[u8]) -> Result<Option<&'a [u8]>, BadDer> {
+    let mut tbs = tbs_at_subject(cert)?;
+    tbs.expect(der::SEQUENCE)?;
+    tbs.expect(der::SEQUENCE)?;
+    // Skip the issuerUniqueID and subjectUniqueID, if present.
+    while !tbs.is_empty() {
+        let (tag, value) = tbs.read()?;
+        if tag != der::CONTEXT_SPECIFIC_CONSTRUCTED_3 {
+            continue;
+        }
+        let mut extensions = der::Reader::new(der::expect_only(value, der::SEQUENCE)?);
+        while !extensions.is_empty() {
+            let mut fields = der::Reader::new(extensions.expect(der::SEQUENCE)?);
+            if fields.expect(der::OID)? != oid {
+                continue;
+            }
+            if fields.peek_tag() == Some(der::BOOLEAN) {
+                fields.read()?;
+            }
+            return Ok(Some(fields.expect(der::OCTET_STRING)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Return the KeyPurposeId OIDs in the certificate's extended key usage
+/// extension, or None if it has none, in which case its use isn't
+/// restricted.
+pub(crate) fn extended_key_usage(cert: &[u8]) -> Result<Option<Vec<&[u8]>>, BadDer> {
+    let value = match extension(cert, EXT_KEY_USAGE)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let mut purposes = der::Reader::new(der::expect_only(value, der::SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !purposes.is_empty() {
+        oids.push(purposes.expect(der::OID)?);
+    }
+    Ok(Some(oids))
+}
+
+/// Return the policy identifier OIDs in the certificate's certificate
+/// policies extension, or None if it has none. Policy qualifiers are
+/// ignored.
+pub(crate) fn certificate_policies(cert: &[u8]) -> Result<Option<Vec<&[u8]>>, BadDer> {
+    let value = match extension(cert, CERTIFICATE_POLICIES)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let mut policies = der::Reader::new(der::expect_only(value, der::SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !policies.is_empty() {
+        let mut policy = der::Reader::new(policies.expect(der::SEQUENCE)?);
+        oids.push(policy.expect(der::OID)?);
+    }
+    Ok(Some(oids))
+}
+
+/// Return the key bits of the certificate's subjectPublicKey BIT STRING,
+/// without the leading count of unused bits, as hashed for an OCSP CertID.
+pub(crate) fn subject_public_key(cert: &[u8]) -> Result<&[u8], BadDer> {
+    let spki = der::expect_only(subject_public_key_info(cert)?, der::SEQUENCE)?;
+    let mut spki = der::Reader::new(spki);
+    spki.expect(der::SEQUENCE)?;
+    match spki.expect(der::BIT_STRING)?.split_first() {
+        Some((0, key)) => Ok(key),
+        _ => Err(BadDer {}),
+    }
+}
+
+/// Return a name for the algorithm of the public key in `spki`, a complete
+/// SubjectPublicKeyInfo element: "ECDSA " and the curve name for EC keys,
+/// "Unknown(<OID>)" for algorithms or curves not listed above.
+pub(crate) fn key_algorithm_name(spki: &[u8]) -> Result<String, BadDer> {
+    let mut spki = der::Reader::new(der::expect_only(spki, der::SEQUENCE)?);
+    let mut algorithm = der::Reader::new(spki.expect(der::SEQUENCE)?);
+    let oid = algorithm.expect(der::OID)?;
+    let (oid, table, prefix) = if oid == ID_EC_PUBLIC_KEY {
+        (algorithm.expect(der::OID)?, CURVES, "ECDSA ")
+    } else {
+        (oid, KEY_ALGORITHMS, "")
+    };
+    let name = match table.iter().find(|(o, _)| *o == oid) {
+        Some((_, name)) => name.to_string(),
+        None => format!("Unknown({})", der::oid_to_string(oid)?),
+    };
+    Ok(format!("{}{}", prefix, name))
+}
+
+/// Return the size in bits of the modulus of the RSA public key in `spki`,
+/// a complete SubjectPublicKeyInfo element, or None if it isn't an RSA key.
+pub(crate) fn rsa_key_bits(spki: &[u8]) -> Result<Option<usize>, BadDer> {
+    let mut spki = der::Reader::new(der::expect_only(spki, der::SEQUENCE)?);
+    let oid = der::Reader::new(spki.expect(der::SEQUENCE)?).expect(der::OID)?;
+    if oid != RSA_ENCRYPTION && oid != ID_RSASSA_PSS {
+        return Ok(None);
+    }
+    let key = match spki.expect(der::BIT_STRING)?.split_first() {
+        Some((0, key)) => key,
+        _ => return Err(BadDer {}),
+    };
+    // RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }
+    let modulus = der::Reader::new(der::expect_only(key, der::SEQUENCE)?).expect(der::INTEGER)?;
+    let modulus = match modulus.iter().position(|&b| b != 0) {
+        Some(i) => &modulus[i..],
+        None => return Err(BadDer {}),
+    };
+    Ok(Some(
+        modulus.len() * 8 - modulus[0].leading_zeros() as usize,
+    ))
+}
+
+/// Read a UTCTime or GeneralizedTime in the restricted forms RFC 5280
+/// requires: YYMMDDHHMMSSZ and YYYYMMDDHHMMSSZ respectively.
+pub(crate) fn read_time(reader: &mut der::Reader) -> Result<i64, BadDer> {
+    let (tag, value) = reader.read()?;
+    let (year, rest) = match (tag, value.len()) {
+        (UTC_TIME, 13) => {
+            // Two-digit years 50-99 are 1950-1999; 00-49 are 2000-2049.
+            let yy = digits(&value[..2])?;
+            (if yy >= 50 { 1900 + yy } else { 2000 + yy }, &value[2..])
+        }
+        (GENERALIZED_TIME, 15) => (digits(&value[..4])?, &value[4..]),
+        _ => return Err(BadDer {}),
+    };
+    if rest[10] != b'Z' {
+        return Err(BadDer {});
+    }
+    let month = digits(&rest[0..2])?;
+    let day = digits(&rest[2..4])?;
+    let hour = digits(&rest[4..6])?;
+    let minute = digits(&rest[6..8])?;
+    let second = digits(&rest[8..10])?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(BadDer {});
+    }
+    let days = days_from_civil(year, month, day);
+    Ok(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+fn digits(input: &[u8]) -> Result<i64, BadDer> {
+    input.iter().try_fold(0, |acc, &b| match b {
+        b'0'..=b'9' => Ok(acc * 10 + (b - b'0') as i64),
+        _ => Err(BadDer {}),
+    })
+}
+
+/// Days since 1970-01-01 of the given proleptic Gregorian date.
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn test_days_from_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn test_validity() {
+        let cert = testutil::certs(testutil::LOCALHOST_2030_PEM).remove(0);
+        // 2030-01-01T00:00:00Z and 2030-12-31T23:59:59Z.
+        assert_eq!(validity(&cert.0), Ok((1893456000, 1924991999)));
+        assert!(validity(&cert.0[..cert.0.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_subject_public_key_info() {
+        // Every test certificate is issued for the same key.
+        let cert = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        let spki = subject_public_key_info(&cert.0).unwrap();
+        let cert_2030 = testutil::certs(testutil::LOCALHOST_2030_PEM).remove(0);
+        assert_eq!(subject_public_key_info(&cert_2030.0), Ok(spki));
+        assert_eq!(spki[0], der::SEQUENCE);
+        assert!(der::expect_only(spki, der::SEQUENCE).is_ok());
+    }
+
+    #[test]
+    fn test_key_algorithm_name() {
+        let cert = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        let spki = subject_public_key_info(&cert.0).unwrap();
+        assert_eq!(key_algorithm_name(spki), Ok("ECDSA P-256".to_string()));
+
+        let spki_with = |algorithm: &[u8]| {
+            let mut body = Vec::new();
+            der::write(&mut body, der::SEQUENCE, algorithm);
+            der::write(&mut body, der::BIT_STRING, &[0, 1, 2, 3]);
+            let mut spki = Vec::new();
+            der::write(&mut spki, der::SEQUENCE, &body);
+            key_algorithm_name(&spki)
+        };
+        let mut ed25519 = Vec::new();
+        der::write(&mut ed25519, der::OID, &[0x2b, 0x65, 0x70]);
+        assert_eq!(spki_with(&ed25519), Ok("Ed25519".to_string()));
+        let mut unknown = Vec::new();
+        der::write(&mut unknown, der::OID, &[0x2a, 0x03, 0x04]);
+        assert_eq!(spki_with(&unknown), Ok("Unknown(1.2.3.4)".to_string()));
+        let mut ec_unknown = Vec::new();
+        der::write(&mut ec_unknown, der::OID, ID_EC_PUBLIC_KEY);
+        der::write(&mut ec_unknown, der::OID, &[0x2a, 0x03]);
+        assert_eq!(
+            spki_with(&ec_unknown),
+            Ok("ECDSA Unknown(1.2.3)".to_string())
+        );
+        assert!(key_algorithm_name(&spki[..spki.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_rsa_key_bits() {
+        let cert = testutil::certs(testutil::LOCALHOST_RSA2048_PEM).remove(0);
+        let spki = subject_public_key_info(&cert.0).unwrap();
+        assert_eq!(rsa_key_bits(spki), Ok(Some(2048)));
+        let cert = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        let spki = subject_public_key_info(&cert.0).unwrap();
+        assert_eq!(rsa_key_bits(spki), Ok(None));
+    }
+
+    #[test]
+    fn test_issuer() {
+        let cert = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        let intermediate = testutil::certs(testutil::INTERMEDIATE_PEM).remove(0);
+        let leaf = testutil::certs(testutil::LOCALHOST_VIA_INTERMEDIATE_PEM).remove(0);
+        // Both issued by the test CA.
+        assert_eq!(issuer(&cert.0), issuer(&intermediate.0));
+        assert_ne!(issuer(&cert.0), issuer(&leaf.0));
+        assert!(der::expect_only(issuer(&leaf.0).unwrap(), der::SEQUENCE).is_ok());
+        assert_eq!(subject(&intermediate.0), issuer(&leaf.0));
+        assert_eq!(serial_number(&leaf.0), Ok(&[0x10, 0x05][..]));
+    }
+
+    #[test]
+    fn test_extended_key_usage() {
+        let cert = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        // id-kp-serverAuth, 1.3.6.1.5.5.7.3.1.
+        let server_auth: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+        assert_eq!(
+            extended_key_usage(&cert.0),
+            Ok(Some(vec![server_auth, ID_KP_CLIENT_AUTH]))
+        );
+        let ca = testutil::certs(testutil::CA_PEM).remove(0);
+        assert_eq!(extended_key_usage(&ca.0), Ok(None));
+    }
+
+    #[test]
+    fn test_certificate_policies() {
+        let cert = testutil::certs(testutil::LOCALHOST_POLICY_PEM).remove(0);
+        let policies = certificate_policies(&cert.0).unwrap().unwrap();
+        let policies: Vec<String> = policies
+            .into_iter()
+            .map(|oid| der::oid_to_string(oid).unwrap())
+            .collect();
+        assert_eq!(policies, vec!["1.3.6.1.4.1.55555.1.2", "2.23.140.1.2.1"]);
+        let cert = testutil::certs(testutil::LOCALHOST_PEM).remove(0);
+        assert_eq!(certificate_policies(&cert.0), Ok(None));
+    }
+}
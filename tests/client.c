@@ -151,7 +151,8 @@ copy_plaintext_to_stdout(struct rustls_connection *client_conn)
  *  - CRUSTLS_DEMO_OK for success
  *  - CRUSTLS_DEMO_AGAIN if we got an EAGAIN or EWOULDBLOCK reading from the
  *    socket
- *  - CRUSTLS_DEMO_EOF if we got EOF
+ *  - CRUSTLS_DEMO_EOF if the server closed the connection without sending
+ *    close_notify
  *  - CRUSTLS_DEMO_ERROR for other errors.
  */
 enum crustls_demo_result
@@ -177,6 +178,11 @@ do_read(struct conndata *conn, struct rustls_connection *rconn)
   }
 
   result = rustls_connection_process_new_packets(rconn);
+  if(result == RUSTLS_RESULT_UNEXPECTED_EOF) {
+    /* The server closed the socket without sending close_notify. Any
+       plaintext sent before that has already been read. */
+    return CRUSTLS_DEMO_EOF;
+  }
   if(result != RUSTLS_RESULT_OK) {
     print_error("in process_new_packets", result);
     return CRUSTLS_DEMO_ERROR;
@@ -243,15 +249,16 @@ send_request_and_read_response(struct conndata *conn,
            path,
            hostname);
   result = rustls_connection_write(rconn, (uint8_t *)buf, strlen(buf), &n);
-  if(result != RUSTLS_RESULT_OK) {
-    fprintf(stderr, "error writing plaintext bytes to rustls_connection\n");
-    goto cleanup;
-  }
-  if(n != strlen(buf)) {
+  if(result == RUSTLS_RESULT_WRITE_WOULD_BLOCK) {
+    /* We set no buffer limit, so the whole request should fit. */
     fprintf(stderr,
             "short write writing plaintext bytes to rustls_connection\n");
     goto cleanup;
   }
+  if(result != RUSTLS_RESULT_OK) {
+    fprintf(stderr, "error writing plaintext bytes to rustls_connection\n");
+    goto cleanup;
+  }
 
   for(;;) {
     FD_ZERO(&read_fds);
@@ -286,6 +293,15 @@ send_request_and_read_response(struct conndata *conn,
           ret = 0;
           goto cleanup;
         }
+        else if(result == CRUSTLS_DEMO_EOF) {
+          /* Some servers close the socket without close_notify once the
+             response is sent. A complete response would already have
+             ended the loop, so this one may have been truncated. */
+          fprintf(stderr,
+                  "server closed the connection without close_notify "
+                  "before the response was complete\n");
+          goto cleanup;
+        }
         else if(result != CRUSTLS_DEMO_OK) {
           goto cleanup;
         }
@@ -144,12 +144,18 @@ send_response(struct conndata *conn)
   struct rustls_connection *rconn = conn->rconn;
   const char *response = "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello\n";
   size_t n;
-  rustls_connection_write(
+  int result;
+  result = rustls_connection_write(
     rconn, (const uint8_t *)response, strlen(response), &n);
-  if(n != strlen(response)) {
+  if(result == RUSTLS_RESULT_WRITE_WOULD_BLOCK) {
+    /* We set no buffer limit, so the whole response should fit. */
     fprintf(stderr, "failed to write all response bytes. wrote %ld\n", n);
     return CRUSTLS_DEMO_ERROR;
   }
+  if(result != RUSTLS_RESULT_OK) {
+    print_error("writing response", result);
+    return CRUSTLS_DEMO_ERROR;
+  }
   return CRUSTLS_DEMO_OK;
 }
 